@@ -0,0 +1,9 @@
+#![no_main]
+
+use ftp::parse;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse::list_data(data);
+    let _ = parse::mlsd_data(data);
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use ftp::parse;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse::pasv_reply(data);
+    if let Ok(reply) = std::str::from_utf8(data) {
+        let _ = parse::epsv(reply);
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use ftp::{parse, types::ParseMode};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse::reply(data, ParseMode::Strict);
+    if let Ok(reply) = parse::reply(data, ParseMode::Lenient) {
+        let _ = parse::mdtm(&reply.line.1);
+        let _ = parse::size(&reply.line.1);
+    }
+});
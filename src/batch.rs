@@ -0,0 +1,131 @@
+//! Sequences of operations executed in one pass.
+//!
+//! An `FtpBatch` queues directory creations, uploads, renames and removals,
+//! then runs them on a connection, returning the result of every operation
+//! instead of leaving the looping and error aggregation to the caller.
+//!
+//! ```rust,no_run
+//! use ftp::batch::FtpBatch;
+//! use ftp::FtpStream;
+//!
+//! let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
+//! ftp_stream.login("anonymous", "anonymous").unwrap();
+//! let mut batch = FtpBatch::new();
+//! batch
+//!     .mkdir("release")
+//!     .put("build/app.tar.gz", "release/app.tar.gz.part")
+//!     .rename("release/app.tar.gz.part", "release/app.tar.gz")
+//!     .stop_on_error(true);
+//! for (op, result) in batch.ops().iter().zip(batch.run(&mut ftp_stream)) {
+//!     println!("{:?}: {:?}", op, result);
+//! }
+//! ```
+
+use super::{transport::Transport, GenericFtpStream};
+
+use std::{fs::File, path::PathBuf};
+
+/// An operation of an `FtpBatch`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchOp {
+    /// Create the remote directory.
+    Mkdir(String),
+    /// Store the local file `local` on the server as `remote`.
+    Put { local: PathBuf, remote: String },
+    /// Rename the remote file `from` to `to`.
+    Rename { from: String, to: String },
+    /// Remove the remote file.
+    Rm(String),
+}
+
+impl BatchOp {
+    fn execute<S: Transport>(&self, ftp_stream: &mut GenericFtpStream<S>) -> crate::Result<()> {
+        match *self {
+            BatchOp::Mkdir(ref pathname) => ftp_stream.mkdir(pathname).map(|_| ()),
+            BatchOp::Put {
+                ref local,
+                ref remote,
+            } => ftp_stream.put(remote, &mut File::open(local)?),
+            BatchOp::Rename { ref from, ref to } => ftp_stream.rename(from, to),
+            BatchOp::Rm(ref filename) => ftp_stream.rm(filename),
+        }
+    }
+}
+
+/// A list of operations executed in order by `run`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FtpBatch {
+    ops: Vec<BatchOp>,
+    stop_on_error: bool,
+}
+
+impl FtpBatch {
+    /// Creates an empty batch, which continues after failed operations.
+    pub fn new() -> FtpBatch {
+        FtpBatch::default()
+    }
+
+    /// Adds an operation to the batch.
+    pub fn push(&mut self, op: BatchOp) -> &mut Self {
+        self.ops.push(op);
+        self
+    }
+
+    /// Adds the creation of the remote directory `pathname`.
+    pub fn mkdir<S: Into<String>>(&mut self, pathname: S) -> &mut Self {
+        self.push(BatchOp::Mkdir(pathname.into()))
+    }
+
+    /// Adds the upload of the local file `local` to `remote`.
+    pub fn put<P: Into<PathBuf>, S: Into<String>>(&mut self, local: P, remote: S) -> &mut Self {
+        self.push(BatchOp::Put {
+            local: local.into(),
+            remote: remote.into(),
+        })
+    }
+
+    /// Adds the renaming of the remote file `from` to `to`.
+    pub fn rename<S: Into<String>, T: Into<String>>(&mut self, from: S, to: T) -> &mut Self {
+        self.push(BatchOp::Rename {
+            from: from.into(),
+            to: to.into(),
+        })
+    }
+
+    /// Adds the removal of the remote file `filename`.
+    pub fn rm<S: Into<String>>(&mut self, filename: S) -> &mut Self {
+        self.push(BatchOp::Rm(filename.into()))
+    }
+
+    /// Sets whether `run` stops at the first failed operation, rather than
+    /// continuing with the next ones.
+    pub fn stop_on_error(&mut self, stop: bool) -> &mut Self {
+        self.stop_on_error = stop;
+        self
+    }
+
+    /// The operations of the batch, in order.
+    pub fn ops(&self) -> &[BatchOp] {
+        &self.ops
+    }
+
+    /// Executes the operations in order, returning the result of each one.
+    ///
+    /// When stopping on errors, the last result is the failure and the
+    /// operations after it have no result.
+    pub fn run<S: Transport>(
+        &self,
+        ftp_stream: &mut GenericFtpStream<S>,
+    ) -> Vec<crate::Result<()>> {
+        let mut results = Vec::with_capacity(self.ops.len());
+        for op in &self.ops {
+            let result = op.execute(ftp_stream);
+            let failed = result.is_err();
+            results.push(result);
+            if failed && self.stop_on_error {
+                break;
+            }
+        }
+        results
+    }
+}
@@ -0,0 +1,328 @@
+//! Block transmission mode, `MODE B`.
+//!
+//! In block mode the data connection carries a sequence of blocks, each
+//! with a header giving its kind and length. Besides file data, a block may
+//! be a restart marker: a checkpoint the receiver records, which `REST`
+//! later resumes the transfer from. Some servers, mainframe ones notably,
+//! only transfer files in block mode.
+//!
+//! `FtpStream::transmission_mode` switches a connection to block mode,
+//! its transfers then use `BlockReader` and `BlockWriter`, which can also be
+//! used on their own:
+//!
+//! ```rust
+//! use ftp::block::{BlockReader, BlockWriter};
+//! use std::io::{Read, Write};
+//!
+//! let mut writer = BlockWriter::new(Vec::new());
+//! writer.set_marker_interval(Some(5));
+//! writer.write_all(b"hello, world!").unwrap();
+//! let blocks = writer.finish().unwrap();
+//!
+//! let mut reader = BlockReader::new(&blocks[..]);
+//! let mut data = String::new();
+//! reader.read_to_string(&mut data).unwrap();
+//! assert_eq!(data, "hello, world!");
+//! let offsets: Vec<_> = reader.markers().iter().map(|m| m.offset).collect();
+//! assert_eq!(offsets, [5, 10]);
+//! ```
+
+use super::{buffer::write_all_vectored, data_stream::DataStream, types::RestartMarker};
+
+use std::{
+    cmp,
+    io::{self, IoSlice, Read, Write},
+};
+
+/// The block is the last one of the file.
+const END_OF_FILE: u8 = 0x40;
+/// The block is a restart marker rather than data.
+const RESTART_MARKER: u8 = 0x10;
+/// The largest block length.
+const MAX_BLOCK: usize = 0xffff;
+
+/// Decodes the data of a block mode transfer, recording its restart markers.
+#[derive(Debug)]
+pub struct BlockReader<R> {
+    inner: R,
+    remaining: usize,
+    last: bool,
+    offset: u64,
+    markers: Vec<RestartMarker>,
+}
+
+impl<R: Read> BlockReader<R> {
+    /// Creates a reader decoding the blocks read from `inner`.
+    pub fn new(inner: R) -> BlockReader<R> {
+        BlockReader {
+            inner,
+            remaining: 0,
+            last: false,
+            offset: 0,
+            markers: Vec::new(),
+        }
+    }
+
+    /// The restart markers read so far, with the number of data bytes which
+    /// preceded each one.
+    pub fn markers(&self) -> &[RestartMarker] {
+        &self.markers
+    }
+
+    /// Returns the inner reader and the restart markers read.
+    pub fn into_parts(self) -> (R, Vec<RestartMarker>) {
+        (self.inner, self.markers)
+    }
+
+    /// Reads the next block header, returning `false` at the end of the data.
+    fn next_block(&mut self) -> io::Result<bool> {
+        let mut header = [0; 3];
+        // Some senders close the connection without an end of file block.
+        if self.inner.read(&mut header[..1])? == 0 {
+            return Ok(false);
+        }
+        self.inner.read_exact(&mut header[1..])?;
+        let descriptor = header[0];
+        let len = usize::from(u16::from_be_bytes([header[1], header[2]]));
+        if descriptor & RESTART_MARKER != 0 {
+            let mut marker = vec![0; len];
+            self.inner.read_exact(&mut marker)?;
+            self.markers.push(RestartMarker {
+                offset: self.offset,
+                marker: String::from_utf8_lossy(&marker).into_owned(),
+            });
+        } else {
+            self.remaining = len;
+        }
+        self.last = descriptor & END_OF_FILE != 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for BlockReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.remaining == 0 {
+            if self.last || !self.next_block()? {
+                return Ok(0);
+            }
+        }
+        let len = cmp::min(buf.len(), self.remaining);
+        let n = self.inner.read(&mut buf[..len])?;
+        if n == 0 && len > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "data connection closed in the middle of a block",
+            ));
+        }
+        self.remaining -= n;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+/// Encodes data into blocks, optionally followed by restart markers at
+/// regular intervals. `finish` must be called to send the end of file block.
+#[derive(Debug)]
+pub struct BlockWriter<W: Write> {
+    inner: W,
+    offset: u64,
+    marker_interval: Option<u64>,
+    next_marker: u64,
+}
+
+impl<W: Write> BlockWriter<W> {
+    /// Creates a writer sending blocks to `inner`, without restart markers.
+    pub fn new(inner: W) -> BlockWriter<W> {
+        BlockWriter {
+            inner,
+            offset: 0,
+            marker_interval: None,
+            next_marker: u64::MAX,
+        }
+    }
+
+    /// Sends a restart marker every `interval` bytes of data. The marker is
+    /// the number of bytes sent before it, in decimal.
+    pub fn set_marker_interval(&mut self, interval: Option<u64>) {
+        self.marker_interval = interval.filter(|&interval| interval > 0);
+        self.next_marker = match self.marker_interval {
+            Some(interval) => self.offset + interval,
+            None => u64::MAX,
+        };
+    }
+
+    /// Sends the end of file block and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(&[END_OF_FILE, 0, 0])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_marker(&mut self) -> io::Result<()> {
+        let marker = self.offset.to_string();
+        let len = marker.len() as u8;
+        write_all_vectored(
+            &mut self.inner,
+            &mut [
+                IoSlice::new(&[RESTART_MARKER, 0, len]),
+                IoSlice::new(marker.as_bytes()),
+            ],
+        )
+    }
+}
+
+impl<W: Write> Write for BlockWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let until_marker = cmp::min(self.next_marker - self.offset, MAX_BLOCK as u64);
+        let len = cmp::min(buf.len(), until_marker as usize);
+        if len == 0 {
+            return Ok(0);
+        }
+        let [hi, lo] = (len as u16).to_be_bytes();
+        // The header and the data leave together.
+        write_all_vectored(
+            &mut self.inner,
+            &mut [IoSlice::new(&[0, hi, lo]), IoSlice::new(&buf[..len])],
+        )?;
+        self.offset += len as u64;
+        if let Some(interval) = self.marker_interval {
+            if self.offset == self.next_marker {
+                self.write_marker()?;
+                self.next_marker += interval;
+            }
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The reading end of a data connection in the current transmission mode.
+pub(crate) enum TransferReader {
+    Stream(DataStream),
+    Block(BlockReader<DataStream>),
+}
+
+impl TransferReader {
+    /// The restart markers read, none in stream mode.
+    pub fn into_markers(self) -> Vec<RestartMarker> {
+        match self {
+            TransferReader::Stream(_) => Vec::new(),
+            TransferReader::Block(reader) => reader.into_parts().1,
+        }
+    }
+}
+
+impl Read for TransferReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            TransferReader::Stream(ref mut stream) => stream.read(buf),
+            TransferReader::Block(ref mut reader) => reader.read(buf),
+        }
+    }
+}
+
+/// The writing end of a data connection in the current transmission mode.
+pub(crate) enum TransferWriter {
+    Stream(DataStream),
+    Block(BlockWriter<DataStream>),
+}
+
+impl TransferWriter {
+    /// Ends the data and returns the data connection.
+    pub fn finish(self) -> io::Result<DataStream> {
+        match self {
+            TransferWriter::Stream(stream) => Ok(stream),
+            TransferWriter::Block(writer) => writer.finish(),
+        }
+    }
+}
+
+impl Write for TransferWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            TransferWriter::Stream(ref mut stream) => stream.write(buf),
+            TransferWriter::Block(ref mut writer) => writer.write(buf),
+        }
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        match *self {
+            TransferWriter::Stream(ref mut stream) => stream.write_vectored(bufs),
+            TransferWriter::Block(ref mut writer) => writer.write_vectored(bufs),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            TransferWriter::Stream(ref mut stream) => stream.flush(),
+            TransferWriter::Block(ref mut writer) => writer.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_reader() {
+        let blocks = [
+            &[0, 0, 3][..],
+            b"abc",
+            &[RESTART_MARKER, 0, 4],
+            b"M001",
+            // The end of record flag is meaningless for files.
+            &[0x80, 0, 2],
+            b"de",
+            &[END_OF_FILE, 0, 1],
+            b"f",
+            b"ignored",
+        ]
+        .concat();
+        let mut reader = BlockReader::new(&blocks[..]);
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"abcdef");
+        assert_eq!(
+            reader.markers(),
+            [RestartMarker {
+                offset: 3,
+                marker: "M001".to_owned()
+            }]
+        );
+
+        let truncated = [0, 0, 3, b'a'];
+        let err = BlockReader::new(&truncated[..])
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn block_writer() {
+        let mut writer = BlockWriter::new(Vec::new());
+        writer.set_marker_interval(Some(4));
+        writer.write_all(b"abcdef").unwrap();
+        let blocks = writer.finish().unwrap();
+        let expected = [
+            &[0, 0, 4][..],
+            b"abcd",
+            &[RESTART_MARKER, 0, 1],
+            b"4",
+            &[0, 0, 2],
+            b"ef",
+            &[END_OF_FILE, 0, 0],
+        ]
+        .concat();
+        assert_eq!(blocks, expected);
+
+        let mut writer = BlockWriter::new(Vec::new());
+        writer.write_all(&vec![7; MAX_BLOCK + 1]).unwrap();
+        let blocks = writer.finish().unwrap();
+        assert_eq!(blocks.len(), MAX_BLOCK + 1 + 3 * 3);
+        assert_eq!(blocks[..3], [0, 0xff, 0xff]);
+    }
+}
@@ -0,0 +1,233 @@
+//! Buffered reading and writing of data connections through a buffer owned
+//! by the stream, see `FtpStream::set_transfer_buffer_size`.
+//!
+//! `BufReader` and `BufWriter` allocate their buffer for every transfer,
+//! and `io::copy` copies through one more on the stack. Sessions moving
+//! thousands of small files reuse a single buffer instead.
+//!
+//! Pieces of data written together, such as a block header and its data,
+//! go through `write_all_vectored` to leave in one system call.
+
+use std::io::{self, BufRead, ErrorKind, IoSlice, Read, Write};
+
+/// The size of the transfer buffer of a new stream.
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A `BufReader` reading into a borrowed buffer.
+pub struct BufferedReader<'a, R> {
+    inner: R,
+    buf: &'a mut [u8],
+    pos: usize,
+    filled: usize,
+}
+
+impl<'a, R: Read> BufferedReader<'a, R> {
+    pub fn new(inner: R, buf: &'a mut [u8]) -> BufferedReader<'a, R> {
+        BufferedReader {
+            inner,
+            buf,
+            pos: 0,
+            filled: 0,
+        }
+    }
+
+    /// Returns the reader, dropping the data buffered but not read.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for BufferedReader<'_, R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        // Large reads skip the buffer once it is empty.
+        if self.pos == self.filled && out.len() >= self.buf.len() {
+            return self.inner.read(out);
+        }
+        let n = self.fill_buf()?.read(out)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for BufferedReader<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.filled {
+            self.filled = self.inner.read(self.buf)?;
+            self.pos = 0;
+        }
+        Ok(&self.buf[self.pos..self.filled])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.filled);
+    }
+}
+
+/// A `BufWriter` writing from a borrowed buffer. Unlike `BufWriter`, the
+/// data still buffered is lost when it is dropped: `into_inner` writes it.
+pub struct BufferedWriter<'a, W: Write> {
+    inner: W,
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a, W: Write> BufferedWriter<'a, W> {
+    pub fn new(inner: W, buf: &'a mut [u8]) -> BufferedWriter<'a, W> {
+        BufferedWriter { inner, buf, len: 0 }
+    }
+
+    /// Writes the buffered data and returns the writer.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.flush_buf()?;
+        Ok(self.inner)
+    }
+
+    fn flush_buf(&mut self) -> io::Result<()> {
+        let res = self.inner.write_all(&self.buf[..self.len]);
+        self.len = 0;
+        res
+    }
+}
+
+impl<W: Write> Write for BufferedWriter<'_, W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.len + data.len() > self.buf.len() {
+            // The buffered data and `data` leave together.
+            let mut slices = [IoSlice::new(&self.buf[..self.len]), IoSlice::new(data)];
+            self.len = 0;
+            write_all_vectored(&mut self.inner, &mut slices)?;
+            return Ok(data.len());
+        }
+        self.buf[self.len..self.len + data.len()].copy_from_slice(data);
+        self.len += data.len();
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_buf()?;
+        self.inner.flush()
+    }
+}
+
+/// `io::copy` through `buf`, returning the number of bytes copied.
+pub fn copy<R, W>(reader: &mut R, writer: &mut W, buf: &mut [u8]) -> io::Result<u64>
+where
+    R: Read + ?Sized,
+    W: Write + ?Sized,
+{
+    let mut bytes = 0;
+    loop {
+        let n = match reader.read(buf) {
+            Ok(0) => return Ok(bytes),
+            Ok(n) => n,
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        writer.write_all(&buf[..n])?;
+        bytes += n as u64;
+    }
+}
+
+/// Writes every slice of `slices`, with as few `write_vectored` calls as
+/// the writer allows. Writers which don't implement vectored writes write
+/// the slices one by one.
+pub fn write_all_vectored<W: Write + ?Sized>(
+    writer: &mut W,
+    mut slices: &mut [IoSlice],
+) -> io::Result<()> {
+    IoSlice::advance_slices(&mut slices, 0);
+    while !slices.is_empty() {
+        match writer.write_vectored(slices) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(n) => IoSlice::advance_slices(&mut slices, n),
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn buffered_reader() {
+        let data: Vec<u8> = (0..100).collect();
+        let mut buf = [0; 16];
+        let mut reader = BufferedReader::new(Cursor::new(&data), &mut buf);
+        let mut small = [0; 3];
+        reader.read_exact(&mut small).unwrap();
+        assert_eq!(small, [0, 1, 2]);
+        assert_eq!(reader.fill_buf().unwrap(), &data[3..16]);
+        reader.consume(10);
+        let mut large = [0; 32];
+        reader.read_exact(&mut large).unwrap();
+        assert_eq!(large[..], data[13..45]);
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, &data[45..]);
+    }
+
+    #[test]
+    fn buffered_writer_and_copy() {
+        let data: Vec<u8> = (0..100).collect();
+        let mut buf = [0; 16];
+        let mut writer = BufferedWriter::new(Vec::new(), &mut buf);
+        writer.write_all(&data[..10]).unwrap();
+        writer.write_all(&data[10..20]).unwrap();
+        writer.write_all(&data[20..50]).unwrap();
+        let mut copy_buf = [0; 7];
+        let bytes = copy(&mut Cursor::new(&data[50..]), &mut writer, &mut copy_buf).unwrap();
+        assert_eq!(bytes, 50);
+        assert_eq!(writer.into_inner().unwrap(), data);
+    }
+
+    /// Writes at most 5 bytes per call, counting the calls.
+    struct Trickle(Vec<u8>, usize);
+
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.write_vectored(&[IoSlice::new(buf)])
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+            self.1 += 1;
+            let mut n = 0;
+            for buf in bufs {
+                let len = buf.len().min(5 - n);
+                self.0.extend_from_slice(&buf[..len]);
+                n += len;
+            }
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn vectored_writes() {
+        let mut writer = Trickle(Vec::new(), 0);
+        let mut slices = [
+            IoSlice::new(b"NOOP\r\n"),
+            IoSlice::new(b""),
+            IoSlice::new(b"PWD\r\n"),
+        ];
+        write_all_vectored(&mut writer, &mut slices).unwrap();
+        assert_eq!(writer.0, b"NOOP\r\nPWD\r\n");
+        assert_eq!(writer.1, 3);
+
+        // Buffered data and the write overflowing the buffer leave together.
+        let mut buf = [0; 8];
+        let mut buffered = BufferedWriter::new(Trickle(Vec::new(), 0), &mut buf);
+        buffered.write_all(b"abc").unwrap();
+        buffered.write_all(b"defgh").unwrap();
+        assert_eq!(buffered.inner.1, 0);
+        buffered.write_all(b"ij").unwrap();
+        assert_eq!(buffered.inner.1, 2);
+        assert_eq!(buffered.into_inner().unwrap().0, b"abcdefghij");
+    }
+}
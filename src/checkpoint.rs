@@ -0,0 +1,178 @@
+//! Checkpoints of transfers, to resume them after a restart of the process.
+//!
+//! `FtpStream::retr_checkpointed` and `put_checkpointed` hand a
+//! `TransferCheckpoint` to a callback every few megabytes, and once the
+//! transfer completes. Batch jobs save it, e.g. with `save` of the `json`
+//! feature, and after a crash pass the last one saved back to the same
+//! method to resume the transfer from its `offset` with `REST`. The size and modification time
+//! of the file, recorded when the transfer started, tell whether it
+//! changed meanwhile, in which case resuming fails with
+//! `FtpError::Changed`.
+//!
+//! ```rust,no_run
+//! use ftp::checkpoint::TransferCheckpoint;
+//! use ftp::FtpStream;
+//! use std::fs::File;
+//! use std::path::Path;
+//!
+//! let state = Path::new("dump.sql.checkpoint");
+//! let checkpoint = TransferCheckpoint::load(state)
+//!     .unwrap()
+//!     .unwrap_or_else(|| TransferCheckpoint::new("/backups/dump.sql"));
+//! let mut local = File::options().create(true).append(true).open("dump.sql").unwrap();
+//! // Data written after the last checkpoint saved is downloaded again.
+//! local.set_len(checkpoint.offset).unwrap();
+//!
+//! let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
+//! ftp_stream.login("anonymous", "anonymous").unwrap();
+//! ftp_stream
+//!     .retr_checkpointed(checkpoint, &mut local, 16 << 20, |checkpoint| {
+//!         checkpoint.save(state)
+//!     })
+//!     .unwrap();
+//! ```
+
+#[cfg(feature = "json")]
+use {
+    super::state,
+    serde_json::{json, Value},
+    std::{io, path::Path},
+};
+
+use chrono::{DateTime, Utc};
+
+/// The progress of a transfer, see the module documentation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferCheckpoint {
+    /// The path of the remote file.
+    pub remote_path: String,
+    /// The number of bytes of the file transferred.
+    pub offset: u64,
+    /// The size of the file when the transfer started: the remote one for a
+    /// download, if the server reports it, and the local one for an upload.
+    pub size: Option<u64>,
+    /// The modification time of the remote file when a download started,
+    /// if the server reports it. Always `None` for an upload.
+    pub mdtm: Option<DateTime<Utc>>,
+}
+
+impl TransferCheckpoint {
+    /// A checkpoint at the start of the file at `remote_path`, to start a
+    /// new transfer.
+    pub fn new<S: Into<String>>(remote_path: S) -> TransferCheckpoint {
+        TransferCheckpoint {
+            remote_path: remote_path.into(),
+            offset: 0,
+            size: None,
+            mdtm: None,
+        }
+    }
+
+    /// Returns `true` if the whole file was transferred.
+    pub fn is_complete(&self) -> bool {
+        self.size.is_some_and(|size| self.offset >= size)
+    }
+
+    /// The checkpoint as a JSON object.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> String {
+        json!({
+            "remote_path": self.remote_path,
+            "offset": self.offset,
+            "size": self.size,
+            "mdtm": self.mdtm.map(|mdtm| mdtm.to_rfc3339()),
+        })
+        .to_string()
+    }
+
+    /// Reads a checkpoint from the JSON object written by `to_json`.
+    #[cfg(feature = "json")]
+    pub fn from_json(data: &str) -> io::Result<TransferCheckpoint> {
+        let value: Value = serde_json::from_str(data)?;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid transfer checkpoint");
+        let mdtm = match value["mdtm"] {
+            Value::Null => None,
+            ref mdtm => {
+                let mdtm = mdtm.as_str().ok_or_else(invalid)?;
+                let mdtm = DateTime::parse_from_rfc3339(mdtm).map_err(|_| invalid())?;
+                Some(mdtm.with_timezone(&Utc))
+            }
+        };
+        Ok(TransferCheckpoint {
+            remote_path: value["remote_path"]
+                .as_str()
+                .ok_or_else(invalid)?
+                .to_owned(),
+            offset: value["offset"].as_u64().ok_or_else(invalid)?,
+            size: match value["size"] {
+                Value::Null => None,
+                ref size => Some(size.as_u64().ok_or_else(invalid)?),
+            },
+            mdtm,
+        })
+    }
+
+    /// Reads the checkpoint saved at `path`, or returns `None` if there is
+    /// no such file.
+    #[cfg(feature = "json")]
+    pub fn load(path: &Path) -> io::Result<Option<TransferCheckpoint>> {
+        state::load(path)?
+            .map(|data| TransferCheckpoint::from_json(&data))
+            .transpose()
+    }
+
+    /// Saves the checkpoint to `path`, replacing it atomically.
+    #[cfg(feature = "json")]
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        state::save(path, &self.to_json())
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::fs;
+
+    #[test]
+    fn json_round_trip() {
+        let checkpoint = TransferCheckpoint {
+            remote_path: "/backups/dump.sql".to_owned(),
+            offset: 1 << 33,
+            size: Some(5 << 32),
+            mdtm: Some(Utc.with_ymd_and_hms(2024, 2, 29, 23, 59, 1).unwrap()),
+        };
+        let json = checkpoint.to_json();
+        assert_eq!(TransferCheckpoint::from_json(&json).unwrap(), checkpoint);
+        let new = TransferCheckpoint::new("a.txt");
+        assert_eq!(TransferCheckpoint::from_json(&new.to_json()).unwrap(), new);
+        assert!(!new.is_complete());
+
+        for invalid in [
+            "nope",
+            "[]",
+            r#"{"remote_path": "a", "offset": -1, "size": null, "mdtm": null}"#,
+            r#"{"remote_path": "a", "offset": 1, "size": "2", "mdtm": null}"#,
+            r#"{"remote_path": "a", "offset": 1, "size": 2, "mdtm": "yesterday"}"#,
+        ] {
+            let err = TransferCheckpoint::from_json(invalid).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join("ftp_transfer_checkpoint.json");
+        let _ = fs::remove_file(&path);
+        assert_eq!(TransferCheckpoint::load(&path).unwrap(), None);
+        let checkpoint = TransferCheckpoint {
+            offset: 3,
+            size: Some(3),
+            ..TransferCheckpoint::new("a.txt")
+        };
+        assert!(checkpoint.is_complete());
+        checkpoint.save(&path).unwrap();
+        assert_eq!(TransferCheckpoint::load(&path).unwrap(), Some(checkpoint));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,165 @@
+//! Trait abstracting the operations of an FTP client.
+
+use super::{
+    transport::Transport,
+    types::{FileType, LoginInfo},
+    GenericFtpStream,
+};
+
+use {
+    chrono::{DateTime, Utc},
+    std::io::{Cursor, Read},
+};
+
+/// The operations of an FTP session, implemented by `FtpStream`.
+///
+/// Application code written against this trait rather than the concrete
+/// stream can be unit-tested with a mock implementation instead of a live
+/// server. The trait is object safe, so `Box<dyn FtpClient>` works as well.
+///
+/// ```rust,no_run
+/// use ftp::{FtpClient, FtpStream};
+///
+/// fn fetch_report<C: FtpClient>(client: &mut C) -> ftp::Result<Vec<u8>> {
+///     client.cwd("reports")?;
+///     client.simple_retr("latest.csv").map(|cursor| cursor.into_inner())
+/// }
+///
+/// let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
+/// ftp_stream.login("anonymous", "anonymous").unwrap();
+/// let report = fetch_report(&mut ftp_stream).unwrap();
+///
+/// // The trait can be used as a trait object too.
+/// let mut client: Box<dyn FtpClient> = Box::new(ftp_stream);
+/// client.quit().unwrap();
+/// ```
+pub trait FtpClient {
+    /// Log in to the FTP server.
+    fn login(&mut self, user: &str, password: &str) -> crate::Result<LoginInfo>;
+
+    /// Change the current directory to the path specified.
+    fn cwd(&mut self, path: &str) -> crate::Result<()>;
+
+    /// Move the current directory to the parent directory.
+    fn cdup(&mut self) -> crate::Result<()>;
+
+    /// Gets the current directory.
+    fn pwd(&mut self) -> crate::Result<String>;
+
+    /// Sends a NOOP, usually to keep the connection open.
+    fn noop(&mut self) -> crate::Result<()>;
+
+    /// Creates a new directory on the server, returning its path if the
+    /// server replied it.
+    fn mkdir(&mut self, pathname: &str) -> crate::Result<Option<String>>;
+
+    /// Sets the type of file to be transferred.
+    fn transfer_type(&mut self, file_type: FileType) -> crate::Result<()>;
+
+    /// Quits the current FTP session.
+    fn quit(&mut self) -> crate::Result<()>;
+
+    /// Renames the file `from_name` to `to_name`.
+    fn rename(&mut self, from_name: &str, to_name: &str) -> crate::Result<()>;
+
+    /// Retrieves a file, storing it in memory.
+    fn simple_retr(&mut self, file_name: &str) -> crate::Result<Cursor<Vec<u8>>>;
+
+    /// Retrieves a file into `buffer`, returning the number of bytes read.
+    fn retr_into(&mut self, file_name: &str, buffer: &mut Vec<u8>) -> crate::Result<usize>;
+
+    /// Removes the remote directory `pathname`.
+    fn rmdir(&mut self, pathname: &str) -> crate::Result<()>;
+
+    /// Removes the remote file `filename`.
+    fn rm(&mut self, filename: &str) -> crate::Result<()>;
+
+    /// Stores the contents of `r` on the server as `filename`.
+    fn put(&mut self, filename: &str, r: &mut dyn Read) -> crate::Result<()>;
+
+    /// Detailed listing of `pathname`, or of the current directory.
+    fn list(&mut self, pathname: Option<&str>) -> crate::Result<Vec<String>>;
+
+    /// Names of the files in `pathname`, or in the current directory.
+    fn nlst(&mut self, pathname: Option<&str>) -> crate::Result<Vec<String>>;
+
+    /// Modification time of the file at `pathname`.
+    fn mdtm(&mut self, pathname: &str) -> crate::Result<Option<DateTime<Utc>>>;
+
+    /// Size in bytes of the file at `pathname`.
+    fn size(&mut self, pathname: &str) -> crate::Result<Option<usize>>;
+}
+
+impl<S: Transport> FtpClient for GenericFtpStream<S> {
+    fn login(&mut self, user: &str, password: &str) -> crate::Result<LoginInfo> {
+        GenericFtpStream::login(self, user, password)
+    }
+
+    fn cwd(&mut self, path: &str) -> crate::Result<()> {
+        GenericFtpStream::cwd(self, path)
+    }
+
+    fn cdup(&mut self) -> crate::Result<()> {
+        GenericFtpStream::cdup(self)
+    }
+
+    fn pwd(&mut self) -> crate::Result<String> {
+        GenericFtpStream::pwd(self)
+    }
+
+    fn noop(&mut self) -> crate::Result<()> {
+        GenericFtpStream::noop(self)
+    }
+
+    fn mkdir(&mut self, pathname: &str) -> crate::Result<Option<String>> {
+        GenericFtpStream::mkdir(self, pathname)
+    }
+
+    fn transfer_type(&mut self, file_type: FileType) -> crate::Result<()> {
+        GenericFtpStream::transfer_type(self, file_type)
+    }
+
+    fn quit(&mut self) -> crate::Result<()> {
+        GenericFtpStream::quit(self)
+    }
+
+    fn rename(&mut self, from_name: &str, to_name: &str) -> crate::Result<()> {
+        GenericFtpStream::rename(self, from_name, to_name)
+    }
+
+    fn simple_retr(&mut self, file_name: &str) -> crate::Result<Cursor<Vec<u8>>> {
+        GenericFtpStream::simple_retr(self, file_name)
+    }
+
+    fn retr_into(&mut self, file_name: &str, buffer: &mut Vec<u8>) -> crate::Result<usize> {
+        GenericFtpStream::retr_into(self, file_name, buffer)
+    }
+
+    fn rmdir(&mut self, pathname: &str) -> crate::Result<()> {
+        GenericFtpStream::rmdir(self, pathname)
+    }
+
+    fn rm(&mut self, filename: &str) -> crate::Result<()> {
+        GenericFtpStream::rm(self, filename)
+    }
+
+    fn put(&mut self, filename: &str, mut r: &mut dyn Read) -> crate::Result<()> {
+        GenericFtpStream::put(self, filename, &mut r)
+    }
+
+    fn list(&mut self, pathname: Option<&str>) -> crate::Result<Vec<String>> {
+        GenericFtpStream::list(self, pathname)
+    }
+
+    fn nlst(&mut self, pathname: Option<&str>) -> crate::Result<Vec<String>> {
+        GenericFtpStream::nlst(self, pathname)
+    }
+
+    fn mdtm(&mut self, pathname: &str) -> crate::Result<Option<DateTime<Utc>>> {
+        GenericFtpStream::mdtm(self, pathname)
+    }
+
+    fn size(&mut self, pathname: &str) -> crate::Result<Option<usize>> {
+        GenericFtpStream::size(self, pathname)
+    }
+}
@@ -3,73 +3,81 @@ use native_tls::TlsStream;
 #[cfg(all(feature = "secure", not(feature = "native-tls")))]
 use openssl::ssl::SslStream;
 
+use super::transport::Transport;
+
 use std::{
-    io::{Read, Result, Write},
+    io::{IoSlice, Read, Result, Write},
     net::TcpStream,
 };
 
-/// Data Stream used for communications
+/// Data Stream used for communications: a data connection, or the control
+/// connection of a `GenericFtpStream`, plain or secured with TLS.
 #[derive(Debug)]
-pub enum DataStream {
-    Tcp(TcpStream),
+pub enum DataStream<S = TcpStream> {
+    Plain(S),
     #[cfg(all(feature = "secure", not(feature = "native-tls")))]
-    Ssl(SslStream<TcpStream>),
+    Ssl(SslStream<S>),
     #[cfg(all(feature = "secure", feature = "native-tls"))]
-    Ssl(TlsStream<TcpStream>),
+    Ssl(TlsStream<S>),
 }
 
 #[cfg(feature = "secure")]
-impl DataStream {
-    /// Unwrap the stream into TcpStream. This method is only used in secure connection.
-    pub fn into_tcp_stream(self) -> TcpStream {
+impl<S: Transport> DataStream<S> {
+    /// Unwrap the stream into the underlying transport. This method is only used in secure connection.
+    pub fn into_transport(self) -> Result<S> {
         match self {
-            DataStream::Tcp(stream) => stream,
-            DataStream::Ssl(stream) => stream.get_ref().try_clone().unwrap(),
+            DataStream::Plain(stream) => Ok(stream),
+            DataStream::Ssl(stream) => stream.get_ref().try_clone(),
         }
     }
 
     /// Test if the stream is secured
     pub fn is_ssl(&self) -> bool {
-        match self {
-            &DataStream::Ssl(_) => true,
-            _ => false,
-        }
+        matches!(*self, DataStream::Ssl(_))
     }
 }
 
-impl DataStream {
-    /// Returns a reference to the underlying TcpStream.
-    pub fn get_ref(&self) -> &TcpStream {
+impl<S: Transport> DataStream<S> {
+    /// Returns a reference to the underlying transport.
+    pub fn get_ref(&self) -> &S {
         match *self {
-            DataStream::Tcp(ref stream) => stream,
+            DataStream::Plain(ref stream) => stream,
             #[cfg(feature = "secure")]
             DataStream::Ssl(ref stream) => stream.get_ref(),
         }
     }
 }
 
-impl Read for DataStream {
+impl<S: Transport> Read for DataStream<S> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         match *self {
-            DataStream::Tcp(ref mut stream) => stream.read(buf),
+            DataStream::Plain(ref mut stream) => stream.read(buf),
             #[cfg(feature = "secure")]
             DataStream::Ssl(ref mut stream) => stream.read(buf),
         }
     }
 }
 
-impl Write for DataStream {
+impl<S: Transport> Write for DataStream<S> {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
         match *self {
-            DataStream::Tcp(ref mut stream) => stream.write(buf),
+            DataStream::Plain(ref mut stream) => stream.write(buf),
             #[cfg(feature = "secure")]
             DataStream::Ssl(ref mut stream) => stream.write(buf),
         }
     }
 
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> Result<usize> {
+        match *self {
+            DataStream::Plain(ref mut stream) => stream.write_vectored(bufs),
+            #[cfg(feature = "secure")]
+            DataStream::Ssl(ref mut stream) => stream.write_vectored(bufs),
+        }
+    }
+
     fn flush(&mut self) -> Result<()> {
         match *self {
-            DataStream::Tcp(ref mut stream) => stream.flush(),
+            DataStream::Plain(ref mut stream) => stream.flush(),
             #[cfg(feature = "secure")]
             DataStream::Ssl(ref mut stream) => stream.flush(),
         }
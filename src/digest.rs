@@ -0,0 +1,147 @@
+//! Digests of transferred data, computed as the bytes flow.
+//!
+//! With the `digest` feature, `FtpStream::put_with_digest` and
+//! `FtpStream::retr_with_digest` return a CRC32, MD5 or SHA-256 digest of
+//! the data alongside the result, to compare with a checksum published by
+//! the server without reading the data a second time.
+//!
+//! ```rust,no_run
+//! use ftp::digest::Algorithm;
+//! use ftp::FtpStream;
+//! use std::io::Cursor;
+//!
+//! let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
+//! ftp_stream.login("anonymous", "anonymous").unwrap();
+//! let digest = ftp_stream
+//!     .put_with_digest("data.bin", &mut Cursor::new(vec![0u8; 1024]), Algorithm::Sha256)
+//!     .unwrap();
+//! println!("uploaded data.bin, sha256 {}", digest);
+//! ```
+
+use md5::Md5;
+use sha2::Digest as _;
+use sha2::Sha256;
+
+use std::{
+    fmt,
+    io::{Read, Result},
+};
+
+/// A digest algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Crc32,
+    Md5,
+    Sha256,
+}
+
+/// The digest of some data, displayed as lowercase hexadecimal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Digest {
+    /// The algorithm which computed the digest.
+    pub algorithm: Algorithm,
+    /// The digest, big-endian for CRC32.
+    pub bytes: Vec<u8>,
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in &self.bytes {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+enum Hasher {
+    Crc32(crc32fast::Hasher),
+    Md5(Md5),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    fn new(algorithm: Algorithm) -> Hasher {
+        match algorithm {
+            Algorithm::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+            Algorithm::Md5 => Hasher::Md5(Md5::new()),
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match *self {
+            Hasher::Crc32(ref mut hasher) => hasher.update(data),
+            Hasher::Md5(ref mut hasher) => hasher.update(data),
+            Hasher::Sha256(ref mut hasher) => hasher.update(data),
+        }
+    }
+
+    fn finish(self) -> Digest {
+        match self {
+            Hasher::Crc32(hasher) => Digest {
+                algorithm: Algorithm::Crc32,
+                bytes: hasher.finalize().to_be_bytes().to_vec(),
+            },
+            Hasher::Md5(hasher) => Digest {
+                algorithm: Algorithm::Md5,
+                bytes: hasher.finalize().to_vec(),
+            },
+            Hasher::Sha256(hasher) => Digest {
+                algorithm: Algorithm::Sha256,
+                bytes: hasher.finalize().to_vec(),
+            },
+        }
+    }
+}
+
+/// A reader computing the digest of the bytes read through it.
+pub struct DigestReader<R> {
+    inner: R,
+    hasher: Hasher,
+}
+
+impl<R: Read> DigestReader<R> {
+    pub fn new(inner: R, algorithm: Algorithm) -> DigestReader<R> {
+        DigestReader {
+            inner,
+            hasher: Hasher::new(algorithm),
+        }
+    }
+
+    /// The digest of the bytes read so far.
+    pub fn finish(self) -> Digest {
+        self.hasher.finish()
+    }
+}
+
+impl<R: Read> Read for DigestReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(data: &[u8], algorithm: Algorithm) -> String {
+        let mut reader = DigestReader::new(data, algorithm);
+        std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+        reader.finish().to_string()
+    }
+
+    #[test]
+    fn known_digests() {
+        assert_eq!(digest(b"123456789", Algorithm::Crc32), "cbf43926");
+        assert_eq!(
+            digest(b"abc", Algorithm::Md5),
+            "900150983cd24fb0d6963f7d28e17f72"
+        );
+        assert_eq!(
+            digest(b"abc", Algorithm::Sha256),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Typed events of a session, for user interfaces.
+//!
+//! A channel set with `FtpStream::set_event_sender` receives a
+//! `SessionEvent` when the connection is established, secured, logged in
+//! or closed, and when transfers start, progress and end. A GUI file
+//! manager can run the session on a worker thread and update its window
+//! from the events, without polling the stream.
+//!
+//! ```rust,no_run
+//! use ftp::events::SessionEvent;
+//! use ftp::FtpStream;
+//! use std::sync::mpsc;
+//! use std::thread;
+//!
+//! let (sender, receiver) = mpsc::channel();
+//! let worker = thread::spawn(move || {
+//!     let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
+//!     ftp_stream.set_event_sender(Some(sender));
+//!     ftp_stream.login("anonymous", "anonymous").unwrap();
+//!     ftp_stream.get_bytes("big.iso").unwrap();
+//!     ftp_stream.quit().unwrap();
+//! });
+//! // Ends once the worker drops the stream, and the sender with it.
+//! for event in receiver {
+//!     match event {
+//!         SessionEvent::TransferProgress { bytes } => println!("{} bytes", bytes),
+//!         event => println!("{:?}", event),
+//!     }
+//! }
+//! worker.join().unwrap();
+//! ```
+
+use std::{
+    net::SocketAddr,
+    sync::mpsc::Sender,
+    time::{Duration, Instant},
+};
+
+/// How often `TransferProgress` is sent during a transfer at most.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An event of a session, see the module documentation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// The control connection is established. Sent when the sender is set,
+    /// for the connection already open, and for every reconnection.
+    Connected {
+        /// The address of the server, if the transport has one.
+        peer: Option<SocketAddr>,
+    },
+    /// The control connection is secured with TLS.
+    TlsEstablished,
+    /// The server accepted the login of `user`.
+    LoggedIn { user: String },
+    /// A transfer or listing started with `command`, such as `RETR` or
+    /// `LIST`, on `path` if it has one.
+    TransferStarted {
+        command: &'static str,
+        path: Option<String>,
+    },
+    /// `bytes` bytes went through the data connection of the transfer so
+    /// far, sent about ten times a second by downloads and uploads.
+    TransferProgress { bytes: u64 },
+    /// The transfer completed after `bytes` bytes.
+    TransferCompleted { bytes: u64 },
+    /// The transfer failed or was aborted.
+    TransferFailed,
+    /// The session ended: the client quit or the server closed it.
+    Disconnected,
+}
+
+/// Sends `event` through `sender`, if any. A receiver which hung up is
+/// ignored, so that closing a window never fails the session.
+pub(crate) fn send(sender: Option<&Sender<SessionEvent>>, event: SessionEvent) {
+    if let Some(sender) = sender {
+        let _ = sender.send(event);
+    }
+}
+
+/// Sends the `TransferProgress` of a transfer, at most every
+/// `PROGRESS_INTERVAL`.
+pub(crate) struct Progress {
+    sender: Sender<SessionEvent>,
+    last: Instant,
+}
+
+impl Progress {
+    pub fn new(sender: Sender<SessionEvent>) -> Progress {
+        Progress {
+            sender,
+            last: Instant::now(),
+        }
+    }
+
+    /// Reports that `bytes` bytes were transferred so far.
+    pub fn update(&mut self, bytes: u64) {
+        if self.last.elapsed() >= PROGRESS_INTERVAL {
+            self.last = Instant::now();
+            let _ = self.sender.send(SessionEvent::TransferProgress { bytes });
+        }
+    }
+}
@@ -0,0 +1,284 @@
+//! Remote files read like local ones.
+//!
+//! `FtpStream::open` returns an `FtpFile`, which implements `Read` and
+//! `Seek`, so it can be handed to code expecting a local file, such as a
+//! parser reading the index at the end of an archive. Reading starts a
+//! download from the current position, and seeking elsewhere closes it: the
+//! next read resumes the download from the new position with `REST`.
+//!
+//! `FtpStream::create` returns an `FtpFile` which can also be written,
+//! uploading from the current position the same way, e.g. to fill in a
+//! header once content of unknown length was streamed after it.
+//!
+//! ```rust,no_run
+//! use ftp::FtpStream;
+//! use std::io::{Read, Seek, SeekFrom};
+//!
+//! let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
+//! ftp_stream.login("anonymous", "anonymous").unwrap();
+//! let mut file = ftp_stream.open("archive.zip").unwrap();
+//! // The end of central directory record is in the last 22 bytes.
+//! file.seek(SeekFrom::End(-22)).unwrap();
+//! let mut record = [0; 22];
+//! file.read_exact(&mut record).unwrap();
+//! ```
+
+use super::{
+    block::{TransferReader, TransferWriter},
+    ftp::GenericFtpStream,
+    trace::TransferTrace,
+    transport::Transport,
+    FtpError,
+};
+
+use std::{
+    fmt,
+    io::{self, Read, Seek, SeekFrom, Write},
+    net::TcpStream,
+};
+
+/// The transfer an `FtpFile` reads from or writes to.
+enum Transfer {
+    Download(TransferReader),
+    Upload(TransferWriter),
+}
+
+/// A remote file opened by `GenericFtpStream::open` or `create`. It borrows
+/// the connection, since the control connection is busy while a transfer
+/// is open; dropping the file ends the transfer.
+///
+/// Writing at offset 0 fails with `Unsupported` unless the file is empty:
+/// servers truncate the file on a `STOR` without a restart offset.
+pub struct FtpFile<'a, S: Transport = TcpStream> {
+    ftp_stream: &'a mut GenericFtpStream<S>,
+    path: String,
+    pos: u64,
+    len: Option<u64>,
+    transfer: Option<Transfer>,
+    /// The span of the transfer and the position it started from.
+    trace: Option<(TransferTrace, u64)>,
+}
+
+impl<'a, S: Transport> FtpFile<'a, S> {
+    pub(crate) fn new(
+        ftp_stream: &'a mut GenericFtpStream<S>,
+        path: String,
+        len: Option<u64>,
+    ) -> FtpFile<'a, S> {
+        FtpFile {
+            ftp_stream,
+            path,
+            pos: 0,
+            len,
+            transfer: None,
+            trace: None,
+        }
+    }
+
+    /// The path the file was opened with.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The length of the file, as reported by `SIZE` when the file was
+    /// opened, or found by reading it to the end if the server doesn't
+    /// support `SIZE`, and extended by writes past its end.
+    pub fn len(&self) -> Option<u64> {
+        self.len
+    }
+
+    /// Returns `true` if the file is known to be empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == Some(0)
+    }
+
+    /// Ends the transfer in progress, if any, so the connection can be used
+    /// again. Dropping the file does the same but ignores errors, such as
+    /// the server failing to store the data written.
+    pub fn close(mut self) -> crate::Result<()> {
+        self.close_transfer()
+    }
+
+    fn close_transfer(&mut self) -> crate::Result<()> {
+        self.finish_trace();
+        match self.transfer.take() {
+            Some(Transfer::Download(reader)) => {
+                // Closing the data connection first makes the server end
+                // the transfer even if it has data left to send.
+                drop(reader);
+                self.ftp_stream.finish_retr(false, self.pos)
+            }
+            Some(Transfer::Upload(writer)) => self.ftp_stream.finish_stor(writer, self.pos),
+            None => Ok(()),
+        }
+    }
+
+    fn start_trace(&mut self, command: &'static str) {
+        let trace = self.ftp_stream.transfer_trace(command, Some(&self.path));
+        self.trace = Some((trace, self.pos));
+    }
+
+    fn finish_trace(&mut self) {
+        if let Some((trace, start)) = self.trace.take() {
+            trace.finish(self.pos - start);
+        }
+    }
+}
+
+/// Converts an error of the connection to the error type of the `std::io` traits.
+fn io_error(err: FtpError) -> io::Error {
+    match err {
+        FtpError::ConnectionError(err) => err,
+        err => io::Error::other(err),
+    }
+}
+
+impl<S: Transport> Read for FtpFile<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.len.is_some_and(|len| self.pos >= len) {
+            return Ok(0);
+        }
+        let transfer = match self.transfer {
+            Some(Transfer::Download(ref mut reader)) => reader,
+            _ => {
+                self.close_transfer().map_err(io_error)?;
+                let reader = self
+                    .ftp_stream
+                    .retr_from(&self.path, self.pos)
+                    .map_err(io_error)?;
+                self.start_trace("RETR");
+                match self.transfer.insert(Transfer::Download(reader)) {
+                    Transfer::Download(reader) => reader,
+                    Transfer::Upload(_) => unreachable!(),
+                }
+            }
+        };
+        match transfer.read(buf) {
+            Ok(0) => {
+                self.transfer = None;
+                self.finish_trace();
+                self.ftp_stream
+                    .finish_retr(true, self.pos)
+                    .map_err(io_error)?;
+                self.len = Some(self.pos);
+                Ok(0)
+            }
+            Ok(n) => {
+                self.pos += n as u64;
+                Ok(n)
+            }
+            Err(err) => {
+                // The reply may explain the failure better, as when the
+                // server aborted the transfer.
+                match self.close_transfer() {
+                    Err(FtpError::ConnectionError(_)) | Ok(()) => Err(err),
+                    Err(reply) => Err(io_error(reply)),
+                }
+            }
+        }
+    }
+}
+
+impl<S: Transport> Write for FtpFile<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let transfer = match self.transfer {
+            Some(Transfer::Upload(ref mut writer)) => writer,
+            _ => {
+                self.close_transfer().map_err(io_error)?;
+                if self.pos == 0 && self.len != Some(0) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "writing at offset 0 would truncate the remote file",
+                    ));
+                }
+                let writer = self
+                    .ftp_stream
+                    .stor_from(&self.path, self.pos)
+                    .map_err(io_error)?;
+                self.start_trace("STOR");
+                match self.transfer.insert(Transfer::Upload(writer)) {
+                    Transfer::Upload(writer) => writer,
+                    Transfer::Download(_) => unreachable!(),
+                }
+            }
+        };
+        match transfer.write(buf) {
+            Ok(n) => {
+                self.pos += n as u64;
+                self.len = self.len.map(|len| len.max(self.pos));
+                Ok(n)
+            }
+            Err(err) => match self.close_transfer() {
+                Err(FtpError::ConnectionError(_)) | Ok(()) => Err(err),
+                Err(reply) => Err(io_error(reply)),
+            },
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.transfer {
+            Some(Transfer::Upload(ref mut writer)) => writer.flush(),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<S: Transport> Seek for FtpFile<'_, S> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+            SeekFrom::End(offset) => match self.len {
+                Some(len) => len.checked_add_signed(offset),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "the server did not report the file size",
+                    ))
+                }
+            },
+        };
+        let target = target.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        if target != self.pos {
+            self.close_transfer().map_err(io_error)?;
+            self.pos = target;
+        }
+        Ok(self.pos)
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        Ok(self.pos)
+    }
+}
+
+impl<S: Transport> Drop for FtpFile<'_, S> {
+    fn drop(&mut self) {
+        let _ = self.close_transfer();
+    }
+}
+
+impl<S: Transport> fmt::Debug for FtpFile<'_, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FtpFile")
+            .field("path", &self.path)
+            .field("pos", &self.pos)
+            .field("len", &self.len)
+            .field(
+                "reading",
+                &matches!(self.transfer, Some(Transfer::Download(_))),
+            )
+            .field(
+                "writing",
+                &matches!(self.transfer, Some(Transfer::Upload(_))),
+            )
+            .finish()
+    }
+}
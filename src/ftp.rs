@@ -1,117 +1,471 @@
 //! FTP module.
 
 use super::{
+    block::{BlockReader, BlockWriter, TransferReader, TransferWriter},
+    buffer::{self, BufferedReader, BufferedWriter, DEFAULT_BUFFER_SIZE},
+    checkpoint::TransferCheckpoint,
     data_stream::DataStream,
-    status,
-    types::{FileType, FtpError, Line},
+    events::{self, Progress, SessionEvent},
+    file::FtpFile,
+    metrics::Metrics,
+    observer::{self, FtpObserver},
+    parse,
+    path::{self, FtpPath},
+    status::{ResponseClass, Status},
+    trace::{CommandTrace, CountingReader, CountingWriter, TransferTrace},
+    transport::Transport,
+    types::{
+        AbortHandle, ActiveConfig, DataConnectOrder, Dialer, DiffEntry, DirEntry, EntryKind,
+        Features, FileStat, FileType, FormatControl, FtpError, Line, LoginInfo, Mode,
+        ModificationTime, OverwritePolicy, ParseMode, PassiveAddressPolicy, ProxyScheme, Quota,
+        Reply, RestartMarker, ServerInfo, SocketConfig, TextEncoding, TlsMode, TransmissionMode,
+        UploadOutcome,
+    },
+    watch::{Follower, Watcher},
 };
 
 use {
-    chrono::{offset::TimeZone, DateTime, Utc},
-    regex::Regex,
+    chrono::{DateTime, Utc},
     std::{
-        borrow::Cow,
-        io::{copy, BufRead, BufReader, BufWriter, Cursor, Read, Write},
-        net::{SocketAddr, TcpStream, ToSocketAddrs},
-        str::FromStr,
+        collections::BTreeMap,
+        fmt,
+        fs::{self, File},
+        io::{copy, BufRead, BufReader, BufWriter, Cursor, IoSlice, Read, Seek, SeekFrom, Write},
+        net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+        path::Path,
+        sync::{mpsc::Sender, Arc},
+        time::{Duration, Instant, SystemTime},
     },
 };
 
+#[cfg(feature = "digest")]
+use digest::{Algorithm, Digest, DigestReader};
+#[cfg(feature = "gzip")]
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
 #[cfg(all(feature = "secure", feature = "native-tls"))]
 use native_tls::TlsConnector;
 #[cfg(all(feature = "secure", not(feature = "native-tls")))]
 use openssl::ssl::{Ssl, SslContext};
 
-lazy_static! {
-    // This regex extracts IP and Port details from PASV command response.
-    // The regex looks for the pattern (h1,h2,h3,h4,p1,p2).
-    static ref PORT_RE: Regex = Regex::new(r"\((\d+),(\d+),(\d+),(\d+),(\d+),(\d+)\)").unwrap();
+/// The time left before `deadline`, failing once it has passed.
+fn remaining_until(deadline: Instant) -> crate::Result<Duration> {
+    let now = Instant::now();
+    if now < deadline {
+        Ok(deadline - now)
+    } else {
+        Err(timed_out(
+            FtpError::ConnectionError(std::io::ErrorKind::TimedOut.into()),
+            "deadline exceeded",
+        ))
+    }
+}
+
+/// Where an upload goes under an `OverwritePolicy`.
+enum UploadTarget {
+    Skip,
+    Store(String),
+    /// Append to the file at the path, which has the given length.
+    Append(String, u64),
+}
+
+/// The replies accepted by a command: some codes, or a whole class.
+#[derive(Clone, Copy)]
+enum Expected<'a> {
+    Codes(&'a [Status]),
+    Class(ResponseClass),
+}
+
+impl Expected<'_> {
+    fn contains(self, code: Status) -> bool {
+        match self {
+            Expected::Codes(codes) => codes.contains(&code),
+            Expected::Class(class) => class.contains(code),
+        }
+    }
+}
+
+/// Turns the refusal of a command into `None`, for optional steps which
+/// servers may not support.
+fn ignore_refusal<T>(res: crate::Result<T>) -> crate::Result<Option<T>> {
+    match res {
+        Ok(res) => Ok(Some(res)),
+        Err(FtpError::UnexpectedResponse(_)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Copies `reader` to `writer`, advancing `checkpoint` by the bytes copied
+/// and passing it to `on_checkpoint` every `interval` bytes, once `writer`
+/// is flushed.
+fn copy_checkpointed<F>(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    checkpoint: &mut TransferCheckpoint,
+    interval: u64,
+    on_checkpoint: &mut F,
+) -> crate::Result<()>
+where
+    F: FnMut(&TransferCheckpoint) -> std::io::Result<()>,
+{
+    let mut buf = vec![0; 64 * 1024];
+    let mut next = checkpoint.offset + interval;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        writer.write_all(&buf[..n])?;
+        checkpoint.offset += n as u64;
+        if checkpoint.offset >= next {
+            writer.flush()?;
+            on_checkpoint(checkpoint)?;
+            next = checkpoint.offset + interval;
+        }
+    }
+}
 
-    // This regex extracts modification time from MDTM command response.
-    static ref MDTM_RE: Regex = Regex::new(r"\b(\d{4})(\d{2})(\d{2})(\d{2})(\d{2})(\d{2})\b").unwrap();
+/// Returns `true` if `err` is the refusal of a command the server doesn't
+/// implement, as opposed to a failure of the command.
+fn is_unsupported(err: &FtpError) -> bool {
+    matches!(
+        err.status(),
+        Some(Status::BadCommand | Status::NotImplemented | Status::NotImplementedParameter)
+    )
+}
 
-    // This regex extracts file size from SIZE command response.
-    static ref SIZE_RE: Regex = Regex::new(r"\s+(\d+)\s*$").unwrap();
+/// Replaces a timeout of a connection with a `TimedOut` error saying what
+/// wasn't done in time.
+fn timed_out(err: FtpError, message: &str) -> FtpError {
+    match err {
+        FtpError::ConnectionError(ref io_err)
+            if io_err.kind() == std::io::ErrorKind::WouldBlock
+                || io_err.kind() == std::io::ErrorKind::TimedOut =>
+        {
+            FtpError::ConnectionError(std::io::Error::new(std::io::ErrorKind::TimedOut, message))
+        }
+        err => err,
+    }
 }
 
+/// The Telnet "interpret as command" byte.
+const IAC: u8 = 0xff;
+
+/// The number of commands `stat_many` sends before reading their replies.
+const DEFAULT_PIPELINE_DEPTH: usize = 16;
+
 /// Stream to interface with the FTP server. This interface is only for the command stream.
+pub type FtpStream = GenericFtpStream<TcpStream>;
+
+/// An FTP control connection over any `Transport`, see the
+/// [`transport`](transport/index.html) module. Data connections always use TCP.
 #[derive(Debug)]
-pub struct FtpStream {
-    reader: BufReader<DataStream>,
+pub struct GenericFtpStream<S> {
+    reader: BufReader<DataStream<S>>,
     welcome_msg: Option<String>,
-    #[cfg(all(feature = "secure", feature = "native-tls"))]
-    tls_ctx: Option<TlsConnector>,
-    #[cfg(all(feature = "secure", feature = "native-tls"))]
-    domain: Option<String>,
+    welcome_lines: Vec<String>,
+    cmd_buf: Vec<u8>,
+    last_command: Vec<u8>,
+    line_buf: String,
+    trace: CommandTrace,
+    proxy: Option<ProxyScheme>,
+    passive_address: PassiveAddressPolicy,
+    mode: Mode,
+    connect_order: DataConnectOrder,
+    data_dial_failed: bool,
+    parse_mode: ParseMode,
+    text_encoding: TextEncoding,
+    max_download_size: Option<u64>,
+    tls_mode: TlsMode,
+    features: Option<Features>,
+    server_info: Option<ServerInfo>,
+    legacy_commands: bool,
+    tolerate_superfluous: bool,
+    pret: bool,
+    marker_interval: Option<u64>,
+    restart_markers: Vec<RestartMarker>,
+    restart_offset: Option<u64>,
+    dialer: Dialer,
+    socket_config: SocketConfig,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    abort: Option<AbortHandle>,
+    keepalive_interval: Option<Duration>,
+    revalidate_after: Option<Duration>,
+    last_command_at: Instant,
+    peer: Option<SocketAddr>,
+    closed: bool,
+    auto_reconnect: bool,
+    auto_binary: bool,
+    preserve_mtime: bool,
+    restart_downloads: bool,
+    resume_supported: Option<bool>,
+    pipeline_depth: usize,
+    buffer_size: usize,
+    transfer_buffer: Vec<u8>,
+    session: SessionState,
+    observer: Option<Box<dyn FtpObserver>>,
+    metrics: Option<Arc<dyn Metrics>>,
+    events: Option<Sender<SessionEvent>>,
+    tls: TlsState,
+}
+
+/// What is needed to restore a session on a new control connection.
+#[derive(Default, Clone)]
+struct SessionState {
+    credentials: Option<(String, String)>,
+    cwd: Option<FtpPath>,
+    file_type: Option<FileType>,
+    transmission_mode: TransmissionMode,
+}
+
+impl fmt::Debug for SessionState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Never print the password.
+        f.debug_struct("SessionState")
+            .field("user", &self.credentials.as_ref().map(|c| &c.0))
+            .field("cwd", &self.cwd)
+            .field("file_type", &self.file_type)
+            .field("transmission_mode", &self.transmission_mode)
+            .finish()
+    }
+}
+
+/// The TLS context data connections are secured with, set once the control
+/// connection is secured. With the `native-tls` backend the context comes
+/// with the domain name the certificates are checked against.
+#[derive(Debug, Default, Clone)]
+enum TlsState {
+    #[default]
+    Plain,
     #[cfg(all(feature = "secure", not(feature = "native-tls")))]
-    ssl_cfg: Option<SslContext>,
+    Secure(SslContext),
+    #[cfg(all(feature = "secure", feature = "native-tls"))]
+    Secure(TlsConnector, String),
+}
+
+impl TlsState {
+    /// Starts TLS on `stream` with this context, or leaves it in plain text.
+    fn connect<T: Transport>(&self, stream: T) -> crate::Result<DataStream<T>> {
+        match *self {
+            TlsState::Plain => Ok(DataStream::Plain(stream)),
+            #[cfg(all(feature = "secure", not(feature = "native-tls")))]
+            TlsState::Secure(ref ssl_context) => {
+                Ok(DataStream::Ssl(Ssl::new(ssl_context)?.connect(stream)?))
+            }
+            #[cfg(all(feature = "secure", feature = "native-tls"))]
+            TlsState::Secure(ref tls_connector, ref domain) => {
+                Ok(DataStream::Ssl(tls_connector.connect(domain, stream)?))
+            }
+        }
+    }
 }
 
 impl FtpStream {
-    /// Creates an FTP Stream and returns the welcome message
-    #[cfg(not(feature = "secure"))]
+    /// Creates an FTP Stream, reading the welcome message of the server,
+    /// see `get_welcome_msg`. The signature is the same whatever the
+    /// features, TLS is started afterwards with `into_secure`.
     pub fn connect<A: ToSocketAddrs>(addr: A) -> crate::Result<FtpStream> {
+        FtpStream::connect_with_parse_mode(addr, ParseMode::Strict)
+    }
+
+    /// Creates an FTP Stream whose replies are parsed with `parse_mode`.
+    pub fn connect_with_parse_mode<A: ToSocketAddrs>(
+        addr: A,
+        parse_mode: ParseMode,
+    ) -> crate::Result<FtpStream> {
         TcpStream::connect(addr)
             .map_err(FtpError::ConnectionError)
-            .and_then(|stream| {
-                let mut ftp_stream = FtpStream {
-                    reader: BufReader::new(DataStream::Tcp(stream)),
-                    welcome_msg: None,
-                };
+            .and_then(|stream| FtpStream::with_control_stream(stream, parse_mode))
+    }
 
-                match ftp_stream.read_response(status::READY) {
-                    Ok(response) => {
-                        ftp_stream.welcome_msg = Some(response.1);
-                        Ok(ftp_stream)
-                    }
-                    Err(err) => Err(err),
+    /// Creates an FTP Stream whose control and data connections are opened
+    /// with the socket options of `config` set before connecting.
+    pub fn connect_with_socket_config<A: ToSocketAddrs>(
+        addr: A,
+        config: SocketConfig,
+    ) -> crate::Result<FtpStream> {
+        let mut last_err = None;
+        for addr in addr.to_socket_addrs()? {
+            match config.connect(addr) {
+                Ok(stream) => {
+                    let mut ftp_stream = FtpStream::with_control_stream(stream, ParseMode::Strict)?;
+                    ftp_stream.use_socket_config(config);
+                    return Ok(ftp_stream);
                 }
-            })
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(FtpError::ConnectionError(last_err.unwrap_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "could not resolve to any addresses",
+            )
+        })))
     }
 
-    /// Creates an FTP Stream and returns the welcome message
-    #[cfg(all(feature = "secure", feature = "native-tls"))]
-    pub fn connect<A: ToSocketAddrs>(addr: A) -> crate::Result<FtpStream> {
-        TcpStream::connect(addr)
-            .map_err(FtpError::ConnectionError)
-            .and_then(|stream| {
-                let mut ftp_stream = FtpStream {
-                    reader: BufReader::new(DataStream::Tcp(stream)),
-                    tls_ctx: None,
-                    domain: None,
-                    welcome_msg: None,
-                };
+    /// Creates an FTP Stream which waits at most `timeout` for each read of
+    /// the welcome message. Some servers delay it on purpose, a server which
+    /// never sends it would otherwise block forever. The read timeout is
+    /// cleared once the welcome message is read, a server which did not send
+    /// it in time fails with a `TimedOut` connection error.
+    ///
+    /// ```rust,no_run
+    /// use ftp::FtpStream;
+    /// use std::time::Duration;
+    ///
+    /// let ftp_stream =
+    ///     FtpStream::connect_with_banner_timeout("127.0.0.1:21", Duration::from_secs(30)).unwrap();
+    /// ```
+    pub fn connect_with_banner_timeout<A: ToSocketAddrs>(
+        addr: A,
+        timeout: Duration,
+    ) -> crate::Result<FtpStream> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(timeout))?;
+        let ftp_stream = FtpStream::with_control_stream(stream, ParseMode::Strict)
+            .map_err(|err| timed_out(err, "timed out waiting for the welcome message"))?;
+        ftp_stream.get_ref().set_read_timeout(None)?;
+        Ok(ftp_stream)
+    }
 
-                match ftp_stream.read_response(status::READY) {
-                    Ok(response) => {
-                        ftp_stream.welcome_msg = Some(response.1);
-                        Ok(ftp_stream)
-                    }
-                    Err(err) => Err(err),
+    /// Creates an FTP Stream, failing with a `TimedOut` connection error if
+    /// connecting and reading the welcome message aren't done by `deadline`.
+    /// The rest of the session can be bounded by the same deadline with
+    /// `with_deadline`.
+    ///
+    /// ```rust,no_run
+    /// use ftp::FtpStream;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let deadline = Instant::now() + Duration::from_secs(60);
+    /// let mut ftp_stream = FtpStream::connect_with_deadline("127.0.0.1:21", deadline).unwrap();
+    /// let report = ftp_stream
+    ///     .with_deadline(deadline, |ftp_stream| {
+    ///         ftp_stream.login("anonymous", "anonymous")?;
+    ///         ftp_stream.get_bytes("report.csv")
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn connect_with_deadline<A: ToSocketAddrs>(
+        addr: A,
+        deadline: Instant,
+    ) -> crate::Result<FtpStream> {
+        let mut last_err = None;
+        for addr in addr.to_socket_addrs()? {
+            let remaining = remaining_until(deadline)?;
+            match TcpStream::connect_timeout(&addr, remaining) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(remaining_until(deadline)?))?;
+                    let ftp_stream = FtpStream::with_control_stream(stream, ParseMode::Strict)
+                        .map_err(|err| timed_out(err, "deadline exceeded"))?;
+                    ftp_stream.get_ref().set_read_timeout(None)?;
+                    return Ok(ftp_stream);
                 }
-            })
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(timed_out(
+            FtpError::ConnectionError(last_err.unwrap_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "could not resolve to any addresses",
+                )
+            })),
+            "deadline exceeded",
+        ))
     }
 
-    /// Creates an FTP Stream and returns the welcome message
-    #[cfg(all(feature = "secure", not(feature = "native-tls")))]
-    pub fn connect<A: ToSocketAddrs>(addr: A) -> crate::Result<FtpStream> {
-        TcpStream::connect(addr)
-            .map_err(FtpError::ConnectionError)
-            .and_then(|stream| {
-                let mut ftp_stream = FtpStream {
-                    reader: BufReader::new(DataStream::Tcp(stream)),
-                    ssl_cfg: None,
-                    welcome_msg: None,
-                };
+    /// Sets the socket options of `config` on the control connection and on
+    /// the data connections opened from now on. This replaces the dialer set
+    /// with `set_dialer`.
+    pub fn set_socket_config(&mut self, config: SocketConfig) -> crate::Result<()> {
+        config.apply(self.get_ref())?;
+        self.use_socket_config(config);
+        Ok(())
+    }
 
-                match ftp_stream.read_response(status::READY) {
-                    Ok(response) => {
-                        ftp_stream.welcome_msg = Some(response.1);
-                        Ok(ftp_stream)
-                    }
-                    Err(err) => Err(err),
-                }
-            })
+    fn use_socket_config(&mut self, config: SocketConfig) {
+        let dial_config = config.clone();
+        self.dialer = Dialer::new(move |addr| dial_config.connect(addr));
+        self.socket_config = config;
+    }
+}
+
+impl<S: Transport> GenericFtpStream<S> {
+    /// Creates an FTP Stream on an already connected control connection,
+    /// e.g. one opened through a tunnel, and reads the welcome message.
+    ///
+    /// ```rust,no_run
+    /// use ftp::FtpStream;
+    /// use std::net::TcpStream;
+    ///
+    /// let stream = TcpStream::connect("127.0.0.1:21").unwrap();
+    /// let mut ftp_stream = FtpStream::from_stream(stream).unwrap();
+    /// ftp_stream.login("anonymous", "anonymous").unwrap();
+    /// ```
+    pub fn from_stream(stream: S) -> crate::Result<Self> {
+        GenericFtpStream::with_control_stream(stream, ParseMode::Strict)
+    }
+
+    /// Reads the welcome message on an established control connection.
+    fn with_control_stream(stream: S, parse_mode: ParseMode) -> crate::Result<Self> {
+        let peer = stream.peer_addr().ok();
+        let mut ftp_stream = GenericFtpStream {
+            reader: BufReader::new(DataStream::Plain(stream)),
+            tls: TlsState::Plain,
+            welcome_msg: None,
+            welcome_lines: Vec::new(),
+            cmd_buf: Vec::new(),
+            last_command: Vec::new(),
+            line_buf: String::new(),
+            trace: CommandTrace::default(),
+            proxy: None,
+            passive_address: PassiveAddressPolicy::default(),
+            mode: Mode::default(),
+            connect_order: DataConnectOrder::default(),
+            data_dial_failed: false,
+            parse_mode,
+            text_encoding: TextEncoding::default(),
+            max_download_size: None,
+            tls_mode: TlsMode::Disabled,
+            features: None,
+            server_info: None,
+            legacy_commands: false,
+            tolerate_superfluous: false,
+            pret: false,
+            marker_interval: None,
+            restart_markers: Vec::new(),
+            restart_offset: None,
+            dialer: Dialer::default(),
+            socket_config: SocketConfig::default(),
+            read_timeout: None,
+            write_timeout: None,
+            deadline: None,
+            abort: None,
+            keepalive_interval: None,
+            revalidate_after: None,
+            last_command_at: Instant::now(),
+            peer,
+            closed: false,
+            auto_reconnect: false,
+            auto_binary: false,
+            preserve_mtime: false,
+            restart_downloads: false,
+            resume_supported: None,
+            pipeline_depth: DEFAULT_PIPELINE_DEPTH,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            transfer_buffer: Vec::new(),
+            session: SessionState::default(),
+            observer: None,
+            metrics: None,
+            events: None,
+        };
+
+        ftp_stream.read_welcome()?;
+        Ok(ftp_stream)
     }
 
     /// Switch to a secure mode if possible, using a provided SSL configuration.
@@ -131,68 +485,74 @@ impl FtpStream {
     /// // Create a TlsConnector
     /// // NOTE: For custom options see <https://docs.rs/native-tls/0.2.6/native_tls/struct.TlsConnectorBuilder.html>
     /// let mut ctx = TlsConnector::new().unwrap();
-    /// let mut (ftp_stream, _welcome_msg) = FtpStream::connect("127.0.0.1:21").unwrap();
+    /// let ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
     /// let mut ftp_stream = ftp_stream.into_secure(ctx, "localhost").unwrap();
     /// ```
     #[cfg(all(feature = "secure", feature = "native-tls"))]
-    pub fn into_secure(
-        mut self,
-        tls_connector: TlsConnector,
-        domain: &str,
-    ) -> crate::Result<FtpStream> {
+    pub fn into_secure(mut self, tls_connector: TlsConnector, domain: &str) -> crate::Result<Self> {
         // Ask the server to start securing data.
         self.write_str("AUTH TLS\r\n")?;
-        self.read_response(status::AUTH_OK)?;
-
-        let mut secured_ftp_tream = FtpStream {
-            reader: BufReader::new(DataStream::Ssl(
-                tls_connector.connect(domain, self.reader.into_inner().into_tcp_stream())?,
-            )),
-            tls_ctx: Some(tls_connector),
-            domain: Some(String::from(domain)),
-        };
-        // Set protection buffer size
-        secured_ftp_tream.write_str("PBSZ 0\r\n")?;
-        secured_ftp_tream.read_response(status::COMMAND_OK)?;
-        // Change the level of data protectio to Private
-        secured_ftp_tream.write_str("PROT P\r\n")?;
-        secured_ftp_tream.read_response(status::COMMAND_OK)?;
+        self.read_response(Status::AuthOk)?;
+        self.start_tls(TlsState::Secure(tls_connector, String::from(domain)))
+    }
 
-        Ok(secured_ftp_tream)
+    /// Same as `into_secure`, but switches the connection to TLS in place,
+    /// for streams held in structs or behind a `&mut`. TLS runs over a
+    /// second handle to the connection from `Transport::try_clone`, which
+    /// `TcpStream` supports.
+    ///
+    /// Fails with `SecureError` if the connection is already secured. If
+    /// TLS can't be started once the server accepted `AUTH TLS`, the stream
+    /// is closed, see `is_closed`.
+    ///
+    /// ```rust,no_run
+    /// use ftp::native_tls::TlsConnector;
+    /// use ftp::FtpStream;
+    ///
+    /// let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
+    /// ftp_stream.secure(TlsConnector::new().unwrap(), "localhost").unwrap();
+    /// assert!(ftp_stream.is_secure());
+    /// ```
+    #[cfg(all(feature = "secure", feature = "native-tls"))]
+    pub fn secure(&mut self, tls_connector: TlsConnector, domain: &str) -> crate::Result<()> {
+        self.ensure_plain()?;
+        self.write_str("AUTH TLS\r\n")?;
+        self.read_response(Status::AuthOk)?;
+        self.start_tls_in_place(TlsState::Secure(tls_connector, String::from(domain)))
     }
 
-    /// Switch to insecure mode. If the connection is already
-    /// insecure does nothing.
+    /// Secures the connection according to the mode set by `set_tls_mode`:
+    /// with `into_secure` if TLS is required, if the server accepts
+    /// `AUTH TLS` if it is opportunistic, and not at all if it is disabled.
     ///
     /// ## Example
     ///
     /// ```rust,no_run
-    /// use std::path::Path;
     /// use ftp::FtpStream;
+    /// use ftp::native_tls::TlsConnector;
+    /// use ftp::types::TlsMode;
     ///
-    /// use ftp::native_tls::{TlsConnector, TlsStream};
-    ///
-    /// // Create an TlsConnector
-    /// let mut ctx = TlsConnector::new().unwrap();
-    /// let mut (ftp_stream, _welcome_msg) = FtpStream::connect("127.0.0.1:21").unwrap();
-    /// let mut ftp_stream = ftp_stream.into_secure(ctx, "localhost").unwrap();
-    /// // Do all secret things
-    /// // Switch back to the insecure mode
-    /// let mut ftp_stream = ftp_stream.into_insecure().unwrap();
-    /// // Do all public things
-    /// let _ = ftp_stream.quit();
+    /// let ctx = TlsConnector::new().unwrap();
+    /// let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
+    /// ftp_stream.set_tls_mode(TlsMode::Opportunistic);
+    /// let mut ftp_stream = ftp_stream.apply_tls_mode(ctx, "localhost").unwrap();
+    /// println!("secure: {}", ftp_stream.is_secure());
     /// ```
     #[cfg(all(feature = "secure", feature = "native-tls"))]
-    pub fn into_insecure(mut self) -> crate::Result<FtpStream> {
-        // Ask the server to stop securing data
-        self.write_str("CCC\r\n")?;
-        self.read_response(status::COMMAND_OK)?;
-        let plain_ftp_stream = FtpStream {
-            reader: BufReader::new(DataStream::Tcp(self.reader.into_inner().into_tcp_stream())),
-            tls_ctx: None,
-            domain: None,
-        };
-        Ok(plain_ftp_stream)
+    pub fn apply_tls_mode(
+        mut self,
+        tls_connector: TlsConnector,
+        domain: &str,
+    ) -> crate::Result<Self> {
+        let mode = self.tls_mode;
+        match mode {
+            TlsMode::Disabled => Ok(self),
+            TlsMode::Required => self.into_secure(tls_connector, domain),
+            TlsMode::Opportunistic if self.try_auth_tls()? => {
+                self.start_tls(TlsState::Secure(tls_connector, String::from(domain)))
+            }
+            TlsMode::Opportunistic => Ok(self),
+        }
     }
 
     /// Switch to a secure mode if possible, using a provided SSL configuration.
@@ -217,244 +577,1854 @@ impl FtpStream {
     /// let mut ftp_stream = ftp_stream.into_secure(ctx).unwrap();
     /// ```
     #[cfg(all(feature = "secure", not(feature = "native-tls")))]
-    pub fn into_secure(mut self, ssl_context: SslContext) -> crate::Result<FtpStream> {
+    pub fn into_secure(mut self, ssl_context: SslContext) -> crate::Result<Self> {
         // Ask the server to start securing data.
         self.write_str("AUTH TLS\r\n")?;
-        self.read_response(status::AUTH_OK)?;
-
-        let mut secured_ftp_tream = FtpStream {
-            reader: BufReader::new(DataStream::Ssl(
-                Ssl::new(&ssl_context)?
-                    .connect(self.reader.into_inner().into_tcp_stream())
-                    .map_err(|e| FtpError::SecureError(e.to_string()))?,
-            )),
-            ssl_cfg: Some(ssl_context),
-            welcome_msg: self.welcome_msg,
-        };
-        // Set protection buffer size
-        secured_ftp_tream.write_str("PBSZ 0\r\n")?;
-        secured_ftp_tream.read_response(status::COMMAND_OK)?;
-        // Change the level of data protectio to Private
-        secured_ftp_tream.write_str("PROT P\r\n")?;
-        secured_ftp_tream.read_response(status::COMMAND_OK)?;
+        self.read_response(Status::AuthOk)?;
+        self.start_tls(TlsState::Secure(ssl_context))
+    }
 
-        Ok(secured_ftp_tream)
+    /// Same as `into_secure`, but switches the connection to TLS in place,
+    /// for streams held in structs or behind a `&mut`. TLS runs over a
+    /// second handle to the connection from `Transport::try_clone`, which
+    /// `TcpStream` supports.
+    ///
+    /// Fails with `SecureError` if the connection is already secured. If
+    /// TLS can't be started once the server accepted `AUTH TLS`, the stream
+    /// is closed, see `is_closed`.
+    ///
+    /// ```rust,no_run
+    /// use ftp::openssl::ssl::{SslContext, SslMethod};
+    /// use ftp::FtpStream;
+    ///
+    /// let ctx = SslContext::builder(SslMethod::tls()).unwrap().build();
+    /// let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
+    /// ftp_stream.secure(ctx).unwrap();
+    /// assert!(ftp_stream.is_secure());
+    /// ```
+    #[cfg(all(feature = "secure", not(feature = "native-tls")))]
+    pub fn secure(&mut self, ssl_context: SslContext) -> crate::Result<()> {
+        self.ensure_plain()?;
+        self.write_str("AUTH TLS\r\n")?;
+        self.read_response(Status::AuthOk)?;
+        self.start_tls_in_place(TlsState::Secure(ssl_context))
     }
 
-    /// Switch to insecure mode.
+    /// Secures the connection according to the mode set by `set_tls_mode`:
+    /// with `into_secure` if TLS is required, if the server accepts
+    /// `AUTH TLS` if it is opportunistic, and not at all if it is disabled.
     ///
     /// ## Example
     ///
     /// ```rust,no_run
-    /// use std::path::Path;
     /// use ftp::FtpStream;
+    /// use ftp::openssl::ssl::{SslContext, SslMethod};
+    /// use ftp::types::TlsMode;
     ///
-    /// use ftp::openssl::ssl::{ SslContext, SslMethod };
-    ///
-    /// // Create an SslContext with a custom cert.
-    /// let mut ctx = SslContext::builder(SslMethod::tls()).unwrap();
-    /// let _ = ctx.set_ca_file(Path::new("/path/to/a/cert.pem")).unwrap();
-    /// let ctx = ctx.build();
+    /// let ctx = SslContext::builder(SslMethod::tls()).unwrap().build();
     /// let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
-    /// let mut ftp_stream = ftp_stream.into_secure(ctx).unwrap();
-    /// // Do all secret things
-    /// // Switch back to the insecure mode
-    /// let mut ftp_stream = ftp_stream.into_insecure().unwrap();
-    /// // Do all public things
-    /// let _ = ftp_stream.quit();
+    /// ftp_stream.set_tls_mode(TlsMode::Opportunistic);
+    /// let mut ftp_stream = ftp_stream.apply_tls_mode(ctx).unwrap();
+    /// println!("secure: {}", ftp_stream.is_secure());
     /// ```
     #[cfg(all(feature = "secure", not(feature = "native-tls")))]
-    pub fn into_insecure(mut self) -> crate::Result<FtpStream> {
+    pub fn apply_tls_mode(mut self, ssl_context: SslContext) -> crate::Result<Self> {
+        let mode = self.tls_mode;
+        match mode {
+            TlsMode::Disabled => Ok(self),
+            TlsMode::Required => self.into_secure(ssl_context),
+            TlsMode::Opportunistic if self.try_auth_tls()? => {
+                self.start_tls(TlsState::Secure(ssl_context))
+            }
+            TlsMode::Opportunistic => Ok(self),
+        }
+    }
+
+    /// Starts TLS on the control connection once the server accepted
+    /// `AUTH TLS`, then protects the data connections.
+    #[cfg(feature = "secure")]
+    fn start_tls(self, tls: TlsState) -> crate::Result<Self> {
+        let mut secured_ftp_tream = GenericFtpStream {
+            reader: BufReader::with_capacity(
+                self.reader.capacity(),
+                tls.connect(self.reader.into_inner().into_transport()?)?,
+            ),
+            tls,
+            ..self
+        };
+        secured_ftp_tream.protect_data()?;
+        Ok(secured_ftp_tream)
+    }
+
+    /// Same as `start_tls`, over a second handle to the connection so the
+    /// stream isn't consumed.
+    #[cfg(feature = "secure")]
+    fn start_tls_in_place(&mut self, tls: TlsState) -> crate::Result<()> {
+        let res = self
+            .connect_tls_in_place(tls)
+            .and_then(|()| self.protect_data());
+        if res.is_err() {
+            // The server expects TLS from now on: a command sent in clear
+            // would only get a confusing failure.
+            self.closed = true;
+            self.emit(SessionEvent::Disconnected);
+        }
+        res
+    }
+
+    #[cfg(feature = "secure")]
+    fn connect_tls_in_place(&mut self, tls: TlsState) -> crate::Result<()> {
+        let transport = self.reader.get_ref().get_ref().try_clone()?;
+        self.reader = BufReader::with_capacity(self.reader.capacity(), tls.connect(transport)?);
+        self.tls = tls;
+        Ok(())
+    }
+
+    /// Fails unless the control connection is in clear.
+    #[cfg(feature = "secure")]
+    fn ensure_plain(&self) -> crate::Result<()> {
+        if self.is_secure() {
+            return Err(FtpError::SecureError(String::from(
+                "the control connection is already secured",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Switch the control connection to insecure mode with `CCC`. Data
+    /// connections stay encrypted, as the `PROT P` protection level still
+    /// applies: a clear control connection lets NAT devices follow the data
+    /// connections announced on it.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ftp::FtpStream;
+    ///
+    /// fn publish(secured: FtpStream) -> ftp::Result<()> {
+    ///     // Switch back to the insecure mode
+    ///     let mut ftp_stream = secured.into_insecure()?;
+    ///     // Do all public things
+    ///     ftp_stream.quit()
+    /// }
+    /// ```
+    #[cfg(feature = "secure")]
+    pub fn into_insecure(mut self) -> crate::Result<Self> {
         // Ask the server to stop securing data
         self.write_str("CCC\r\n")?;
-        self.read_response(status::COMMAND_OK)?;
+        self.read_response(Status::CommandOk)?;
 
-        let plain_ftp_stream = FtpStream {
-            reader: BufReader::new(DataStream::Tcp(self.reader.into_inner().into_tcp_stream())),
-            ssl_cfg: None,
-            welcome_msg: self.welcome_msg,
+        let plain_ftp_stream = GenericFtpStream {
+            reader: BufReader::with_capacity(
+                self.reader.capacity(),
+                DataStream::Plain(self.reader.into_inner().into_transport()?),
+            ),
+            ..self
         };
 
         Ok(plain_ftp_stream)
     }
 
-    /// Execute command which send data back in a separate stream
-    #[cfg(not(feature = "secure"))]
-    fn data_command(&mut self, cmd: &str) -> crate::Result<DataStream> {
-        let addr = self.pasv()?;
-        self.write_str(cmd)?;
-        Ok(DataStream::Tcp(TcpStream::connect(addr)?))
+    /// Protects the data connections once the control connection is
+    /// secured.
+    #[cfg(feature = "secure")]
+    fn protect_data(&mut self) -> crate::Result<()> {
+        // Set protection buffer size
+        self.write_str("PBSZ 0\r\n")?;
+        self.read_negotiation()?;
+        // Change the level of data protectio to Private
+        self.write_str("PROT P\r\n")?;
+        self.read_negotiation()?;
+        self.emit(SessionEvent::TlsEstablished);
+        Ok(())
+    }
+
+    /// Sends `AUTH TLS` unless the cached features show the server doesn't
+    /// support it, returning whether the server accepted it.
+    #[cfg(feature = "secure")]
+    fn try_auth_tls(&mut self) -> crate::Result<bool> {
+        if let Some(ref features) = self.features {
+            if !features.supports("AUTH") {
+                return Ok(false);
+            }
+        }
+        self.write_str("AUTH TLS\r\n")?;
+        match self.read_response(Status::AuthOk) {
+            Ok(_) => Ok(true),
+            Err(FtpError::UnexpectedResponse(_)) => Ok(false),
+            Err(err) => Err(err),
+        }
     }
 
-    /// Execute command which send data back in a separate stream
-    #[cfg(all(feature = "secure", feature = "native-tls"))]
-    fn data_command(&mut self, cmd: &str) -> crate::Result<DataStream> {
-        let addr = self.pasv()?;
-        self.write_str(cmd)?;
-        let stream = TcpStream::connect(addr)?;
+    /// Execute command which send data back in a separate stream, returning
+    /// the data stream once the server has sent its preliminary reply.
+    fn data_command(&mut self, cmd: fmt::Arguments) -> crate::Result<DataStream> {
+        let res = self.retrying(|ftp_stream| {
+            ftp_stream
+                .open_data_stream(&mut |s, prefix| s.write_cmd(format_args!("{}{}", prefix, cmd)))
+        });
+        self.restart_offset = None;
+        res
+    }
+
+    /// Same as `data_command` for a command with a raw byte argument.
+    fn data_command_raw(&mut self, verb: &str, arg: &[u8]) -> crate::Result<DataStream> {
+        let res = self.retrying(|ftp_stream| {
+            ftp_stream.open_data_stream(&mut |s, prefix| match prefix {
+                "" => s.write_raw_cmd(verb, arg),
+                prefix => s.write_raw_cmd(&format!("{}{}", prefix, verb), arg),
+            })
+        });
+        self.restart_offset = None;
+        res
+    }
 
-        Ok(match self.tls_ctx {
-            Some(ref tls_ctx) => {
-                DataStream::Ssl(tls_ctx.connect(self.domain.as_ref().unwrap(), stream)?)
+    /// Opens the data connection of the command written by `send`, which is
+    /// called with a prefix to put before the command, `PRET ` to announce it
+    /// or an empty string to send it.
+    ///
+    /// `data_dial_failed` records whether it failed to connect before the
+    /// command was sent, with no reply pending. The offset set by
+    /// `restart_at` is sent with `REST` right before the command, so that a
+    /// retried command resumes from it too.
+    fn open_data_stream(
+        &mut self,
+        send: &mut dyn FnMut(&mut Self, &str) -> crate::Result<()>,
+    ) -> crate::Result<DataStream> {
+        self.data_dial_failed = false;
+        let stream = match self.mode.clone() {
+            Mode::Passive => {
+                if self.pret || self.supports("PRET") {
+                    send(self, "PRET ")?;
+                    self.read_response(Status::CommandOk)?;
+                }
+                let addr = self.pasv()?;
+                let preliminary = [Status::AboutToSend, Status::AlreadyOpen];
+                match self.connect_order {
+                    DataConnectOrder::ConnectFirst => {
+                        let stream = self
+                            .dialer
+                            .dial(addr)
+                            .inspect_err(|_| self.data_dial_failed = true)?;
+                        self.send_restart()?;
+                        send(self, "")?;
+                        self.read_response_in(&preliminary)?;
+                        stream
+                    }
+                    DataConnectOrder::CommandFirst => {
+                        self.send_restart()?;
+                        send(self, "")?;
+                        let stream = self.with_reply_pending(|s| Ok(s.dialer.dial(addr)?))?;
+                        self.read_response_in(&preliminary)?;
+                        stream
+                    }
+                    DataConnectOrder::ReplyFirst => {
+                        self.send_restart()?;
+                        send(self, "")?;
+                        self.read_response_in(&preliminary)?;
+                        self.with_reply_pending(|s| Ok(s.dialer.dial(addr)?))?
+                    }
+                }
+            }
+            Mode::Active(config) => {
+                let listener = self.port(&config)?;
+                self.send_restart()?;
+                send(self, "")?;
+                self.read_response_in(&[Status::AboutToSend, Status::AlreadyOpen])?;
+                self.with_reply_pending(|s| {
+                    let (stream, _) = listener.accept()?;
+                    s.socket_config.apply(&stream)?;
+                    Ok(stream)
+                })?
+            }
+        };
+        self.with_reply_pending(|s| {
+            if let Some(ref abort) = s.abort {
+                abort.start(&stream);
             }
-            None => DataStream::Tcp(stream),
+            let (read_timeout, write_timeout) = s.bounded_timeouts()?;
+            stream.set_read_timeout(read_timeout)?;
+            stream.set_write_timeout(write_timeout)?;
+            s.secure_data_stream(stream)
         })
     }
 
-    /// Execute command which send data back in a separate stream
-    #[cfg(all(feature = "secure", not(feature = "native-tls")))]
-    fn data_command(&mut self, cmd: &str) -> crate::Result<DataStream> {
-        let addr = self.pasv()?;
-        self.write_str(cmd)?;
-        let stream = TcpStream::connect(addr)?;
+    /// Sends `REST` with the offset set by `restart_at`, if any.
+    fn send_restart(&mut self) -> crate::Result<()> {
+        if let Some(offset) = self.restart_offset {
+            self.write_cmd(format_args!("REST {}\r\n", offset))?;
+            self.read_response(Status::RequestFilePending)?;
+        }
+        Ok(())
+    }
+
+    /// Runs `op`, which opens the data connection of a command already
+    /// sent. If it fails, the reply to the command is still to come, once
+    /// the server gives up on the data connection, and any other reply read
+    /// meanwhile would be mistaken for it: the session is closed instead.
+    fn with_reply_pending<T, F>(&mut self, op: F) -> crate::Result<T>
+    where
+        F: FnOnce(&mut Self) -> crate::Result<T>,
+    {
+        let res = op(self);
+        if res.is_err() {
+            self.closed = true;
+            self.emit(SessionEvent::Disconnected);
+        }
+        res
+    }
 
-        Ok(match self.ssl_cfg {
-            Some(ref ssl_cfg) => {
+    /// Wrap a data connection in TLS if data connections are secured.
+    fn secure_data_stream(&self, stream: TcpStream) -> crate::Result<DataStream> {
+        match self.tls {
+            #[cfg(all(feature = "secure", not(feature = "native-tls")))]
+            TlsState::Secure(ref ssl_cfg) => {
                 let mut ssl = Ssl::new(ssl_cfg)?;
                 if let DataStream::Ssl(ssl_stream) = self.reader.get_ref() {
                     unsafe {
-                        // SAFETY: ssl_stream was also using the context from self.ssl_cfg
+                        // SAFETY: ssl_stream was also using the context from self.tls
                         ssl.set_session(ssl_stream.ssl().session().unwrap())?
                     }
                 };
-                DataStream::Ssl(ssl.connect(stream)?)
+                Ok(DataStream::Ssl(ssl.connect(stream)?))
             }
-            None => DataStream::Tcp(stream),
-        })
+            ref tls => tls.connect(stream),
+        }
+    }
+
+    /// Runs the EPSV command. The data port is on the host of the control
+    /// connection, unless `PassiveAddressPolicy::Override` says otherwise.
+    fn epsv(&mut self) -> crate::Result<SocketAddr> {
+        self.write_str("EPSV\r\n")?;
+        let Line(_, line) = self.read_response(Status::ExtendedPassiveMode)?;
+        let port = parse::epsv(&line)?;
+        let ip = match self.passive_address {
+            PassiveAddressPolicy::Override(ip) => ip,
+            _ => self.get_ref().peer_addr()?.ip(),
+        };
+        Ok(SocketAddr::new(ip, port))
+    }
+
+    /// Listen for an active mode data connection and announce it with `PORT`
+    /// (or `EPRT` for IPv6).
+    fn port(&mut self, config: &ActiveConfig) -> crate::Result<TcpListener> {
+        let local_ip = self.get_ref().local_addr()?.ip();
+        let listener = match config.port_range {
+            None => self.socket_config.listen(SocketAddr::new(local_ip, 0))?,
+            Some(ref range) => range
+                .clone()
+                .filter_map(|port| {
+                    self.socket_config
+                        .listen(SocketAddr::new(local_ip, port))
+                        .ok()
+                })
+                .next()
+                .ok_or_else(|| {
+                    FtpError::ConnectionError(std::io::Error::new(
+                        std::io::ErrorKind::AddrInUse,
+                        format!("no free port in {:?} for active mode", range),
+                    ))
+                })?,
+        };
+        let port = listener.local_addr()?.port();
+        match config.external_ip.unwrap_or(local_ip) {
+            IpAddr::V4(ip) => {
+                let [h1, h2, h3, h4] = ip.octets();
+                self.write_cmd(format_args!(
+                    "PORT {},{},{},{},{},{}\r\n",
+                    h1,
+                    h2,
+                    h3,
+                    h4,
+                    port >> 8,
+                    port & 0xff
+                ))?;
+            }
+            IpAddr::V6(ip) => self.write_cmd(format_args!("EPRT |2|{}|{}|\r\n", ip, port))?,
+        }
+        self.read_response(Status::CommandOk)?;
+        Ok(listener)
     }
 
-    /// Returns a reference to the underlying TcpStream.
+    /// Sets how data connections are established. The default is passive
+    /// mode, `Mode::Active` makes the server connect back to the client.
     ///
-    /// Example:
-    /// ```no_run
-    /// use std::net::TcpStream;
+    /// ```rust,no_run
     /// use ftp::FtpStream;
-    /// use std::time::Duration;
+    /// use ftp::types::{ActiveConfig, Mode};
     ///
-    /// let stream = FtpStream::connect("127.0.0.1:21")
-    ///                        .expect("Couldn't connect to the server...");
-    /// stream.get_ref().set_read_timeout(Some(Duration::from_secs(10)))
-    ///                 .expect("set_read_timeout call failed");
+    /// let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
+    /// // Listen on the forwarded ports and advertise the public address
+    /// ftp_stream.set_mode(Mode::Active(ActiveConfig {
+    ///     port_range: Some(50000..=50100),
+    ///     external_ip: Some("203.0.113.7".parse().unwrap()),
+    /// }));
     /// ```
-    pub fn get_ref(&self) -> &TcpStream {
-        self.reader.get_ref().get_ref()
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
     }
 
-    /// Get welcome message from the server on connect.
-    pub fn get_welcome_msg(&self) -> Option<&str> {
-        self.welcome_msg.as_deref()
+    /// Sets an observer notified of every command sent and reply received,
+    /// see the [`observer`](observer/index.html) module. `None` removes it.
+    pub fn set_observer(&mut self, observer: Option<Box<dyn FtpObserver>>) {
+        self.observer = observer;
     }
 
-    /// Log in to the FTP server.
-    pub fn login(&mut self, user: &str, password: &str) -> crate::Result<()> {
-        self.write_str(format!("USER {}\r\n", user))?;
-        let Line(code, _) = self.read_response_in(&[status::LOGGED_IN, status::NEED_PASSWORD])?;
-        if code == status::NEED_PASSWORD {
-            self.write_str(format!("PASS {}\r\n", password))?;
-            self.read_response(status::LOGGED_IN)?;
-        }
-        Ok(())
+    /// Sets the metrics counting the commands, replies, transferred bytes
+    /// and reconnections of this connection, see the
+    /// [`metrics`](metrics/index.html) module. `None` removes them.
+    pub fn set_metrics(&mut self, metrics: Option<Arc<dyn Metrics>>) {
+        self.metrics = metrics;
     }
 
-    /// Change the current directory to the path specified.
-    pub fn cwd(&mut self, path: &str) -> crate::Result<()> {
-        self.write_str(format!("CWD {}\r\n", path))?;
-        self.read_response(status::REQUESTED_FILE_ACTION_OK)?;
-        Ok(())
+    /// Sets the channel receiving the events of the session, see the
+    /// [`events`](events/index.html) module. `Connected` is sent at once
+    /// for the connection already open. `None` removes it.
+    pub fn set_event_sender(&mut self, sender: Option<Sender<SessionEvent>>) {
+        self.events = sender;
+        self.emit(SessionEvent::Connected { peer: self.peer });
     }
 
-    /// Move the current directory to the parent directory.
-    pub fn cdup(&mut self) -> crate::Result<()> {
-        self.write_str("CDUP\r\n")?;
-        self.read_response_in(&[status::COMMAND_OK, status::REQUESTED_FILE_ACTION_OK])?;
-        Ok(())
+    /// Sends `event` to the channel set with `set_event_sender`, if any.
+    fn emit(&self, event: SessionEvent) {
+        events::send(self.events.as_ref(), event);
     }
 
-    /// Gets the current directory
-    pub fn pwd(&mut self) -> crate::Result<String> {
-        self.write_str("PWD\r\n")?;
-        let Line(_, content) = self.read_response(status::PATH_CREATED)?;
-        match (content.find('"'), content.rfind('"')) {
-            (Some(begin), Some(end)) if begin < end => Ok(content[begin + 1..end].to_string()),
-            _ => {
-                let cause = format!("Invalid PWD Response: {}", content);
-                Err(FtpError::InvalidResponse(cause))
-            }
-        }
+    /// Opens the span of a transfer started by `command` on `path`, counted
+    /// in the metrics and reported to the events channel.
+    pub(crate) fn transfer_trace(
+        &self,
+        command: &'static str,
+        path: Option<&str>,
+    ) -> TransferTrace {
+        TransferTrace::start(command, path, self.metrics.clone(), self.events.clone())
     }
 
-    /// This does nothing. This is usually just used to keep the connection open.
-    pub fn noop(&mut self) -> crate::Result<()> {
-        self.write_str("NOOP\r\n")?;
-        self.read_response(status::COMMAND_OK).map(|_| ())
+    /// Sets the order in which a passive mode data connection is opened, the
+    /// transfer command sent and its preliminary reply read. The default
+    /// suits most servers; some picky ones need another order.
+    pub fn set_data_connect_order(&mut self, order: DataConnectOrder) {
+        self.connect_order = order;
     }
 
-    /// This creates a new directory on the server.
-    pub fn mkdir(&mut self, pathname: &str) -> crate::Result<()> {
-        self.write_str(format!("MKD {}\r\n", pathname))?;
-        self.read_response(status::PATH_CREATED).map(|_| ())
+    /// Sets how strictly replies are parsed, see `ParseMode`. The welcome
+    /// message is read before this can be called; use
+    /// `connect_with_parse_mode` for servers with malformed banners.
+    pub fn set_parse_mode(&mut self, parse_mode: ParseMode) {
+        self.parse_mode = parse_mode;
     }
 
-    /// Runs the PASV command.
-    fn pasv(&mut self) -> crate::Result<SocketAddr> {
-        self.write_str("PASV\r\n")?;
-        // PASV response format : 227 Entering Passive Mode (h1,h2,h3,h4,p1,p2).
-        let Line(_, line) = self.read_response(status::PASSIVE_MODE)?;
-        PORT_RE
-            .captures(&line)
-            .ok_or_else(|| FtpError::InvalidResponse(format!("Invalid PASV response: {}", line)))
-            .and_then(|caps| {
-                // If the regex matches we can be sure groups contains numbers
-                let (oct1, oct2, oct3, oct4) = (
-                    caps[1].parse::<u8>().unwrap(),
-                    caps[2].parse::<u8>().unwrap(),
-                    caps[3].parse::<u8>().unwrap(),
-                    caps[4].parse::<u8>().unwrap(),
-                );
-                let (msb, lsb) = (
-                    caps[5].parse::<u8>().unwrap(),
-                    caps[6].parse::<u8>().unwrap(),
-                );
-                let port = ((msb as u16) << 8) + lsb as u16;
-                let addr = format!("{}.{}.{}.{}:{}", oct1, oct2, oct3, oct4, port);
-                SocketAddr::from_str(&addr).map_err(FtpError::InvalidAddress)
-            })
+    /// Sets the encoding `retr_string` decodes files with, UTF-8 by default.
+    pub fn set_text_encoding(&mut self, encoding: TextEncoding) {
+        self.text_encoding = encoding;
+    }
+
+    /// Makes `login` fail with `FtpError::TlsRequired`, without sending
+    /// anything, unless the control connection has been secured with
+    /// `into_secure`. This guards against credentials leaking in plain text
+    /// when `AUTH TLS` is stripped by an attacker or the code securing the
+    /// connection is skipped by mistake.
+    ///
+    /// ```
+    /// # use ftp::{FtpError, FtpStream};
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// conn.set_require_tls(true);
+    /// match conn.login("Doe", "mumble") {
+    ///     Err(FtpError::TlsRequired) => {}
+    ///     res => panic!("credentials sent in plain text: {:?}", res),
+    /// }
+    /// ```
+    ///
+    /// This is `set_tls_mode` with `TlsMode::Required`. With `required`
+    /// `false`, `TlsMode::Required` goes back to the default
+    /// `TlsMode::Disabled`, and any other mode, e.g.
+    /// `TlsMode::Opportunistic`, is kept.
+    pub fn set_require_tls(&mut self, required: bool) {
+        if required {
+            self.tls_mode = TlsMode::Required;
+        } else if self.tls_mode == TlsMode::Required {
+            self.tls_mode = TlsMode::Disabled;
+        }
+    }
+
+    /// Sets whether the control connection is secured with TLS by
+    /// `apply_tls_mode` and must be before `login`.
+    pub fn set_tls_mode(&mut self, mode: TlsMode) {
+        self.tls_mode = mode;
+    }
+
+    /// The TLS mode set by `set_tls_mode`.
+    pub fn tls_mode(&self) -> TlsMode {
+        self.tls_mode
+    }
+
+    /// Returns `true` if the control connection is secured with TLS.
+    pub fn is_secure(&self) -> bool {
+        #[cfg(feature = "secure")]
+        {
+            self.reader.get_ref().is_ssl()
+        }
+        #[cfg(not(feature = "secure"))]
+        {
+            false
+        }
+    }
+
+    /// Returns `true` if data connections are secured with TLS, which they
+    /// stay after `into_insecure` clears the control connection.
+    pub fn is_data_secure(&self) -> bool {
+        !matches!(self.tls, TlsState::Plain)
+    }
+
+    /// Limits the size of the files `simple_retr`, `retr_into`,
+    /// `retr_string` and `retr_to_writer` download. A larger download is
+    /// aborted with `FtpError::SizeLimitExceeded`, protecting against servers
+    /// sending unbounded data. `None` (the default) removes the limit.
+    pub fn set_max_download_size(&mut self, max_size: Option<u64>) {
+        self.max_download_size = max_size;
+    }
+
+    /// Sets the function opening passive mode data connections, and the
+    /// control connections of reconnections and spawned sessions, to route
+    /// them through a tunnel or instrument the sockets.
+    ///
+    /// ```rust,no_run
+    /// use ftp::FtpStream;
+    /// use std::net::TcpStream;
+    ///
+    /// let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
+    /// ftp_stream.set_dialer(|addr| {
+    ///     let stream = TcpStream::connect(addr)?;
+    ///     stream.set_nodelay(true)?;
+    ///     Ok(stream)
+    /// });
+    /// ```
+    pub fn set_dialer<F>(&mut self, dial: F)
+    where
+        F: Fn(SocketAddr) -> std::io::Result<TcpStream> + Send + Sync + 'static,
+    {
+        self.dialer = Dialer::new(dial);
+    }
+
+    /// Replaces the buffer of the control connection reader with one of
+    /// `capacity` bytes, 8 KiB by default. A smaller buffer saves memory
+    /// with many idle connections, a larger one fewer reads with servers
+    /// sending long multi-line replies. Reconnections and spawned sessions
+    /// keep the capacity.
+    ///
+    /// Fails if the reader holds bytes of a reply not read yet.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// let conn = FtpStream::connect(server.addr()).unwrap();
+    /// let conn = conn.with_reader_capacity(1024).unwrap();
+    /// assert_eq!(conn.reader_capacity(), 1024);
+    /// ```
+    pub fn with_reader_capacity(self, capacity: usize) -> crate::Result<Self> {
+        if !self.reader.buffer().is_empty() {
+            return Err(FtpError::InvalidArgument(String::from(
+                "the control connection has unread data",
+            )));
+        }
+        Ok(GenericFtpStream {
+            reader: BufReader::with_capacity(capacity, self.reader.into_inner()),
+            ..self
+        })
+    }
+
+    /// The capacity of the control connection reader buffer.
+    pub fn reader_capacity(&self) -> usize {
+        self.reader.capacity()
+    }
+
+    /// Returns a reference to the underlying transport, a `TcpStream` for
+    /// an `FtpStream`. Once the connection is secured, reading or writing
+    /// it directly bypasses TLS; use `get_data_stream_ref` instead.
+    ///
+    /// Example:
+    /// ```no_run
+    /// use std::net::TcpStream;
+    /// use ftp::FtpStream;
+    ///
+    /// let stream = FtpStream::connect("127.0.0.1:21")
+    ///                        .expect("Couldn't connect to the server...");
+    /// println!("connected from {}", stream.get_ref().local_addr().unwrap());
+    /// ```
+    pub fn get_ref(&self) -> &S {
+        self.reader.get_ref().get_ref()
+    }
+
+    /// Returns a reference to the control connection, TLS-wrapped after
+    /// `into_secure`.
+    pub fn get_data_stream_ref(&self) -> &DataStream<S> {
+        self.reader.get_ref()
+    }
+
+    /// Sets the read and write timeouts of the control connection, secured
+    /// or not, and of the data connections opened from now on. `None`
+    /// blocks indefinitely.
+    ///
+    /// ```no_run
+    /// use ftp::FtpStream;
+    /// use std::time::Duration;
+    ///
+    /// let mut stream = FtpStream::connect("127.0.0.1:21").unwrap();
+    /// stream
+    ///     .set_timeouts(Some(Duration::from_secs(10)), Some(Duration::from_secs(10)))
+    ///     .expect("set_timeouts call failed");
+    /// ```
+    pub fn set_timeouts(
+        &mut self,
+        read: Option<Duration>,
+        write: Option<Duration>,
+    ) -> crate::Result<()> {
+        self.set_read_timeout(read)?;
+        self.set_write_timeout(write)
+    }
+
+    /// Sets the read timeout of the control connection and of the data
+    /// connections opened from now on. `None` blocks indefinitely.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> crate::Result<()> {
+        self.get_ref().set_read_timeout(timeout)?;
+        self.read_timeout = timeout;
+        Ok(())
+    }
+
+    /// Sets the write timeout of the control connection and of the data
+    /// connections opened from now on. `None` blocks indefinitely.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) -> crate::Result<()> {
+        self.get_ref().set_write_timeout(timeout)?;
+        self.write_timeout = timeout;
+        Ok(())
+    }
+
+    /// Runs `op`, failing with a `TimedOut` connection error if it isn't
+    /// done by `deadline`, see `connect_with_deadline`. No command is sent
+    /// once the deadline has passed, and the timeouts of the control and data
+    /// connections are shortened so that a server which stops responding
+    /// can't hold the operation past it. A transfer whose data still flows
+    /// at the deadline isn't cut short, the next command fails instead.
+    ///
+    /// An operation cut short by the deadline may leave a reply unread, so
+    /// the session is then closed: the next command fails, or reconnects
+    /// if automatic reconnection is enabled.
+    pub fn with_deadline<T, F>(&mut self, deadline: Instant, op: F) -> crate::Result<T>
+    where
+        F: FnOnce(&mut Self) -> crate::Result<T>,
+    {
+        let outer = self.deadline;
+        let deadline = outer.map_or(deadline, |outer| outer.min(deadline));
+        self.deadline = Some(deadline);
+        let res = op(self);
+        self.deadline = outer;
+        let res = match res {
+            Err(err) if Instant::now() >= deadline => match timed_out(err, "deadline exceeded") {
+                FtpError::ConnectionError(err) if err.kind() == std::io::ErrorKind::TimedOut => {
+                    self.closed = true;
+                    self.emit(SessionEvent::Disconnected);
+                    Err(FtpError::ConnectionError(err))
+                }
+                err => Err(err),
+            },
+            res => res,
+        };
+        if self.closed {
+            return res;
+        }
+        let restored = self.apply_deadline();
+        res.and_then(|res| restored.map(|_| res))
+    }
+
+    /// Bounds the timeouts of the control connection by the deadline set
+    /// with `with_deadline`, or sets them back when there is none.
+    fn apply_deadline(&mut self) -> crate::Result<()> {
+        let (read, write) = self.bounded_timeouts()?;
+        self.get_ref().set_read_timeout(read)?;
+        self.get_ref().set_write_timeout(write)?;
+        Ok(())
+    }
+
+    /// The read and write timeouts, shortened to the time left before the
+    /// deadline.
+    fn bounded_timeouts(&self) -> crate::Result<(Option<Duration>, Option<Duration>)> {
+        let remaining = match self.deadline {
+            Some(deadline) => remaining_until(deadline)?,
+            None => return Ok((self.read_timeout, self.write_timeout)),
+        };
+        let bound = |timeout: Option<Duration>| {
+            Some(timeout.map_or(remaining, |timeout| timeout.min(remaining)))
+        };
+        Ok((bound(self.read_timeout), bound(self.write_timeout)))
+    }
+
+    /// Get the last line of the welcome message from the server on connect,
+    /// with its `220` reply code. `get_welcome_lines` has every line.
+    pub fn get_welcome_msg(&self) -> Option<&str> {
+        self.welcome_msg.as_deref()
+    }
+
+    /// Takes the last line of the welcome message, as `get_welcome_msg`
+    /// returns it, leaving `None` in its place. Every line stays available
+    /// through `get_welcome_lines`.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// let banner = conn.take_welcome_msg().unwrap();
+    /// assert_eq!(banner, "220 rust-ftp test server ready");
+    /// assert_eq!(conn.get_welcome_msg(), None);
+    /// assert_eq!(conn.get_welcome_lines(), ["rust-ftp test server ready"]);
+    /// ```
+    pub fn take_welcome_msg(&mut self) -> Option<String> {
+        self.welcome_msg.take()
+    }
+
+    /// Get every line of the welcome message, without the `220` reply code.
+    /// A multi-line welcome message often carries the server's rules or
+    /// notices before its last line.
+    pub fn get_welcome_lines(&self) -> &[String] {
+        &self.welcome_lines
+    }
+
+    /// Sets the login convention of the FTP proxy this stream is connected
+    /// to. `login` then authenticates against the proxy as needed and asks it
+    /// to reach the target server. `None` (the default) logs in directly.
+    ///
+    /// ```rust,no_run
+    /// use ftp::FtpStream;
+    /// use ftp::types::ProxyScheme;
+    ///
+    /// let mut ftp_stream = FtpStream::connect("proxy.example.com:21").unwrap();
+    /// ftp_stream.set_proxy_scheme(Some(ProxyScheme::UserAtHost {
+    ///     host: String::from("ftp.example.com"),
+    /// }));
+    /// // Sends `USER anonymous@ftp.example.com`
+    /// ftp_stream.login("anonymous", "anonymous").unwrap();
+    /// ```
+    pub fn set_proxy_scheme(&mut self, scheme: Option<ProxyScheme>) {
+        self.proxy = scheme;
+    }
+
+    /// Log in to the FTP server, returning the lines of the server's reply.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// let info = conn.login("Doe", "mumble").unwrap();
+    /// assert_eq!(info.lines, ["Login successful."]);
+    /// ```
+    pub fn login(&mut self, user: &str, password: &str) -> crate::Result<LoginInfo> {
+        let info = self.proxy_login(user, password)?;
+        self.session.credentials = Some((user.to_owned(), password.to_owned()));
+        // The user may start in their own home directory.
+        self.session.cwd = None;
+        Ok(info)
+    }
+
+    /// Log in, going through the proxy scheme if one is set.
+    fn proxy_login(&mut self, user: &str, password: &str) -> crate::Result<LoginInfo> {
+        let info = match self.proxy.clone() {
+            None => self.user_pass(user, password),
+            Some(ProxyScheme::UserAtHost { host }) => {
+                self.user_pass(&format!("{}@{}", user, host), password)
+            }
+            Some(ProxyScheme::Open { host }) => {
+                self.write_cmd(format_args!("OPEN {}\r\n", host))?;
+                self.read_response_in(&[Status::Ready, Status::CommandOk])?;
+                self.user_pass(user, password)
+            }
+            Some(ProxyScheme::Site {
+                proxy_user,
+                proxy_password,
+                host,
+            }) => {
+                self.user_pass(&proxy_user, &proxy_password)?;
+                self.write_cmd(format_args!("SITE {}\r\n", host))?;
+                self.read_response_in(&[Status::Ready, Status::CommandOk])?;
+                self.user_pass(user, password)
+            }
+            Some(ProxyScheme::UserAtHostAfterLogon {
+                proxy_user,
+                proxy_password,
+                host,
+            }) => {
+                self.user_pass(&proxy_user, &proxy_password)?;
+                self.user_pass(&format!("{}@{}", user, host), password)
+            }
+        }?;
+        self.emit(SessionEvent::LoggedIn {
+            user: user.to_owned(),
+        });
+        Ok(info)
+    }
+
+    /// Send `USER` and, if the server asks for it, `PASS`.
+    fn user_pass(&mut self, user: &str, password: &str) -> crate::Result<LoginInfo> {
+        if self.tls_mode == TlsMode::Required && !self.is_secure() {
+            return Err(FtpError::TlsRequired);
+        }
+        self.write_cmd(format_args!("USER {}\r\n", user))?;
+        let (Line(code, _), mut lines) =
+            self.read_reply_lines(&[Status::LoggedIn, Status::NeedPassword])?;
+        if code == Status::NeedPassword {
+            self.write_cmd(format_args!("PASS {}\r\n", password))?;
+            lines = self.read_reply_lines(&[Status::LoggedIn])?.1;
+        }
+        Ok(LoginInfo { lines })
+    }
+
+    /// Change the current directory to the path specified.
+    pub fn cwd<P: AsRef<str>>(&mut self, path: P) -> crate::Result<()> {
+        self.command_class(
+            format_args!("CWD {}\r\n", path.as_ref()),
+            ResponseClass::PositiveCompletion,
+        )?;
+        let cwd = self.resolve(path);
+        self.remember_cwd(cwd)
+    }
+
+    /// Move the current directory to the parent directory.
+    pub fn cdup(&mut self) -> crate::Result<()> {
+        self.command_class(format_args!("CDUP\r\n"), ResponseClass::PositiveCompletion)?;
+        let cwd = self.resolve("..");
+        self.remember_cwd(cwd)
+    }
+
+    /// Record the absolute current directory, asking the server for it when
+    /// it is unknown and a reconnection would need to return to it.
+    fn remember_cwd(&mut self, cwd: Option<FtpPath>) -> crate::Result<()> {
+        self.session.cwd = cwd;
+        if self.session.cwd.is_none() && (self.auto_reconnect || self.revalidate_after.is_some()) {
+            self.pwd()?;
+        }
+        Ok(())
+    }
+
+    /// Gets the current directory
+    pub fn pwd(&mut self) -> crate::Result<String> {
+        let Line(_, content) = self.command(format_args!("PWD\r\n"), &[Status::PathCreated])?;
+        let cwd = parse::pwd(&content)?;
+        self.session.cwd = Some(FtpPath::new(cwd.as_str()));
+        Ok(cwd)
+    }
+
+    /// The current directory as tracked by the client, without a round-trip
+    /// to the server. It is known after `pwd`, and kept up to date by `cwd`
+    /// and `cdup` from there or from an absolute path. The tracking is
+    /// lexical: after changing to a symbolic link, `pwd` tells where the
+    /// server actually is.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// # conn.mkdir("/pub").unwrap();
+    /// # conn.mkdir("/pub/docs").unwrap();
+    /// conn.cwd("/pub").unwrap();
+    /// conn.cwd("docs").unwrap();
+    /// assert_eq!(conn.current_dir().unwrap().as_str(), "/pub/docs");
+    /// assert_eq!(conn.resolve("../file.txt").unwrap().as_str(), "/pub/file.txt");
+    /// ```
+    pub fn current_dir(&self) -> Option<&FtpPath> {
+        self.session.cwd.as_ref()
+    }
+
+    /// The absolute, normalized form of `path` relative to the current
+    /// directory, or `None` if `path` is relative and `current_dir` unknown.
+    pub fn resolve<P: AsRef<str>>(&self, path: P) -> Option<FtpPath> {
+        let path = match self.session.cwd {
+            Some(ref cwd) => cwd.join(path),
+            None => FtpPath::new(path.as_ref()),
+        };
+        if path.is_absolute() {
+            Some(path.normalize())
+        } else {
+            None
+        }
+    }
+
+    /// This does nothing. This is usually just used to keep the connection open.
+    pub fn noop(&mut self) -> crate::Result<()> {
+        self.command_class(format_args!("NOOP\r\n"), ResponseClass::PositiveCompletion)
+            .map(|_| ())
+    }
+
+    /// Sends `command`, e.g. `SITE EXEC unpack.sh`, and passes the lines of
+    /// the replies to `on_line` as soon as they are read: the lines of the
+    /// 1xx replies and of a multi-line final reply, except the last. That
+    /// one is returned if its code is 2xx. Meant for commands running for a
+    /// long time and reporting their progress.
+    ///
+    /// As the lines were already handed to `on_line`, the `Reply` of an
+    /// error holds the last line only.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// conn.put_bytes("a.txt", b"a").unwrap();
+    /// let mut lines = Vec::new();
+    /// let last = conn
+    ///     .raw_command_streaming("STAT .", |line| lines.push(line.to_owned()))
+    ///     .unwrap();
+    /// assert_eq!(lines[0], "213-Status of .:");
+    /// assert_eq!(last.1, "213 End of status");
+    /// ```
+    pub fn raw_command_streaming<F>(&mut self, command: &str, mut on_line: F) -> crate::Result<Line>
+    where
+        F: FnMut(&str),
+    {
+        self.write_cmd(format_args!("{}\r\n", command))?;
+        let lenient = self.parse_mode == ParseMode::Lenient;
+        let mut line = std::mem::take(&mut self.line_buf);
+        let res = loop {
+            line.clear();
+            let observer = &mut self.observer;
+            let reply = parse::read_reply_with(&mut self.reader, &mut line, lenient, |body| {
+                if let Some(ref mut observer) = *observer {
+                    observer.reply_line(body);
+                }
+                on_line(body);
+            });
+            let reply = match reply {
+                Ok(reply) => reply,
+                Err(err) => break Err(err),
+            };
+            // 1xx replies come before the final one.
+            let preliminary = reply.0.is_preliminary();
+            let class = if preliminary {
+                ResponseClass::PositivePreliminary
+            } else {
+                ResponseClass::PositiveCompletion
+            };
+            match self.check_reply(reply, Expected::Class(class), &[]) {
+                Ok(Line(_, ref text)) if preliminary => on_line(text),
+                res => break res,
+            }
+        };
+        self.line_buf = line;
+        res
+    }
+
+    /// Sets how long the control connection may stay idle before `keepalive`
+    /// sends `NOOP`. Servers end sessions idle for too long, typically five
+    /// minutes, which long local processing between commands can exceed.
+    /// `None` (the default) disables the keepalive.
+    pub fn set_keepalive_interval(&mut self, interval: Option<Duration>) {
+        self.keepalive_interval = interval;
+    }
+
+    /// Checks that the connection is still alive before a command when it
+    /// has been idle for `idle` or longer, and reconnects if it isn't: a
+    /// new control connection is opened, the session restored as with
+    /// `set_auto_reconnect` and the command then sent. The check is a
+    /// `NOOP`, so servers, NAT gateways and firewalls which silently drop
+    /// idle connections don't make the next command fail.
+    ///
+    /// Enable it before `login` and `cwd` so the session can be restored,
+    /// and set a read timeout: a connection dropped silently may otherwise
+    /// take long to fail the check. `None` (the default) disables it.
+    pub fn set_idle_revalidation(&mut self, idle: Option<Duration>) {
+        self.revalidate_after = idle;
+    }
+
+    /// The time since the last command was sent.
+    pub fn idle_time(&self) -> Duration {
+        self.last_command_at.elapsed()
+    }
+
+    /// Sends `NOOP` if the control connection has been idle for longer than
+    /// the interval set with `set_keepalive_interval`, returning whether it
+    /// did. Call it regularly during long work between commands, or let
+    /// `shared::SharedFtpStream::start_keepalive` do it from a thread.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// use std::time::Duration;
+    ///
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// conn.set_keepalive_interval(Some(Duration::from_secs(60)));
+    /// for chunk in 0..10 {
+    ///     // ... long local work ...
+    ///     conn.keepalive().unwrap();
+    /// }
+    /// ```
+    pub fn keepalive(&mut self) -> crate::Result<bool> {
+        match self.keepalive_interval {
+            Some(interval) if self.idle_time() >= interval => self.noop().map(|_| true),
+            _ => Ok(false),
+        }
+    }
+
+    /// Asks the server for the extensions it supports with `FEAT` and caches
+    /// them on the stream. The cached features then drive the choice of
+    /// commands: `EPSV` over `PASV`, `MLSD` over `LIST` in `list_entries`,
+    /// and `MFMT` for `sync::mirror` to preserve times. `OPTS UTF8 ON` is
+    /// sent when the server supports UTF-8 file names. A server which
+    /// doesn't know `FEAT` supports no extension.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// let features = conn.feat().unwrap();
+    /// assert!(features.supports("EPSV"));
+    /// assert!(conn.supports("MLST"));
+    /// ```
+    pub fn feat(&mut self) -> crate::Result<&Features> {
+        let mut lines = Vec::new();
+        let res = self.retrying(|ftp_stream| {
+            lines.clear();
+            ftp_stream.write_str("FEAT\r\n")?;
+            ftp_stream.read_reply(Expected::Codes(&[Status::System]), Some(&mut lines))
+        });
+        let features = match res {
+            Ok(_) => parse::feat(lines.get(1..).unwrap_or_default()),
+            Err(ref err) if is_unsupported(err) => Features::default(),
+            Err(err) => return Err(err),
+        };
+        self.features = Some(features);
+        self.enable_utf8()?;
+        Ok(self.features.get_or_insert_with(Features::default))
+    }
+
+    /// Asks the server for the space available to the user at `path`, in
+    /// bytes, with the `AVBL` extension.
+    pub fn avail(&mut self, path: &str) -> crate::Result<u64> {
+        let Line(_, reply) = self.command(format_args!("AVBL {}\r\n", path), &[Status::File])?;
+        parse::avbl(&reply)
+            .ok_or_else(|| FtpError::InvalidResponse(format!("invalid AVBL reply: {:?}", reply)))
+    }
+
+    /// Asks the server for the user's storage quota with `SITE QUOTA`, see
+    /// `parse::quota` for the replies understood. Returns `None` if the
+    /// reply doesn't hold a quota.
+    pub fn quota(&mut self) -> crate::Result<Option<Quota>> {
+        let (_, lines) = self.retrying(|ftp_stream| {
+            ftp_stream.write_str("SITE QUOTA\r\n")?;
+            ftp_stream.read_reply_lines(&[Status::CommandOk])
+        })?;
+        Ok(parse::quota(&lines))
+    }
+
+    /// Checks there is room for `bytes` more bytes at `path` before
+    /// uploading them, failing fast with `InsufficientStorage` otherwise.
+    /// The space left is asked with `AVBL`, or else derived from the quota.
+    /// If the server reports neither, the check passes.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// conn.ensure_available("/", 1 << 30).unwrap();
+    /// ```
+    pub fn ensure_available(&mut self, path: &str, bytes: u64) -> crate::Result<()> {
+        let available = match ignore_refusal(self.avail(path))? {
+            Some(available) => Some(available),
+            None => ignore_refusal(self.quota())?
+                .flatten()
+                .and_then(|quota| quota.available()),
+        };
+        match available {
+            Some(available) if available < bytes => Err(FtpError::InsufficientStorage {
+                needed: bytes,
+                available,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Asks the server for its system type with `SYST`, e.g. `UNIX Type: L8`.
+    pub fn system(&mut self) -> crate::Result<String> {
+        let Line(_, reply) = self.command(format_args!("SYST\r\n"), &[Status::Name])?;
+        Ok(reply.get(4..).unwrap_or_default().to_owned())
+    }
+
+    /// Identifies the client to the server with `CLNT`, which some servers
+    /// use to adjust their behavior, such as the facts listed by `MLSD`.
+    /// `None` sends the default name, `rust-ftp` followed by the version of
+    /// the crate.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// conn.clnt(Some("backup-agent 2.1")).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// ```
+    pub fn clnt(&mut self, name: Option<&str>) -> crate::Result<()> {
+        let name = name.unwrap_or(concat!("rust-ftp ", env!("CARGO_PKG_VERSION")));
+        self.command_class(
+            format_args!("CLNT {}\r\n", name),
+            ResponseClass::PositiveCompletion,
+        )
+        .map(|_| ())
+    }
+
+    /// Recognizes the server software from the welcome message and the reply
+    /// to `SYST`, which is sent the first time only. The result is cached on
+    /// the stream, a server which doesn't know `SYST` reports no system.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// let info = conn.server_info().unwrap();
+    /// assert_eq!(info.system.as_deref(), Some("UNIX Type: L8"));
+    /// ```
+    pub fn server_info(&mut self) -> crate::Result<&ServerInfo> {
+        if self.server_info.is_none() {
+            let syst = match self.command(format_args!("SYST\r\n"), &[Status::Name]) {
+                Ok(Line(_, reply)) => Some(reply),
+                Err(FtpError::UnexpectedResponse(_)) => None,
+                Err(err) => return Err(err),
+            };
+            self.server_info = Some(parse::server_info(&self.welcome_lines, syst.as_deref()));
+        }
+        Ok(self.server_info.get_or_insert_with(ServerInfo::default))
+    }
+
+    /// The features cached by `feat`, if it was called.
+    pub fn features(&self) -> Option<&Features> {
+        self.features.as_ref()
+    }
+
+    /// Returns `true` if the features cached by `feat` include `feature`,
+    /// ignoring case, and legacy commands aren't forced.
+    pub fn supports(&self, feature: &str) -> bool {
+        !self.legacy_commands
+            && self
+                .features
+                .as_ref()
+                .is_some_and(|features| features.supports(feature))
+    }
+
+    /// Sticks to the commands every server knows, `PASV` and `LIST`,
+    /// whatever the features cached by `feat`, for servers advertising
+    /// extensions they don't implement properly.
+    pub fn set_legacy_commands(&mut self, legacy: bool) {
+        self.legacy_commands = legacy;
+    }
+
+    /// Accepts `202`, "command not implemented, superfluous at this site",
+    /// as the success of `PBSZ`, `PROT` and `OPTS`, which servers needing
+    /// no such negotiation may reply. Disabled by default: a `202` to
+    /// `PROT P` leaves the protection of data connections to the server.
+    pub fn set_tolerate_superfluous(&mut self, tolerate: bool) {
+        self.tolerate_superfluous = tolerate;
+    }
+
+    /// Reads the reply to a command negotiating the session, see
+    /// `set_tolerate_superfluous`.
+    fn read_negotiation(&mut self) -> crate::Result<Line> {
+        if self.tolerate_superfluous {
+            self.read_response_in(&[Status::CommandOk, Status::CommandNotImplemented])
+        } else {
+            self.read_response(Status::CommandOk)
+        }
+    }
+
+    /// Asks for UTF-8 file names if the server supports them.
+    fn enable_utf8(&mut self) -> crate::Result<()> {
+        if self.supports("UTF8") {
+            // Servers always using UTF-8 may refuse the command.
+            match self.retrying(|ftp_stream| {
+                ftp_stream.write_str("OPTS UTF8 ON\r\n")?;
+                ftp_stream.read_negotiation()
+            }) {
+                Ok(_) | Err(FtpError::UnexpectedResponse(_)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// This creates a new directory on the server, returning its path as
+    /// the server replied it, usually absolute and normalized. Servers whose
+    /// reply has no path get the path resolved by `resolve`, `None` if the
+    /// current directory isn't known.
+    pub fn mkdir<P: AsRef<str>>(&mut self, pathname: P) -> crate::Result<Option<String>> {
+        let pathname = pathname.as_ref();
+        let Line(_, content) =
+            self.command(format_args!("MKD {}\r\n", pathname), &[Status::PathCreated])?;
+        Ok(parse::quoted_path(&content)
+            .or_else(|| self.resolve(pathname).map(|path| path.to_string())))
+    }
+
+    /// Runs the PASV command, or EPSV if the server supports it.
+    fn pasv(&mut self) -> crate::Result<SocketAddr> {
+        if self.supports("EPSV") {
+            return self.epsv();
+        }
+        self.write_str("PASV\r\n")?;
+        // PASV response format : 227 Entering Passive Mode (h1,h2,h3,h4,p1,p2).
+        let Line(_, line) = self.read_response(Status::PassiveMode)?;
+        parse::pasv(&line).and_then(|mut addr| {
+            match self.passive_address {
+                PassiveAddressPolicy::UseServerAddress => {}
+                PassiveAddressPolicy::UseControlPeer => {
+                    addr.set_ip(self.get_ref().peer_addr()?.ip())
+                }
+                PassiveAddressPolicy::Override(ip) => addr.set_ip(ip),
+            }
+            Ok(addr)
+        })
+    }
+
+    /// Announces every passive mode data command with `PRET` before `PASV`,
+    /// as distributed servers such as DrFTPD require to pick the node serving
+    /// the transfer. `PRET` is sent anyway when the features cached by `feat`
+    /// include it.
+    pub fn set_pret(&mut self, enabled: bool) {
+        self.pret = enabled;
+    }
+
+    /// Sets which address passive mode data connections are made to. By
+    /// default the address advertised by the server is used, which fails with
+    /// servers behind a NAT advertising their private address.
+    pub fn set_passive_address_policy(&mut self, policy: PassiveAddressPolicy) {
+        self.passive_address = policy;
     }
 
     /// Sets the type of file to be transferred. That is the implementation
     /// of `TYPE` command.
     pub fn transfer_type(&mut self, file_type: FileType) -> crate::Result<()> {
-        let type_command = format!("TYPE {}\r\n", file_type.to_string());
-        self.write_str(&type_command)?;
-        self.read_response(status::COMMAND_OK).map(|_| ())
+        self.command_class(
+            format_args!("TYPE {}\r\n", file_type),
+            ResponseClass::PositiveCompletion,
+        )?;
+        self.session.file_type = Some(file_type);
+        Ok(())
+    }
+
+    /// Sets the transmission mode of the following transfers. That is the
+    /// implementation of `MODE` command.
+    ///
+    /// In block mode the data of `retr`, `stor`, `put`, `append` and the
+    /// listings is decoded and encoded by this stream, but `get` returns the
+    /// raw blocks. The restart markers of the last transfer are returned by
+    /// `restart_markers`.
+    pub fn transmission_mode(&mut self, mode: TransmissionMode) -> crate::Result<()> {
+        self.command_class(
+            format_args!("MODE {}\r\n", mode),
+            ResponseClass::PositiveCompletion,
+        )?;
+        self.session.transmission_mode = mode;
+        Ok(())
+    }
+
+    /// Sends a restart marker every `interval` bytes of the files uploaded
+    /// in block mode, none by default. The server acknowledges each marker
+    /// with a `110` reply, returned by `restart_markers`.
+    pub fn set_restart_marker_interval(&mut self, interval: Option<u64>) {
+        self.marker_interval = interval;
+    }
+
+    /// The restart markers of the last block mode transfer: those sent by the
+    /// server for a download, and the server acknowledgements of those sent
+    /// by this stream for an upload. Each one can be saved as a checkpoint,
+    /// an interrupted transfer then resumes from its `offset` after
+    /// `restart` with its `marker`.
+    pub fn restart_markers(&self) -> &[RestartMarker] {
+        &self.restart_markers
+    }
+
+    /// Makes the next transfer resume from `marker`. That is the
+    /// implementation of `REST` command.
+    pub fn restart(&mut self, marker: &str) -> crate::Result<()> {
+        self.command(
+            format_args!("REST {}\r\n", marker),
+            &[Status::RequestFilePending],
+        )
+        .map(|_| ())
+    }
+
+    /// Returns `true` if the server resumes stream mode transfers from a
+    /// byte offset with `REST`, as `REST STREAM` in the features cached by
+    /// `feat` tells. Without it, `REST 0` is sent to find out, which
+    /// restarts nothing. The result is cached on the stream.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// if conn.supports_resume().unwrap() {
+    ///     conn.restart("1024").unwrap();
+    /// }
+    /// ```
+    pub fn supports_resume(&mut self) -> crate::Result<bool> {
+        if let Some(supported) = self.resume_supported {
+            return Ok(supported);
+        }
+        let advertised = !self.legacy_commands
+            && self
+                .features
+                .as_ref()
+                .and_then(|features| features.params("REST"))
+                .is_some_and(|params| params.eq_ignore_ascii_case("STREAM"));
+        let supported = advertised
+            || match self.restart("0") {
+                Ok(()) => true,
+                Err(ref err)
+                    if is_unsupported(err) || err.status() == Some(Status::BadArguments) =>
+                {
+                    false
+                }
+                Err(err) => return Err(err),
+            };
+        self.resume_supported = Some(supported);
+        Ok(supported)
+    }
+
+    /// Wraps a data connection to read a transfer in the current transmission mode.
+    fn transfer_reader(&mut self, data_stream: DataStream) -> TransferReader {
+        self.restart_markers.clear();
+        match self.session.transmission_mode {
+            TransmissionMode::Stream => TransferReader::Stream(data_stream),
+            TransmissionMode::Block => TransferReader::Block(BlockReader::new(data_stream)),
+        }
+    }
+
+    /// Wraps a data connection to write a transfer in the current transmission mode.
+    fn transfer_writer(&mut self, data_stream: DataStream) -> TransferWriter {
+        self.restart_markers.clear();
+        match self.session.transmission_mode {
+            TransmissionMode::Stream => TransferWriter::Stream(data_stream),
+            TransmissionMode::Block => {
+                let mut writer = BlockWriter::new(data_stream);
+                writer.set_marker_interval(self.marker_interval);
+                TransferWriter::Block(writer)
+            }
+        }
+    }
+
+    /// Returns a handle aborting the transfers of this stream from another
+    /// thread, e.g. for the Cancel button of a GUI. The data connection of
+    /// an aborted transfer is shut down at once, then the thread running
+    /// the transfer sends `ABOR` and reads the replies, so the session can
+    /// go on. Transfers read by the caller, such as `get`, are only cut off.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// # conn.put_bytes("big.iso", &[0; 4096]).unwrap();
+    /// use ftp::FtpError;
+    ///
+    /// let handle = conn.abort_handle();
+    /// let res = conn.retr("big.iso", |reader| {
+    ///     reader.read_exact(&mut [0; 1024])?;
+    ///     // Typically called by another thread.
+    ///     handle.abort();
+    ///     Ok(std::io::copy(reader, &mut std::io::sink())?)
+    /// });
+    /// assert!(matches!(res, Err(FtpError::Aborted)));
+    /// conn.noop().unwrap();
+    /// ```
+    pub fn abort_handle(&mut self) -> AbortHandle {
+        self.abort.get_or_insert_with(AbortHandle::default).clone()
+    }
+
+    /// Ends the transfer in progress before its final reply is read. If it
+    /// was aborted with an `AbortHandle`, sends `ABOR`, reads the reply to
+    /// the transfer command and the one to `ABOR`, and fails with `Aborted`.
+    fn finish_transfer(&mut self) -> crate::Result<()> {
+        if !self.abort.as_ref().is_some_and(AbortHandle::finish) {
+            return Ok(());
+        }
+        self.write_str("ABOR\r\n")?;
+        self.read_response_in(&[
+            Status::ClosingDataConnection,
+            Status::RequestedFileActionOk,
+            Status::TransferAborted,
+            Status::ActionAborted,
+        ])?;
+        self.read_response_in(&[Status::ClosingDataConnection, Status::DataConnectionOpen])?;
+        Err(FtpError::Aborted)
+    }
+
+    /// Ends an upload of `bytes` bytes whose data connection was closed
+    /// with `res`: reads the final reply, and reports the bytes sent to
+    /// `transfer` whether the upload succeeded or not.
+    fn end_upload(
+        &mut self,
+        transfer: TransferTrace,
+        res: crate::Result<()>,
+        bytes: u64,
+    ) -> crate::Result<u64> {
+        // The server replies once the data connection is closed, even when
+        // the upload was cut short.
+        let reply = self.read_transfer_reply();
+        let res = Self::transfer_result(res.map(|()| bytes), reply, bytes);
+        match res {
+            Ok(_) => transfer.finish(bytes),
+            Err(_) => transfer.fail(bytes),
+        }
+        res
+    }
+
+    /// Reads the final reply of an upload, recording the restart markers the
+    /// server acknowledged before it.
+    fn read_transfer_reply(&mut self) -> crate::Result<Line> {
+        self.finish_transfer()?;
+        loop {
+            let line = self.read_response_in(&[
+                Status::RestartMarker,
+                Status::ClosingDataConnection,
+                Status::RequestedFileActionOk,
+            ])?;
+            if line.0 != Status::RestartMarker {
+                return Ok(line);
+            }
+            self.restart_markers.extend(parse::restart_marker(&line.1));
+        }
+    }
+
+    /// Enables or disables automatic reconnection. When enabled and the server
+    /// ends the session with `421 Service not available`, the next command
+    /// opens a new control connection, logs in again with the credentials of
+    /// the last `login`, restores the current directory and transfer type and
+    /// then retries the command. Transfers whose data already started flowing
+    /// are not retried.
+    ///
+    /// Enable it before `login` and `cwd` so the session can be restored.
+    pub fn set_auto_reconnect(&mut self, enabled: bool) {
+        self.auto_reconnect = enabled;
+    }
+
+    /// Enables or disables automatic binary mode. When enabled, `size`, `retr`
+    /// and the `put` methods switch to `TYPE I` if another type is set and
+    /// restore the previous type afterwards, so sizes and transfers don't
+    /// depend on the last `transfer_type` call. Many servers refuse `SIZE`
+    /// in ASCII mode.
+    pub fn set_auto_binary(&mut self, enabled: bool) {
+        self.auto_binary = enabled;
+    }
+
+    /// Enables or disables preserving modification times: `download_to`
+    /// then sets the time of the local file to the `MDTM` of the remote one,
+    /// and `upload_from` sets the time of the remote file with `MFMT`. The
+    /// transfer still succeeds if the server doesn't support the command.
+    pub fn set_preserve_mtime(&mut self, enabled: bool) {
+        self.preserve_mtime = enabled;
+    }
+
+    /// Run `op` in binary mode if automatic binary mode is enabled.
+    fn with_binary<T, F>(&mut self, op: F) -> crate::Result<T>
+    where
+        F: FnOnce(&mut Self) -> crate::Result<T>,
+    {
+        let auto_binary = self.auto_binary;
+        self.in_binary(auto_binary, op)
+    }
+
+    /// Run `op` in binary mode if `switch` is set, restoring the previous
+    /// type afterwards.
+    fn in_binary<T, F>(&mut self, switch: bool, op: F) -> crate::Result<T>
+    where
+        F: FnOnce(&mut Self) -> crate::Result<T>,
+    {
+        let previous = self.session.file_type.clone();
+        if !switch || matches!(previous, Some(FileType::Image | FileType::Binary)) {
+            return op(self);
+        }
+        self.transfer_type(FileType::Binary)?;
+        let res = op(self);
+        // Servers start in ASCII mode when no type was set.
+        let restored = self.transfer_type(
+            previous
+                .clone()
+                .unwrap_or(FileType::Ascii(FormatControl::Default)),
+        );
+        // If the type couldn't be restored, the server is still in binary.
+        self.session.file_type = match restored {
+            Ok(()) => previous,
+            Err(_) => Some(FileType::Binary),
+        };
+        let res = res?;
+        restored?;
+        Ok(res)
+    }
+
+    /// Returns `true` once the server closed the session with a `421` reply,
+    /// TLS failed to start on it in `secure`, or a data connection failed
+    /// while the reply to its command was pending. Any further command fails
+    /// unless automatic reconnection is enabled.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Open a new control connection to the same server and restore the
+    /// session on it.
+    fn reconnect(&mut self) -> crate::Result<()> {
+        let mut fresh = self.connect_again()?;
+        std::mem::swap(&mut self.reader, &mut fresh.reader);
+        self.welcome_msg = fresh.welcome_msg.take();
+        self.welcome_lines = std::mem::take(&mut fresh.welcome_lines);
+        self.closed = false;
+        self.restore_timeouts()?;
+
+        if let Some(ref metrics) = self.metrics {
+            metrics.reconnected();
+        }
+        self.emit(SessionEvent::Connected { peer: self.peer });
+        if self.is_secure() {
+            self.emit(SessionEvent::TlsEstablished);
+        }
+
+        // Don't recurse into another reconnection if restoring fails.
+        let auto_reconnect = std::mem::replace(&mut self.auto_reconnect, false);
+        let res = self.restore_session();
+        self.auto_reconnect = auto_reconnect;
+        res
+    }
+
+    /// Open a new control connection to the same server, secured like this one.
+    fn connect_again(&self) -> crate::Result<Self> {
+        let peer = self.peer.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "the server address of the control connection is unknown",
+            )
+        })?;
+        let stream = S::reopen(peer, &self.dialer)?;
+        #[allow(unused_mut)]
+        let mut fresh = GenericFtpStream::with_control_stream(stream, self.parse_mode)?
+            .with_reader_capacity(self.reader_capacity())?;
+        fresh.dialer = self.dialer.clone();
+        fresh.tolerate_superfluous = self.tolerate_superfluous;
+        #[cfg(feature = "secure")]
+        {
+            if self.is_data_secure() {
+                fresh.write_str("AUTH TLS\r\n")?;
+                fresh.read_response(Status::AuthOk)?;
+                fresh = fresh.start_tls(self.tls.clone())?;
+                if !self.is_secure() {
+                    fresh = fresh.into_insecure()?;
+                }
+            }
+        }
+        Ok(fresh)
+    }
+
+    /// Opens a second control connection to the same server, logged in with
+    /// the credentials of the last `login` and in the same directory and
+    /// transfer type as this one. A single control connection can only run
+    /// one command at a time; with a second session a listing and a download
+    /// can proceed concurrently, e.g. from different threads.
+    ///
+    /// ```rust,no_run
+    /// use ftp::FtpStream;
+    /// use std::thread;
+    ///
+    /// let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
+    /// ftp_stream.login("anonymous", "anonymous").unwrap();
+    /// let mut other = ftp_stream.spawn_session().unwrap();
+    /// let download = thread::spawn(move || other.simple_retr("big.iso"));
+    /// let names = ftp_stream.nlst(None).unwrap();
+    /// let data = download.join().unwrap().unwrap();
+    /// ```
+    pub fn spawn_session(&mut self) -> crate::Result<Self> {
+        let cwd = match self.session.cwd {
+            Some(ref cwd) => cwd.clone(),
+            None => FtpPath::new(self.pwd()?),
+        };
+        let mut spawned = self.connect_again()?;
+        spawned.proxy = self.proxy.clone();
+        spawned.passive_address = self.passive_address;
+        spawned.mode = self.mode.clone();
+        spawned.connect_order = self.connect_order;
+        spawned.text_encoding = self.text_encoding;
+        spawned.max_download_size = self.max_download_size;
+        spawned.tls_mode = self.tls_mode;
+        spawned.features = self.features.clone();
+        spawned.legacy_commands = self.legacy_commands;
+        spawned.tolerate_superfluous = self.tolerate_superfluous;
+        spawned.pret = self.pret;
+        spawned.marker_interval = self.marker_interval;
+        spawned.keepalive_interval = self.keepalive_interval;
+        spawned.revalidate_after = self.revalidate_after;
+        spawned.socket_config = self.socket_config.clone();
+        spawned.read_timeout = self.read_timeout;
+        spawned.write_timeout = self.write_timeout;
+        spawned.restore_timeouts()?;
+        spawned.session = SessionState {
+            cwd: Some(cwd),
+            ..self.session.clone()
+        };
+        spawned.restore_session()?;
+        spawned.auto_reconnect = self.auto_reconnect;
+        spawned.auto_binary = self.auto_binary;
+        spawned.preserve_mtime = self.preserve_mtime;
+        spawned.restart_downloads = self.restart_downloads;
+        spawned.resume_supported = self.resume_supported;
+        spawned.pipeline_depth = self.pipeline_depth;
+        spawned.buffer_size = self.buffer_size;
+        spawned.metrics = self.metrics.clone();
+        spawned.events = self.events.clone();
+        Ok(spawned)
+    }
+
+    /// Sets the timeouts remembered by `set_timeouts` on a new control connection.
+    fn restore_timeouts(&mut self) -> crate::Result<()> {
+        if self.read_timeout.is_some() {
+            self.get_ref().set_read_timeout(self.read_timeout)?;
+        }
+        if self.write_timeout.is_some() {
+            self.get_ref().set_write_timeout(self.write_timeout)?;
+        }
+        Ok(())
+    }
+
+    fn restore_session(&mut self) -> crate::Result<()> {
+        if let Some((user, password)) = self.session.credentials.clone() {
+            self.proxy_login(&user, &password)?;
+        }
+        if let Some(cwd) = self.session.cwd.clone() {
+            self.command_class(
+                format_args!("CWD {}\r\n", cwd),
+                ResponseClass::PositiveCompletion,
+            )?;
+        }
+        if let Some(file_type) = self.session.file_type.clone() {
+            self.command_class(
+                format_args!("TYPE {}\r\n", file_type),
+                ResponseClass::PositiveCompletion,
+            )?;
+        }
+        if self.session.transmission_mode != TransmissionMode::Stream {
+            let mode = self.session.transmission_mode;
+            self.command_class(
+                format_args!("MODE {}\r\n", mode),
+                ResponseClass::PositiveCompletion,
+            )?;
+        }
+        self.enable_utf8()
+    }
+
+    /// Run `op`, and if the server closed the session, reconnect and run it
+    /// once more when automatic reconnection is enabled.
+    fn retrying<T, F>(&mut self, mut op: F) -> crate::Result<T>
+    where
+        F: FnMut(&mut Self) -> crate::Result<T>,
+    {
+        if self.closed && self.auto_reconnect || self.needs_revalidation() && !self.is_alive() {
+            self.reconnect()?;
+        }
+        match op(self) {
+            Err(FtpError::ServiceNotAvailable(_)) if self.auto_reconnect => {
+                self.reconnect()?;
+                op(self)
+            }
+            res => res,
+        }
+    }
+
+    /// Returns `true` if the connection has been idle long enough to be
+    /// checked before the next command, see `set_idle_revalidation`.
+    fn needs_revalidation(&self) -> bool {
+        self.revalidate_after
+            .is_some_and(|idle| self.closed || self.idle_time() >= idle)
+    }
+
+    /// Checks with `NOOP` that the server still answers.
+    fn is_alive(&mut self) -> bool {
+        self.write_str("NOOP\r\n")
+            .and_then(|_| self.read_response_class(ResponseClass::PositiveCompletion))
+            .is_ok()
+    }
+
+    /// Send a command expecting a single reply.
+    fn command(&mut self, cmd: fmt::Arguments, expected_code: &[Status]) -> crate::Result<Line> {
+        self.retrying(|ftp_stream| {
+            ftp_stream.write_cmd(cmd)?;
+            ftp_stream.read_response_in(expected_code)
+        })
+    }
+
+    /// Send a command expecting a single reply of `class`.
+    fn command_class(&mut self, cmd: fmt::Arguments, class: ResponseClass) -> crate::Result<Line> {
+        self.retrying(|ftp_stream| {
+            ftp_stream.write_cmd(cmd)?;
+            ftp_stream.read_response_class(class)
+        })
     }
 
     /// Quits the current FTP session.
     pub fn quit(&mut self) -> crate::Result<()> {
         self.write_str("QUIT\r\n")?;
-        self.read_response(status::CLOSING).map(|_| ())
+        self.read_response(Status::Closing)?;
+        self.emit(SessionEvent::Disconnected);
+        Ok(())
     }
 
     /// Retrieves the file name specified from the server.
     /// This method is a more complicated way to retrieve a file.
     /// The reader returned should be dropped.
     /// Also you will have to read the response to make sure it has the correct value.
+    /// In block mode the reader returns the raw blocks, see `block::BlockReader`.
     pub fn get(&mut self, file_name: &str) -> crate::Result<BufReader<DataStream>> {
-        let retr_command = format!("RETR {}\r\n", file_name);
-        let data_stream = BufReader::new(self.data_command(&retr_command)?);
-        self.read_response_in(&[status::ABOUT_TO_SEND, status::ALREADY_OPEN])?;
+        let data_stream =
+            BufReader::new(self.data_command(format_args!("RETR {}\r\n", file_name))?);
         Ok(data_stream)
     }
 
     /// Renames the file from_name to to_name
     pub fn rename(&mut self, from_name: &str, to_name: &str) -> crate::Result<()> {
-        self.write_str(format!("RNFR {}\r\n", from_name))?;
-        self.read_response(status::REQUEST_FILE_PENDING)
-            .and_then(|_| {
-                self.write_str(format!("RNTO {}\r\n", to_name))?;
-                self.read_response(status::REQUESTED_FILE_ACTION_OK)
+        self.retrying(|ftp_stream| {
+            ftp_stream.write_cmd(format_args!("RNFR {}\r\n", from_name))?;
+            ftp_stream.read_response(Status::RequestFilePending)?;
+            ftp_stream.write_cmd(format_args!("RNTO {}\r\n", to_name))?;
+            ftp_stream
+                .read_response(Status::RequestedFileActionOk)
+                .map(|_| ())
+        })
+    }
+
+    /// Moves `from` to `to`, possibly in another directory, with `RNFR` and
+    /// `RNTO`. With `create_dirs`, the missing parent directories of `to`
+    /// are created first.
+    ///
+    /// Fails with `NotFound` if `from` doesn't exist, and with
+    /// `AlreadyExists` if the server refused to replace an existing file at
+    /// `to`. Many servers replace it silently.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// conn.put_bytes("upload.part", b"data").unwrap();
+    /// conn.mv("upload.part", "archive/2024/upload.bin", true).unwrap();
+    /// assert_eq!(conn.get_bytes("archive/2024/upload.bin").unwrap(), b"data");
+    /// ```
+    pub fn mv(&mut self, from: &str, to: &str, create_dirs: bool) -> crate::Result<()> {
+        if create_dirs {
+            self.create_parents(to)?;
+        }
+        let res = self.retrying(|ftp_stream| {
+            ftp_stream.write_cmd(format_args!("RNFR {}\r\n", from))?;
+            match ftp_stream.read_response(Status::RequestFilePending) {
+                Err(FtpError::UnexpectedResponse(ref reply))
+                    if reply.line.0 == Status::FileUnavailable =>
+                {
+                    return Err(FtpError::NotFound(from.to_owned()));
+                }
+                res => res?,
+            };
+            ftp_stream.write_cmd(format_args!("RNTO {}\r\n", to))?;
+            ftp_stream
+                .read_response(Status::RequestedFileActionOk)
+                .map(|_| ())
+        });
+        match res {
+            Err(FtpError::UnexpectedResponse(reply))
+                if reply
+                    .command
+                    .as_deref()
+                    .is_some_and(|command| command.starts_with("RNTO")) =>
+            {
+                // The refusal is reported as it is if the check fails.
+                match self.in_binary(true, |ftp_stream| ftp_stream.existing_size(to)) {
+                    Ok(Some(_)) => Err(FtpError::AlreadyExists(to.to_owned())),
+                    _ => Err(FtpError::UnexpectedResponse(reply)),
+                }
+            }
+            res => res,
+        }
+    }
+
+    /// Copies the remote file `from` to `to`. FTP has no copy command, so
+    /// this uses the `SITE CPFR` and `SITE CPTO` extension of ProFTPD's
+    /// mod_copy when the server knows it, and otherwise downloads the file
+    /// on this session while uploading it on a second one opened with
+    /// `spawn_session`, in binary mode.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// conn.put_bytes("config.ini", b"[main]").unwrap();
+    /// conn.copy("config.ini", "config.ini.bak").unwrap();
+    /// assert_eq!(conn.get_bytes("config.ini.bak").unwrap(), b"[main]");
+    /// ```
+    pub fn copy(&mut self, from: &str, to: &str) -> crate::Result<()> {
+        match self.command(
+            format_args!("SITE CPFR {}\r\n", from),
+            &[Status::RequestFilePending],
+        ) {
+            Ok(_) => {
+                return self
+                    .command(
+                        format_args!("SITE CPTO {}\r\n", to),
+                        &[Status::RequestedFileActionOk],
+                    )
                     .map(|_| ())
-            })
+            }
+            Err(ref err) if is_unsupported(err) => {}
+            Err(FtpError::UnexpectedResponse(ref reply))
+                if reply.line.0 == Status::FileUnavailable =>
+            {
+                return Err(FtpError::NotFound(from.to_owned()));
+            }
+            Err(err) => return Err(err),
+        }
+        let mut other = self.spawn_session()?;
+        other.transfer_type(FileType::Binary)?;
+        let res = self.in_binary(true, |ftp_stream| {
+            ftp_stream.retr(from, |mut reader| other.put(to, &mut reader))
+        });
+        let _ = other.quit();
+        res
+    }
+
+    /// Creates the parent directories of `path` which don't exist yet.
+    fn create_parents(&mut self, path: &str) -> crate::Result<()> {
+        let mut parents = Vec::new();
+        let mut path = FtpPath::new(path);
+        while let Some(parent) = path.parent() {
+            // Stop at the root, or at the current directory.
+            if parent.file_name().is_none() {
+                break;
+            }
+            parents.push(parent.clone());
+            path = parent;
+        }
+        // Directories which already exist are refused, and a directory which
+        // can't be created makes the following step fail anyway.
+        for parent in parents.iter().rev() {
+            ignore_refusal(self.mkdir(parent.as_str()))?;
+        }
+        Ok(())
     }
 
     /// The implementation of `RETR` command where `filename` is the name of the file
@@ -462,141 +2432,1106 @@ impl FtpStream {
     /// data stream opened.
     ///
     /// ```
-    /// # use ftp::{FtpStream, FtpError};
-    /// # use std::io::Cursor;
-    /// # let mut conn = FtpStream::connect("127.0.0.1:21").unwrap();
-    /// # conn.login("Doe", "mumble").and_then(|_| {
-    /// #     let mut reader = Cursor::new("hello, world!".as_bytes());
-    /// #     conn.put("retr.txt", &mut reader)
-    /// # }).unwrap();
-    /// assert!(conn.retr("retr.txt", |stream| {
-    ///     let mut buf = Vec::new();
-    ///     stream.read_to_end(&mut buf).map(|_|
-    ///         assert_eq!(buf, "hello, world!".as_bytes())
-    ///     ).map_err(|e| FtpError::ConnectionError(e))
-    /// }).is_ok());
-    /// # assert!(conn.rm("retr.txt").is_ok());
+    /// # use ftp::{FtpStream, FtpError};
+    /// # use ftp::testing::TestServer;
+    /// # use std::io::Cursor;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").and_then(|_| {
+    /// #     let mut reader = Cursor::new("hello, world!".as_bytes());
+    /// #     conn.put("retr.txt", &mut reader)
+    /// # }).unwrap();
+    /// assert!(conn.retr("retr.txt", |stream| {
+    ///     let mut buf = Vec::new();
+    ///     stream.read_to_end(&mut buf).map(|_|
+    ///         assert_eq!(buf, "hello, world!".as_bytes())
+    ///     ).map_err(|e| FtpError::ConnectionError(e))
+    /// }).is_ok());
+    /// # assert!(conn.rm("retr.txt").is_ok());
+    /// ```
+    pub fn retr<F, T>(&mut self, filename: &str, reader: F) -> crate::Result<T>
+    where
+        F: FnOnce(&mut dyn Read) -> crate::Result<T>,
+    {
+        self.retr_bytes(filename.as_bytes(), reader)
+    }
+
+    /// Same as `retr`, for a file name which is not valid UTF-8, such as one
+    /// returned by `nlst_bytes`.
+    pub fn retr_bytes<F, T>(&mut self, filename: &[u8], reader: F) -> crate::Result<T>
+    where
+        F: FnOnce(&mut dyn Read) -> crate::Result<T>,
+    {
+        self.with_binary(|ftp_stream| ftp_stream.retr_data(filename, reader))
+    }
+
+    /// Opens the remote file `path` as an `FtpFile`, read like a local file
+    /// and seekable by resuming the download with `REST`. Switches the
+    /// connection to `TYPE I`, so offsets count bytes.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// use std::io::{Read, Seek, SeekFrom};
+    ///
+    /// conn.put_bytes("hello.txt", b"hello, world!").unwrap();
+    /// let mut file = conn.open("hello.txt").unwrap();
+    /// file.seek(SeekFrom::End(-6)).unwrap();
+    /// let mut end = String::new();
+    /// file.read_to_string(&mut end).unwrap();
+    /// assert_eq!(end, "world!");
+    /// ```
+    pub fn open(&mut self, path: &str) -> crate::Result<FtpFile<'_, S>> {
+        self.prepare_file()?;
+        let len = match self.size(path) {
+            Ok(len) => len.map(|len| len as u64),
+            // Without `SIZE` the length is only known once read to the end.
+            Err(ref err) if is_unsupported(err) => None,
+            Err(err) => return Err(err),
+        };
+        Ok(FtpFile::new(self, path.to_owned(), len))
+    }
+
+    /// Creates the remote file `path`, replacing any existing one, and opens
+    /// it as an `FtpFile` for writing and reading. Switches the connection
+    /// to `TYPE I`, so offsets count bytes.
+    ///
+    /// Writing after seeking resumes the upload with `REST`, which the server
+    /// must support for `STOR`. Servers truncate the file on a `STOR` without
+    /// a restart offset, so the first byte can't be rewritten once written.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// use std::io::{Seek, SeekFrom, Write};
+    ///
+    /// let mut file = conn.create("log.txt").unwrap();
+    /// file.write_all(b"#length=??\nhello, world!\n").unwrap();
+    /// file.seek(SeekFrom::Start(8)).unwrap();
+    /// file.write_all(b"14").unwrap();
+    /// file.close().unwrap();
+    /// assert_eq!(conn.get_bytes("log.txt").unwrap(), b"#length=14\nhello, world!\n");
+    /// ```
+    pub fn create(&mut self, path: &str) -> crate::Result<FtpFile<'_, S>> {
+        self.prepare_file()?;
+        self.put_bytes(path, &[])?;
+        Ok(FtpFile::new(self, path.to_owned(), Some(0)))
+    }
+
+    /// Checks the connection can transfer parts of files and switches it to
+    /// binary mode, for `open` and `create`.
+    fn prepare_file(&mut self) -> crate::Result<()> {
+        if self.session.transmission_mode != TransmissionMode::Stream {
+            return Err(FtpError::InvalidArgument(
+                "remote files can only be opened in stream mode".to_owned(),
+            ));
+        }
+        self.transfer_type(FileType::Binary)
+    }
+
+    /// Starts downloading `path` from `offset`, for `FtpFile`.
+    pub(crate) fn retr_from(&mut self, path: &str, offset: u64) -> crate::Result<TransferReader> {
+        if offset > 0 {
+            self.restart_at(offset)?;
+        }
+        let data_stream = self.data_command(format_args!("RETR {}\r\n", path))?;
+        Ok(self.transfer_reader(data_stream))
+    }
+
+    /// Starts uploading to `path` from `offset`, for `FtpFile`.
+    pub(crate) fn stor_from(&mut self, path: &str, offset: u64) -> crate::Result<TransferWriter> {
+        if offset > 0 {
+            self.restart_at(offset)?;
+        }
+        let data_stream = self.data_command(format_args!("STOR {}\r\n", path))?;
+        Ok(self.transfer_writer(data_stream))
+    }
+
+    /// Ends an upload started by `stor_from` after `bytes` were written, and
+    /// reads its final reply.
+    pub(crate) fn finish_stor(&mut self, writer: TransferWriter, bytes: u64) -> crate::Result<()> {
+        let res = writer
+            .finish()
+            .map_err(FtpError::from)
+            .and_then(Self::close_data_stream);
+        let reply = self.read_transfer_reply();
+        Self::transfer_result(res, reply, bytes)
+    }
+
+    /// Reads the final reply of a download started by `retr_from`, once its
+    /// data connection is closed, after `bytes` were read. A download cut
+    /// short by the client may also end with `426` or `451`.
+    pub(crate) fn finish_retr(&mut self, complete: bool, bytes: u64) -> crate::Result<()> {
+        self.finish_transfer()?;
+        let reply =
+            self.read_response_in(&[Status::ClosingDataConnection, Status::RequestedFileActionOk]);
+        match reply {
+            Err(FtpError::UnexpectedResponse(ref reply))
+                if !complete
+                    && (reply.line.0 == Status::TransferAborted
+                        || reply.line.0 == Status::ActionAborted) =>
+            {
+                Ok(())
+            }
+            reply => Self::transfer_result(Ok(()), reply, bytes),
+        }
+    }
+
+    fn retr_data<F, T>(&mut self, filename: &[u8], reader: F) -> crate::Result<T>
+    where
+        F: FnOnce(&mut dyn Read) -> crate::Result<T>,
+    {
+        let transfer = self.transfer_trace("RETR", Some(&String::from_utf8_lossy(filename)));
+        let data_stream = self.data_command_raw("RETR", filename)?;
+        let mut buf = self.take_buffer();
+        let (res, bytes, markers) = {
+            let data_stream = BufferedReader::new(self.transfer_reader(data_stream), &mut buf);
+            let mut data_stream =
+                CountingReader::new(data_stream).with_progress(transfer.progress());
+            let res = reader(&mut data_stream);
+            let markers = data_stream.inner.into_inner().into_markers();
+            (res, data_stream.bytes, markers)
+        };
+        self.transfer_buffer = buf;
+        self.restart_markers = markers;
+        self.finish_transfer()?;
+        // The server replies once the data connection is closed, even when
+        // the download was cut short.
+        let reply =
+            self.read_response_in(&[Status::ClosingDataConnection, Status::RequestedFileActionOk]);
+        let res = Self::transfer_result(res, reply, bytes);
+        match res {
+            Ok(_) => transfer.finish(bytes),
+            Err(_) => transfer.fail(bytes),
+        }
+        res
+    }
+
+    /// Combines the result of the data transfer with the final reply. When
+    /// the server aborted the transfer, the data connection failing is only
+    /// a consequence of it, so `TransferAborted` is reported instead.
+    fn transfer_result<T>(
+        res: crate::Result<T>,
+        reply: crate::Result<Line>,
+        bytes: u64,
+    ) -> crate::Result<T> {
+        let reply = match reply {
+            Err(FtpError::UnexpectedResponse(reply))
+                if reply.line.0 == Status::TransferAborted
+                    || reply.line.0 == Status::ActionAborted =>
+            {
+                Err(FtpError::TransferAborted { bytes, reply })
+            }
+            reply => reply,
+        };
+        match (res, reply) {
+            (_, Err(FtpError::Aborted)) => Err(FtpError::Aborted),
+            (Err(FtpError::ConnectionError(_)), Err(err @ FtpError::TransferAborted { .. })) => {
+                Err(err)
+            }
+            (res, reply) => res.and_then(|res| reply.map(|_| res)),
+        }
+    }
+
+    /// Simple way to retr a file from the server. This stores the file in memory.
+    ///
+    /// ```
+    /// # use ftp::{FtpStream, FtpError};
+    /// # use ftp::testing::TestServer;
+    /// # use std::io::Cursor;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").and_then(|_| {
+    /// #     let mut reader = Cursor::new("hello, world!".as_bytes());
+    /// #     conn.put("simple_retr.txt", &mut reader)
+    /// # }).unwrap();
+    /// let cursor = conn.simple_retr("simple_retr.txt").unwrap();
+    /// // do something with bytes
+    /// assert_eq!(cursor.into_inner(), "hello, world!".as_bytes());
+    /// # assert!(conn.rm("simple_retr.txt").is_ok());
+    /// ```
+    pub fn simple_retr(&mut self, file_name: &str) -> crate::Result<Cursor<Vec<u8>>> {
+        self.get_bytes(file_name).map(Cursor::new)
+    }
+
+    /// Retrieves a file from the server into memory.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// conn.put_bytes("hello.txt", b"hello, world!").unwrap();
+    /// assert_eq!(conn.get_bytes("hello.txt").unwrap(), b"hello, world!");
+    /// ```
+    pub fn get_bytes(&mut self, file_name: &str) -> crate::Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        self.retr_into(file_name, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Retrieves the text file `file_name`, decoded with the encoding set by
+    /// `set_text_encoding`. A UTF-8 byte order mark is stripped.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # use std::io::Cursor;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// # conn.put("notes.txt", &mut Cursor::new("\u{feff}hello")).unwrap();
+    /// assert_eq!(conn.retr_string("notes.txt").unwrap(), "hello");
+    /// ```
+    pub fn retr_string(&mut self, file_name: &str) -> crate::Result<String> {
+        let mut buffer = Vec::new();
+        self.retr_into(file_name, &mut buffer)?;
+        Ok(self.text_encoding.decode(buffer)?)
+    }
+
+    /// Retrieves the file `file_name` into `buffer`, returning the number of
+    /// bytes read. The buffer is cleared first, so reusing the same one across
+    /// calls avoids a new allocation per file.
+    pub fn retr_into(&mut self, file_name: &str, buffer: &mut Vec<u8>) -> crate::Result<usize> {
+        buffer.clear();
+        self.retr_to_writer(file_name, buffer)
+            .map(|bytes| bytes as usize)
+    }
+
+    /// Retrieves the file `file_name`, streaming it into `writer` (a file, a
+    /// hasher, a socket...) without holding it in memory. Returns the number
+    /// of bytes written.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # use std::io::Cursor;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// # conn.put("big.iso", &mut Cursor::new(vec![0u8; 4096])).unwrap();
+    /// let mut file = Vec::new(); // e.g. a std::fs::File
+    /// assert_eq!(conn.retr_to_writer("big.iso", &mut file).unwrap(), 4096);
+    /// ```
+    pub fn retr_to_writer<W: Write>(
+        &mut self,
+        file_name: &str,
+        writer: &mut W,
+    ) -> crate::Result<u64> {
+        let max_size = self.max_download_size;
+        self.retr(file_name, |reader| match max_size {
+            None => Ok(copy(reader, writer)?),
+            Some(max_size) => {
+                // Read one byte more than allowed to tell a file of exactly
+                // the maximum size from a larger one.
+                let bytes = copy(&mut reader.take(max_size + 1), writer)?;
+                if bytes > max_size {
+                    Err(FtpError::SizeLimitExceeded(max_size))
+                } else {
+                    Ok(bytes)
+                }
+            }
+        })
+    }
+
+    /// Removes the remote pathname from the server.
+    pub fn rmdir<P: AsRef<str>>(&mut self, pathname: P) -> crate::Result<()> {
+        self.command_class(
+            format_args!("RMD {}\r\n", pathname.as_ref()),
+            ResponseClass::PositiveCompletion,
+        )
+        .map(|_| ())
+    }
+
+    /// Remove the remote file from the server.
+    pub fn rm(&mut self, filename: &str) -> crate::Result<()> {
+        self.rm_bytes(filename.as_bytes())
+    }
+
+    /// Same as `rm`, for a file name which is not valid UTF-8.
+    pub fn rm_bytes(&mut self, filename: &[u8]) -> crate::Result<()> {
+        self.retrying(|ftp_stream| {
+            ftp_stream.write_raw_cmd("DELE", filename)?;
+            ftp_stream.read_response_class(ResponseClass::PositiveCompletion)
+        })
+        .map(|_| ())
+    }
+
+    /// Copy `r` into the data stream and close it, returning the result
+    /// with the number of bytes written, which are reported to `progress`.
+    fn write_data<R: Read>(
+        &mut self,
+        writer: TransferWriter,
+        r: &mut R,
+        progress: Option<Progress>,
+    ) -> (crate::Result<()>, u64) {
+        let mut buf = self.take_buffer();
+        let mut writer = CountingWriter::new(writer).with_progress(progress);
+        let res = buffer::copy(r, &mut writer, &mut buf);
+        self.transfer_buffer = buf;
+        let bytes = writer.bytes;
+        let res = res
+            .map_err(FtpError::from)
+            .and_then(|_| Self::close_data_stream(writer.inner.finish()?));
+        (res, bytes)
+    }
+
+    /// Takes the buffer transfers are read and written through out of the
+    /// stream, allocating it the first time. Transfers put it back once done.
+    fn take_buffer(&mut self) -> Vec<u8> {
+        let mut buf = std::mem::take(&mut self.transfer_buffer);
+        buf.resize(self.buffer_size.max(1), 0);
+        buf
+    }
+
+    /// Sets the size of the buffer data connections are read and written
+    /// through, 64 KiB by default. The buffer is allocated by the first
+    /// transfer and reused by the following ones.
+    pub fn set_transfer_buffer_size(&mut self, size: usize) {
+        self.buffer_size = size;
+        self.transfer_buffer = Vec::new();
+    }
+
+    /// Close a data stream written to, shutting its TLS session down cleanly
+    /// so the server knows the data is complete.
+    fn close_data_stream(data_stream: DataStream) -> crate::Result<()> {
+        match data_stream {
+            DataStream::Plain(_) => {}
+            #[cfg(all(feature = "secure", not(feature = "native-tls")))]
+            DataStream::Ssl(mut ssl_stream) => {
+                ssl_stream.shutdown()?;
+            }
+            #[cfg(all(feature = "secure", feature = "native-tls"))]
+            DataStream::Ssl(mut tls_stream) => tls_stream.shutdown()?,
+        }
+        Ok(())
+    }
+
+    /// Stores a file on the server, its contents written by `writer`. This
+    /// is the counterpart of `retr`: the transfer is started before calling
+    /// `writer` and its final reply read afterwards.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// use std::io::Write;
+    ///
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// conn.stor("report.csv", |writer| {
+    ///     for row in 0..3 {
+    ///         writeln!(writer, "row,{}", row)?;
+    ///     }
+    ///     Ok(())
+    /// })
+    /// .unwrap();
+    /// # assert_eq!(conn.simple_retr("report.csv").unwrap().into_inner(), b"row,0\nrow,1\nrow,2\n");
+    /// ```
+    pub fn stor<F, T>(&mut self, filename: &str, writer: F) -> crate::Result<T>
+    where
+        F: FnOnce(&mut dyn Write) -> crate::Result<T>,
+    {
+        self.with_binary(|ftp_stream| {
+            let transfer = ftp_stream.transfer_trace("STOR", Some(filename));
+            let data_stream = ftp_stream.data_command(format_args!("STOR {}\r\n", filename))?;
+            let data_stream = ftp_stream.transfer_writer(data_stream);
+            let mut buf = ftp_stream.take_buffer();
+            let mut data_stream = CountingWriter::new(BufferedWriter::new(data_stream, &mut buf))
+                .with_progress(transfer.progress());
+            let res = writer(&mut data_stream);
+            let bytes = data_stream.bytes;
+            // What was written before a failure is still sent.
+            let data_stream = data_stream.inner.into_inner();
+            let res = res.and_then(|res| {
+                Self::close_data_stream(data_stream?.finish()?)?;
+                Ok(res)
+            });
+            ftp_stream.transfer_buffer = buf;
+            // The server replies once the data connection is closed, even
+            // when the upload was cut short.
+            let reply = ftp_stream.read_transfer_reply();
+            let res = Self::transfer_result(res, reply, bytes);
+            match res {
+                Ok(_) => transfer.finish(bytes),
+                Err(_) => transfer.fail(bytes),
+            }
+            res
+        })
+    }
+
+    /// This stores a file on the server.
+    pub fn put<R: Read>(&mut self, filename: &str, r: &mut R) -> crate::Result<()> {
+        self.with_binary(|ftp_stream| {
+            let transfer = ftp_stream.transfer_trace("STOR", Some(filename));
+            let data_stream = ftp_stream.data_command(format_args!("STOR {}\r\n", filename))?;
+            let writer = ftp_stream.transfer_writer(data_stream);
+            let (res, bytes) = ftp_stream.write_data(writer, r, transfer.progress());
+            ftp_stream.end_upload(transfer, res, bytes).map(|_| ())
+        })
+    }
+
+    /// Stores `data` on the server as `filename`.
+    pub fn put_bytes(&mut self, filename: &str, data: &[u8]) -> crate::Result<()> {
+        self.put(filename, &mut &data[..])
+    }
+
+    /// Stores the chunks yielded by `chunks` on the server as `filename`, for
+    /// data produced on the fly, such as generated CSV rows. Returns the
+    /// number of bytes stored. The first error yielded ends the upload and
+    /// is returned; the server keeps the data stored so far.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// let rows = (0..3).map(|row| Ok(format!("row,{}\n", row).into_bytes()));
+    /// assert_eq!(conn.put_chunks("report.csv", rows).unwrap(), 18);
+    /// ```
+    pub fn put_chunks<I>(&mut self, filename: &str, chunks: I) -> crate::Result<u64>
+    where
+        I: IntoIterator<Item = std::io::Result<Vec<u8>>>,
+    {
+        self.stor(filename, |writer| {
+            let mut bytes = 0;
+            for chunk in chunks {
+                let chunk = chunk?;
+                writer.write_all(&chunk)?;
+                bytes += chunk.len() as u64;
+            }
+            Ok(bytes)
+        })
+    }
+
+    /// This appends to a file on the server, creating it if needed.
+    pub fn append<R: Read>(&mut self, filename: &str, r: &mut R) -> crate::Result<()> {
+        self.with_binary(|ftp_stream| {
+            let transfer = ftp_stream.transfer_trace("APPE", Some(filename));
+            let data_stream = ftp_stream.data_command(format_args!("APPE {}\r\n", filename))?;
+            let writer = ftp_stream.transfer_writer(data_stream);
+            let (res, bytes) = ftp_stream.write_data(writer, r, transfer.progress());
+            ftp_stream.end_upload(transfer, res, bytes).map(|_| ())
+        })
+    }
+
+    /// Same as `put`, also returning the digest of the uploaded data.
+    #[cfg(feature = "digest")]
+    pub fn put_with_digest<R: Read>(
+        &mut self,
+        filename: &str,
+        r: &mut R,
+        algorithm: Algorithm,
+    ) -> crate::Result<Digest> {
+        let mut reader = DigestReader::new(r, algorithm);
+        self.put(filename, &mut reader)?;
+        Ok(reader.finish())
+    }
+
+    /// Same as `retr`, also returning the digest of the data `reader` read.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # use std::io::Cursor;
+    /// use ftp::digest::Algorithm;
+    ///
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// # conn.put("abc.txt", &mut Cursor::new("abc")).unwrap();
+    /// let (data, digest) = conn
+    ///     .retr_with_digest("abc.txt", Algorithm::Md5, |reader| {
+    ///         let mut data = Vec::new();
+    ///         reader.read_to_end(&mut data)?;
+    ///         Ok(data)
+    ///     })
+    ///     .unwrap();
+    /// assert_eq!(digest.to_string(), "900150983cd24fb0d6963f7d28e17f72");
+    /// ```
+    #[cfg(feature = "digest")]
+    pub fn retr_with_digest<F, T>(
+        &mut self,
+        filename: &str,
+        algorithm: Algorithm,
+        reader: F,
+    ) -> crate::Result<(T, Digest)>
+    where
+        F: FnOnce(&mut dyn Read) -> crate::Result<T>,
+    {
+        self.retr(filename, |data| {
+            let mut data = DigestReader::new(data, algorithm);
+            let res = reader(&mut data)?;
+            Ok((res, data.finish()))
+        })
+    }
+
+    /// Stores the local file at `local` on the server as `remote`.
+    ///
+    /// Over a plain TCP data connection the file is copied straight into the
+    /// socket, which lets the standard library use `sendfile`/`copy_file_range`
+    /// on Linux instead of copying through user space. TLS data connections
+    /// fall back to the buffered copy used by `put`.
+    pub fn put_from_path<P: AsRef<Path>>(&mut self, remote: &str, local: P) -> crate::Result<()> {
+        let mut file = File::open(local)?;
+        self.with_binary(|ftp_stream| {
+            let transfer = ftp_stream.transfer_trace("STOR", Some(remote));
+            let data_stream = ftp_stream.data_command(format_args!("STOR {}\r\n", remote))?;
+            let (res, bytes) = match ftp_stream.transfer_writer(data_stream) {
+                TransferWriter::Stream(DataStream::Plain(mut stream)) => {
+                    // Copied in slices of 1 MiB to report the progress between
+                    // them.
+                    let mut progress = transfer.progress();
+                    let mut bytes = 0;
+                    loop {
+                        match copy(&mut (&mut file).take(1 << 20), &mut stream) {
+                            Ok(0) => break (Ok(()), bytes),
+                            Ok(n) => bytes += n,
+                            // The copy advanced the file past what was sent.
+                            Err(err) => {
+                                break (Err(err.into()), file.stream_position().unwrap_or(0))
+                            }
+                        }
+                        if let Some(ref mut progress) = progress {
+                            progress.update(bytes);
+                        }
+                    }
+                }
+                writer => ftp_stream.write_data(writer, &mut file, transfer.progress()),
+            };
+            ftp_stream.end_upload(transfer, res, bytes).map(|_| ())
+        })
+    }
+
+    /// Stores the local file at `local` on the server as `remote`, mapping
+    /// it in memory and writing it to the data connection in large slices
+    /// rather than copying it through the transfer buffer of the stream,
+    /// 64 KiB by default. Returns the number of bytes stored. Meant for
+    /// files of several gigabytes; see `put_from_path`, which avoids the
+    /// copy altogether over plain data connections on Linux.
+    ///
+    /// The file must not be truncated while it is uploaded: accessing the
+    /// missing part of the mapping would kill the process with `SIGBUS`.
+    #[cfg(feature = "mmap")]
+    pub fn put_mmap<P: AsRef<Path>>(&mut self, remote: &str, local: P) -> crate::Result<u64> {
+        // Slices of 2 MiB keep the write calls few.
+        const SLICE_LEN: usize = 2 << 20;
+        let file = File::open(local)?;
+        // SAFETY: the mapping is read only and dropped before returning. It
+        // may still change under us if the file is modified meanwhile,
+        // which only garbles the upload, as with any other concurrent write.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        #[cfg(unix)]
+        map.advise(memmap2::Advice::Sequential)?;
+        self.with_binary(|ftp_stream| {
+            let transfer = ftp_stream.transfer_trace("STOR", Some(remote));
+            let data_stream = ftp_stream.data_command(format_args!("STOR {}\r\n", remote))?;
+            let mut writer = CountingWriter::new(ftp_stream.transfer_writer(data_stream))
+                .with_progress(transfer.progress());
+            let res = map
+                .chunks(SLICE_LEN)
+                .try_for_each(|slice| writer.write_all(slice))
+                .map_err(FtpError::from);
+            let bytes = writer.bytes;
+            let res = res.and_then(|()| Self::close_data_stream(writer.inner.finish()?));
+            ftp_stream.end_upload(transfer, res, bytes)
+        })
+    }
+
+    /// Stores the data read from `r` on the server as `filename` with a
+    /// `.gz` suffix, compressing it with gzip on the fly. Returns the number
+    /// of bytes read from `r`, before compression. Meant for highly
+    /// compressible data, such as logs or CSV exports, sent to servers
+    /// without `MODE Z`; the file stays compressed on the server, see
+    /// `retr_gzip` to read it back.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// let log = "GET /index.html 200\n".repeat(1000);
+    /// assert_eq!(conn.put_gzip("access.log", &mut log.as_bytes()).unwrap(), 20000);
+    /// assert!(conn.get_bytes("access.log.gz").unwrap().len() < 1000);
+    /// ```
+    #[cfg(feature = "gzip")]
+    pub fn put_gzip<R: Read>(&mut self, filename: &str, r: &mut R) -> crate::Result<u64> {
+        // Compressed data must not go through ASCII conversions.
+        self.in_binary(true, |ftp_stream| {
+            ftp_stream.stor(&format!("{}.gz", filename), |writer| {
+                let mut encoder = GzEncoder::new(writer, Compression::default());
+                let bytes = copy(r, &mut encoder)?;
+                encoder.finish()?;
+                Ok(bytes)
+            })
+        })
+    }
+
+    /// Retrieves the file `filename` with a `.gz` suffix, as stored by
+    /// `put_gzip`, and calls `reader` with the data stream decompressed on
+    /// the fly.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # use std::io::Read;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// conn.put_gzip("rows.csv", &mut &b"a,b\n1,2\n"[..]).unwrap();
+    /// let rows = conn
+    ///     .retr_gzip("rows.csv", |reader| {
+    ///         let mut rows = String::new();
+    ///         reader.read_to_string(&mut rows)?;
+    ///         Ok(rows)
+    ///     })
+    ///     .unwrap();
+    /// assert_eq!(rows, "a,b\n1,2\n");
+    /// ```
+    #[cfg(feature = "gzip")]
+    pub fn retr_gzip<F, T>(&mut self, filename: &str, reader: F) -> crate::Result<T>
+    where
+        F: FnOnce(&mut dyn Read) -> crate::Result<T>,
+    {
+        self.in_binary(true, |ftp_stream| {
+            ftp_stream.retr(&format!("{}.gz", filename), |stream| {
+                reader(&mut GzDecoder::new(stream))
+            })
+        })
+    }
+
+    /// Downloads the file of `checkpoint` to `writer` in binary mode, from
+    /// the checkpoint's `offset`: a new transfer starts from a checkpoint
+    /// made with `TransferCheckpoint::new`. `on_checkpoint` is called with
+    /// the progress every `interval` bytes, once `writer` is flushed, and
+    /// when the download completes; an error it returns ends the download.
+    /// Returns the final checkpoint.
+    ///
+    /// When resuming, the file fails with `FtpError::Changed` if its size or
+    /// modification time differs from the checkpoint's. Resuming needs
+    /// stream mode.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// # conn.put_bytes("dump.sql", &[b';'; 10_000]).unwrap();
+    /// use ftp::checkpoint::TransferCheckpoint;
+    ///
+    /// let mut local = Vec::new();
+    /// let mut offsets = Vec::new();
+    /// let checkpoint = TransferCheckpoint::new("dump.sql");
+    /// let checkpoint = conn
+    ///     .retr_checkpointed(checkpoint, &mut local, 4096, |checkpoint| {
+    ///         offsets.push(checkpoint.offset);
+    ///         Ok(())
+    ///     })
+    ///     .unwrap();
+    /// assert!(checkpoint.is_complete());
+    /// assert_eq!(offsets.last(), Some(&10_000));
     /// ```
-    pub fn retr<F, T>(&mut self, filename: &str, reader: F) -> crate::Result<T>
+    pub fn retr_checkpointed<W, F>(
+        &mut self,
+        checkpoint: TransferCheckpoint,
+        writer: &mut W,
+        interval: u64,
+        mut on_checkpoint: F,
+    ) -> crate::Result<TransferCheckpoint>
     where
-        F: Fn(&mut dyn Read) -> crate::Result<T>,
+        W: Write,
+        F: FnMut(&TransferCheckpoint) -> std::io::Result<()>,
     {
-        let retr_command = format!("RETR {}\r\n", filename);
-        {
-            let mut data_stream = BufReader::new(self.data_command(&retr_command)?);
-            self.read_response_in(&[status::ABOUT_TO_SEND, status::ALREADY_OPEN])
-                .and_then(|_| reader(&mut data_stream))
-        }
-        .and_then(|res| {
-            self.read_response_in(&[
-                status::CLOSING_DATA_CONNECTION,
-                status::REQUESTED_FILE_ACTION_OK,
-            ])
-            .map(|_| res)
+        self.in_binary(true, |ftp_stream| {
+            let path = checkpoint.remote_path.clone();
+            let size = ignore_refusal(ftp_stream.size(&path))?
+                .flatten()
+                .map(|size| size as u64);
+            let mdtm = ignore_refusal(ftp_stream.mdtm(&path))?.flatten();
+            let mut current = TransferCheckpoint {
+                size,
+                mdtm,
+                ..checkpoint.clone()
+            };
+            if current.offset > 0 {
+                if (checkpoint.size, checkpoint.mdtm) != (size, mdtm)
+                    || size.is_some_and(|size| size < current.offset)
+                {
+                    return Err(FtpError::Changed(path));
+                }
+                if !current.is_complete() {
+                    ftp_stream.restart_at(current.offset)?;
+                }
+            }
+            if current.offset == 0 || !current.is_complete() {
+                ftp_stream.retr(&path, |reader| {
+                    copy_checkpointed(reader, writer, &mut current, interval, &mut on_checkpoint)
+                })?;
+                writer.flush()?;
+            }
+            on_checkpoint(&current)?;
+            Ok(current)
         })
     }
 
-    /// Simple way to retr a file from the server. This stores the file in memory.
+    /// Stores the data read from `r` on the server as the file of
+    /// `checkpoint`, in binary mode, from the checkpoint's `offset`, like
+    /// `retr_checkpointed`. The checkpoints tell how much data was sent,
+    /// which the server may not have stored if the connection was lost:
+    /// resuming starts from the size of the remote file instead when it is
+    /// smaller.
+    ///
+    /// When resuming, `r` fails with `FtpError::Changed` if its length
+    /// differs from the checkpoint's `size`.
+    pub fn put_checkpointed<R, F>(
+        &mut self,
+        checkpoint: TransferCheckpoint,
+        r: &mut R,
+        interval: u64,
+        mut on_checkpoint: F,
+    ) -> crate::Result<TransferCheckpoint>
+    where
+        R: Read + Seek,
+        F: FnMut(&TransferCheckpoint) -> std::io::Result<()>,
+    {
+        let len = r.seek(SeekFrom::End(0))?;
+        if checkpoint.offset > 0 && checkpoint.size != Some(len) {
+            return Err(FtpError::Changed(checkpoint.remote_path));
+        }
+        self.in_binary(true, |ftp_stream| {
+            let path = checkpoint.remote_path.clone();
+            let mut current = TransferCheckpoint {
+                size: Some(len),
+                mdtm: None,
+                ..checkpoint
+            };
+            if current.offset > 0 {
+                if let Some(size) = ignore_refusal(ftp_stream.size(&path))?.flatten() {
+                    current.offset = current.offset.min(size as u64);
+                }
+            }
+            r.seek(SeekFrom::Start(current.offset))?;
+            if current.offset > 0 {
+                ftp_stream.restart_at(current.offset)?;
+            }
+            ftp_stream.stor(&path, |writer| {
+                copy_checkpointed(r, writer, &mut current, interval, &mut on_checkpoint)
+            })?;
+            on_checkpoint(&current)?;
+            Ok(current)
+        })
+    }
+
+    /// Makes the next transfer resume from the byte `offset`, which `REST`
+    /// only means in stream mode. `REST` is sent along with the transfer
+    /// command, see `open_data_stream`.
+    pub(crate) fn restart_at(&mut self, offset: u64) -> crate::Result<()> {
+        if self.session.transmission_mode != TransmissionMode::Stream {
+            return Err(FtpError::InvalidArgument(
+                "transfers can only resume from an offset in stream mode".to_owned(),
+            ));
+        }
+        self.restart_offset = Some(offset);
+        Ok(())
+    }
+
+    /// Downloads `remote` into the local file `local` in binary mode.
+    /// Returns the number of bytes downloaded.
+    ///
+    /// An existing local file shorter than the remote one, and not older
+    /// than its last modification, is taken as an interrupted download: it
+    /// is resumed with `REST` from its length, unless `set_restart_downloads`
+    /// is set or the transmission mode is not stream mode. Otherwise the local file is created or replaced. If the
+    /// download fails, the partial local file is kept for resuming when the
+    /// error is transient, and removed otherwise.
     ///
     /// ```
-    /// # use ftp::{FtpStream, FtpError};
-    /// # use std::io::Cursor;
-    /// # let mut conn = FtpStream::connect("127.0.0.1:21").unwrap();
-    /// # conn.login("Doe", "mumble").and_then(|_| {
-    /// #     let mut reader = Cursor::new("hello, world!".as_bytes());
-    /// #     conn.put("simple_retr.txt", &mut reader)
-    /// # }).unwrap();
-    /// let cursor = conn.simple_retr("simple_retr.txt").unwrap();
-    /// // do something with bytes
-    /// assert_eq!(cursor.into_inner(), "hello, world!".as_bytes());
-    /// # assert!(conn.rm("simple_retr.txt").is_ok());
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// # conn.put_bytes("report.csv", b"a,b").unwrap();
+    /// let local = std::env::temp_dir().join("ftp_download_to.csv");
+    /// # let _ = std::fs::remove_file(&local);
+    /// conn.download_to("report.csv", &local).unwrap();
+    /// assert_eq!(std::fs::read(&local).unwrap(), b"a,b");
+    /// # std::fs::remove_file(&local).unwrap();
     /// ```
-    pub fn simple_retr(&mut self, file_name: &str) -> crate::Result<Cursor<Vec<u8>>> {
-        self.retr(file_name, |reader| {
-            let mut buffer = Vec::new();
-            reader
-                .read_to_end(&mut buffer)
-                .map(|_| buffer)
-                .map_err(FtpError::ConnectionError)
+    pub fn download_to<P: AsRef<Path>>(&mut self, remote: &str, local: P) -> crate::Result<u64> {
+        self.in_binary(true, |ftp_stream| {
+            let res = ftp_stream.download_file(remote, local.as_ref());
+            if let Err(ref err) = res {
+                if ftp_stream.restart_downloads || !err.is_transient() {
+                    let _ = fs::remove_file(local);
+                }
+            }
+            res
         })
-        .map(Cursor::new)
     }
 
-    /// Removes the remote pathname from the server.
-    pub fn rmdir(&mut self, pathname: &str) -> crate::Result<()> {
-        self.write_str(format!("RMD {}\r\n", pathname))?;
-        self.read_response(status::REQUESTED_FILE_ACTION_OK)
-            .map(|_| ())
+    /// Makes `download_to` always download whole files, replacing partial
+    /// local files instead of resuming them.
+    pub fn set_restart_downloads(&mut self, enabled: bool) {
+        self.restart_downloads = enabled;
     }
 
-    /// Remove the remote file from the server.
-    pub fn rm(&mut self, filename: &str) -> crate::Result<()> {
-        self.write_str(format!("DELE {}\r\n", filename))?;
-        self.read_response(status::REQUESTED_FILE_ACTION_OK)
-            .map(|_| ())
+    fn download_file(&mut self, remote: &str, local: &Path) -> crate::Result<u64> {
+        let offset = match self.restart_downloads {
+            true => None,
+            false if self.session.transmission_mode != TransmissionMode::Stream => None,
+            false => self.resume_offset(remote, local)?,
+        };
+        let (file, bytes) = match offset {
+            Some((offset, size)) => {
+                let mut file = BufWriter::new(File::options().append(true).open(local)?);
+                let bytes = match offset < size {
+                    true => {
+                        self.restart_at(offset)?;
+                        self.retr_to_writer(remote, &mut file)?
+                    }
+                    false => 0,
+                };
+                (file, bytes)
+            }
+            None => {
+                let mut file = BufWriter::new(File::create(local)?);
+                let bytes = self.retr_to_writer(remote, &mut file)?;
+                (file, bytes)
+            }
+        };
+        let file = file.into_inner().map_err(std::io::Error::from)?;
+        if self.preserve_mtime {
+            if let Some(time) = ignore_refusal(self.mdtm(remote))?.flatten() {
+                file.set_modified(time.into())?;
+            }
+        }
+        Ok(bytes)
     }
 
-    fn put_file<R: Read>(&mut self, filename: &str, r: &mut R) -> crate::Result<()> {
-        let stor_command = format!("STOR {}\r\n", filename);
-        let mut data_stream = BufWriter::new(self.data_command(&stor_command)?);
-        self.read_response_in(&[status::ALREADY_OPEN, status::ABOUT_TO_SEND])?;
-        copy(r, &mut data_stream)?;
-        #[cfg(all(feature = "secure", not(feature = "native-tls")))]
-        {
-            if let DataStream::Ssl(mut ssl_stream) =
-                data_stream.into_inner().map_err(std::io::Error::from)?
-            {
-                ssl_stream.shutdown()?;
+    /// The length of the local file `local` and the size of `remote`, if the
+    /// local file is a part of the remote one: no longer than it, and not
+    /// older than its last modification when the server reports it.
+    fn resume_offset(&mut self, remote: &str, local: &Path) -> crate::Result<Option<(u64, u64)>> {
+        let metadata = match fs::metadata(local) {
+            Ok(metadata) if metadata.is_file() && metadata.len() > 0 => metadata,
+            _ => return Ok(None),
+        };
+        let size = match ignore_refusal(self.size(remote))?.flatten() {
+            Some(size) if size as u64 >= metadata.len() => size as u64,
+            _ => return Ok(None),
+        };
+        if let Some(time) = ignore_refusal(self.mdtm(remote))?.flatten() {
+            if SystemTime::from(time) > metadata.modified()? {
+                return Ok(None);
             }
         }
-        Ok(())
+        Ok(Some((metadata.len(), size)))
     }
 
-    /// This stores a file on the server.
-    pub fn put<R: Read>(&mut self, filename: &str, r: &mut R) -> crate::Result<()> {
-        self.put_file(filename, r)?;
-        self.read_response_in(&[
-            status::CLOSING_DATA_CONNECTION,
-            status::REQUESTED_FILE_ACTION_OK,
-        ])
-        .map(|_| ())
+    /// Uploads the local file `local` to the server as `remote`, in binary
+    /// mode, doing what `policy` says if the remote file already exists.
+    /// If the upload fails once started, the partial remote file is removed,
+    /// unless the upload was resuming it.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// use ftp::types::{OverwritePolicy, UploadOutcome};
+    ///
+    /// let local = std::env::temp_dir().join("ftp_upload_from.csv");
+    /// std::fs::write(&local, "a,b").unwrap();
+    /// conn.put_bytes("report.csv", b"x,y").unwrap();
+    /// let outcome = conn
+    ///     .upload_from(&local, "report.csv", OverwritePolicy::RenameNew)
+    ///     .unwrap();
+    /// assert_eq!(
+    ///     outcome,
+    ///     UploadOutcome::Stored { path: "report.1.csv".to_owned(), bytes: 3 }
+    /// );
+    /// # std::fs::remove_file(&local).unwrap();
+    /// ```
+    pub fn upload_from<P: AsRef<Path>>(
+        &mut self,
+        local: P,
+        remote: &str,
+        policy: OverwritePolicy,
+    ) -> crate::Result<UploadOutcome> {
+        let mut file = File::open(local)?;
+        let metadata = file.metadata()?;
+        let modified = match self.preserve_mtime {
+            true => Some(metadata.modified()?),
+            false => None,
+        };
+        self.in_binary(true, |ftp_stream| {
+            let (path, offset) =
+                match ftp_stream.upload_target(remote, Some(metadata.len()), policy)? {
+                    UploadTarget::Skip => return Ok(UploadOutcome::Skipped),
+                    UploadTarget::Store(path) => (path, 0),
+                    UploadTarget::Append(path, offset) => (path, offset),
+                };
+            file.seek(SeekFrom::Start(offset))?;
+            let (transfer, data_stream) = match offset {
+                0 => (
+                    ftp_stream.transfer_trace("STOR", Some(&path)),
+                    ftp_stream.data_command(format_args!("STOR {}\r\n", path))?,
+                ),
+                _ => (
+                    ftp_stream.transfer_trace("APPE", Some(&path)),
+                    ftp_stream.data_command(format_args!("APPE {}\r\n", path))?,
+                ),
+            };
+            let writer = ftp_stream.transfer_writer(data_stream);
+            let (res, bytes) = ftp_stream.write_data(writer, &mut file, transfer.progress());
+            // The server replies once the data connection is closed, even
+            // when the upload was cut short.
+            let reply = ftp_stream.read_transfer_reply();
+            match Self::transfer_result(res, reply, bytes) {
+                Ok(()) => transfer.finish(bytes),
+                Err(err) => {
+                    transfer.fail(bytes);
+                    if offset == 0 {
+                        let _ = ftp_stream.rm(&path);
+                    }
+                    return Err(err);
+                }
+            };
+            if let Some(modified) = modified {
+                ignore_refusal(ftp_stream.mfmt(&path, modified.into()))?;
+            }
+            Ok(UploadOutcome::Stored { path, bytes })
+        })
     }
 
-    /// Execute a command which returns list of strings in a separate stream
-    fn list_command(
+    /// Same as `put`, doing what `policy` says if the remote file already
+    /// exists. When resuming, the data `r` starts with, as long as the
+    /// remote file, is read and skipped.
+    pub fn put_with_policy<R: Read>(
         &mut self,
-        cmd: Cow<'static, str>,
-        open_code: u32,
-        close_code: &[u32],
-    ) -> crate::Result<Vec<String>> {
-        let data_stream = BufReader::new(self.data_command(&cmd)?);
-        self.read_response_in(&[open_code, status::ALREADY_OPEN])?;
-        let lines = Self::get_lines_from_stream(data_stream);
-        self.read_response_in(close_code)?;
-        lines
+        filename: &str,
+        r: &mut R,
+        policy: OverwritePolicy,
+    ) -> crate::Result<UploadOutcome> {
+        // Checking in binary mode, as many servers refuse `SIZE` in ASCII mode.
+        let target = self.in_binary(true, |ftp_stream| {
+            ftp_stream.upload_target(filename, None, policy)
+        })?;
+        let mut r = CountingReader::new(r);
+        let path = match target {
+            UploadTarget::Skip => return Ok(UploadOutcome::Skipped),
+            UploadTarget::Store(path) => {
+                self.put(&path, &mut r)?;
+                path
+            }
+            UploadTarget::Append(path, offset) => {
+                copy(&mut (&mut r).take(offset), &mut std::io::sink())?;
+                r.bytes = 0;
+                self.append(&path, &mut r)?;
+                path
+            }
+        };
+        Ok(UploadOutcome::Stored {
+            path,
+            bytes: r.bytes,
+        })
     }
 
-    fn get_lines_from_stream(data_stream: BufReader<DataStream>) -> crate::Result<Vec<String>> {
-        let mut lines: Vec<String> = Vec::new();
-
-        let mut lines_stream = data_stream.lines();
-        loop {
-            let line = lines_stream.next();
-            match line {
-                Some(line) => match line {
-                    Ok(l) => {
-                        if l.is_empty() {
-                            continue;
-                        }
-                        lines.push(l);
-                    }
-                    Err(_) => {
-                        return Err(FtpError::InvalidResponse(String::from(
-                            "Invalid lines in response",
-                        )))
+    /// Where to upload data of length `len`, if known, to `remote` under
+    /// `policy`.
+    fn upload_target(
+        &mut self,
+        remote: &str,
+        len: Option<u64>,
+        policy: OverwritePolicy,
+    ) -> crate::Result<UploadTarget> {
+        if policy == OverwritePolicy::Overwrite {
+            return Ok(UploadTarget::Store(remote.to_owned()));
+        }
+        let size = match self.existing_size(remote)? {
+            Some(size) => size,
+            None => return Ok(UploadTarget::Store(remote.to_owned())),
+        };
+        match policy {
+            OverwritePolicy::Overwrite => Ok(UploadTarget::Store(remote.to_owned())),
+            OverwritePolicy::Skip => Ok(UploadTarget::Skip),
+            OverwritePolicy::Fail => Err(FtpError::AlreadyExists(remote.to_owned())),
+            OverwritePolicy::Resume => match len {
+                Some(len) if size == len => Ok(UploadTarget::Skip),
+                Some(len) if size > len => Ok(UploadTarget::Store(remote.to_owned())),
+                _ if size == 0 => Ok(UploadTarget::Store(remote.to_owned())),
+                _ => Ok(UploadTarget::Append(remote.to_owned(), size)),
+            },
+            OverwritePolicy::RenameNew => {
+                let path = FtpPath::new(remote);
+                for n in 1.. {
+                    let numbered = path.numbered(n);
+                    if self.existing_size(numbered.as_str())?.is_none() {
+                        return Ok(UploadTarget::Store(numbered.to_string()));
                     }
-                },
-                None => break Ok(lines),
+                }
+                unreachable!("every numbered name is taken")
+            }
+        }
+    }
+
+    /// The size of `remote`, `None` if it doesn't exist. A file whose size
+    /// the server doesn't tell counts as empty.
+    fn existing_size(&mut self, remote: &str) -> crate::Result<Option<u64>> {
+        match self.size(remote) {
+            Ok(size) => Ok(Some(size.unwrap_or(0) as u64)),
+            Err(FtpError::UnexpectedResponse(ref reply))
+                if reply.line.0 == Status::FileUnavailable =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Execute a command which returns list of strings in a separate stream.
+    /// The lines replace the contents of `lines`, reusing its strings.
+    fn list_command(
+        &mut self,
+        cmd: &'static str,
+        pathname: Option<&str>,
+        close_code: &[Status],
+        lines: &mut Vec<String>,
+    ) -> crate::Result<()> {
+        let transfer = self.transfer_trace(cmd, pathname);
+        let data_stream = match pathname {
+            Some(path) => self.data_command(format_args!("{} {}\r\n", cmd, path))?,
+            None => self.data_command(format_args!("{}\r\n", cmd))?,
+        };
+        let mut buf = self.take_buffer();
+        let mut data_stream = BufferedReader::new(self.transfer_reader(data_stream), &mut buf);
+        let res = parse::read_lines(&mut data_stream, lines);
+        drop(data_stream);
+        self.transfer_buffer = buf;
+        let reply = self
+            .finish_transfer()
+            .and_then(|()| self.read_response_in(close_code));
+        match (reply, res) {
+            (Ok(_), Ok(bytes)) => {
+                transfer.finish(bytes);
+                Ok(())
+            }
+            (reply, res) => {
+                // The lines are only counted once all were read.
+                transfer.fail(*res.as_ref().unwrap_or(&0));
+                reply.and(res).map(|_| ())
             }
         }
     }
@@ -605,133 +3540,705 @@ impl FtpStream {
     /// If `pathname` is omited then the list of files in the current directory will be
     /// returned otherwise it will the list of files on `pathname`.
     pub fn list(&mut self, pathname: Option<&str>) -> crate::Result<Vec<String>> {
-        let command = pathname.map_or("LIST\r\n".into(), |path| {
-            format!("LIST {}\r\n", path).into()
-        });
+        let mut lines = Vec::new();
+        self.list_into(pathname, &mut lines)?;
+        Ok(lines)
+    }
 
+    /// Same as `list`, but the listing replaces the contents of `lines`,
+    /// reusing the strings already allocated in it.
+    pub fn list_into(
+        &mut self,
+        pathname: Option<&str>,
+        lines: &mut Vec<String>,
+    ) -> crate::Result<()> {
         self.list_command(
-            command,
-            status::ABOUT_TO_SEND,
-            &[
-                status::CLOSING_DATA_CONNECTION,
-                status::REQUESTED_FILE_ACTION_OK,
-            ],
+            "LIST",
+            pathname,
+            &[Status::ClosingDataConnection, Status::RequestedFileActionOk],
+            lines,
         )
     }
 
+    /// Same as `list`, with each line parsed into a `DirEntry` by
+    /// `parse::list_line`. Lines in an unknown format are skipped. The
+    /// entries can be sorted and filtered with `types::DirEntries`.
+    ///
+    /// The best listing the server offers is used: `mlsd` when the features
+    /// cached by `feat` include `MLST`, then `LIST`, and as a last resort
+    /// `nlst_entries`, when the server implements neither or `LIST` shows
+    /// lines in no known format. If the data connection can't be
+    /// established, e.g. because a firewall blocks it, the `LIST` listing is
+    /// retrieved with `list_via_stat` instead. That needs the connection to
+    /// fail before `LIST` is sent, with `DataConnectOrder::ConnectFirst`, or
+    /// the server to refuse it with a `425` reply.
+    pub fn list_entries(&mut self, pathname: Option<&str>) -> crate::Result<Vec<DirEntry>> {
+        if self.supports("MLST") {
+            match self.mlsd(pathname) {
+                Err(ref err) if is_unsupported(err) => {}
+                res => return res,
+            }
+        }
+        let lines = match self.list(pathname) {
+            Ok(lines) => lines,
+            Err(FtpError::ConnectionError(_)) if self.data_dial_failed => {
+                self.list_via_stat(pathname)?
+            }
+            Err(ref err) if err.status() == Some(Status::CannotOpenDataConnection) => {
+                self.list_via_stat(pathname)?
+            }
+            Err(ref err) if is_unsupported(err) => return self.nlst_entries(pathname),
+            Err(err) => return Err(err),
+        };
+        let entries: Vec<DirEntry> = lines
+            .iter()
+            .filter_map(|line| parse::list_line(line))
+            .collect();
+        if entries.is_empty() && !lines.is_empty() {
+            return self.nlst_entries(pathname);
+        }
+        Ok(entries)
+    }
+
+    /// Lists `pathname` with `NLST`, asking the size and modification time
+    /// of each entry with `SIZE` and `MDTM`, for servers whose `LIST` can't
+    /// be parsed. As servers only tell these for files, an entry is a
+    /// `File` if they answer and `Other` if not, directories included.
+    pub fn nlst_entries(&mut self, pathname: Option<&str>) -> crate::Result<Vec<DirEntry>> {
+        let names = self.nlst(pathname)?;
+        let listed = pathname.map(FtpPath::new);
+        // Servers may refuse SIZE in ASCII mode.
+        self.in_binary(true, |ftp_stream| {
+            let mut entries = Vec::new();
+            for name in &names {
+                let path = match path::listed_path(listed.as_ref(), name) {
+                    Some(path) => path,
+                    None => continue,
+                };
+                let size = ignore_refusal(ftp_stream.size(path.as_str()))?.flatten();
+                let modified = ignore_refusal(ftp_stream.mdtm(path.as_str()))?.flatten();
+                let kind = if size.is_some() || modified.is_some() {
+                    EntryKind::File
+                } else {
+                    EntryKind::Other
+                };
+                entries.push(DirEntry {
+                    name: path.file_name().unwrap_or(name).to_owned(),
+                    kind,
+                    size: size.map(|size| size as u64),
+                    modified: modified.map(|time| time.naive_utc()),
+                    permissions: None,
+                    raw: name.clone(),
+                });
+            }
+            Ok(entries)
+        })
+    }
+
+    /// Returns the path the symbolic link at `path` points to, resolved
+    /// against the directory of the link and normalized, or `None` if `path`
+    /// isn't a symbolic link or its listing doesn't show the target.
+    ///
+    /// The link is looked up in the listing of its directory, as listing the
+    /// link itself would list the directory it points to.
+    pub fn readlink(&mut self, path: &str) -> crate::Result<Option<FtpPath>> {
+        let path = FtpPath::new(path);
+        let name = match path.file_name() {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        let dir = path.parent().unwrap_or_else(|| FtpPath::new(""));
+        let listed = match dir.as_str() {
+            "" => None,
+            dir => Some(dir),
+        };
+        Ok(self
+            .list_entries(listed)?
+            .into_iter()
+            .find(|entry| entry.name == name)
+            .and_then(|entry| entry.link_path_in(&dir)))
+    }
+
+    /// Compares the files of the local directory `local` with those of the
+    /// remote directory `remote`, reporting the files missing on either side
+    /// and those whose size differs. Files of the same size are compared by
+    /// their `MDTM` modification time, to the second, if the server supports
+    /// it. Subdirectories are not compared. The entries are sorted by name.
+    ///
+    /// ```rust,no_run
+    /// use ftp::types::DiffEntry;
+    /// use ftp::FtpStream;
+    /// use std::path::Path;
+    ///
+    /// let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
+    /// ftp_stream.login("anonymous", "anonymous").unwrap();
+    /// for entry in ftp_stream.diff(Path::new("site"), "/www").unwrap() {
+    ///     if let DiffEntry::OnlyLocal(name) = entry {
+    ///         println!("{} isn't published yet", name);
+    ///     }
+    /// }
+    /// ```
+    pub fn diff(&mut self, local: &Path, remote: &str) -> crate::Result<Vec<DiffEntry>> {
+        let mut local_files = BTreeMap::new();
+        for entry in fs::read_dir(local)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if let (true, Ok(name)) = (metadata.is_file(), entry.file_name().into_string()) {
+                local_files.insert(name, metadata);
+            }
+        }
+        let mut remote_files: BTreeMap<String, DirEntry> = self
+            .list_entries(Some(remote))?
+            .into_iter()
+            .filter(|entry| entry.is_file())
+            .map(|entry| (entry.name.clone(), entry))
+            .collect();
+
+        let mut diff = Vec::new();
+        for (name, metadata) in local_files {
+            let remote_entry = match remote_files.remove(&name) {
+                Some(remote_entry) => remote_entry,
+                None => {
+                    diff.push(DiffEntry::OnlyLocal(name));
+                    continue;
+                }
+            };
+            let local_size = metadata.len();
+            let local_modified = metadata.modified().ok().map(DateTime::<Utc>::from);
+            let mut remote_modified = None;
+            let differs = if remote_entry.size != Some(local_size) {
+                true
+            } else {
+                let path = format!("{}/{}", remote.trim_end_matches('/'), name);
+                remote_modified = match self.mdtm(&path) {
+                    Ok(time) => time,
+                    // Without MDTM only the sizes can be compared.
+                    Err(FtpError::UnexpectedResponse(_)) => None,
+                    Err(err) => return Err(err),
+                };
+                match (local_modified, remote_modified) {
+                    (Some(local), Some(remote)) => local.timestamp() != remote.timestamp(),
+                    _ => false,
+                }
+            };
+            if differs {
+                diff.push(DiffEntry::Differs {
+                    name,
+                    local_size,
+                    remote_size: remote_entry.size,
+                    local_modified,
+                    remote_modified,
+                });
+            }
+        }
+        diff.extend(remote_files.into_keys().map(DiffEntry::OnlyRemote));
+        diff.sort_by(|a, b| a.name().cmp(b.name()));
+        Ok(diff)
+    }
+
+    /// Watches the remote directory `path`, listing it every `interval` and
+    /// reporting the files created, modified and deleted, see the `watch`
+    /// module. The directory is listed once before returning.
+    pub fn watch(&mut self, path: &str, interval: Duration) -> crate::Result<Watcher<'_, S>> {
+        Watcher::new(self, path, interval)
+    }
+
+    /// Follows the remote file `path` like `tail -f`, checking its size
+    /// every `interval` and downloading the data appended to it, see the
+    /// `watch` module. Following starts at the current end of the file.
+    /// Switches the connection to `TYPE I`, so offsets count bytes.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # use std::time::Duration;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// # let mut writer = FtpStream::connect(server.addr()).unwrap();
+    /// # writer.login("Doe", "mumble").unwrap();
+    /// conn.put_bytes("app.log", b"started\n").unwrap();
+    /// let mut follower = conn.follow("app.log", Duration::from_secs(1)).unwrap();
+    /// writer.append("app.log", &mut &b"request served\n"[..]).unwrap();
+    /// assert_eq!(follower.next().unwrap().unwrap(), b"request served\n");
+    /// ```
+    pub fn follow(&mut self, path: &str, interval: Duration) -> crate::Result<Follower<'_, S>> {
+        self.prepare_file()?;
+        let size = self
+            .size(path)?
+            .ok_or_else(|| FtpError::InvalidResponse(format!("no size for {}", path)))?;
+        Ok(Follower::new(self, path, interval, size as u64))
+    }
+
+    /// Execute `MLSD`, listing `pathname` or the current directory in the
+    /// machine readable format of RFC 3659, parsed by `parse::mlsd_line`.
+    /// The entries of the directory itself and of its parent are skipped.
+    pub fn mlsd(&mut self, pathname: Option<&str>) -> crate::Result<Vec<DirEntry>> {
+        let mut lines = Vec::new();
+        self.list_command(
+            "MLSD",
+            pathname,
+            &[Status::ClosingDataConnection, Status::RequestedFileActionOk],
+            &mut lines,
+        )?;
+        Ok(parse::mlsd_entries(&lines))
+    }
+
+    /// Retrieves the detailed listing of `pathname`, or of the current
+    /// directory, from the reply to `STAT` over the control connection.
+    /// No data connection is needed, but not every server supports it.
+    pub fn list_via_stat(&mut self, pathname: Option<&str>) -> crate::Result<Vec<String>> {
+        let mut lines = Vec::new();
+        self.retrying(|ftp_stream| {
+            lines.clear();
+            ftp_stream.write_cmd(format_args!("STAT {}\r\n", pathname.unwrap_or(".")))?;
+            ftp_stream.read_reply(
+                Expected::Codes(&[Status::System, Status::Directory, Status::File]),
+                Some(&mut lines),
+            )
+        })?;
+        if !lines.is_empty() {
+            lines.remove(0);
+        }
+        // Some servers indent the lines of multi-line replies.
+        for line in &mut lines {
+            if line.starts_with(' ') {
+                line.remove(0);
+            }
+        }
+        lines.retain(|line| !line.is_empty());
+        Ok(lines)
+    }
+
     /// Execute `NLST` command which returns the list of file names only.
     /// If `pathname` is omited then the list of files in the current directory will be
     /// returned otherwise it will the list of files on `pathname`.
     pub fn nlst(&mut self, pathname: Option<&str>) -> crate::Result<Vec<String>> {
-        let command = pathname.map_or("NLST\r\n".into(), |path| {
-            format!("NLST {}\r\n", path).into()
-        });
+        let mut lines = Vec::new();
+        self.nlst_into(pathname, &mut lines)?;
+        Ok(lines)
+    }
 
+    /// Same as `nlst`, but the names replace the contents of `lines`,
+    /// reusing the strings already allocated in it.
+    pub fn nlst_into(
+        &mut self,
+        pathname: Option<&str>,
+        lines: &mut Vec<String>,
+    ) -> crate::Result<()> {
         self.list_command(
-            command,
-            status::ABOUT_TO_SEND,
-            &[
-                status::CLOSING_DATA_CONNECTION,
-                status::REQUESTED_FILE_ACTION_OK,
-            ],
+            "NLST",
+            pathname,
+            &[Status::ClosingDataConnection, Status::RequestedFileActionOk],
+            lines,
         )
     }
 
+    /// Same as `nlst`, with each name resolved against `pathname` into a
+    /// path that can be passed to `retr` or `rm`, whether the server lists
+    /// bare names, names prefixed with `pathname` or absolute paths. The
+    /// paths are normalized, and relative if `pathname` is; the `.` and `..`
+    /// entries are skipped.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// # conn.mkdir("/pub").unwrap();
+    /// # conn.put_bytes("/pub/notes.txt", b"").unwrap();
+    /// for path in conn.nlst_paths(Some("/pub")).unwrap() {
+    ///     assert_eq!(path.as_str(), "/pub/notes.txt");
+    /// }
+    /// ```
+    pub fn nlst_paths(&mut self, pathname: Option<&str>) -> crate::Result<Vec<FtpPath>> {
+        let listed = pathname.map(FtpPath::new);
+        Ok(self
+            .nlst(pathname)?
+            .iter()
+            .filter_map(|name| path::listed_path(listed.as_ref(), name))
+            .collect())
+    }
+
+    /// Same as `nlst`, but the names are returned as raw bytes. Servers often
+    /// host files whose names are not valid UTF-8, which `nlst` rejects; the
+    /// names returned here can be passed to `retr_bytes` and `rm_bytes`.
+    pub fn nlst_bytes(&mut self, pathname: Option<&[u8]>) -> crate::Result<Vec<Vec<u8>>> {
+        let transfer =
+            self.transfer_trace("NLST", pathname.map(String::from_utf8_lossy).as_deref());
+        let data_stream = match pathname {
+            Some(path) => self.data_command_raw("NLST", path)?,
+            None => self.data_command(format_args!("NLST\r\n"))?,
+        };
+        let mut buf = self.take_buffer();
+        let mut data_stream = BufferedReader::new(self.transfer_reader(data_stream), &mut buf);
+        let mut names = Vec::new();
+        let mut bytes = 0;
+        let res = loop {
+            let mut name = Vec::new();
+            match data_stream.read_until(b'\n', &mut name) {
+                Ok(0) => break Ok(bytes),
+                Ok(n) => {
+                    bytes += n as u64;
+                    while name.last() == Some(&b'\n') || name.last() == Some(&b'\r') {
+                        name.pop();
+                    }
+                    if !name.is_empty() {
+                        names.push(name);
+                    }
+                }
+                Err(err) => break Err(FtpError::ConnectionError(err)),
+            }
+        };
+        drop(data_stream);
+        self.transfer_buffer = buf;
+        let reply = self.finish_transfer().and_then(|()| {
+            self.read_response_in(&[Status::ClosingDataConnection, Status::RequestedFileActionOk])
+        });
+        match reply.and(res) {
+            Ok(_) => transfer.finish(bytes),
+            Err(err) => {
+                transfer.fail(bytes);
+                return Err(err);
+            }
+        }
+        Ok(names)
+    }
+
     /// Retrieves the modification time of the file at `pathname` if it exists.
     /// In case the file does not exist `None` is returned.
     pub fn mdtm(&mut self, pathname: &str) -> crate::Result<Option<DateTime<Utc>>> {
-        self.write_str(format!("MDTM {}\r\n", pathname))?;
-        let Line(_, content) = self.read_response(status::FILE)?;
+        Ok(self.mdtm_details(pathname)?.map(|mtime| mtime.time))
+    }
+
+    /// Sets the modification time of the file at `pathname` with `MFMT`.
+    pub fn mfmt(&mut self, pathname: &str, time: DateTime<Utc>) -> crate::Result<()> {
+        self.command(
+            format_args!("MFMT {} {}\r\n", time.format("%Y%m%d%H%M%S"), pathname),
+            &[Status::File],
+        )
+        .map(|_| ())
+    }
+
+    /// Same as `mdtm`, but also returns the UTC offset and any unparsed text
+    /// some servers append to the timestamp.
+    pub fn mdtm_details(&mut self, pathname: &str) -> crate::Result<Option<ModificationTime>> {
+        let Line(_, content) =
+            self.command(format_args!("MDTM {}\r\n", pathname), &[Status::File])?;
+        parse::mdtm(&content)
+    }
+
+    /// Asks the size and modification time of every file of `paths` with
+    /// `SIZE` and `MDTM`, in binary mode. The commands are pipelined: a
+    /// batch of them is sent before their replies are read, so that
+    /// thousands of files take a few round trips rather than thousands,
+    /// see `set_pipeline_depth`. A command the server refuses, e.g. for a
+    /// missing file, leaves its field `None`.
+    ///
+    /// ```
+    /// # use ftp::FtpStream;
+    /// # use ftp::testing::TestServer;
+    /// # let server = TestServer::start().unwrap();
+    /// # let mut conn = FtpStream::connect(server.addr()).unwrap();
+    /// # conn.login("Doe", "mumble").unwrap();
+    /// conn.put_bytes("a.csv", b"a,b").unwrap();
+    /// let stats = conn.stat_many(&["a.csv", "missing.csv"]).unwrap();
+    /// assert_eq!(stats[0].size, Some(3));
+    /// assert!(stats[0].modified.is_some());
+    /// assert_eq!(stats[1].size, None);
+    /// ```
+    pub fn stat_many<P: AsRef<str>>(&mut self, paths: &[P]) -> crate::Result<Vec<FileStat>> {
+        // Checked beforehand, as failing to send a command of a batch would
+        // leave the replies of the previous ones unread.
+        if paths
+            .iter()
+            .any(|path| path.as_ref().contains(['\r', '\n']))
+        {
+            return Err(FtpError::InvalidArgument(String::from(
+                "command arguments must not contain CR or LF",
+            )));
+        }
+        let batch = (self.pipeline_depth / 2).max(1);
+        self.in_binary(true, |ftp_stream| {
+            let mut stats = Vec::with_capacity(paths.len());
+            for paths in paths.chunks(batch) {
+                let commands: Vec<_> = paths
+                    .iter()
+                    .flat_map(|path| {
+                        let path = path.as_ref();
+                        [format!("SIZE {}\r\n", path), format!("MDTM {}\r\n", path)]
+                    })
+                    .collect();
+                let replies = ftp_stream
+                    .retrying(|ftp_stream| ftp_stream.pipeline(&commands, &[Status::File]))?;
+                let mut replies = replies.into_iter();
+                while let (Some(size), Some(mdtm)) = (replies.next(), replies.next()) {
+                    let size = match ignore_refusal(size)? {
+                        Some(Line(_, content)) => parse::size(&content)?,
+                        None => None,
+                    };
+                    let modified = match ignore_refusal(mdtm)? {
+                        Some(Line(_, content)) => parse::mdtm(&content)?,
+                        None => None,
+                    };
+                    stats.push(FileStat {
+                        size: size.map(|size| size as u64),
+                        modified: modified.map(|mtime| mtime.time),
+                    });
+                }
+            }
+            Ok(stats)
+        })
+    }
+
+    /// Sets how many commands `stat_many` sends before reading their
+    /// replies, 16 by default. Servers read commands one at a time, so the
+    /// others wait in the socket buffers; `1` sends each command after the
+    /// reply to the previous one, for servers which mishandle that.
+    pub fn set_pipeline_depth(&mut self, depth: usize) {
+        self.pipeline_depth = depth;
+    }
 
-        match MDTM_RE.captures(&content) {
-            Some(caps) => {
-                let (year, month, day) = (
-                    caps[1].parse::<i32>().unwrap(),
-                    caps[2].parse::<u32>().unwrap(),
-                    caps[3].parse::<u32>().unwrap(),
-                );
-                let (hour, minute, second) = (
-                    caps[4].parse::<u32>().unwrap(),
-                    caps[5].parse::<u32>().unwrap(),
-                    caps[6].parse::<u32>().unwrap(),
-                );
-                Ok(Some(
-                    Utc.ymd(year, month, day).and_hms(hour, minute, second),
-                ))
+    /// Sends every command of `commands`, then reads their replies in
+    /// order. A reply other than `expected` is returned as the error of
+    /// its command, while failing to send a command or read a reply fails
+    /// the whole pipeline.
+    fn pipeline(
+        &mut self,
+        commands: &[String],
+        expected: &[Status],
+    ) -> crate::Result<Vec<crate::Result<Line>>> {
+        let bytes: Vec<&[u8]> = commands.iter().map(|command| command.as_bytes()).collect();
+        self.write_commands(&bytes)?;
+        let mut replies = Vec::with_capacity(commands.len());
+        for command in commands {
+            // Errors and traces name the command the reply answers.
+            self.trace.start(command);
+            self.last_command.clear();
+            self.last_command
+                .extend_from_slice(command.trim_end().as_bytes());
+            match self.read_response_in(expected) {
+                Err(err @ FtpError::UnexpectedResponse(_)) => replies.push(Err(err)),
+                res => replies.push(Ok(res?)),
             }
-            None => Ok(None),
         }
+        Ok(replies)
     }
 
     /// Retrieves the size of the file in bytes at `pathname` if it exists.
     /// In case the file does not exist `None` is returned.
     pub fn size(&mut self, pathname: &str) -> crate::Result<Option<usize>> {
-        self.write_str(format!("SIZE {}\r\n", pathname))?;
-        let Line(_, content) = self.read_response(status::FILE)?;
+        let Line(_, content) = self.with_binary(|ftp_stream| {
+            ftp_stream.command(format_args!("SIZE {}\r\n", pathname), &[Status::File])
+        })?;
+        parse::size(&content)
+    }
 
-        match SIZE_RE.captures(&content) {
-            Some(caps) => Ok(Some(caps[1].parse().unwrap())),
-            None => Ok(None),
+    /// Format a command into the reusable command buffer and send it, so
+    /// sending a command doesn't allocate.
+    fn write_cmd(&mut self, command: fmt::Arguments) -> crate::Result<()> {
+        let mut cmd_buf = std::mem::take(&mut self.cmd_buf);
+        cmd_buf.clear();
+        // Writing into a `Vec` can't fail.
+        let _ = cmd_buf.write_fmt(command);
+        self.trace
+            .start(std::str::from_utf8(&cmd_buf).unwrap_or_default());
+        let res = self.write_bytes(&cmd_buf);
+        self.cmd_buf = cmd_buf;
+        res
+    }
+
+    /// Send `verb` followed by an argument which may not be valid UTF-8.
+    fn write_raw_cmd(&mut self, verb: &str, arg: &[u8]) -> crate::Result<()> {
+        let mut cmd_buf = std::mem::take(&mut self.cmd_buf);
+        cmd_buf.clear();
+        cmd_buf.extend_from_slice(verb.as_bytes());
+        cmd_buf.push(b' ');
+        for &byte in arg {
+            // A Telnet IAC byte is escaped by doubling it.
+            if byte == IAC {
+                cmd_buf.push(IAC);
+            }
+            cmd_buf.push(byte);
         }
+        cmd_buf.extend_from_slice(b"\r\n");
+        self.trace.start(verb);
+        let res = self.write_bytes(&cmd_buf);
+        self.cmd_buf = cmd_buf;
+        res
+    }
+
+    fn write_str(&mut self, command: &str) -> crate::Result<()> {
+        self.trace.start(command);
+        self.write_bytes(command.as_bytes())
+    }
+
+    fn write_bytes(&mut self, command: &[u8]) -> crate::Result<()> {
+        self.write_commands(&[command])
     }
 
-    fn write_str<S: AsRef<str>>(&mut self, command: S) -> crate::Result<()> {
-        if cfg!(feature = "debug_print") {
-            print!("CMD {}", command.as_ref());
+    /// Sends `commands` in a single vectored write, so that commands sent
+    /// back to back leave in as few packets as possible.
+    fn write_commands(&mut self, commands: &[&[u8]]) -> crate::Result<()> {
+        // An argument containing CR or LF would end the command early and
+        // let the rest of it be read as another command.
+        for command in commands {
+            let body = command.strip_suffix(b"\r\n").unwrap_or(command);
+            if body.iter().any(|&b| b == b'\r' || b == b'\n') {
+                return Err(FtpError::InvalidArgument(String::from(
+                    "command arguments must not contain CR or LF",
+                )));
+            }
+        }
+        if self.closed {
+            return Err(FtpError::ConnectionError(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "the server closed the session",
+            )));
+        }
+        if self.deadline.is_some() {
+            self.apply_deadline()?;
+        }
+        self.last_command_at = Instant::now();
+        for command in commands {
+            let body = command.strip_suffix(b"\r\n").unwrap_or(command);
+            if cfg!(feature = "debug_print") {
+                print!("CMD {}", String::from_utf8_lossy(command));
+            }
+            // Kept for the errors the reply may turn into.
+            self.last_command.clear();
+            self.last_command.extend_from_slice(body);
+            if let Some(ref mut observer) = self.observer {
+                observer.command_sent(&observer::mask(body));
+            }
+            if let Some(ref metrics) = self.metrics {
+                metrics.command_sent();
+            }
         }
 
-        Ok(self
-            .reader
-            .get_mut()
-            .write_all(command.as_ref().as_bytes())?)
+        let mut slices: Vec<IoSlice> = commands
+            .iter()
+            .map(|command| IoSlice::new(command))
+            .collect();
+        Ok(buffer::write_all_vectored(
+            self.reader.get_mut(),
+            &mut slices,
+        )?)
+    }
+
+    /// Reads the `220` welcome message, keeping every line of it.
+    fn read_welcome(&mut self) -> crate::Result<()> {
+        let (Line(_, last), lines) = self.read_reply_lines(&[Status::Ready])?;
+        self.welcome_lines = lines;
+        self.welcome_msg = Some(last);
+        Ok(())
+    }
+
+    /// Reads a reply, returning its last line and the text of every line,
+    /// without the reply code.
+    fn read_reply_lines(&mut self, expected_code: &[Status]) -> crate::Result<(Line, Vec<String>)> {
+        let mut lines = Vec::new();
+        let line = self.read_reply(Expected::Codes(expected_code), Some(&mut lines))?;
+        lines.push(line.1.clone());
+        let code = line.1.get(..3).unwrap_or_default();
+        for text in &mut lines {
+            if let Some(rest) = text.strip_prefix(code) {
+                let rest = rest.strip_prefix(['-', ' ']).unwrap_or(rest);
+                *text = rest.to_owned();
+            }
+        }
+        Ok((line, lines))
     }
 
-    pub fn read_response(&mut self, expected_code: u32) -> crate::Result<Line> {
+    pub fn read_response(&mut self, expected_code: Status) -> crate::Result<Line> {
         self.read_response_in(&[expected_code])
     }
 
     /// Retrieve single line response
-    pub fn read_response_in(&mut self, expected_code: &[u32]) -> crate::Result<Line> {
-        let mut line = String::with_capacity(5);
-        self.reader.read_line(&mut line)?;
-
-        if cfg!(feature = "debug_print") {
-            print!("FTP {}", line);
-        }
+    pub fn read_response_in(&mut self, expected_code: &[Status]) -> crate::Result<Line> {
+        self.read_reply(Expected::Codes(expected_code), None)
+    }
 
-        if line.len() < 5 {
-            return Err(FtpError::InvalidResponse(
-                "error: could not read reply code".to_owned(),
-            ));
-        }
+    /// Reads a reply, accepting any code of `class`. Servers don't agree on
+    /// the code of every success, e.g. `250` instead of `200`.
+    pub fn read_response_class(&mut self, class: ResponseClass) -> crate::Result<Line> {
+        self.read_reply(Expected::Class(class), None)
+    }
 
-        let code: u32 = line[0..3].parse().map_err(|err| {
-            FtpError::InvalidResponse(format!("error: could not parse reply code: {}", err))
-        })?;
+    /// Read a reply, storing the lines before the last line of a multi-line
+    /// reply in `body`, the first one included.
+    fn read_reply(
+        &mut self,
+        expected: Expected,
+        body: Option<&mut Vec<String>>,
+    ) -> crate::Result<Line> {
+        // The lines are read into a buffer reused from reply to reply.
+        let mut line = std::mem::take(&mut self.line_buf);
+        line.clear();
+        let res = self.read_reply_into(&mut line, expected, body);
+        self.line_buf = line;
+        res
+    }
 
-        // multiple line reply
-        // loop while the line does not begin with the code and a space
-        let expected = format!("{} ", &line[0..3]);
-        while line.len() < 5 || line[0..4] != expected {
-            line.clear();
-            if let Err(e) = self.reader.read_line(&mut line) {
-                return Err(FtpError::ConnectionError(e));
+    fn read_reply_into(
+        &mut self,
+        line: &mut String,
+        expected: Expected,
+        body: Option<&mut Vec<String>>,
+    ) -> crate::Result<Line> {
+        // The lines are collected even without a `body`, for errors.
+        let mut own_lines = Vec::new();
+        let lines = match body {
+            Some(body) => body,
+            None => &mut own_lines,
+        };
+        let first = lines.len();
+        let lenient = self.parse_mode == ParseMode::Lenient;
+        let observer = &mut self.observer;
+        let line = parse::read_reply_with(&mut self.reader, line, lenient, |body| {
+            if let Some(ref mut observer) = *observer {
+                observer.reply_line(body);
             }
+            lines.push(body.to_owned());
+        })?;
+        self.check_reply(line, expected, &lines[first..])
+    }
 
-            if cfg!(feature = "debug_print") {
-                print!("FTP {}", line);
-            }
+    /// Reports a reply to the trace, the observer and the metrics, and fails
+    /// unless `expected` accepts it. `body` holds the lines before the last.
+    fn check_reply(
+        &mut self,
+        line: Line,
+        expected: Expected,
+        body: &[String],
+    ) -> crate::Result<Line> {
+        let code = line.0;
+        self.trace.reply(code);
+        if let Some(ref mut observer) = self.observer {
+            observer.response_received(&line);
+        }
+        if let Some(ref metrics) = self.metrics {
+            metrics.reply_received(code);
         }
 
-        line = String::from(line.trim());
-
-        if expected_code.iter().any(|ec| code == *ec) {
-            Ok(Line(code, line))
+        if expected.contains(code) {
+            return Ok(line);
+        }
+        let mut reply_lines = body.to_vec();
+        reply_lines.push(line.1.clone());
+        let reply = Reply {
+            command: Some(observer::mask(&self.last_command)).filter(|cmd| !cmd.is_empty()),
+            line,
+            lines: reply_lines,
+        };
+        if code == Status::NotAvailable {
+            // The server is closing the control connection.
+            self.closed = true;
+            self.emit(SessionEvent::Disconnected);
+            Err(FtpError::ServiceNotAvailable(reply))
         } else {
-            Err(FtpError::InvalidResponse(format!(
-                "Expected code {:?}, got response: {}",
-                expected_code, line
-            )))
+            Err(FtpError::UnexpectedResponse(reply))
         }
     }
 }
@@ -7,7 +7,7 @@
 //!
 //! Here is a basic usage example:
 //!
-//! ```rust
+//! ```rust,no_run
 //! use ftp::FtpStream;
 //! let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap_or_else(|err|
 //!     panic!("{}", err)
@@ -15,6 +15,12 @@
 //! let _ = ftp_stream.quit();
 //! ```
 //!
+//! ### Testing
+//!
+//! The `testing` feature adds the [`testing`](testing/index.html) module, an
+//! in-process FTP server which lets integration tests run without an external
+//! server.
+//!
 //! ### FTPS
 //!
 //! The client supports FTPS on demand. To enable it the client should be
@@ -28,6 +34,29 @@
 //! For better security it's the good practice to switch to the secure mode
 //! before authentication.
 //!
+//! ### Tracing
+//!
+//! With the `tracing` feature enabled, every command is recorded in a
+//! `ftp.command` span (command name, reply code and duration) and every data
+//! transfer in a `ftp.transfer` span (bytes transferred and duration).
+//!
+//! ### Digests
+//!
+//! The `digest` feature adds the [`digest`](digest/index.html) module and
+//! methods computing a CRC32, MD5 or SHA-256 digest of uploads and downloads
+//! while they are transferred.
+//!
+//! ### Memory-mapped uploads
+//!
+//! The `mmap` feature adds `FtpStream::put_mmap`, which uploads a local file
+//! mapped in memory, for multi-gigabyte files.
+//!
+//! ### Client-side compression
+//!
+//! The `gzip` feature adds `FtpStream::put_gzip` and `retr_gzip`, which
+//! compress uploads with gzip and decompress downloads on the fly, for
+//! compressible data sent to servers without `MODE Z`.
+//!
 #![cfg_attr(
     all(feature = "secure", not(feature = "native-tls")),
     doc = r##"
@@ -48,6 +77,9 @@ let mut ftp_stream = ftp_stream.into_insecure().unwrap();
 // Do all public stuff
 let _ = ftp_stream.quit();
 ```
+
+`types::TlsConfig` builds the context from a minimum TLS version and a
+cipher policy, the same way with either backend.
 "##
 )]
 #![cfg_attr(
@@ -59,7 +91,7 @@ let _ = ftp_stream.quit();
 use ftp::FtpStream;
 use ftp::native_tls::{TlsConnector, TlsStream};
 
-let (ftp_stream, _welcome_msg) = FtpStream::connect("127.0.0.1:21").unwrap();
+let ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
 let mut ctx = TlsConnector::new().unwrap();
 // Switch to the secure mode
 let mut ftp_stream = ftp_stream.into_secure(ctx, "localhost").unwrap();
@@ -70,24 +102,69 @@ let mut ftp_stream = ftp_stream.into_insecure().unwrap();
 // Do all public stuff
 let _ = ftp_stream.quit();
 ```
+
+`types::TlsConfig` builds the context from a minimum TLS version and a
+cipher policy, the same way with either backend.
 "##
 )]
 #[macro_use]
 extern crate lazy_static;
 extern crate chrono;
+#[cfg(feature = "digest")]
+extern crate crc32fast;
+#[cfg(feature = "gzip")]
+extern crate flate2;
+#[cfg(feature = "digest")]
+extern crate md5;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
 extern crate regex;
+#[cfg(feature = "json")]
+extern crate serde_json;
+#[cfg(feature = "digest")]
+extern crate sha2;
+extern crate socket2;
+#[cfg(feature = "tracing")]
+#[macro_use]
+extern crate tracing;
 
 #[cfg(all(feature = "secure", feature = "native-tls"))]
 pub extern crate native_tls;
 #[cfg(all(feature = "secure", not(feature = "native-tls")))]
 pub extern crate openssl;
 
+pub mod batch;
+pub mod block;
+mod buffer;
+pub mod checkpoint;
+mod client;
 mod data_stream;
+#[cfg(feature = "digest")]
+pub mod digest;
+pub mod events;
+pub mod file;
 mod ftp;
+pub mod metrics;
+pub mod observer;
+pub mod parse;
+pub mod path;
+pub mod pool;
+pub mod queue;
+pub mod shared;
+#[cfg(feature = "json")]
+mod state;
 pub mod status;
+pub mod sync;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod trace;
+pub mod transport;
 pub mod types;
+pub mod watch;
 
-pub use self::ftp::FtpStream;
+pub use self::client::FtpClient;
+pub use self::data_stream::DataStream;
+pub use self::ftp::{FtpStream, GenericFtpStream};
 pub use self::types::FtpError;
 
 /// A shorthand for a Result whose error type is always an FtpError.
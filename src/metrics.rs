@@ -0,0 +1,150 @@
+//! Counters of the traffic of connections, for monitoring.
+//!
+//! Metrics set with `FtpStream::set_metrics` are told of every command sent,
+//! reply received, data transferred and reconnection, so services can export
+//! them, e.g. to Prometheus, without wrapping every call. They are shared:
+//! the connections of a pool, or those opened by `spawn_session`, can report
+//! to the same `Counters`.
+//!
+//! ```rust,no_run
+//! use ftp::metrics::Counters;
+//! use ftp::FtpStream;
+//! use std::sync::Arc;
+//!
+//! let counters = Arc::new(Counters::new());
+//! let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
+//! ftp_stream.set_metrics(Some(counters.clone()));
+//! ftp_stream.login("anonymous", "anonymous").unwrap();
+//! ftp_stream.put_bytes("hello.txt", b"hello").unwrap();
+//! let snapshot = counters.snapshot();
+//! println!(
+//!     "{} commands, {} bytes uploaded, {} errors",
+//!     snapshot.commands,
+//!     snapshot.bytes_uploaded,
+//!     snapshot.replies(4) + snapshot.replies(5)
+//! );
+//! ```
+
+use status::{self, Status};
+
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Callbacks counting the traffic of connections. They take `&self` since
+/// metrics are shared between connections, possibly on several threads.
+pub trait Metrics: Send + Sync {
+    /// Called for every command sent.
+    fn command_sent(&self) {}
+
+    /// Called for every complete reply, with its code.
+    fn reply_received(&self, _status: Status) {}
+
+    /// Called at the end of every upload with the bytes sent over the data
+    /// connection.
+    fn bytes_uploaded(&self, _bytes: u64) {}
+
+    /// Called at the end of every download and listing with the bytes
+    /// received over the data connection.
+    fn bytes_downloaded(&self, _bytes: u64) {}
+
+    /// Called when a closed control connection was replaced by a new one,
+    /// see `FtpStream::set_auto_reconnect`.
+    fn reconnected(&self) {}
+}
+
+impl fmt::Debug for dyn Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Metrics")
+    }
+}
+
+/// `Metrics` adding everything up in atomic counters.
+#[derive(Debug, Default)]
+pub struct Counters {
+    commands: AtomicU64,
+    replies: [AtomicU64; 5],
+    bytes_uploaded: AtomicU64,
+    bytes_downloaded: AtomicU64,
+    reconnects: AtomicU64,
+}
+
+impl Counters {
+    /// Creates counters at zero.
+    pub fn new() -> Counters {
+        Counters::default()
+    }
+
+    /// The current values of the counters.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        MetricsSnapshot {
+            commands: load(&self.commands),
+            replies_by_class: [
+                load(&self.replies[0]),
+                load(&self.replies[1]),
+                load(&self.replies[2]),
+                load(&self.replies[3]),
+                load(&self.replies[4]),
+            ],
+            bytes_uploaded: load(&self.bytes_uploaded),
+            bytes_downloaded: load(&self.bytes_downloaded),
+            reconnects: load(&self.reconnects),
+        }
+    }
+}
+
+impl Metrics for Counters {
+    fn command_sent(&self) {
+        self.commands.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn reply_received(&self, status: Status) {
+        // Codes are 1xx to 5xx; lenient parsing lets others through.
+        if let Some(counter) =
+            status::class(status.code()).and_then(|class| self.replies.get(class as usize - 1))
+        {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn bytes_uploaded(&self, bytes: u64) {
+        self.bytes_uploaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn bytes_downloaded(&self, bytes: u64) {
+        self.bytes_downloaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn reconnected(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The values of `Counters` at some point.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// The number of commands sent.
+    pub commands: u64,
+    /// The number of replies of each class, 1xx first.
+    pub replies_by_class: [u64; 5],
+    /// The number of bytes uploaded.
+    pub bytes_uploaded: u64,
+    /// The number of bytes downloaded, listings included.
+    pub bytes_downloaded: u64,
+    /// The number of reconnections.
+    pub reconnects: u64,
+}
+
+impl MetricsSnapshot {
+    /// The number of replies of `class`, from 1 for 1xx replies to 5 for
+    /// 5xx replies, 0 for any other class.
+    pub fn replies(&self, class: u32) -> u64 {
+        class
+            .checked_sub(1)
+            .and_then(|class| self.replies_by_class.get(class as usize))
+            .cloned()
+            .unwrap_or(0)
+    }
+}
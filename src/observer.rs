@@ -0,0 +1,179 @@
+//! Hooks into the command/response exchange of a session.
+//!
+//! An observer set with `FtpStream::set_observer` sees every command sent and
+//! every reply received, which allows audit logging, metrics or protocol
+//! debugging at runtime, without the `debug_print` feature.
+//!
+//! ```rust,no_run
+//! use ftp::observer::FtpObserver;
+//! use ftp::types::Line;
+//! use ftp::FtpStream;
+//!
+//! struct Logger;
+//!
+//! impl FtpObserver for Logger {
+//!     fn command_sent(&mut self, command: &str) {
+//!         eprintln!("> {}", command);
+//!     }
+//!
+//!     fn response_received(&mut self, response: &Line) {
+//!         eprintln!("< {}", response.1);
+//!     }
+//! }
+//!
+//! let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
+//! ftp_stream.set_observer(Some(Box::new(Logger)));
+//! // Logs `> PASS ****`, never the password itself.
+//! ftp_stream.login("anonymous", "anonymous").unwrap();
+//! ```
+
+use super::{transport::Transport, types::Line, GenericFtpStream};
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+/// Callbacks invoked for the commands and replies of a session.
+pub trait FtpObserver: Send {
+    /// Called before a command is sent, without the trailing CRLF. The
+    /// arguments of `PASS` and `ACCT` are replaced with `****`.
+    fn command_sent(&mut self, _command: &str) {}
+
+    /// Called for every line of a multi-line reply before the last one, as
+    /// it is read, reply code included.
+    fn reply_line(&mut self, _line: &str) {}
+
+    /// Called for every complete reply. For multi-line replies this is the
+    /// last line, which carries the final reply code; the lines before it
+    /// went to `reply_line`.
+    fn response_received(&mut self, _response: &Line) {}
+}
+
+impl fmt::Debug for dyn FtpObserver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("FtpObserver")
+    }
+}
+
+/// Returns `command` as it may be shown to an observer.
+pub(crate) fn mask(command: &[u8]) -> String {
+    let command = String::from_utf8_lossy(command);
+    let verb = command.split(' ').next().unwrap_or_default();
+    if verb.eq_ignore_ascii_case("PASS") || verb.eq_ignore_ascii_case("ACCT") {
+        format!("{} ****", verb)
+    } else {
+        command.into_owned()
+    }
+}
+
+/// One step of a recorded session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TranscriptEntry {
+    /// A command sent by the client, as shown to observers.
+    Command(String),
+    /// A reply line received from the server, reply code included.
+    Reply(String),
+}
+
+/// The control connection exchange of a session.
+///
+/// Its `Display` form is a plain text log with one entry per line, commands
+/// prefixed with `> ` and replies with `< `, which `parse` reads back.
+/// Data connections are not part of the transcript.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transcript {
+    pub entries: Vec<TranscriptEntry>,
+}
+
+impl Transcript {
+    /// Parses a transcript in the format written by its `Display` impl.
+    /// Lines which are neither commands nor replies are ignored.
+    pub fn parse(log: &str) -> Transcript {
+        let entries = log
+            .lines()
+            .filter_map(|line| {
+                if let Some(command) = line.strip_prefix("> ") {
+                    Some(TranscriptEntry::Command(command.to_owned()))
+                } else {
+                    line.strip_prefix("< ")
+                        .map(|reply| TranscriptEntry::Reply(reply.to_owned()))
+                }
+            })
+            .collect();
+        Transcript { entries }
+    }
+}
+
+impl fmt::Display for Transcript {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            match *entry {
+                TranscriptEntry::Command(ref command) => writeln!(f, "> {}", command)?,
+                TranscriptEntry::Reply(ref reply) => writeln!(f, "< {}", reply)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An observer recording a `Transcript`, e.g. to attach to a bug report or
+/// to play back with `testing::ReplayServer`.
+///
+/// ```rust,no_run
+/// use ftp::observer::TranscriptRecorder;
+/// use ftp::FtpStream;
+///
+/// let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
+/// let recorder = TranscriptRecorder::attach(&mut ftp_stream);
+/// ftp_stream.login("anonymous", "anonymous").unwrap();
+/// ftp_stream.quit().unwrap();
+/// std::fs::write("session.log", recorder.transcript().to_string()).unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TranscriptRecorder {
+    transcript: Arc<Mutex<Transcript>>,
+}
+
+impl TranscriptRecorder {
+    /// Starts recording the session of `ftp_stream`, beginning with the
+    /// welcome message it was greeted with. This replaces any observer
+    /// already set on the stream.
+    pub fn attach<S: Transport>(ftp_stream: &mut GenericFtpStream<S>) -> TranscriptRecorder {
+        let recorder = TranscriptRecorder::default();
+        if let Some(welcome) = ftp_stream.get_welcome_msg() {
+            // The lines before the last are kept without their code.
+            let code = welcome.get(..3).unwrap_or_default();
+            let lines = ftp_stream.get_welcome_lines();
+            for line in &lines[..lines.len().saturating_sub(1)] {
+                recorder.push(TranscriptEntry::Reply(format!("{}-{}", code, line)));
+            }
+            recorder.push(TranscriptEntry::Reply(welcome.to_owned()));
+        }
+        ftp_stream.set_observer(Some(Box::new(recorder.clone())));
+        recorder
+    }
+
+    /// A copy of what was recorded so far.
+    pub fn transcript(&self) -> Transcript {
+        self.transcript.lock().unwrap().clone()
+    }
+
+    fn push(&self, entry: TranscriptEntry) {
+        self.transcript.lock().unwrap().entries.push(entry);
+    }
+}
+
+impl FtpObserver for TranscriptRecorder {
+    fn command_sent(&mut self, command: &str) {
+        self.push(TranscriptEntry::Command(command.to_owned()));
+    }
+
+    fn reply_line(&mut self, line: &str) {
+        self.push(TranscriptEntry::Reply(line.to_owned()));
+    }
+
+    fn response_received(&mut self, response: &Line) {
+        self.push(TranscriptEntry::Reply(response.1.clone()));
+    }
+}
@@ -0,0 +1,1134 @@
+//! Parsers for the contents of server replies and listings.
+//!
+//! These are the pure functions the client uses internally. They are public
+//! so that code handling raw replies or listings itself can reuse them. The
+//! reply parsers take a complete reply line, reply code included.
+//!
+//! The parsers taking bytes, `reply`, `pasv_reply`, `list_data` and
+//! `mlsd_data`, parse data as received from the network. They fail on any
+//! malformed input rather than panic, and are the entry points of the fuzz
+//! targets in the `fuzz` directory of the repository.
+//!
+//! ```rust
+//! use ftp::parse;
+//!
+//! let addr = parse::pasv("227 Entering Passive Mode (192,168,1,2,195,80).").unwrap();
+//! assert_eq!(addr.to_string(), "192.168.1.2:50000");
+//! assert_eq!(parse::size("213 1024").unwrap(), Some(1024));
+//!
+//! let entry = parse::list_line("drwxr-xr-x 2 ftp ftp 4096 Jan 01 2024 pub").unwrap();
+//! assert_eq!(entry.name, "pub");
+//! assert!(entry.is_dir());
+//! ```
+
+use super::{
+    status::{self, Status},
+    types::{
+        DirEntry, EntryKind, Features, FtpError, Line, ModificationTime, ParseMode, Permissions,
+        Quota, Reply, RestartMarker, ServerInfo, ServerSoftware,
+    },
+};
+
+use {
+    chrono::{Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc},
+    regex::Regex,
+    std::{
+        io::BufRead,
+        net::{Ipv4Addr, SocketAddr},
+    },
+};
+
+lazy_static! {
+    // This regex extracts IP and Port details from PASV command response.
+    // The regex looks for the pattern (h1,h2,h3,h4,p1,p2).
+    static ref PASV_RE: Regex = Regex::new(r"\((\d+),(\d+),(\d+),(\d+),(\d+),(\d+)\)").unwrap();
+
+    // This regex splits the reply to MDTM into the timestamp, an optional
+    // fraction of a second, an optional UTC offset and the rest of the reply.
+    static ref MDTM_RE: Regex = Regex::new(
+        r"^\s*(\d{4})(\d{2})(\d{2})(\d{2})(\d{2})(\d{2})(?:\.(\d{1,9}))?(?:\s*([+-])(\d{2}):?(\d{2})\b)?\s*(.*)$"
+    ).unwrap();
+
+    // This regex extracts the client and server markers from a restart
+    // marker reply, `110 MARK yyyy = mmmm`.
+    static ref MARK_RE: Regex = Regex::new(r"MARK\s+(\S+)\s*=\s*(\S+)").unwrap();
+
+    // This regex matches the name of a known server software in a welcome
+    // message, followed by its version if the server tells it.
+    static ref SERVER_RE: Regex = Regex::new(
+        r"(?i)\b(vsftpd|proftpd|filezilla server|microsoft ftp service)(?:\s+version)?(?:\s+v?(\d+(?:\.\w+)+))?"
+    ).unwrap();
+
+    // This regex matches the upload line of ProFTPD's `SITE QUOTA` reply,
+    // `Uploaded bytes: 1024.00/1048576.00`, in any display unit, or
+    // `unlimited`.
+    static ref PROFTPD_QUOTA_RE: Regex = Regex::new(
+        r"(?i)^\s*uploaded\s+(bytes|kb|mb|gb):\s*(?:([\d.]+)/([\d.]+)|unlimited)"
+    ).unwrap();
+
+    // This regex matches Pure-FTPd's quota line,
+    // `1024 Kbytes used (10%) - authorized: 10240 Kb`.
+    static ref PUREFTPD_QUOTA_RE: Regex =
+        Regex::new(r"(?i)(\d+)\s*Kbytes used \(\d+%\) - authorized: (\d+)\s*Kb").unwrap();
+
+    // This regex extracts file size from SIZE command response.
+    static ref SIZE_RE: Regex = Regex::new(r"\s+(\d+)\s*$").unwrap();
+
+    // This regex matches a unix `ls -l` line: type and permissions, link
+    // count, owner, optional group, size, the month and the day in either
+    // order, the time or the year, and name.
+    static ref UNIX_LIST_RE: Regex = Regex::new(
+        r"^([-dlbcps])([-rwxsStT]{9})\S*\s+\d+\s+\S+(?:\s+\S+)?\s+(\d+)\s+(\S{1,6})\s+(\S{1,6})\s+(\d{1,2}:\d{2}|\d{4})\s+(.+)$"
+    ).unwrap();
+
+    // This regex matches a DOS / IIS listing line: date, time, `<DIR>` or
+    // size, and name.
+    static ref DOS_LIST_RE: Regex = Regex::new(
+        r"^(\d{2})-(\d{2})-(\d{2,4})\s+(\d{2}):(\d{2})(AM|PM)?\s+(<DIR>|\d+)\s+(.+)$"
+    ).unwrap();
+}
+
+/// Parses the address of a `227 Entering Passive Mode (h1,h2,h3,h4,p1,p2)` reply.
+pub fn pasv(reply: &str) -> crate::Result<SocketAddr> {
+    let invalid = || FtpError::InvalidResponse(format!("Invalid PASV response: {}", reply));
+    let caps = PASV_RE.captures(reply).ok_or_else(invalid)?;
+    let mut nums = [0u8; 6];
+    for (i, num) in nums.iter_mut().enumerate() {
+        *num = caps[i + 1].parse().map_err(|_| invalid())?;
+    }
+    let ip = Ipv4Addr::new(nums[0], nums[1], nums[2], nums[3]);
+    let port = (u16::from(nums[4]) << 8) + u16::from(nums[5]);
+    Ok(SocketAddr::from((ip, port)))
+}
+
+/// Parses a complete reply, the lines of a multi-line reply included, such
+/// as `b"220-Welcome\r\n220 ready\r\n"`. Anything after the reply is
+/// ignored.
+///
+/// ```rust
+/// use ftp::{parse, status::Status, types::ParseMode};
+///
+/// let reply = parse::reply(b"211-Features:\r\n MDTM\r\n211 End\r\n", ParseMode::Strict).unwrap();
+/// assert_eq!(reply.line.0, Status::System);
+/// assert_eq!(reply.lines, ["211-Features:", " MDTM", "211 End"]);
+/// ```
+pub fn reply(input: &[u8], mode: ParseMode) -> crate::Result<Reply> {
+    let mut reader = input;
+    let mut line = String::new();
+    let mut lines = Vec::new();
+    let last = read_reply(
+        &mut reader,
+        &mut line,
+        &mut lines,
+        mode == ParseMode::Lenient,
+    )
+    .map_err(|err| match err {
+        // The input ended before the last line, or isn't UTF-8.
+        FtpError::ConnectionError(err) => {
+            FtpError::InvalidResponse(format!("Invalid reply: {}", err))
+        }
+        err => err,
+    })?;
+    lines.push(last.1.clone());
+    Ok(Reply {
+        command: None,
+        line: last,
+        lines,
+    })
+}
+
+/// Parses the address of a complete `227` reply, see `reply` and `pasv`.
+pub fn pasv_reply(input: &[u8]) -> crate::Result<SocketAddr> {
+    let reply = reply(input, ParseMode::Strict)?;
+    if reply.line.0 != Status::PassiveMode {
+        return Err(FtpError::UnexpectedResponse(reply));
+    }
+    pasv(&reply.line.1)
+}
+
+/// Parses the port of a `229 Entering Extended Passive Mode (|||port|)` reply.
+/// The `|` delimiter may be any character.
+pub fn epsv(reply: &str) -> crate::Result<u16> {
+    let port = || {
+        let begin = reply.find('(')? + 1;
+        let inner = &reply[begin..begin + reply[begin..].find(')')?];
+        let delim = inner.chars().next()?;
+        match inner.split(delim).collect::<Vec<_>>()[..] {
+            ["", "", "", port, ""] => port.parse().ok(),
+            _ => None,
+        }
+    };
+    port().ok_or_else(|| FtpError::InvalidResponse(format!("Invalid EPSV response: {}", reply)))
+}
+
+/// Parses the lines between the first and the last line of a `211` reply
+/// to `FEAT`, each holding a feature name and its optional parameters.
+pub fn feat<S: AsRef<str>>(lines: &[S]) -> Features {
+    let mut features = Features::default();
+    for line in lines {
+        let line = line.as_ref().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (name, params) = line.split_at(line.find(' ').unwrap_or(line.len()));
+        features.insert(name, params.trim_start());
+    }
+    features
+}
+
+/// Recognizes the server software from the lines of its welcome message and
+/// from its `215` reply to `SYST`, if any.
+///
+/// ```rust
+/// use ftp::parse;
+/// use ftp::types::ServerSoftware;
+///
+/// let info = parse::server_info(&["(vsFTPd 3.0.3)"], Some("215 UNIX Type: L8"));
+/// assert_eq!(info.software, Some(ServerSoftware::VsFtpd));
+/// assert_eq!(info.version.as_deref(), Some("3.0.3"));
+/// assert_eq!(info.system.as_deref(), Some("UNIX Type: L8"));
+/// ```
+pub fn server_info<S: AsRef<str>>(welcome: &[S], syst: Option<&str>) -> ServerInfo {
+    let system = syst
+        .and_then(|reply| reply.get(3..))
+        .map(|system| system.trim().to_owned())
+        .filter(|system| !system.is_empty());
+    let mut info = ServerInfo {
+        system,
+        ..ServerInfo::default()
+    };
+    if let Some(caps) = welcome
+        .iter()
+        .find_map(|line| SERVER_RE.captures(line.as_ref()))
+    {
+        let name = caps[1].to_ascii_lowercase();
+        info.software = Some(match name.as_str() {
+            "vsftpd" => ServerSoftware::VsFtpd,
+            "proftpd" => ServerSoftware::ProFtpd,
+            "filezilla server" => ServerSoftware::FileZilla,
+            _ => ServerSoftware::Iis,
+        });
+        info.version = caps.get(2).map(|version| version.as_str().to_owned());
+    } else if info
+        .system
+        .as_ref()
+        .is_some_and(|system| system.starts_with("Windows_NT"))
+    {
+        // IIS may be set up with a welcome message of its own.
+        info.software = Some(ServerSoftware::Iis);
+    }
+    info
+}
+
+/// Parses a `110 MARK yyyy = mmmm` restart marker reply to a marker sent by
+/// `block::BlockWriter`, whose `yyyy` is the number of bytes sent before it.
+pub fn restart_marker(reply: &str) -> Option<RestartMarker> {
+    let caps = MARK_RE.captures(reply)?;
+    Some(RestartMarker {
+        offset: caps[1].parse().ok()?,
+        marker: caps[2].to_owned(),
+    })
+}
+
+/// Parses the directory of a `257 "/path" ...` reply to `PWD`, see
+/// `quoted_path`.
+pub fn pwd(reply: &str) -> crate::Result<String> {
+    quoted_path(reply)
+        .ok_or_else(|| FtpError::InvalidResponse(format!("Invalid PWD Response: {}", reply)))
+}
+
+/// Parses the path of a `257 "/path" ...` reply to `PWD` or `MKD`. Quotes
+/// inside the path are doubled, the path ends at the first single one.
+/// Returns `None` if the reply has no quoted path.
+pub fn quoted_path(reply: &str) -> Option<String> {
+    let mut chars = reply[reply.find('"')? + 1..].chars().peekable();
+    let mut path = String::new();
+    while let Some(c) = chars.next() {
+        if c == '"' && chars.next_if_eq(&'"').is_none() {
+            return Some(path);
+        }
+        path.push(c);
+    }
+    None
+}
+
+/// Parses the size of a `213 size` reply, `None` if there is no number.
+/// Fails if the number is too large for a size.
+pub fn size(reply: &str) -> crate::Result<Option<usize>> {
+    match SIZE_RE.captures(reply) {
+        Some(caps) => caps[1]
+            .parse()
+            .map(Some)
+            .map_err(|_| FtpError::InvalidResponse(format!("Invalid SIZE response: {}", reply))),
+        None => Ok(None),
+    }
+}
+
+/// Parses the available space in bytes from a `213` reply to `AVBL`.
+pub fn avbl(reply: &str) -> Option<u64> {
+    reply.get(4..)?.trim().parse().ok()
+}
+
+/// Finds the storage quota in the lines of a reply, without their reply
+/// code: the reply of ProFTPD's mod_quotatab to `SITE QUOTA`, or the quota
+/// line Pure-FTPd adds to some replies. Returns `None` if no line is
+/// recognized.
+///
+/// ```rust
+/// use ftp::parse;
+///
+/// let lines = [
+///     "The current quota for this session are [current/limit]:",
+///     "Name: doe",
+///     "  Uploaded Kb:         512.00/1024.00",
+///     "  Downloaded Kb:       unlimited",
+/// ];
+/// let quota = parse::quota(&lines).unwrap();
+/// assert_eq!(quota.used, Some(512 * 1024));
+/// assert_eq!(quota.available(), Some(512 * 1024));
+/// ```
+pub fn quota<S: AsRef<str>>(lines: &[S]) -> Option<Quota> {
+    lines.iter().find_map(|line| {
+        let line = line.as_ref();
+        if let Some(caps) = PROFTPD_QUOTA_RE.captures(line) {
+            let unit: f64 = match caps[1].to_ascii_lowercase().as_str() {
+                "kb" => 1024.0,
+                "mb" => 1024.0 * 1024.0,
+                "gb" => 1024.0 * 1024.0 * 1024.0,
+                _ => 1.0,
+            };
+            let amount = |i| {
+                caps.get(i)
+                    .and_then(|m: regex::Match| m.as_str().parse::<f64>().ok())
+                    .map(|amount| (amount * unit) as u64)
+            };
+            return Some(Quota {
+                used: amount(2),
+                limit: amount(3),
+            });
+        }
+        let caps = PUREFTPD_QUOTA_RE.captures(line)?;
+        let kb = |i: usize| caps[i].parse::<u64>().ok().map(|kb| kb * 1024);
+        Some(Quota {
+            used: kb(1),
+            limit: kb(2),
+        })
+    })
+}
+
+/// Parses a `213 YYYYMMDDHHMMSS` reply, also accepting fractional seconds
+/// (`213 20240101010101.123`) and a trailing UTC offset (`+0200`).
+/// Returns `None` if the reply doesn't hold a timestamp, and fails if the
+/// timestamp isn't a valid date and time.
+pub fn mdtm(reply: &str) -> crate::Result<Option<ModificationTime>> {
+    let text = reply.get(4..).unwrap_or_default();
+    let caps = match MDTM_RE.captures(text) {
+        Some(caps) => caps,
+        None => return Ok(None),
+    };
+    let invalid = || FtpError::InvalidResponse(format!("Invalid MDTM response: {}", reply));
+    // The groups match digits of any script, which only parse if ASCII.
+    let num = |i: usize| caps[i].parse::<u32>().map_err(|_| invalid());
+    let nanos = match caps.get(7) {
+        Some(frac) => {
+            let digits = frac.as_str();
+            digits.parse::<u32>().map_err(|_| invalid())? * 10u32.pow(9 - digits.len() as u32)
+        }
+        None => 0,
+    };
+    let (hour, minute, second) = (num(4)?, num(5)?, num(6)?);
+    let naive = NaiveDate::from_ymd_opt(num(1)? as i32, num(2)?, num(3)?)
+        .and_then(|date| date.and_hms_nano_opt(hour, minute, second, nanos))
+        .ok_or_else(invalid)?;
+    let offset = match caps.get(8) {
+        Some(sign) => {
+            let secs = (num(9)? * 3600 + num(10)? * 60) as i32;
+            let secs = if sign.as_str() == "-" { -secs } else { secs };
+            Some(FixedOffset::east_opt(secs).ok_or_else(invalid)?)
+        }
+        None => None,
+    };
+    let time = match offset {
+        Some(offset) => offset
+            .from_local_datetime(&naive)
+            .single()
+            .ok_or_else(invalid)?
+            .with_timezone(&Utc),
+        None => Utc.from_utc_datetime(&naive),
+    };
+    Ok(Some(ModificationTime {
+        time,
+        offset,
+        remainder: caps[11].trim_end().to_owned(),
+    }))
+}
+
+/// Parses one line of a `LIST` reply in the unix `ls -l` or the DOS format.
+/// Returns `None` for lines in neither format, such as `total 12`.
+///
+/// Unix listings show the month and the day as `Mar 05` in English, but
+/// servers running in another locale may show `5. Mär`, `mars 5` or `3月 5`;
+/// the month names of the common European languages are recognized, as well
+/// as numbered months.
+///
+/// Unix listings also omit the year of files modified in the past six
+/// months, showing the time of day instead. The year is then taken to be
+/// the latest placing the date no later than now, allowing a day of clock
+/// skew, so that a listing made on New Year's Eve shows the files of
+/// December in the current year and those of a server already in January in
+/// the next.
+///
+/// ```rust
+/// use ftp::parse;
+///
+/// let entry = parse::list_line("-rw-r--r-- 1 ftp ftp 1024 5. Mär 2021 notes.txt").unwrap();
+/// assert_eq!(entry.modified.unwrap().to_string(), "2021-03-05 00:00:00");
+/// ```
+pub fn list_line(line: &str) -> Option<DirEntry> {
+    unix_list_line(line).or_else(|| dos_list_line(line))
+}
+
+fn unix_list_line(line: &str) -> Option<DirEntry> {
+    let caps = UNIX_LIST_RE.captures(line)?;
+    let (month, day) = match (month(&caps[4]), day(&caps[5])) {
+        (Some(month), Some(day)) => (month, day),
+        _ => (month(&caps[5])?, day(&caps[4])?),
+    };
+    // Recent files have a time of day instead of a year.
+    let modified = match caps[6].find(':') {
+        Some(pos) => {
+            let hour = caps[6][..pos].parse().ok()?;
+            let minute = caps[6][pos + 1..].parse().ok()?;
+            recent_date(month, day, hour, minute, Utc::now().naive_utc())
+        }
+        None => NaiveDate::from_ymd_opt(caps[6].parse().ok()?, month, day)
+            .and_then(|date| date.and_hms_opt(0, 0, 0)),
+    };
+    let name = &caps[7];
+    let (name, kind) = match &caps[1] {
+        "d" => (name, EntryKind::Directory),
+        "l" => match name.find(" -> ") {
+            Some(pos) => (
+                &name[..pos],
+                EntryKind::Symlink {
+                    target: name[pos + 4..].to_owned(),
+                },
+            ),
+            None => (
+                name,
+                EntryKind::Symlink {
+                    target: String::new(),
+                },
+            ),
+        },
+        "-" => (name, EntryKind::File),
+        _ => (name, EntryKind::Other),
+    };
+    Some(DirEntry {
+        name: name.to_owned(),
+        permissions: unix_permissions(&caps[2], &kind),
+        kind,
+        size: caps[3].parse().ok(),
+        modified,
+        raw: line.to_owned(),
+    })
+}
+
+fn dos_list_line(line: &str) -> Option<DirEntry> {
+    let caps = DOS_LIST_RE.captures(line)?;
+    let (month, day) = (caps[1].parse().ok()?, caps[2].parse().ok()?);
+    let year: i32 = caps[3].parse().ok()?;
+    // Two digit years are 1970 to 2069.
+    let year = match year {
+        0..=69 => year + 2000,
+        70..=99 => year + 1900,
+        _ => year,
+    };
+    let (mut hour, minute): (u32, u32) = (caps[4].parse().ok()?, caps[5].parse().ok()?);
+    match caps.get(6).map(|m| m.as_str()) {
+        Some("PM") if hour < 12 => hour += 12,
+        Some("AM") if hour == 12 => hour = 0,
+        _ => {}
+    }
+    let modified: Option<NaiveDateTime> = NaiveDate::from_ymd_opt(year, month, day)
+        .and_then(|date| date.and_hms_opt(hour, minute, 0));
+    let (kind, size) = match &caps[7] {
+        "<DIR>" => (EntryKind::Directory, None),
+        size => (EntryKind::File, size.parse().ok()),
+    };
+    Some(DirEntry {
+        name: caps[8].to_owned(),
+        kind,
+        size,
+        modified,
+        permissions: None,
+        raw: line.to_owned(),
+    })
+}
+
+/// Parses one line of a `MLSD` reply: `fact=value;` pairs, a space and the
+/// name. Returns `None` if the line has no name.
+///
+/// The modification time of `MLSD` is in UTC.
+pub fn mlsd_line(line: &str) -> Option<DirEntry> {
+    let pos = line.find(' ')?;
+    let (facts, name) = (&line[..pos], &line[pos + 1..]);
+    if name.is_empty() {
+        return None;
+    }
+    let mut entry = DirEntry {
+        name: name.to_owned(),
+        kind: EntryKind::Other,
+        size: None,
+        modified: None,
+        permissions: None,
+        raw: line.to_owned(),
+    };
+    for fact in facts.split(';') {
+        let (fact, value) = match fact.find('=') {
+            Some(pos) => (&fact[..pos], &fact[pos + 1..]),
+            None => continue,
+        };
+        match fact.to_ascii_lowercase().as_str() {
+            "type" => {
+                // Unix servers list symlinks as `OS.unix=slink`, or
+                // `OS.unix=symlink`, followed by `:target` if they show it.
+                let (kind, target) = value.split_at(value.find(':').unwrap_or(value.len()));
+                entry.kind = match kind.to_ascii_lowercase().as_str() {
+                    "file" => EntryKind::File,
+                    "dir" | "cdir" | "pdir" => EntryKind::Directory,
+                    "os.unix=slink" | "os.unix=symlink" => EntryKind::Symlink {
+                        target: target.get(1..).unwrap_or_default().to_owned(),
+                    },
+                    _ => EntryKind::Other,
+                }
+            }
+            "size" => entry.size = value.parse().ok(),
+            "perm" => entry.permissions = Some(mlst_permissions(value)),
+            // The fraction of a second, if any, is dropped.
+            "modify" => {
+                entry.modified =
+                    NaiveDateTime::parse_from_str(value.get(..14).unwrap_or(value), "%Y%m%d%H%M%S")
+                        .ok()
+            }
+            _ => {}
+        }
+    }
+    Some(entry)
+}
+
+/// Parses the data of a `LIST` reply, as received on the data connection,
+/// into entries like `FtpStream::list_entries`: lines in an unknown format
+/// are skipped. Fails if the data isn't UTF-8.
+pub fn list_data(input: &[u8]) -> crate::Result<Vec<DirEntry>> {
+    let mut lines = Vec::new();
+    read_lines(&mut &input[..], &mut lines)?;
+    Ok(lines.iter().filter_map(|line| list_line(line)).collect())
+}
+
+/// Parses the data of a `MLSD` reply, as received on the data connection,
+/// into entries like `FtpStream::mlsd`. Fails if the data isn't UTF-8.
+pub fn mlsd_data(input: &[u8]) -> crate::Result<Vec<DirEntry>> {
+    let mut lines = Vec::new();
+    read_lines(&mut &input[..], &mut lines)?;
+    Ok(mlsd_entries(&lines))
+}
+
+/// Parses `MLSD` lines, skipping the entries of the directory itself and of
+/// its parent.
+pub(crate) fn mlsd_entries<S: AsRef<str>>(lines: &[S]) -> Vec<DirEntry> {
+    lines
+        .iter()
+        .filter_map(|line| mlsd_line(line.as_ref()))
+        .filter(|entry| {
+            let facts = entry.raw.to_ascii_lowercase();
+            !facts.contains("type=cdir;") && !facts.contains("type=pdir;")
+        })
+        .collect()
+}
+
+/// Parses Unix permissions such as `rwxr-sr-x`, the owner's of which give
+/// the flags. Returns `None` if `mode` isn't made of 9 permission letters.
+pub fn unix_permissions(mode: &str, kind: &EntryKind) -> Option<Permissions> {
+    let mode = mode.as_bytes();
+    if mode.len() != 9 {
+        return None;
+    }
+    let mut bits = 0;
+    for (class, triplet) in mode.chunks(3).enumerate() {
+        let shift = 6 - 3 * class as u32;
+        match triplet[0] {
+            b'r' => bits |= 0o4 << shift,
+            b'-' => {}
+            _ => return None,
+        }
+        match triplet[1] {
+            b'w' => bits |= 0o2 << shift,
+            b'-' => {}
+            _ => return None,
+        }
+        // The set-user-ID, set-group-ID and sticky bits replace the execute
+        // permission of the owner, group and others: lower case if it is
+        // granted too.
+        let special = 0o4000 >> class;
+        match (triplet[2], class) {
+            (b'x', _) => bits |= 0o1 << shift,
+            (b'-', _) => {}
+            (b's', 0..=1) | (b't', 2) => bits |= special | 0o1 << shift,
+            (b'S', 0..=1) | (b'T', 2) => bits |= special,
+            _ => return None,
+        }
+    }
+    let is_dir = *kind == EntryKind::Directory;
+    let writable = bits & 0o200 != 0;
+    Some(Permissions {
+        mode: Some(bits),
+        readable: !is_dir && bits & 0o400 != 0,
+        writable,
+        listable: is_dir && bits & 0o400 != 0,
+        enterable: is_dir && bits & 0o100 != 0,
+        deletable: writable,
+        renamable: writable,
+    })
+}
+
+/// Parses the `perm` fact of `MLSD`, such as `adfrw` for a file or `flcdmpe`
+/// for a directory.
+pub fn mlst_permissions(perm: &str) -> Permissions {
+    let mut permissions = Permissions::default();
+    for letter in perm.chars() {
+        match letter.to_ascii_lowercase() {
+            'r' => permissions.readable = true,
+            'a' | 'c' | 'm' | 'w' => permissions.writable = true,
+            'l' => permissions.listable = true,
+            'e' => permissions.enterable = true,
+            'd' => permissions.deletable = true,
+            'f' => permissions.renamable = true,
+            _ => {}
+        }
+    }
+    permissions
+}
+
+/// Reads a reply into `line`, returning its last line and storing the lines
+/// before it in `lines`, the first one included.
+pub(crate) fn read_reply<R: BufRead>(
+    reader: &mut R,
+    line: &mut String,
+    lines: &mut Vec<String>,
+    lenient: bool,
+) -> crate::Result<Line> {
+    read_reply_with(reader, line, lenient, |body| lines.push(body.to_owned()))
+}
+
+/// Same as `read_reply`, passing the lines before the last one to `on_line`
+/// as soon as they are read.
+pub(crate) fn read_reply_with<R, F>(
+    reader: &mut R,
+    line: &mut String,
+    lenient: bool,
+    mut on_line: F,
+) -> crate::Result<Line>
+where
+    R: BufRead,
+    F: FnMut(&str),
+{
+    loop {
+        let read = reader.read_line(line)?;
+        if cfg!(feature = "debug_print") {
+            print!("FTP {}", line);
+        }
+        // Lenient parsing skips blank lines between replies.
+        if !lenient || read == 0 || !line.trim().is_empty() {
+            break;
+        }
+        line.clear();
+    }
+
+    let too_short = if lenient {
+        line.trim_end().len() < 3
+    } else {
+        line.len() < 5
+    };
+    if too_short {
+        return Err(FtpError::InvalidResponse(
+            "error: could not read reply code".to_owned(),
+        ));
+    }
+
+    let code = line
+        .get(0..3)
+        .and_then(|code| code.parse::<u32>().ok())
+        // Only lenient parsing accepts codes outside of the reply classes.
+        .filter(|&code| lenient || status::class(code).is_some())
+        .map(Status::from)
+        .ok_or_else(|| {
+            FtpError::InvalidResponse(format!(
+                "error: could not parse reply code: {:?}",
+                line.trim_end()
+            ))
+        })?;
+
+    // multiple line reply
+    // loop while the line does not begin with the code and a space
+    let mut expected = [0; 3];
+    expected.copy_from_slice(&line.as_bytes()[0..3]);
+    if !is_last_line(line, &expected, lenient) {
+        on_line(line.trim_end());
+    }
+    while !is_last_line(line, &expected, lenient) {
+        line.clear();
+        read_reply_line(reader, line)?;
+        let skip = is_last_line(line, &expected, lenient) || lenient && line.trim().is_empty();
+        if !skip {
+            on_line(line.trim_end());
+        }
+    }
+    Ok(Line(code, String::from(line.trim())))
+}
+
+/// Reads one line of a reply, failing if the connection is closed.
+fn read_reply_line<R: BufRead>(reader: &mut R, line: &mut String) -> crate::Result<()> {
+    if reader.read_line(line)? == 0 {
+        return Err(FtpError::ConnectionError(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "connection closed while reading a reply",
+        )));
+    }
+    if cfg!(feature = "debug_print") {
+        print!("FTP {}", line);
+    }
+    Ok(())
+}
+
+/// Whether `line` ends a reply with the given code: the code followed by a
+/// space or, when lenient, anything but a `-` (or nothing at all).
+fn is_last_line(line: &str, code: &[u8], lenient: bool) -> bool {
+    let bytes = line.trim_end_matches(&['\r', '\n'][..]).as_bytes();
+    if !bytes.starts_with(code) {
+        return false;
+    }
+    match bytes.get(3) {
+        Some(b' ') => true,
+        Some(b'-') => false,
+        _ => lenient,
+    }
+}
+
+/// Reads the lines of a listing into `lines`, returning the number of bytes
+/// read. The lines replace the contents of `lines`, reusing its strings.
+pub(crate) fn read_lines<R: BufRead>(
+    reader: &mut R,
+    lines: &mut Vec<String>,
+) -> crate::Result<u64> {
+    let mut count = 0;
+    let mut bytes = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match reader.read_line(&mut line) {
+            Ok(0) => break,
+            Ok(n) => {
+                bytes += n as u64;
+                let l = line.strip_suffix('\n').unwrap_or(&line);
+                let l = l.strip_suffix('\r').unwrap_or(l);
+                if l.is_empty() {
+                    continue;
+                }
+                match lines.get_mut(count) {
+                    Some(entry) => {
+                        entry.clear();
+                        entry.push_str(l);
+                    }
+                    None => lines.push(l.to_owned()),
+                }
+                count += 1;
+            }
+            Err(_) => {
+                return Err(FtpError::InvalidResponse(String::from(
+                    "Invalid lines in response",
+                )))
+            }
+        }
+    }
+    lines.truncate(count);
+    Ok(bytes)
+}
+
+/// The date at `hour:minute` on `day` of `month` in the latest year placing
+/// it no later than a day after `now`.
+fn recent_date(
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    now: NaiveDateTime,
+) -> Option<NaiveDateTime> {
+    (now.year() - 1..=now.year() + 1)
+        .rev()
+        .filter_map(|year| {
+            NaiveDate::from_ymd_opt(year, month, day)
+                .and_then(|date| date.and_hms_opt(hour, minute, 0))
+        })
+        .find(|date| *date <= now + Duration::days(1))
+}
+
+/// Parses the abbreviated name of a month in English or another common
+/// European language, with or without a trailing dot, or a numbered month
+/// such as `3月`.
+fn month(name: &str) -> Option<u32> {
+    const MONTHS: [&[&str]; 12] = [
+        &["jan", "janv", "ene", "gen", "sty", "янв"],
+        &["feb", "févr", "fév", "fev", "lut", "фев"],
+        &["mar", "mär", "mrz", "märz", "mars", "mrt", "мар"],
+        &["apr", "avr", "abr", "kwi", "апр"],
+        &["may", "mai", "mag", "mei", "maj", "мая", "май"],
+        &["jun", "juin", "juni", "giu", "cze", "июн"],
+        &["jul", "juil", "juli", "lug", "lip", "июл"],
+        &["aug", "août", "aou", "ago", "sie", "авг"],
+        &["sep", "sept", "set", "wrz", "сен"],
+        &["oct", "okt", "ott", "out", "paź", "окт"],
+        &["nov", "lis", "ноя"],
+        &["dec", "dez", "déc", "dic", "des", "gru", "дек"],
+    ];
+    if let Some(number) = name.strip_suffix(['月', '월']) {
+        return number.parse().ok().filter(|month| (1..=12).contains(month));
+    }
+    let name = name.strip_suffix('.').unwrap_or(name).to_lowercase();
+    MONTHS
+        .iter()
+        .position(|names| names.contains(&name.as_str()))
+        .map(|i| i as u32 + 1)
+}
+
+/// Parses a day of the month, as `5`, `05`, `5.` or `5日`.
+fn day(day: &str) -> Option<u32> {
+    day.strip_suffix(['.', '日', '일'])
+        .unwrap_or(day)
+        .parse()
+        .ok()
+        .filter(|day| (1..=31).contains(day))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn quota_replies() {
+        let unlimited = quota(&["  Uploaded bytes:\tunlimited"]).unwrap();
+        assert_eq!(unlimited.limit, None);
+        assert_eq!(unlimited.available(), None);
+        let mb = quota(&["Uploaded Mb: 1.50/2.00"]).unwrap();
+        assert_eq!(mb.available(), Some(512 * 1024));
+        let pure = quota(&["Quota: 2048 Kbytes used (20%) - authorized: 10240 Kb"]).unwrap();
+        assert_eq!(pure.used, Some(2048 * 1024));
+        assert_eq!(pure.limit, Some(10240 * 1024));
+        assert_eq!(quota(&["No quota"]), None);
+        assert_eq!(avbl("213 123456"), Some(123456));
+        assert_eq!(avbl("213 plenty"), None);
+    }
+
+    #[test]
+    fn pasv_reply() {
+        assert_eq!(
+            pasv("227 Entering Passive Mode (127,0,0,1,4,1).").unwrap(),
+            "127.0.0.1:1025".parse().unwrap()
+        );
+        assert!(pasv("227 Entering Passive Mode").is_err());
+        assert!(pasv("227 (300,0,0,1,4,1)").is_err());
+        assert!(pasv("227 (127,0,0,1,256,1)").is_err());
+        assert!(pasv("227 (127,0,0,1,4,99999999999999999999)").is_err());
+    }
+
+    #[test]
+    fn reply_bytes() {
+        let welcome = reply(b"220-Welcome\r\n\r\n220 ready\r\n", ParseMode::Strict).unwrap();
+        assert_eq!(welcome.line, Line(Status::Ready, "220 ready".to_owned()));
+        assert_eq!(welcome.lines, ["220-Welcome", "", "220 ready"]);
+        assert!(reply(b"220-Welcome\r\n", ParseMode::Strict).is_err());
+        assert!(reply(b"22\xe9 ready\r\n", ParseMode::Strict).is_err());
+        assert!(reply(b"220", ParseMode::Strict).is_err());
+        assert_eq!(
+            reply(b"220\r\n", ParseMode::Lenient).unwrap().line.0,
+            Status::Ready
+        );
+
+        assert_eq!(
+            super::pasv_reply(b"227 Entering Passive Mode (127,0,0,1,4,1).\r\n").unwrap(),
+            "127.0.0.1:1025".parse().unwrap()
+        );
+        assert!(super::pasv_reply(b"200 (127,0,0,1,4,1)\r\n").is_err());
+    }
+
+    #[test]
+    fn listing_bytes() {
+        let entries =
+            list_data(b"total 1\r\ndrwxr-xr-x 2 ftp ftp 4096 Jan 01 2024 pub\r\n").unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].is_dir());
+        assert!(list_data(b"-rw-r--r-- 1 ftp ftp 1 Jan 01 2024 \xff\r\n").is_err());
+
+        let entries =
+            mlsd_data(b"type=cdir; .\r\ntype=file;modify=2024010203040\xc3\xa9; a\r\n").unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].modified, None);
+    }
+
+    #[test]
+    fn epsv_reply() {
+        assert_eq!(
+            epsv("229 Entering Extended Passive Mode (|||6446|)").unwrap(),
+            6446
+        );
+        assert_eq!(epsv("229 ok (!!!21!)").unwrap(), 21);
+        assert!(epsv("229 Entering Extended Passive Mode (|||port|)").is_err());
+    }
+
+    #[test]
+    fn restart_marker_reply() {
+        assert_eq!(
+            restart_marker("110 MARK 1024 = 0A3F").unwrap(),
+            RestartMarker {
+                offset: 1024,
+                marker: "0A3F".to_owned()
+            }
+        );
+        assert_eq!(restart_marker("110 MARK ABC = 0A3F"), None);
+        assert_eq!(restart_marker("110 Restart marker"), None);
+    }
+
+    #[test]
+    fn feat_reply() {
+        let features = feat(&[" MDTM", " MLST type*;size*;modify*;", " utf8", ""]);
+        assert!(features.supports("mdtm"));
+        assert!(features.supports("UTF8"));
+        assert_eq!(features.params("MLST"), Some("type*;size*;modify*;"));
+        assert_eq!(features.params("MDTM"), Some(""));
+        assert!(!features.supports("EPSV"));
+        assert_eq!(
+            features.names().collect::<Vec<_>>(),
+            ["MDTM", "MLST", "UTF8"]
+        );
+    }
+
+    #[test]
+    fn pwd_reply() {
+        assert_eq!(pwd("257 \"/home/ftp\" is cwd").unwrap(), "/home/ftp");
+        assert_eq!(pwd("257 \"/say \"\"hi\"\"\"").unwrap(), "/say \"hi\"");
+        assert!(pwd("257 /home").is_err());
+        assert_eq!(
+            pwd("257 \"/a\"\"b\" is the \"current\" directory").unwrap(),
+            "/a\"b"
+        );
+        assert_eq!(quoted_path("257 \"\"\"\" created").unwrap(), "\"");
+        assert_eq!(quoted_path("257 \"/unterminated"), None);
+        assert_eq!(quoted_path("257 Directory created"), None);
+    }
+
+    #[test]
+    fn size_reply() {
+        assert_eq!(size("213 1234").unwrap(), Some(1234));
+        assert_eq!(size("213 unknown").unwrap(), None);
+        assert!(size("213 99999999999999999999999").is_err());
+    }
+
+    #[test]
+    fn mdtm_reply() {
+        let mtime = mdtm("213 20240101010101").unwrap().unwrap();
+        assert_eq!(
+            mtime.time,
+            Utc.with_ymd_and_hms(2024, 1, 1, 1, 1, 1).unwrap()
+        );
+        assert_eq!(mtime.offset, None);
+        assert_eq!(mtime.remainder, "");
+
+        let mtime = mdtm("213 20240101010101.123").unwrap().unwrap();
+        assert_eq!(mtime.time.timestamp_subsec_millis(), 123);
+
+        let mtime = mdtm("213 20240101010101 +0200 local").unwrap().unwrap();
+        assert_eq!(
+            mtime.time,
+            Utc.with_ymd_and_hms(2023, 12, 31, 23, 1, 1).unwrap()
+        );
+        assert_eq!(mtime.offset, FixedOffset::east_opt(7200));
+        assert_eq!(mtime.remainder, "local");
+
+        assert_eq!(mdtm("213 File not found").unwrap(), None);
+        assert!(mdtm("213 20241301010101").is_err());
+        assert!(mdtm("213 20240101250101").is_err());
+        assert!(mdtm("213 20240101010101 +9900").is_err());
+        assert!(mdtm("213 2024\u{661}101010101").is_err());
+        assert!(mdtm("213 20240101010101.12\u{663}").is_err());
+    }
+
+    #[test]
+    fn mlsd_listing() {
+        let entry =
+            mlsd_line("type=file;size=1234;modify=20240102030405.5;UNIX.mode=0644; a b.txt")
+                .unwrap();
+        assert_eq!(entry.name, "a b.txt");
+        assert_eq!(entry.kind, EntryKind::File);
+        assert_eq!(entry.size, Some(1234));
+        assert_eq!(
+            entry.modified,
+            NaiveDate::from_ymd_opt(2024, 1, 2).and_then(|d| d.and_hms_opt(3, 4, 5))
+        );
+        assert_eq!(
+            mlsd_line("Type=cdir; .").unwrap().kind,
+            EntryKind::Directory
+        );
+        assert_eq!(mlsd_line("type=file;"), None);
+
+        let link = mlsd_line("type=OS.unix=slink:/pub/v1.2;size=4; latest").unwrap();
+        assert_eq!(link.link_target(), Some("/pub/v1.2"));
+        let link = mlsd_line("type=OS.unix=symlink; latest").unwrap();
+        assert!(link.is_symlink());
+        assert_eq!(link.link_target(), None);
+    }
+
+    #[test]
+    fn permissions() {
+        let dir = unix_permissions("rwxr-sr-T", &EntryKind::Directory).unwrap();
+        assert_eq!(dir.mode, Some(0o3754));
+        assert!(dir.listable && dir.enterable && dir.writable && !dir.readable);
+        let file = unix_permissions("r-S------", &EntryKind::File).unwrap();
+        assert_eq!(file.mode, Some(0o4400));
+        assert!(file.readable && !file.writable && !file.deletable);
+        assert_eq!(unix_permissions("rwxrwxrws", &EntryKind::File), None);
+        assert_eq!(unix_permissions("rwx", &EntryKind::File), None);
+
+        let entry = mlsd_line("type=dir;perm=flcdmpe; pub").unwrap();
+        assert_eq!(
+            entry.permissions,
+            Some(Permissions {
+                mode: None,
+                readable: false,
+                writable: true,
+                listable: true,
+                enterable: true,
+                deletable: true,
+                renamable: true,
+            })
+        );
+        let file = mlst_permissions("adfrw");
+        assert!(file.readable && file.writable && !file.listable);
+    }
+
+    #[test]
+    fn unix_listing() {
+        let entry =
+            list_line("-rw-r--r--    1 ftp      ftp          1234 Mar 05  2021 notes.txt").unwrap();
+        assert_eq!(entry.name, "notes.txt");
+        assert_eq!(entry.kind, EntryKind::File);
+        assert_eq!(entry.size, Some(1234));
+        let permissions = entry.permissions.unwrap();
+        assert_eq!(permissions.mode, Some(0o644));
+        assert!(permissions.readable && permissions.writable && !permissions.listable);
+        assert_eq!(
+            entry.modified,
+            NaiveDate::from_ymd_opt(2021, 3, 5).and_then(|d| d.and_hms_opt(0, 0, 0))
+        );
+
+        let entry = list_line("lrwxrwxrwx 1 owner 7 Jan 1 12:30 latest -> v1.2.3").unwrap();
+        assert_eq!(entry.name, "latest");
+        assert_eq!(
+            entry.kind,
+            EntryKind::Symlink {
+                target: String::from("v1.2.3")
+            }
+        );
+        assert!(entry.modified.is_some());
+
+        assert!(list_line("total 12").is_none());
+    }
+
+    #[test]
+    fn localized_listing() {
+        let date = |line| {
+            list_line(line)
+                .and_then(|entry| entry.modified)
+                .map(|modified| modified.date())
+        };
+        let march = NaiveDate::from_ymd_opt(2021, 3, 5);
+        for line in [
+            "-rw-r--r-- 1 ftp ftp 1 Mär  5  2021 a",
+            "-rw-r--r-- 1 ftp ftp 1 5. Mär 2021 a",
+            "-rw-r--r-- 1 ftp ftp 1 mars 5 2021 a",
+            "-rw-r--r-- 1 ftp ftp 1 5 mar. 2021 a",
+            "-rw-r--r-- 1 ftp ftp 1 мар 5 2021 a",
+            "-rw-r--r-- 1 ftp ftp 1 3月 5 2021 a",
+            "-rw-r--r-- 1 ftp ftp 1 3월 5일 2021 a",
+        ] {
+            assert_eq!(date(line), march, "{}", line);
+        }
+        assert_eq!(
+            date("-rw-r--r-- 1 ftp ftp 1 déc. 24 2020 a"),
+            NaiveDate::from_ymd_opt(2020, 12, 24)
+        );
+        assert_eq!(date("-rw-r--r-- 1 ftp ftp 1 Foo 5 2021 a"), None);
+        assert_eq!(date("-rw-r--r-- 1 ftp ftp 1 13月 5 2021 a"), None);
+    }
+
+    #[test]
+    fn recent_dates() {
+        let at = |y, m, d, h| NaiveDate::from_ymd_opt(y, m, d).and_then(|d| d.and_hms_opt(h, 0, 0));
+        let now = at(2024, 6, 15, 12).unwrap();
+        assert_eq!(recent_date(1, 10, 8, 0, now), at(2024, 1, 10, 8));
+        assert_eq!(recent_date(6, 16, 8, 0, now), at(2024, 6, 16, 8));
+        assert_eq!(recent_date(12, 1, 8, 0, now), at(2023, 12, 1, 8));
+        // Around New Year, from either side.
+        let eve = at(2024, 12, 31, 20).unwrap();
+        assert_eq!(recent_date(12, 31, 8, 0, eve), at(2024, 12, 31, 8));
+        assert_eq!(recent_date(1, 1, 1, 0, eve), at(2025, 1, 1, 1));
+        let new_year = at(2025, 1, 1, 2).unwrap();
+        assert_eq!(recent_date(12, 31, 23, 0, new_year), at(2024, 12, 31, 23));
+        assert_eq!(recent_date(2, 29, 8, 0, new_year), at(2024, 2, 29, 8));
+    }
+
+    #[test]
+    fn dos_listing() {
+        let entry = list_line("01-15-24  03:04PM       <DIR>          Reports").unwrap();
+        assert_eq!(entry.name, "Reports");
+        assert!(entry.is_dir());
+        assert_eq!(
+            entry.modified,
+            NaiveDate::from_ymd_opt(2024, 1, 15).and_then(|d| d.and_hms_opt(15, 4, 0))
+        );
+
+        let entry = list_line("12-31-99  12:00AM              2048 old file.txt").unwrap();
+        assert_eq!(entry.name, "old file.txt");
+        assert_eq!(entry.size, Some(2048));
+        assert_eq!(
+            entry.modified,
+            NaiveDate::from_ymd_opt(1999, 12, 31).and_then(|d| d.and_hms_opt(0, 0, 0))
+        );
+    }
+
+    #[test]
+    fn server_software() {
+        let info = server_info(&["ProFTPD 1.3.5e Server (Debian) [::ffff:10.0.0.1]"], None);
+        assert_eq!(info.software, Some(ServerSoftware::ProFtpd));
+        assert_eq!(info.version.as_deref(), Some("1.3.5e"));
+        assert_eq!(info.system, None);
+
+        let welcome = [
+            "FileZilla Server version 0.9.41 beta",
+            "written by Tim Kosse (Tim.Kosse@gmx.de)",
+        ];
+        let info = server_info(&welcome, Some("215 UNIX emulated by FileZilla"));
+        assert_eq!(info.software, Some(ServerSoftware::FileZilla));
+        assert_eq!(info.version.as_deref(), Some("0.9.41"));
+
+        let info = server_info(&["Microsoft FTP Service"], None);
+        assert_eq!(info.software, Some(ServerSoftware::Iis));
+        assert_eq!(info.version, None);
+        let info = server_info(&["Welcome to Contoso"], Some("215 Windows_NT"));
+        assert_eq!(info.software, Some(ServerSoftware::Iis));
+
+        let info = server_info(&["ready"], Some("215 UNIX Type: L8"));
+        assert_eq!(info.software, None);
+        assert_eq!(info.system.as_deref(), Some("UNIX Type: L8"));
+    }
+}
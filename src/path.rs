@@ -0,0 +1,365 @@
+//! Remote paths.
+//!
+//! Servers don't share the local path conventions: most use POSIX paths
+//! such as `/pub/file.txt`, while Windows servers may use DOS paths such as
+//! `C:\pub\file.txt`. `FtpPath` manipulates a remote path with the
+//! separators of its `PathStyle`, whatever the local platform.
+//!
+//! ```rust
+//! use ftp::path::FtpPath;
+//!
+//! let dir = FtpPath::new("/pub/releases/");
+//! let file = dir.join("../docs/./guide.pdf").normalize();
+//! assert_eq!(file.as_str(), "/pub/docs/guide.pdf");
+//! assert_eq!(file.parent().unwrap().as_str(), "/pub/docs");
+//! assert_eq!(file.file_name(), Some("guide.pdf"));
+//!
+//! let dos = FtpPath::new(r"C:\pub\file.txt");
+//! assert_eq!(dos.parent().unwrap().as_str(), r"C:\pub");
+//! ```
+
+use std::fmt;
+
+/// The separator convention of a remote path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PathStyle {
+    /// `/` separated paths, absolute when starting with `/`.
+    Posix,
+    /// `\` separated paths, with an optional drive such as `C:`. `/` is
+    /// accepted as a separator too.
+    Dos,
+}
+
+impl PathStyle {
+    /// Guesses the style of `path`: DOS if it has a drive or a `\`.
+    pub fn detect(path: &str) -> PathStyle {
+        if path.contains('\\') || drive_len(path) > 0 {
+            PathStyle::Dos
+        } else {
+            PathStyle::Posix
+        }
+    }
+
+    /// The separator written between components.
+    pub fn separator(self) -> char {
+        match self {
+            PathStyle::Posix => '/',
+            PathStyle::Dos => '\\',
+        }
+    }
+
+    fn is_separator(self, c: char) -> bool {
+        c == '/' || (self == PathStyle::Dos && c == '\\')
+    }
+}
+
+/// A path on the server.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FtpPath {
+    style: PathStyle,
+    path: String,
+}
+
+impl FtpPath {
+    /// Creates a path, its style detected with `PathStyle::detect`.
+    pub fn new<S: Into<String>>(path: S) -> FtpPath {
+        let path = path.into();
+        FtpPath {
+            style: PathStyle::detect(&path),
+            path,
+        }
+    }
+
+    /// Creates a path of the given style.
+    pub fn with_style<S: Into<String>>(path: S, style: PathStyle) -> FtpPath {
+        FtpPath {
+            style,
+            path: path.into(),
+        }
+    }
+
+    /// The style of the path.
+    pub fn style(&self) -> PathStyle {
+        self.style
+    }
+
+    /// The path as a string, as sent to the server.
+    pub fn as_str(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns `true` if the path starts at the root, of its drive if any.
+    pub fn is_absolute(&self) -> bool {
+        let rest = &self.path[self.drive().len()..];
+        rest.starts_with(|c| self.style.is_separator(c))
+    }
+
+    /// Appends `path` to this one, unless it is absolute, in which case it
+    /// replaces it.
+    pub fn join<P: AsRef<str>>(&self, path: P) -> FtpPath {
+        let path = path.as_ref();
+        let other = FtpPath::with_style(path, self.style);
+        if other.is_absolute() || !other.drive().is_empty() || self.path.is_empty() {
+            return other;
+        }
+        let mut joined = self.path.clone();
+        if !joined.ends_with(|c| self.style.is_separator(c)) {
+            joined.push(self.style.separator());
+        }
+        joined.push_str(path);
+        FtpPath::with_style(joined, self.style)
+    }
+
+    /// The path without its last component, or `None` for a root or an
+    /// empty path.
+    pub fn parent(&self) -> Option<FtpPath> {
+        let (prefix, mut components) = self.components();
+        components.pop()?;
+        Some(FtpPath::with_style(
+            self.assemble(&prefix, &components),
+            self.style,
+        ))
+    }
+
+    /// The last component of the path, if it isn't a root.
+    pub fn file_name(&self) -> Option<&str> {
+        self.components().1.pop()
+    }
+
+    /// The path with `.n` inserted before the extension of its file name,
+    /// such as `report.1.csv` for `report.csv`, to name a copy stored next
+    /// to the original.
+    pub fn numbered(&self, n: u32) -> FtpPath {
+        let end = self
+            .path
+            .trim_end_matches(|c| self.style.is_separator(c))
+            .len();
+        let name_start = self.path[..end]
+            .rfind(|c| self.style.is_separator(c))
+            .map_or(self.drive().len(), |i| i + 1);
+        // A leading dot starts a hidden file name, not an extension.
+        let insert = match self.path[name_start..end].rfind('.') {
+            Some(i) if i > 0 => name_start + i,
+            _ => end,
+        };
+        FtpPath::with_style(
+            format!("{}.{}{}", &self.path[..insert], n, &self.path[insert..end]),
+            self.style,
+        )
+    }
+
+    /// Removes repeated separators and `.` components, and resolves `..`
+    /// components lexically. `..` components above the root are dropped,
+    /// those above the start of a relative path are kept.
+    pub fn normalize(&self) -> FtpPath {
+        let (prefix, components) = self.components();
+        let mut normalized: Vec<&str> = Vec::new();
+        for component in components {
+            match component {
+                "." => {}
+                ".." => match normalized.last() {
+                    Some(&last) if last != ".." => {
+                        normalized.pop();
+                    }
+                    _ if self.is_absolute() => {}
+                    _ => normalized.push(".."),
+                },
+                component => normalized.push(component),
+            }
+        }
+        let path = self.assemble(&prefix, &normalized);
+        FtpPath::with_style(
+            if path.is_empty() {
+                ".".to_owned()
+            } else {
+                path
+            },
+            self.style,
+        )
+    }
+
+    /// The drive of a DOS path, such as `C:`, or an empty string.
+    fn drive(&self) -> &str {
+        match self.style {
+            PathStyle::Posix => "",
+            PathStyle::Dos => &self.path[..drive_len(&self.path)],
+        }
+    }
+
+    /// Splits the path into its drive and root, and its non-empty components.
+    fn components(&self) -> (String, Vec<&str>) {
+        let drive = self.drive();
+        let mut prefix = drive.to_owned();
+        if self.is_absolute() {
+            prefix.push(self.style.separator());
+        }
+        let components = self.path[drive.len()..]
+            .split(|c| self.style.is_separator(c))
+            .filter(|c| !c.is_empty())
+            .collect();
+        (prefix, components)
+    }
+
+    fn assemble(&self, prefix: &str, components: &[&str]) -> String {
+        let mut sep = [0; 4];
+        let sep = self.style.separator().encode_utf8(&mut sep);
+        format!("{}{}", prefix, components.join(sep))
+    }
+}
+
+impl fmt::Display for FtpPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.path)
+    }
+}
+
+impl AsRef<str> for FtpPath {
+    fn as_ref(&self) -> &str {
+        &self.path
+    }
+}
+
+impl<'a> From<&'a str> for FtpPath {
+    fn from(path: &'a str) -> FtpPath {
+        FtpPath::new(path)
+    }
+}
+
+impl From<String> for FtpPath {
+    fn from(path: String) -> FtpPath {
+        FtpPath::new(path)
+    }
+}
+
+/// Resolves a name listed by `NLST` against the path listed, `None` for the
+/// `.` and `..` entries. Servers list bare names, names prefixed with the
+/// path as sent, or absolute paths, and a file by the path it was listed
+/// with. A pattern such as `logs/*.txt` lists the names in its directory.
+pub(crate) fn listed_path(listed: Option<&FtpPath>, name: &str) -> Option<FtpPath> {
+    if name == "." || name == ".." {
+        return None;
+    }
+    let dir = match listed {
+        Some(listed) if name == listed.as_str() => return Some(listed.normalize()),
+        Some(listed)
+            if listed
+                .file_name()
+                .is_some_and(|file| file.contains(['*', '?', '['])) =>
+        {
+            listed.parent()
+        }
+        listed => listed.cloned(),
+    };
+    let dir = match dir {
+        Some(dir) => dir,
+        None => return Some(FtpPath::new(name).normalize()),
+    };
+    let path = FtpPath::with_style(name, dir.style());
+    if path.is_absolute() {
+        return Some(path.normalize());
+    }
+    let (prefix, normalized) = (dir.normalize(), path.normalize());
+    let echoed = normalized
+        .as_str()
+        .strip_prefix(prefix.as_str())
+        .is_some_and(|rest| rest.starts_with(['/', dir.style().separator()]));
+    Some(if echoed {
+        normalized
+    } else {
+        dir.join(name).normalize()
+    })
+}
+
+/// The length of the drive, such as `C:`, at the start of `path`.
+fn drive_len(path: &str) -> usize {
+    let bytes = path.as_bytes();
+    if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        2
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(path: &str) -> String {
+        FtpPath::new(path).normalize().to_string()
+    }
+
+    #[test]
+    fn normalization() {
+        assert_eq!(normalize("/a//b/./c/"), "/a/b/c");
+        assert_eq!(normalize("/a/../../b"), "/b");
+        assert_eq!(normalize("a/../../b"), "../b");
+        assert_eq!(normalize("a/.."), ".");
+        assert_eq!(normalize("/"), "/");
+        assert_eq!(normalize(r"C:\a\..\b\\c"), r"C:\b\c");
+        assert_eq!(normalize("C:/a/./b"), r"C:\a\b");
+    }
+
+    #[test]
+    fn join_and_parent() {
+        let dir = FtpPath::new("/pub");
+        assert_eq!(dir.join("file.txt").as_str(), "/pub/file.txt");
+        assert_eq!(dir.join("/etc").as_str(), "/etc");
+        assert_eq!(FtpPath::new("").join("a").as_str(), "a");
+        assert_eq!(dir.parent().unwrap().as_str(), "/");
+        assert_eq!(FtpPath::new("/").parent(), None);
+        assert_eq!(FtpPath::new("a").parent().unwrap().as_str(), "");
+        let dos = FtpPath::new(r"C:\pub");
+        assert_eq!(dos.join("file.txt").as_str(), r"C:\pub\file.txt");
+        assert_eq!(dos.join(r"D:\x").as_str(), r"D:\x");
+        assert_eq!(dos.parent().unwrap().as_str(), r"C:\");
+        assert_eq!(FtpPath::new(r"C:\").parent(), None);
+    }
+
+    #[test]
+    fn file_name_and_style() {
+        assert_eq!(FtpPath::new("/pub/file.txt/").file_name(), Some("file.txt"));
+        assert_eq!(FtpPath::new("/").file_name(), None);
+        assert_eq!(FtpPath::new(r"C:\").file_name(), None);
+        assert_eq!(FtpPath::new("/pub").style(), PathStyle::Posix);
+        assert_eq!(FtpPath::new(r"pub\file").style(), PathStyle::Dos);
+        assert!(FtpPath::new(r"C:\pub").is_absolute());
+        assert!(!FtpPath::new("C:pub").is_absolute());
+        assert!(!FtpPath::new("pub").is_absolute());
+    }
+
+    #[test]
+    fn numbered() {
+        let numbered = |path: &str| FtpPath::new(path).numbered(2).to_string();
+        assert_eq!(numbered("/pub/report.csv"), "/pub/report.2.csv");
+        assert_eq!(numbered("archive.tar.gz"), "archive.tar.2.gz");
+        assert_eq!(numbered("/home/.profile"), "/home/.profile.2");
+        assert_eq!(numbered("/v1.0/README/"), "/v1.0/README.2");
+        assert_eq!(numbered(r"C:\pub\app.exe"), r"C:\pub\app.2.exe");
+    }
+
+    #[test]
+    fn listed_paths() {
+        let listed = |path: Option<&str>, name| {
+            listed_path(path.map(FtpPath::new).as_ref(), name).map(|path| path.to_string())
+        };
+        let pub_dir = Some("pub");
+        assert_eq!(listed(pub_dir, "a.txt").unwrap(), "pub/a.txt");
+        assert_eq!(listed(pub_dir, "pub/a.txt").unwrap(), "pub/a.txt");
+        assert_eq!(listed(pub_dir, "/srv/pub/a.txt").unwrap(), "/srv/pub/a.txt");
+        assert_eq!(listed(pub_dir, "."), None);
+        assert_eq!(listed(Some("pub/a.txt"), "pub/a.txt").unwrap(), "pub/a.txt");
+        assert_eq!(listed(Some("/pub/"), "a.txt").unwrap(), "/pub/a.txt");
+        assert_eq!(listed(Some("logs/*.log"), "x.log").unwrap(), "logs/x.log");
+        assert_eq!(
+            listed(Some("logs/*.log"), "logs/x.log").unwrap(),
+            "logs/x.log"
+        );
+        assert_eq!(listed(Some("*.log"), "x.log").unwrap(), "x.log");
+        assert_eq!(listed(None, "./a.txt").unwrap(), "a.txt");
+        assert_eq!(listed(Some(r"C:\pub"), "a.txt").unwrap(), r"C:\pub\a.txt");
+        assert_eq!(
+            listed(Some(r"C:\pub"), r"C:\pub\a.txt").unwrap(),
+            r"C:\pub\a.txt"
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! A blocking pool of authenticated connections.
+//!
+//! An `FtpConnectionPool` keeps a fixed number of control connections open so
+//! multi-threaded batch jobs don't pay for a new connection and login per
+//! file. Connections are checked with `NOOP` when they are taken out of the
+//! pool, and broken ones are transparently replaced.
+//!
+//! ```rust,no_run
+//! use ftp::FtpStream;
+//! use ftp::pool::FtpConnectionPool;
+//! use std::thread;
+//!
+//! let pool = FtpConnectionPool::new(4, || {
+//!     let mut ftp_stream = FtpStream::connect("127.0.0.1:21")?;
+//!     ftp_stream.login("anonymous", "anonymous")?;
+//!     Ok(ftp_stream)
+//! })
+//! .unwrap();
+//!
+//! thread::scope(|scope| {
+//!     for i in 0..16 {
+//!         let pool = &pool;
+//!         scope.spawn(move || {
+//!             let mut conn = pool.get().unwrap();
+//!             conn.rm(&format!("file_{}.txt", i))
+//!         });
+//!     }
+//! });
+//! ```
+
+use super::FtpStream;
+
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::{Condvar, Mutex},
+};
+
+/// A fixed-size pool of connections opened by a user supplied closure.
+pub struct FtpConnectionPool<F> {
+    connect: F,
+    size: usize,
+    state: Mutex<PoolState>,
+    released: Condvar,
+}
+
+struct PoolState {
+    idle: Vec<FtpStream>,
+    checked_out: usize,
+}
+
+impl<F> FtpConnectionPool<F>
+where
+    F: Fn() -> crate::Result<FtpStream>,
+{
+    /// Creates a pool of `size` connections, each one opened (and logged in)
+    /// by `connect`. All the connections are opened right away.
+    pub fn new(size: usize, connect: F) -> crate::Result<FtpConnectionPool<F>> {
+        let size = size.max(1);
+        let idle = (0..size)
+            .map(|_| connect())
+            .collect::<crate::Result<Vec<_>>>()?;
+        Ok(FtpConnectionPool {
+            connect,
+            size,
+            state: Mutex::new(PoolState {
+                idle,
+                checked_out: 0,
+            }),
+            released: Condvar::new(),
+        })
+    }
+
+    /// Takes a connection out of the pool, blocking until one is available.
+    ///
+    /// The connection is checked with `NOOP` first and replaced with a new
+    /// one if it doesn't answer. It goes back to the pool when the returned
+    /// guard is dropped.
+    pub fn get(&self) -> crate::Result<PooledConnection<'_, F>> {
+        let idle = {
+            let mut state = self.state.lock().unwrap();
+            while state.idle.is_empty() && state.checked_out >= self.size {
+                state = self.released.wait(state).unwrap();
+            }
+            state.checked_out += 1;
+            state.idle.pop()
+        };
+        let stream = match idle {
+            Some(mut ftp_stream) => match ftp_stream.noop() {
+                Ok(()) => Ok(ftp_stream),
+                Err(_) => (self.connect)(),
+            },
+            None => (self.connect)(),
+        };
+        match stream {
+            Ok(stream) => Ok(PooledConnection {
+                pool: self,
+                stream: Some(stream),
+            }),
+            Err(err) => {
+                self.release(None);
+                Err(err)
+            }
+        }
+    }
+
+    /// Number of connections currently waiting in the pool.
+    pub fn idle(&self) -> usize {
+        self.state.lock().unwrap().idle.len()
+    }
+
+    fn release(&self, stream: Option<FtpStream>) {
+        let mut state = self.state.lock().unwrap();
+        state.checked_out -= 1;
+        if let Some(stream) = stream {
+            state.idle.push(stream);
+        }
+        self.released.notify_one();
+    }
+}
+
+impl<F> fmt::Debug for FtpConnectionPool<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FtpConnectionPool")
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+/// A connection taken out of an `FtpConnectionPool`, returned to it on drop.
+pub struct PooledConnection<'a, F>
+where
+    F: Fn() -> crate::Result<FtpStream>,
+{
+    pool: &'a FtpConnectionPool<F>,
+    stream: Option<FtpStream>,
+}
+
+impl<'a, F> PooledConnection<'a, F>
+where
+    F: Fn() -> crate::Result<FtpStream>,
+{
+    /// Closes the connection instead of returning it to the pool, e.g. after
+    /// changing its state in a way the next user wouldn't expect. The pool
+    /// opens a new connection in its place when needed.
+    pub fn discard(mut self) {
+        if let Some(mut stream) = self.stream.take() {
+            let _ = stream.quit();
+        }
+        self.pool.release(None);
+    }
+}
+
+impl<'a, F> Deref for PooledConnection<'a, F>
+where
+    F: Fn() -> crate::Result<FtpStream>,
+{
+    type Target = FtpStream;
+
+    fn deref(&self) -> &FtpStream {
+        self.stream.as_ref().unwrap()
+    }
+}
+
+impl<'a, F> DerefMut for PooledConnection<'a, F>
+where
+    F: Fn() -> crate::Result<FtpStream>,
+{
+    fn deref_mut(&mut self) -> &mut FtpStream {
+        self.stream.as_mut().unwrap()
+    }
+}
+
+impl<'a, F> Drop for PooledConnection<'a, F>
+where
+    F: Fn() -> crate::Result<FtpStream>,
+{
+    fn drop(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            // A connection closed by the server isn't worth keeping.
+            let stream = if stream.is_closed() {
+                None
+            } else {
+                Some(stream)
+            };
+            self.pool.release(stream);
+        }
+    }
+}
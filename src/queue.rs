@@ -0,0 +1,277 @@
+//! Concurrent execution of upload and download batches.
+//!
+//! A `TransferQueue` spreads its jobs over several control connections, each
+//! opened by a user supplied closure, so a bulk migration isn't serialized on
+//! a single control channel.
+//!
+//! ```rust,no_run
+//! use ftp::FtpStream;
+//! use ftp::queue::{TransferJob, TransferQueue};
+//!
+//! let mut queue = TransferQueue::new(4, || {
+//!     let mut ftp_stream = FtpStream::connect("127.0.0.1:21")?;
+//!     ftp_stream.login("anonymous", "anonymous")?;
+//!     Ok(ftp_stream)
+//! });
+//! queue.push(TransferJob::upload("report.csv", "upload/report.csv"));
+//! queue.push(TransferJob::download("upload/report.csv", "report.copy.csv"));
+//! let report = queue.run();
+//! println!("{} of {} jobs done", report.succeeded(), report.len());
+//! ```
+
+use super::{
+    types::{FileType, FtpError},
+    FtpStream,
+};
+
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{self, copy, Read, Write},
+    path::PathBuf,
+    sync::Mutex,
+    thread,
+};
+
+/// A single transfer handled by a `TransferQueue`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferJob {
+    /// Store the local file `local` on the server as `remote`.
+    Upload { local: PathBuf, remote: String },
+    /// Retrieve the remote file `remote` into the local file `local`.
+    Download { remote: String, local: PathBuf },
+}
+
+impl TransferJob {
+    /// Creates a job uploading `local` to `remote`.
+    pub fn upload<P: Into<PathBuf>, S: Into<String>>(local: P, remote: S) -> TransferJob {
+        TransferJob::Upload {
+            local: local.into(),
+            remote: remote.into(),
+        }
+    }
+
+    /// Creates a job downloading `remote` to `local`.
+    pub fn download<S: Into<String>, P: Into<PathBuf>>(remote: S, local: P) -> TransferJob {
+        TransferJob::Download {
+            remote: remote.into(),
+            local: local.into(),
+        }
+    }
+
+    fn execute(&self, ftp_stream: &mut FtpStream, progress: &dyn Fn(u64)) -> crate::Result<u64> {
+        match *self {
+            TransferJob::Upload {
+                ref local,
+                ref remote,
+            } => {
+                let mut reader = Progress::new(File::open(local)?, progress);
+                ftp_stream.put(remote, &mut reader)?;
+                Ok(reader.bytes)
+            }
+            TransferJob::Download {
+                ref remote,
+                ref local,
+            } => {
+                let file = File::create(local)?;
+                let res = ftp_stream.retr(remote, |stream| {
+                    let mut writer = Progress::new(&file, progress);
+                    copy(stream, &mut writer).map_err(FtpError::ConnectionError)
+                });
+                if res.is_err() {
+                    // Don't leave a truncated file behind.
+                    let _ = fs::remove_file(local);
+                }
+                res
+            }
+        }
+    }
+}
+
+/// The outcome of a single job.
+#[derive(Debug)]
+pub struct JobResult {
+    /// The job as it was pushed to the queue.
+    pub job: TransferJob,
+    /// The number of bytes transferred, or the error which stopped the job.
+    pub result: crate::Result<u64>,
+}
+
+/// The aggregate outcome of `TransferQueue::run`, with one result per job in
+/// the order the jobs were pushed.
+#[derive(Debug)]
+pub struct TransferReport {
+    pub results: Vec<JobResult>,
+}
+
+impl TransferReport {
+    /// Number of jobs in the report.
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+
+    /// Returns `true` if the queue was run without any job.
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+
+    /// Number of jobs which completed successfully.
+    pub fn succeeded(&self) -> usize {
+        self.results.iter().filter(|r| r.result.is_ok()).count()
+    }
+
+    /// Iterator over the jobs which failed.
+    pub fn failures(&self) -> impl Iterator<Item = &JobResult> {
+        self.results.iter().filter(|r| r.result.is_err())
+    }
+
+    /// Total number of bytes transferred by successful jobs.
+    pub fn total_bytes(&self) -> u64 {
+        self.results
+            .iter()
+            .filter_map(|r| r.result.as_ref().ok())
+            .sum()
+    }
+}
+
+type ProgressFn<'a> = Box<dyn Fn(usize, u64) + Send + Sync + 'a>;
+
+/// Runs a batch of transfers across up to `workers` connections.
+pub struct TransferQueue<'a, F> {
+    connect: F,
+    workers: usize,
+    jobs: Vec<TransferJob>,
+    progress: Option<ProgressFn<'a>>,
+}
+
+impl<'a, F> TransferQueue<'a, F>
+where
+    F: Fn() -> crate::Result<FtpStream> + Sync,
+{
+    /// Creates an empty queue which will use at most `workers` connections,
+    /// each one opened (and logged in) by `connect`.
+    pub fn new(workers: usize, connect: F) -> TransferQueue<'a, F> {
+        TransferQueue {
+            connect,
+            workers: workers.max(1),
+            jobs: Vec::new(),
+            progress: None,
+        }
+    }
+
+    /// Adds a job to the queue.
+    pub fn push(&mut self, job: TransferJob) -> &mut Self {
+        self.jobs.push(job);
+        self
+    }
+
+    /// Sets a callback invoked with the index of a job and the total number
+    /// of bytes it has transferred so far. It is called from the worker threads.
+    pub fn on_progress<P>(&mut self, progress: P) -> &mut Self
+    where
+        P: Fn(usize, u64) + Send + Sync + 'a,
+    {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Executes every job and waits for all of them to finish.
+    ///
+    /// A connection is reused for the next job of the same worker unless the
+    /// previous job failed, in which case a fresh connection is opened.
+    pub fn run(self) -> TransferReport {
+        let TransferQueue {
+            connect,
+            workers,
+            jobs,
+            progress,
+        } = self;
+        let count = jobs.len();
+        let pending = Mutex::new(jobs.into_iter().enumerate().collect::<VecDeque<_>>());
+        let done = Mutex::new(Vec::with_capacity(count));
+
+        thread::scope(|scope| {
+            for _ in 0..workers.min(count) {
+                scope.spawn(|| {
+                    let mut conn: Option<FtpStream> = None;
+                    loop {
+                        let (index, job) = match pending.lock().unwrap().pop_front() {
+                            Some(next) => next,
+                            None => break,
+                        };
+                        let report = |bytes| {
+                            if let Some(ref progress) = progress {
+                                progress(index, bytes)
+                            }
+                        };
+                        let result = match conn.take() {
+                            Some(ftp_stream) => Ok(ftp_stream),
+                            None => connect().and_then(|mut ftp_stream| {
+                                ftp_stream.transfer_type(FileType::Binary)?;
+                                Ok(ftp_stream)
+                            }),
+                        }
+                        .and_then(|mut ftp_stream| {
+                            let res = job.execute(&mut ftp_stream, &report);
+                            if res.is_ok() {
+                                conn = Some(ftp_stream);
+                            }
+                            res
+                        });
+                        done.lock()
+                            .unwrap()
+                            .push((index, JobResult { job, result }));
+                    }
+                    if let Some(mut ftp_stream) = conn {
+                        let _ = ftp_stream.quit();
+                    }
+                });
+            }
+        });
+
+        let mut results = done.into_inner().unwrap();
+        results.sort_by_key(|&(index, _)| index);
+        TransferReport {
+            results: results.into_iter().map(|(_, r)| r).collect(),
+        }
+    }
+}
+
+/// Counts the bytes flowing through a reader or writer.
+struct Progress<'a, T> {
+    inner: T,
+    bytes: u64,
+    callback: &'a dyn Fn(u64),
+}
+
+impl<'a, T> Progress<'a, T> {
+    fn new(inner: T, callback: &'a dyn Fn(u64)) -> Progress<'a, T> {
+        Progress {
+            inner,
+            bytes: 0,
+            callback,
+        }
+    }
+}
+
+impl<'a, T: Read> Read for Progress<'a, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes += n as u64;
+        (self.callback)(self.bytes);
+        Ok(n)
+    }
+}
+
+impl<'a, T: Write> Write for Progress<'a, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.bytes += n as u64;
+        (self.callback)(self.bytes);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,109 @@
+//! A control connection shared between threads.
+//!
+//! A `SharedFtpStream` can be cloned and sent to other threads, all the
+//! clones using the same control connection. Commands are serialized: a
+//! thread waits for the others' commands to complete before running its
+//! own. The connection reconnects and restores its session by itself when
+//! the server closes it with `421 Service not available`, and can be kept
+//! alive from a background thread with `start_keepalive`.
+//!
+//! ```rust,no_run
+//! use ftp::shared::SharedFtpStream;
+//! use ftp::FtpStream;
+//! use std::thread;
+//!
+//! let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
+//! ftp_stream.login("anonymous", "anonymous").unwrap();
+//! let shared = SharedFtpStream::new(ftp_stream);
+//! let handles: Vec<_> = (0..4)
+//!     .map(|i| {
+//!         let shared = shared.clone();
+//!         thread::spawn(move || shared.with(|ftp_stream| ftp_stream.mkdir(format!("dir_{}", i))))
+//!     })
+//!     .collect();
+//! for handle in handles {
+//!     handle.join().unwrap().unwrap();
+//! }
+//! ```
+
+use super::FtpStream;
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+/// A cloneable handle to a control connection, see the module documentation.
+#[derive(Clone)]
+pub struct SharedFtpStream {
+    inner: Arc<Mutex<FtpStream>>,
+}
+
+impl SharedFtpStream {
+    /// Shares `ftp_stream`, enabling its automatic reconnection. Wrap it
+    /// right after `login` so the session can be restored, see
+    /// `FtpStream::set_auto_reconnect`.
+    pub fn new(mut ftp_stream: FtpStream) -> SharedFtpStream {
+        ftp_stream.set_auto_reconnect(true);
+        SharedFtpStream {
+            inner: Arc::new(Mutex::new(ftp_stream)),
+        }
+    }
+
+    /// Runs `op` with exclusive use of the connection.
+    pub fn with<T, F>(&self, op: F) -> T
+    where
+        F: FnOnce(&mut FtpStream) -> T,
+    {
+        op(&mut self.lock())
+    }
+
+    /// Locks the connection for a sequence of commands which must not be
+    /// interleaved with other threads' ones, e.g. `cwd` then `list`. The
+    /// other threads wait until the guard is dropped.
+    pub fn lock(&self) -> MutexGuard<'_, FtpStream> {
+        self.inner.lock().unwrap()
+    }
+
+    /// Sends `NOOP` from a background thread whenever the connection has been
+    /// idle for `interval`, see `FtpStream::keepalive`. The thread stops once
+    /// every handle to the connection is dropped or unwrapped.
+    pub fn start_keepalive(&self, interval: Duration) {
+        self.lock().set_keepalive_interval(Some(interval));
+        let inner = Arc::downgrade(&self.inner);
+        // Check often enough that the connection is never idle for much
+        // longer than the interval.
+        let period = (interval / 4).max(Duration::from_millis(10));
+        thread::spawn(move || loop {
+            thread::sleep(period);
+            let inner = match inner.upgrade() {
+                Some(inner) => inner,
+                None => return,
+            };
+            let mut ftp_stream = match inner.lock() {
+                Ok(ftp_stream) => ftp_stream,
+                Err(_) => return,
+            };
+            // Errors are left for the next command to notice.
+            let _ = ftp_stream.keepalive();
+        });
+    }
+
+    /// Returns the connection if this is the last handle to it.
+    pub fn try_unwrap(self) -> Result<FtpStream, SharedFtpStream> {
+        match Arc::try_unwrap(self.inner) {
+            Ok(mutex) => Ok(mutex.into_inner().unwrap()),
+            Err(inner) => Err(SharedFtpStream { inner }),
+        }
+    }
+}
+
+impl fmt::Debug for SharedFtpStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SharedFtpStream")
+            .field("handles", &Arc::strong_count(&self.inner))
+            .finish()
+    }
+}
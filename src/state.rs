@@ -0,0 +1,21 @@
+//! Files recording the progress of long operations, such as the
+//! checkpoints of `checkpoint` and the state of `sync::mirror_resumable`.
+
+use std::{fs, io, path::Path};
+
+/// Reads the file at `path`, or returns `None` if there is no such file.
+pub fn load(path: &Path) -> io::Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Writes `data` to `path` through a temporary file renamed over it, so an
+/// interruption never leaves a truncated file behind.
+pub fn save(path: &Path, data: &str) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, data)?;
+    fs::rename(tmp, path)
+}
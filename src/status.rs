@@ -1,56 +1,319 @@
-// 1xx: Positive Preliminary Reply
-pub const INITIATING: u32 = 100;
-pub const RESTART_MARKER: u32 = 110;
-pub const READY_MINUTE: u32 = 120;
-pub const ALREADY_OPEN: u32 = 125;
-pub const ABOUT_TO_SEND: u32 = 150;
-
-// 2xx: Positive Completion Reply
-pub const COMMAND_OK: u32 = 200;
-pub const COMMAND_NOT_IMPLEMENTED: u32 = 202;
-pub const SYSTEM: u32 = 211;
-pub const DIRECTORY: u32 = 212;
-pub const FILE: u32 = 213;
-pub const HELP: u32 = 214;
-pub const NAME: u32 = 215;
-pub const READY: u32 = 220;
-pub const CLOSING: u32 = 221;
-pub const DATA_CONNECTION_OPEN: u32 = 225;
-pub const CLOSING_DATA_CONNECTION: u32 = 226;
-pub const PASSIVE_MODE: u32 = 227;
-pub const LONG_PASSIVE_MODE: u32 = 228;
-pub const EXTENDED_PASSIVE_MODE: u32 = 229;
-pub const LOGGED_IN: u32 = 230;
-pub const LOGGED_OUT: u32 = 231;
-pub const LOGOUT_ACK: u32 = 232;
-pub const AUTH_OK: u32 = 234;
-pub const REQUESTED_FILE_ACTION_OK: u32 = 250;
-pub const PATH_CREATED: u32 = 257;
-
-// 3xx: Positive intermediate Reply
-pub const NEED_PASSWORD: u32 = 331;
-pub const LOGIN_NEED_ACCOUNT: u32 = 332;
-pub const REQUEST_FILE_PENDING: u32 = 350;
-
-// 4xx: Transient Negative Completion Reply
-pub const NOT_AVAILABLE: u32 = 421;
-pub const CANNOT_OPEN_DATA_CONNECTION: u32 = 425;
-pub const TRANSER_ABORTED: u32 = 426;
-pub const INVALID_CREDENTIALS: u32 = 430;
-pub const HOST_UNAVAILABLE: u32 = 434;
-pub const REQUEST_FILE_ACTION_IGNORED: u32 = 450;
-pub const ACTION_ABORTED: u32 = 451;
-pub const REQUESTED_ACTION_NOT_TAKEN: u32 = 452;
-
-// 5xx: Permanent Negative Completion Reply
-pub const BAD_COMMAND: u32 = 500;
-pub const BAD_ARGUMENTS: u32 = 501;
-pub const NOT_IMPLEMENTED: u32 = 502;
-pub const BAD_SEQUENCE: u32 = 503;
-pub const NOT_IMPLEMENTED_PARAMETER: u32 = 504;
-pub const NOT_LOGGED_IN: u32 = 530;
-pub const STORING_NEED_ACCOUNT: u32 = 532;
-pub const FILE_UNAVAILABLE: u32 = 550;
-pub const PAGE_TYPE_UNKNOWN: u32 = 551;
-pub const EXCEEDED_STORAGE: u32 = 552;
-pub const BAD_FILENAME: u32 = 553;
+//! FTP reply codes.
+//!
+//! Every reply read from the server carries a `Status`. The named variants
+//! cover the codes of RFC 959 and its extensions; any other code is kept as
+//! `Status::Other` so no information is lost.
+//!
+//! ```rust
+//! use ftp::status::Status;
+//!
+//! assert_eq!(Status::from(550), Status::FileUnavailable);
+//! assert_eq!(u32::from(Status::PathCreated), 257);
+//! assert_eq!(Status::from(299), Status::Other(299));
+//! ```
+//!
+//! The first digit of a code tells its class, whatever the code: the
+//! functions and ranges below check it on raw codes, and the methods of
+//! `Status` on replies.
+//!
+//! ```rust
+//! use ftp::status::{self, Status};
+//!
+//! assert!(status::is_positive_completion(250));
+//! assert!(status::TRANSIENT_NEGATIVE.contains(&421));
+//! assert!(Status::from(550).is_permanent_negative());
+//! ```
+
+use std::{fmt, ops::RangeInclusive};
+
+/// 1xx: the command was accepted and another reply will follow.
+pub const POSITIVE_PRELIMINARY: RangeInclusive<u32> = 100..=199;
+/// 2xx: the command completed.
+pub const POSITIVE_COMPLETION: RangeInclusive<u32> = 200..=299;
+/// 3xx: the command was accepted and waits for another one, e.g. `PASS`.
+pub const POSITIVE_INTERMEDIATE: RangeInclusive<u32> = 300..=399;
+/// 4xx: the command failed but may succeed if sent again later.
+pub const TRANSIENT_NEGATIVE: RangeInclusive<u32> = 400..=499;
+/// 5xx: the command failed and will fail again unless it is changed.
+pub const PERMANENT_NEGATIVE: RangeInclusive<u32> = 500..=599;
+/// The codes of the five classes, 1xx first. RFC 959 allows no others.
+pub const REPLY_CLASSES: [RangeInclusive<u32>; 5] = [
+    POSITIVE_PRELIMINARY,
+    POSITIVE_COMPLETION,
+    POSITIVE_INTERMEDIATE,
+    TRANSIENT_NEGATIVE,
+    PERMANENT_NEGATIVE,
+];
+
+/// A class of replies, told by the first digit of their code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResponseClass {
+    /// 1xx, see `POSITIVE_PRELIMINARY`.
+    PositivePreliminary,
+    /// 2xx, see `POSITIVE_COMPLETION`.
+    PositiveCompletion,
+    /// 3xx, see `POSITIVE_INTERMEDIATE`.
+    PositiveIntermediate,
+    /// 4xx, see `TRANSIENT_NEGATIVE`.
+    TransientNegative,
+    /// 5xx, see `PERMANENT_NEGATIVE`.
+    PermanentNegative,
+}
+
+impl ResponseClass {
+    /// The codes of the class.
+    pub fn codes(self) -> RangeInclusive<u32> {
+        match self {
+            ResponseClass::PositivePreliminary => POSITIVE_PRELIMINARY,
+            ResponseClass::PositiveCompletion => POSITIVE_COMPLETION,
+            ResponseClass::PositiveIntermediate => POSITIVE_INTERMEDIATE,
+            ResponseClass::TransientNegative => TRANSIENT_NEGATIVE,
+            ResponseClass::PermanentNegative => PERMANENT_NEGATIVE,
+        }
+    }
+
+    /// Returns `true` if `status` belongs to the class.
+    pub fn contains(self, status: Status) -> bool {
+        self.codes().contains(&status.code())
+    }
+}
+
+/// Returns `true` if `code` is a 1xx code.
+pub fn is_positive_preliminary(code: u32) -> bool {
+    POSITIVE_PRELIMINARY.contains(&code)
+}
+
+/// Returns `true` if `code` is a 2xx code.
+pub fn is_positive_completion(code: u32) -> bool {
+    POSITIVE_COMPLETION.contains(&code)
+}
+
+/// Returns `true` if `code` is a 3xx code.
+pub fn is_positive_intermediate(code: u32) -> bool {
+    POSITIVE_INTERMEDIATE.contains(&code)
+}
+
+/// Returns `true` if `code` is a 4xx code.
+pub fn is_transient_negative(code: u32) -> bool {
+    TRANSIENT_NEGATIVE.contains(&code)
+}
+
+/// Returns `true` if `code` is a 5xx code.
+pub fn is_permanent_negative(code: u32) -> bool {
+    PERMANENT_NEGATIVE.contains(&code)
+}
+
+/// The class of `code`, from 1 for 1xx codes to 5 for 5xx codes, or `None`
+/// if it is not a reply code.
+pub fn class(code: u32) -> Option<u32> {
+    REPLY_CLASSES
+        .iter()
+        .position(|class| class.contains(&code))
+        .map(|class| class as u32 + 1)
+}
+
+macro_rules! statuses {
+    ($($(#[$doc:meta])* $name:ident = $code:expr,)*) => {
+        /// A reply code sent by the server.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[non_exhaustive]
+        pub enum Status {
+            $($(#[$doc])* $name,)*
+            /// A code without a named variant. Statuses are built from codes
+            /// with `Status::from`, which only uses `Other` for such codes:
+            /// an `Other` holding a named code isn't equal to its variant.
+            Other(u32),
+        }
+
+        impl From<u32> for Status {
+            fn from(code: u32) -> Status {
+                match code {
+                    $($code => Status::$name,)*
+                    code => Status::Other(code),
+                }
+            }
+        }
+
+        impl From<Status> for u32 {
+            fn from(status: Status) -> u32 {
+                match status {
+                    $(Status::$name => $code,)*
+                    Status::Other(code) => code,
+                }
+            }
+        }
+    };
+}
+
+statuses! {
+    // 1xx: Positive Preliminary Reply
+    /// 100: Initiating.
+    Initiating = 100,
+    /// 110: Restart marker reply.
+    RestartMarker = 110,
+    /// 120: Service ready in a few minutes.
+    ReadyMinute = 120,
+    /// 125: Data connection already open, transfer starting.
+    AlreadyOpen = 125,
+    /// 150: File status okay, about to open data connection.
+    AboutToSend = 150,
+
+    // 2xx: Positive Completion Reply
+    /// 200: Command okay.
+    CommandOk = 200,
+    /// 202: Command not implemented, superfluous at this site.
+    CommandNotImplemented = 202,
+    /// 211: System status, or system help reply.
+    System = 211,
+    /// 212: Directory status.
+    Directory = 212,
+    /// 213: File status.
+    File = 213,
+    /// 214: Help message.
+    Help = 214,
+    /// 215: NAME system type.
+    Name = 215,
+    /// 220: Service ready for new user.
+    Ready = 220,
+    /// 221: Service closing control connection.
+    Closing = 221,
+    /// 225: Data connection open, no transfer in progress.
+    DataConnectionOpen = 225,
+    /// 226: Closing data connection, requested file action successful.
+    ClosingDataConnection = 226,
+    /// 227: Entering passive mode.
+    PassiveMode = 227,
+    /// 228: Entering long passive mode.
+    LongPassiveMode = 228,
+    /// 229: Entering extended passive mode.
+    ExtendedPassiveMode = 229,
+    /// 230: User logged in, proceed.
+    LoggedIn = 230,
+    /// 231: User logged out, service terminated.
+    LoggedOut = 231,
+    /// 232: Logout command noted, will complete when transfer done.
+    LogoutAck = 232,
+    /// 234: Security mechanism accepted.
+    AuthOk = 234,
+    /// 250: Requested file action okay, completed.
+    RequestedFileActionOk = 250,
+    /// 257: Pathname created.
+    PathCreated = 257,
+
+    // 3xx: Positive intermediate Reply
+    /// 331: User name okay, need password.
+    NeedPassword = 331,
+    /// 332: Need account for login.
+    LoginNeedAccount = 332,
+    /// 350: Requested file action pending further information.
+    RequestFilePending = 350,
+
+    // 4xx: Transient Negative Completion Reply
+    /// 421: Service not available, closing control connection.
+    NotAvailable = 421,
+    /// 425: Can't open data connection.
+    CannotOpenDataConnection = 425,
+    /// 426: Connection closed, transfer aborted.
+    TransferAborted = 426,
+    /// 430: Invalid username or password.
+    InvalidCredentials = 430,
+    /// 434: Requested host unavailable.
+    HostUnavailable = 434,
+    /// 450: Requested file action not taken.
+    RequestFileActionIgnored = 450,
+    /// 451: Requested action aborted, local error in processing.
+    ActionAborted = 451,
+    /// 452: Requested action not taken, insufficient storage space.
+    RequestedActionNotTaken = 452,
+
+    // 5xx: Permanent Negative Completion Reply
+    /// 500: Syntax error, command unrecognized.
+    BadCommand = 500,
+    /// 501: Syntax error in parameters or arguments.
+    BadArguments = 501,
+    /// 502: Command not implemented.
+    NotImplemented = 502,
+    /// 503: Bad sequence of commands.
+    BadSequence = 503,
+    /// 504: Command not implemented for that parameter.
+    NotImplementedParameter = 504,
+    /// 530: Not logged in.
+    NotLoggedIn = 530,
+    /// 532: Need account for storing files.
+    StoringNeedAccount = 532,
+    /// 550: Requested action not taken, file unavailable.
+    FileUnavailable = 550,
+    /// 551: Requested action aborted, page type unknown.
+    PageTypeUnknown = 551,
+    /// 552: Requested file action aborted, exceeded storage allocation.
+    ExceededStorage = 552,
+    /// 553: Requested action not taken, file name not allowed.
+    BadFilename = 553,
+}
+
+impl Status {
+    /// The numeric reply code.
+    pub fn code(self) -> u32 {
+        self.into()
+    }
+
+    /// Returns `true` for 1xx replies, which are followed by another reply.
+    pub fn is_preliminary(self) -> bool {
+        is_positive_preliminary(self.code())
+    }
+
+    /// Returns `true` for 2xx replies: the command completed.
+    pub fn is_positive_completion(self) -> bool {
+        is_positive_completion(self.code())
+    }
+
+    /// Returns `true` for 3xx replies: the command waits for another one.
+    pub fn is_positive_intermediate(self) -> bool {
+        is_positive_intermediate(self.code())
+    }
+
+    /// Returns `true` for 4xx replies: the command failed but may succeed
+    /// if sent again later.
+    pub fn is_transient_negative(self) -> bool {
+        is_transient_negative(self.code())
+    }
+
+    /// Returns `true` for 5xx replies: the command failed and will fail
+    /// again unless it is changed.
+    pub fn is_permanent_negative(self) -> bool {
+        is_permanent_negative(self.code())
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classes() {
+        assert_eq!(class(99), None);
+        assert_eq!(class(100), Some(1));
+        assert_eq!(class(257), Some(2));
+        assert_eq!(class(350), Some(3));
+        assert_eq!(class(421), Some(4));
+        assert_eq!(class(599), Some(5));
+        assert_eq!(class(600), None);
+        assert!(is_positive_preliminary(150) && !is_positive_preliminary(200));
+        assert!(is_positive_completion(299) && !is_positive_completion(300));
+        assert!(is_positive_intermediate(331) && !is_positive_intermediate(230));
+        assert!(is_transient_negative(450) && !is_transient_negative(550));
+        assert!(is_permanent_negative(502) && !is_permanent_negative(402));
+        assert!(Status::AboutToSend.is_preliminary());
+        assert!(Status::from(299).is_positive_completion());
+        assert!(Status::NeedPassword.is_positive_intermediate());
+        assert!(ResponseClass::PositiveCompletion.contains(Status::RequestedFileActionOk));
+        assert!(!ResponseClass::PositiveCompletion.contains(Status::AboutToSend));
+        assert!(ResponseClass::PermanentNegative.contains(Status::Other(599)));
+    }
+}
@@ -0,0 +1,365 @@
+//! Mirroring a local directory to the server.
+//!
+//! `mirror` compares the files of a local directory with those of a remote
+//! one, as `FtpStream::diff` does, and uploads the new and changed files.
+//! `SyncOptions` can restrict the files considered, delete remote files
+//! which no longer exist locally, preserve modification times, or only plan
+//! the actions without executing them. `mirror_resumable`, with the `json`
+//! feature, also records its progress in a state file, so an interrupted
+//! mirror resumes where it left off.
+//!
+//! ```rust,no_run
+//! use ftp::sync::{self, SyncOptions};
+//! use ftp::FtpStream;
+//! use std::path::Path;
+//!
+//! let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
+//! ftp_stream.login("anonymous", "anonymous").unwrap();
+//! let options = SyncOptions {
+//!     dry_run: true,
+//!     delete: true,
+//!     exclude: vec!["*.tmp".to_owned()],
+//!     ..SyncOptions::default()
+//! };
+//! for action in sync::mirror(&mut ftp_stream, Path::new("site"), "/www", &options).unwrap() {
+//!     println!("would {:?}", action);
+//! }
+//! ```
+
+use super::{transport::Transport, types::DiffEntry, GenericFtpStream};
+
+#[cfg(feature = "json")]
+use {
+    super::state,
+    serde_json::{json, Value},
+    std::{
+        fs,
+        io::{self, Seek, SeekFrom},
+    },
+};
+
+use {
+    chrono::{DateTime, Utc},
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        fs::File,
+        path::Path,
+    },
+};
+
+/// Options of `mirror`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SyncOptions {
+    /// Only report the actions which would be taken.
+    pub dry_run: bool,
+    /// Delete remote files which don't exist locally.
+    pub delete: bool,
+    /// Set the modification time of uploaded files to the local one with
+    /// `MFMT`, so they compare equal the next time.
+    pub preserve_times: bool,
+    /// Only consider files whose name matches one of these glob patterns
+    /// (`*` and `?` wildcards). Empty means every file.
+    pub include: Vec<String>,
+    /// Ignore files whose name matches one of these glob patterns.
+    pub exclude: Vec<String>,
+}
+
+impl SyncOptions {
+    /// Whether the file `name` is considered with these options.
+    pub fn matches(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| glob_match(p, name)))
+            && !self.exclude.iter().any(|p| glob_match(p, name))
+    }
+}
+
+/// An action taken, or planned in a dry run, by `mirror`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncAction {
+    /// Upload the local file, new or changed.
+    Upload(String),
+    /// Delete the remote file, which doesn't exist locally.
+    Delete(String),
+    /// Set the modification time of the uploaded file.
+    SetModified(String, DateTime<Utc>),
+}
+
+impl SyncAction {
+    /// The name of the file the action applies to.
+    pub fn name(&self) -> &str {
+        match *self {
+            SyncAction::Upload(ref name)
+            | SyncAction::Delete(ref name)
+            | SyncAction::SetModified(ref name, _) => name,
+        }
+    }
+}
+
+/// The progress of `mirror_resumable`, saved as JSON.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SyncState {
+    /// The files whose actions all completed.
+    pub completed: BTreeSet<String>,
+    /// The files whose upload was interrupted, with the number of bytes sent.
+    pub partial: BTreeMap<String, u64>,
+}
+
+#[cfg(feature = "json")]
+impl SyncState {
+    /// Reads the state saved at `path`, or returns an empty state if there
+    /// is no such file.
+    pub fn load(path: &Path) -> io::Result<SyncState> {
+        let data = match state::load(path)? {
+            Some(data) => data,
+            None => return Ok(SyncState::default()),
+        };
+        let value: Value = serde_json::from_str(&data)?;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid sync state");
+        let mut state = SyncState::default();
+        for name in value["completed"].as_array().ok_or_else(invalid)? {
+            state
+                .completed
+                .insert(name.as_str().ok_or_else(invalid)?.to_owned());
+        }
+        for (name, offset) in value["partial"].as_object().ok_or_else(invalid)? {
+            state
+                .partial
+                .insert(name.clone(), offset.as_u64().ok_or_else(invalid)?);
+        }
+        Ok(state)
+    }
+
+    /// Saves the state to `path`, replacing it atomically.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let value = json!({
+            "completed": self.completed,
+            "partial": self.partial,
+        });
+        state::save(path, &value.to_string())
+    }
+}
+
+/// Makes the files of the remote directory `remote` match those of the
+/// local directory `local`, returning the actions taken in order. Like
+/// `FtpStream::diff`, subdirectories are not mirrored.
+pub fn mirror<S: Transport>(
+    ftp_stream: &mut GenericFtpStream<S>,
+    local: &Path,
+    remote: &str,
+    options: &SyncOptions,
+) -> crate::Result<Vec<SyncAction>> {
+    let actions = plan(ftp_stream, local, remote, options)?;
+    if !options.dry_run {
+        for action in &actions {
+            execute(ftp_stream, local, remote, action)?;
+        }
+    }
+    Ok(actions)
+}
+
+/// Same as `mirror`, saving its progress to the file `state_path` after
+/// every file. When the state file exists, the files it records as
+/// completed are skipped and interrupted uploads are resumed with `APPE`
+/// from where the server's copy ends. The state file is removed once the
+/// mirror completes.
+#[cfg(feature = "json")]
+pub fn mirror_resumable<S: Transport>(
+    ftp_stream: &mut GenericFtpStream<S>,
+    local: &Path,
+    remote: &str,
+    options: &SyncOptions,
+    state_path: &Path,
+) -> crate::Result<Vec<SyncAction>> {
+    let mut state = SyncState::load(state_path)?;
+    let actions: Vec<_> = plan(ftp_stream, local, remote, options)?
+        .into_iter()
+        .filter(|action| !state.completed.contains(action.name()))
+        .collect();
+    if options.dry_run {
+        return Ok(actions);
+    }
+    for (i, action) in actions.iter().enumerate() {
+        let name = action.name();
+        match *action {
+            SyncAction::Upload(_) => {
+                let offset = state.partial.get(name).cloned().unwrap_or(0);
+                if let Err((err, sent)) = resume_upload(ftp_stream, local, remote, name, offset) {
+                    if sent > 0 {
+                        state.partial.insert(name.to_owned(), sent);
+                        state.save(state_path)?;
+                    }
+                    return Err(err);
+                }
+            }
+            _ => execute(ftp_stream, local, remote, action)?,
+        }
+        if actions.get(i + 1).is_none_or(|next| next.name() != name) {
+            state.partial.remove(name);
+            state.completed.insert(name.to_owned());
+            state.save(state_path)?;
+        }
+    }
+    fs::remove_file(state_path)?;
+    Ok(actions)
+}
+
+/// Uploads `name`, appending to the server's copy when a previous upload
+/// sent `offset` bytes of it. On failure, returns the number of bytes of
+/// the file sent so far.
+#[cfg(feature = "json")]
+fn resume_upload<S: Transport>(
+    ftp_stream: &mut GenericFtpStream<S>,
+    local: &Path,
+    remote: &str,
+    name: &str,
+    offset: u64,
+) -> Result<(), (crate::FtpError, u64)> {
+    let remote_path = remote_path(remote, name);
+    let mut file = File::open(local.join(name)).map_err(|err| (err.into(), 0))?;
+    // The server may not have received every byte sent, so only its copy
+    // tells where to resume.
+    let start = if offset > 0 {
+        match ftp_stream.size(&remote_path) {
+            Ok(Some(size)) => (size as u64).min(offset),
+            _ => 0,
+        }
+    } else {
+        0
+    };
+    let res = if start > 0 {
+        file.seek(SeekFrom::Start(start))
+            .map_err(Into::into)
+            .and_then(|_| ftp_stream.append(&remote_path, &mut file))
+    } else {
+        ftp_stream.put(&remote_path, &mut file)
+    };
+    res.map_err(|err| (err, file.stream_position().unwrap_or(start)))
+}
+
+fn plan<S: Transport>(
+    ftp_stream: &mut GenericFtpStream<S>,
+    local: &Path,
+    remote: &str,
+    options: &SyncOptions,
+) -> crate::Result<Vec<SyncAction>> {
+    let mut actions = Vec::new();
+    for entry in ftp_stream.diff(local, remote)? {
+        if !options.matches(entry.name()) {
+            continue;
+        }
+        match entry {
+            DiffEntry::OnlyLocal(name) | DiffEntry::Differs { name, .. } => {
+                actions.push(SyncAction::Upload(name.clone()));
+                // Servers which told `feat` they lack MFMT can't set times.
+                let mfmt = ftp_stream.features().is_none() || ftp_stream.supports("MFMT");
+                if options.preserve_times && mfmt {
+                    if let Ok(modified) = local.join(&name).metadata()?.modified() {
+                        actions.push(SyncAction::SetModified(name, modified.into()));
+                    }
+                }
+            }
+            DiffEntry::OnlyRemote(name) => {
+                if options.delete {
+                    actions.push(SyncAction::Delete(name));
+                }
+            }
+        }
+    }
+    Ok(actions)
+}
+
+fn execute<S: Transport>(
+    ftp_stream: &mut GenericFtpStream<S>,
+    local: &Path,
+    remote: &str,
+    action: &SyncAction,
+) -> crate::Result<()> {
+    match *action {
+        SyncAction::Upload(ref name) => ftp_stream.put(
+            &remote_path(remote, name),
+            &mut File::open(local.join(name))?,
+        ),
+        SyncAction::Delete(ref name) => ftp_stream.rm(&remote_path(remote, name)),
+        SyncAction::SetModified(ref name, time) => {
+            ftp_stream.mfmt(&remote_path(remote, name), time)
+        }
+    }
+}
+
+fn remote_path(remote: &str, name: &str) -> String {
+    format!("{}/{}", remote.trim_end_matches('/'), name)
+}
+
+/// Matches `name` against `pattern`, where `*` matches any run of
+/// characters and `?` any single character.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was and the name position it is retried from.
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("*.txt", "notes.txt"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a?c*", "abcdef"));
+        assert!(glob_match("*a*b", "xaxxab"));
+        assert!(!glob_match("*.txt", "notes.txt.bak"));
+        assert!(!glob_match("a?c", "ac"));
+    }
+
+    #[test]
+    fn include_exclude() {
+        let options = SyncOptions {
+            include: vec!["*.html".to_owned(), "*.css".to_owned()],
+            exclude: vec!["draft*".to_owned()],
+            ..SyncOptions::default()
+        };
+        assert!(options.matches("index.html"));
+        assert!(!options.matches("draft.html"));
+        assert!(!options.matches("script.js"));
+        assert!(SyncOptions::default().matches("anything"));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn state_round_trip() {
+        let path = std::env::temp_dir().join("ftp_sync_state.json");
+        let _ = fs::remove_file(&path);
+        assert_eq!(SyncState::load(&path).unwrap(), SyncState::default());
+        let mut state = SyncState::default();
+        state.completed.insert("a \"quoted\" name".to_owned());
+        state.partial.insert("big.bin".to_owned(), 1 << 40);
+        state.save(&path).unwrap();
+        assert_eq!(SyncState::load(&path).unwrap(), state);
+        fs::write(&path, "{\"completed\": 1}").unwrap();
+        assert!(SyncState::load(&path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,769 @@
+//! A minimal in-process FTP server for integration tests.
+//!
+//! Enabled with the `testing` feature. The server serves a temporary directory
+//! on a random local port and understands just enough of the protocol for the
+//! client operations of this crate: `USER`, `PASS`, `PASV`, `LIST`, `NLST`,
+//! `RETR`, `STOR` and the usual navigation and file management commands,
+//! plus the `FEAT`, `EPSV`, `MLSD` and `PRET` extensions. `MODE B` sends a
+//! restart marker every 1024 bytes, and `REST` takes a byte offset.
+//! It accepts any user name and password, and can simulate sessions closed,
+//! downloads aborted by the server or commands it doesn't implement.
+//!
+//! `ReplayServer` plays a recorded `observer::Transcript` back instead.
+//!
+//! ```rust
+//! use ftp::testing::TestServer;
+//! use ftp::FtpStream;
+//! use std::io::Cursor;
+//!
+//! let server = TestServer::start().unwrap();
+//! let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+//! ftp_stream.login("anonymous", "anonymous").unwrap();
+//! ftp_stream.put("hello.txt", &mut Cursor::new("hello")).unwrap();
+//! assert!(server.root().join("hello.txt").exists());
+//! ftp_stream.quit().unwrap();
+//! ```
+
+use super::{
+    block::{BlockReader, BlockWriter},
+    observer::{self, Transcript, TranscriptEntry},
+};
+
+use {
+    chrono::{DateTime, NaiveDateTime, Utc},
+    std::{
+        borrow::Cow,
+        ffi::{OsStr, OsString},
+        fs,
+        io::{self, copy, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+        net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream},
+        path::{Component, Path, PathBuf},
+        process,
+        sync::{
+            atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        thread::{self, JoinHandle},
+    },
+};
+
+static SERVER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// The interval of the restart markers sent in block mode.
+const MARKER_INTERVAL: u64 = 1024;
+
+/// An FTP server running on background threads until it is dropped.
+///
+/// Dropping the server stops accepting new connections and removes the
+/// temporary directory it was serving.
+pub struct TestServer {
+    addr: SocketAddr,
+    root: PathBuf,
+    stop: Arc<AtomicBool>,
+    generation: Arc<AtomicUsize>,
+    abort_after: Arc<AtomicU64>,
+    disabled: Arc<Mutex<Vec<String>>>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    /// Starts a server on `127.0.0.1` with a fresh, empty temporary directory.
+    pub fn start() -> io::Result<TestServer> {
+        let root = std::env::temp_dir().join(format!(
+            "ftp-testing-{}-{}",
+            process::id(),
+            SERVER_COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&root)?;
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let generation = Arc::new(AtomicUsize::new(0));
+        let abort_after = Arc::new(AtomicU64::new(u64::MAX));
+        let disabled = Arc::new(Mutex::new(Vec::new()));
+        let acceptor = {
+            let root = root.clone();
+            let stop = Arc::clone(&stop);
+            let generation = Arc::clone(&generation);
+            let abort_after = Arc::clone(&abort_after);
+            let disabled = Arc::clone(&disabled);
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        let root = root.clone();
+                        let generation = Arc::clone(&generation);
+                        let abort_after = Arc::clone(&abort_after);
+                        let disabled = Arc::clone(&disabled);
+                        thread::spawn(move || {
+                            let _ = Session::new(root, stream, generation, abort_after, disabled)
+                                .and_then(|s| s.run());
+                        });
+                    }
+                }
+            })
+        };
+
+        Ok(TestServer {
+            addr,
+            root,
+            stop,
+            generation,
+            abort_after,
+            disabled,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    /// The address the server is listening on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The local directory served as `/`.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Makes every session open so far answer its next command with
+    /// `421 Service not available` and close the control connection.
+    pub fn expire_sessions(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Makes `RETR` stop after sending `bytes` bytes, close the data
+    /// connection and reply `426`, as a server killing the transfer would.
+    /// `None` lets downloads complete again.
+    pub fn abort_downloads_after(&self, bytes: Option<u64>) {
+        self.abort_after
+            .store(bytes.unwrap_or(u64::MAX), Ordering::SeqCst);
+    }
+
+    /// Makes every session answer `commands`, such as `MLSD`, with `502 Command
+    /// not implemented`, as a server lacking them would. The list replaces
+    /// the previous one; an empty list enables every command again.
+    pub fn disable_commands(&self, commands: &[&str]) {
+        *self.disabled.lock().unwrap() = commands
+            .iter()
+            .map(|command| command.to_ascii_uppercase())
+            .collect();
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wake the acceptor up so it notices the stop flag.
+        let _ = TcpStream::connect(self.addr);
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// A server playing back a recorded `Transcript`, to reproduce a session
+/// with an unusual server offline.
+///
+/// The server accepts a single connection. Each command the client sends is
+/// compared with the next command of the transcript, and the replies that
+/// followed it in the transcript are sent back. Only the control connection
+/// is replayed, so commands opening a data connection can't be.
+///
+/// ```rust
+/// use ftp::observer::Transcript;
+/// use ftp::testing::ReplayServer;
+/// use ftp::FtpStream;
+///
+/// let transcript = Transcript::parse(
+///     "< 220 Odd server\n\
+///      > PWD\n\
+///      < 257 \"/home/odd\" is cwd\n\
+///      > QUIT\n\
+///      < 221 Bye\n",
+/// );
+/// let server = ReplayServer::start(transcript).unwrap();
+/// let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+/// assert_eq!(ftp_stream.pwd().unwrap(), "/home/odd");
+/// ftp_stream.quit().unwrap();
+/// server.finish().unwrap();
+/// ```
+pub struct ReplayServer {
+    addr: SocketAddr,
+    player: JoinHandle<io::Result<()>>,
+}
+
+impl ReplayServer {
+    /// Starts playing `transcript` back on a random port of `127.0.0.1`.
+    pub fn start(transcript: Transcript) -> io::Result<ReplayServer> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let player = thread::spawn(move || {
+            let (stream, _) = listener.accept()?;
+            replay(transcript, stream)
+        });
+        Ok(ReplayServer { addr, player })
+    }
+
+    /// The address the server is listening on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Waits for the client to disconnect, failing if it sent a command
+    /// other than the one recorded or stopped before the end of the transcript.
+    pub fn finish(self) -> io::Result<()> {
+        self.player
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("replay panicked")))
+    }
+}
+
+fn replay(transcript: Transcript, stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    let mut line = Vec::new();
+    for entry in transcript.entries {
+        match entry {
+            TranscriptEntry::Reply(reply) => {
+                writer.write_all(format!("{}\r\n", reply).as_bytes())?
+            }
+            TranscriptEntry::Command(expected) => {
+                line.clear();
+                if reader.read_until(b'\n', &mut line)? == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("client disconnected, expected `{}`", expected),
+                    ));
+                }
+                let sent = observer::mask(line.strip_suffix(b"\r\n").unwrap_or(&line));
+                if sent != expected {
+                    writer.write_all(b"503 Command not in transcript.\r\n")?;
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("expected `{}`, got `{}`", expected, sent),
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The state of one control connection.
+struct Session {
+    root: PathBuf,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    cwd: String,
+    logged_in: bool,
+    binary: bool,
+    passive: Option<TcpListener>,
+    active: Option<SocketAddr>,
+    rename_from: Option<PathBuf>,
+    block: bool,
+    restart: u64,
+    generation: Arc<AtomicUsize>,
+    born: usize,
+    abort_after: Arc<AtomicU64>,
+    disabled: Arc<Mutex<Vec<String>>>,
+}
+
+impl Session {
+    fn new(
+        root: PathBuf,
+        stream: TcpStream,
+        generation: Arc<AtomicUsize>,
+        abort_after: Arc<AtomicU64>,
+        disabled: Arc<Mutex<Vec<String>>>,
+    ) -> io::Result<Session> {
+        Ok(Session {
+            born: generation.load(Ordering::SeqCst),
+            generation,
+            abort_after,
+            disabled,
+            root,
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            cwd: String::from("/"),
+            logged_in: false,
+            binary: false,
+            passive: None,
+            active: None,
+            rename_from: None,
+            block: false,
+            restart: 0,
+        })
+    }
+
+    fn reply(&mut self, code: u32, text: &str) -> io::Result<()> {
+        self.writer
+            .write_all(format!("{} {}\r\n", code, text).as_bytes())
+    }
+
+    fn run(mut self) -> io::Result<()> {
+        self.reply(220, "rust-ftp test server ready")?;
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if self.reader.read_until(b'\n', &mut line)? == 0 {
+                return Ok(());
+            }
+            while line.last() == Some(&b'\n') || line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            // Arguments are kept as raw bytes so file names which are not
+            // valid UTF-8 can be exercised.
+            let (cmd, arg) = match line.iter().position(|&b| b == b' ') {
+                Some(pos) => (&line[..pos], bytes_to_os_str(&line[pos + 1..])),
+                None => (&line[..], OsString::new()),
+            };
+            let cmd = String::from_utf8_lossy(cmd).to_ascii_uppercase();
+            if self.generation.load(Ordering::SeqCst) != self.born {
+                self.reply(421, "Service not available, closing control connection.")?;
+                return Ok(());
+            }
+            if !self.logged_in && !["USER", "PASS", "QUIT", "NOOP", "CLNT"].contains(&cmd.as_str())
+            {
+                self.reply(530, "Please login with USER and PASS.")?;
+                continue;
+            }
+            if self.disabled.lock().unwrap().contains(&cmd) {
+                self.reply(502, "Command not implemented.")?;
+                continue;
+            }
+            match cmd.as_str() {
+                "USER" => self.reply(331, "Please specify the password.")?,
+                "PASS" => {
+                    self.logged_in = true;
+                    self.reply(230, "Login successful.")?
+                }
+                "QUIT" => {
+                    self.reply(221, "Goodbye.")?;
+                    return Ok(());
+                }
+                "NOOP" => self.reply(200, "NOOP ok.")?,
+                // Transfers run to completion before the next command is read.
+                "ABOR" => self.reply(225, "No transfer to ABOR.")?,
+                "SYST" => self.reply(215, "UNIX Type: L8")?,
+                "CLNT" => self.reply(200, "Noted.")?,
+                "FEAT" => self.writer.write_all(
+                    b"211-Features:\r\n EPSV\r\n MDTM\r\n MFMT\r\n \
+                      MLST type*;size*;modify*;\r\n REST STREAM\r\n SIZE\r\n UTF8\r\n211 End\r\n",
+                )?,
+                "OPTS" if arg.eq_ignore_ascii_case("UTF8 ON") => {
+                    self.reply(200, "Always in UTF8 mode.")?
+                }
+                "TYPE" => {
+                    self.binary = arg.to_string_lossy().eq_ignore_ascii_case("I");
+                    self.reply(200, "Type set.")?
+                }
+                "PWD" => {
+                    let cwd = self.cwd.replace('"', "\"\"");
+                    let msg = format!("\"{}\" is the current directory", cwd);
+                    self.reply(257, &msg)?
+                }
+                "CWD" => self.cwd(&arg)?,
+                "CDUP" => self.cwd("..")?,
+                "MKD" => {
+                    let (virt, path) = self.resolve(&arg);
+                    match fs::create_dir(path) {
+                        Ok(()) => {
+                            let virt = virt.replace('"', "\"\"");
+                            self.reply(257, &format!("\"{}\" created", virt))?
+                        }
+                        Err(_) => self.reply(550, "Create directory operation failed.")?,
+                    }
+                }
+                "RMD" => match fs::remove_dir(self.resolve(&arg).1) {
+                    Ok(()) => self.reply(250, "Remove directory operation successful.")?,
+                    Err(_) => self.reply(550, "Remove directory operation failed.")?,
+                },
+                "DELE" => match fs::remove_file(self.resolve(&arg).1) {
+                    Ok(()) => self.reply(250, "Delete operation successful.")?,
+                    Err(_) => self.reply(550, "Delete operation failed.")?,
+                },
+                "RNFR" => {
+                    let path = self.resolve(&arg).1;
+                    if path.exists() {
+                        self.rename_from = Some(path);
+                        self.reply(350, "Ready for RNTO.")?
+                    } else {
+                        self.reply(550, "RNFR command failed.")?
+                    }
+                }
+                "RNTO" => {
+                    let to = self.resolve(&arg).1;
+                    match self.rename_from.take().map(|from| fs::rename(from, to)) {
+                        Some(Ok(())) => self.reply(250, "Rename successful.")?,
+                        Some(Err(_)) => self.reply(550, "Rename failed.")?,
+                        None => self.reply(503, "RNFR required first.")?,
+                    }
+                }
+                // Like many servers, refuse SIZE in ASCII mode.
+                "SIZE" if !self.binary => self.reply(550, "SIZE not allowed in ASCII mode.")?,
+                "SIZE" => match fs::metadata(self.resolve(&arg).1) {
+                    Ok(ref meta) if meta.is_file() => self.reply(213, &meta.len().to_string())?,
+                    _ => self.reply(550, "Could not get file size.")?,
+                },
+                // Like vsftpd, only give the time of regular files.
+                "MDTM" => match fs::metadata(self.resolve(&arg).1)
+                    .and_then(|meta| Ok((meta.is_file(), meta.modified()?)))
+                {
+                    Ok((true, modified)) => {
+                        let modified: DateTime<Utc> = modified.into();
+                        self.reply(213, &modified.format("%Y%m%d%H%M%S").to_string())?
+                    }
+                    _ => self.reply(550, "Could not get file modification time.")?,
+                },
+                "MFMT" => self.mfmt(&arg)?,
+                "MODE" => match arg.to_string_lossy().to_ascii_uppercase().as_str() {
+                    "S" => {
+                        self.block = false;
+                        self.reply(200, "Mode set to S.")?
+                    }
+                    "B" => {
+                        self.block = true;
+                        self.reply(200, "Mode set to B.")?
+                    }
+                    _ => self.reply(504, "Bad MODE command.")?,
+                },
+                "REST" => match arg.to_string_lossy().parse() {
+                    Ok(offset) => {
+                        self.restart = offset;
+                        self.reply(350, &format!("Restart position accepted ({}).", offset))?
+                    }
+                    Err(_) => self.reply(501, "Bad REST command.")?,
+                },
+                "PRET" => self.reply(200, "OK, will use master for upcoming transfer.")?,
+                "PASV" | "EPSV" => self.pasv(&cmd)?,
+                "PORT" | "EPRT" => match parse_port(&cmd, &arg.to_string_lossy()) {
+                    Some(addr) => {
+                        self.passive = None;
+                        self.active = Some(addr);
+                        self.reply(200, "PORT command successful.")?
+                    }
+                    None => self.reply(501, "Illegal PORT command.")?,
+                },
+                "LIST" | "NLST" | "MLSD" => self.list(&cmd, &arg)?,
+                "STAT" => self.stat(&arg)?,
+                "RETR" => self.retr(&arg)?,
+                "STOR" => self.stor(&arg, false)?,
+                "APPE" => self.stor(&arg, true)?,
+                _ => self.reply(502, "Command not implemented.")?,
+            }
+        }
+    }
+
+    /// Resolves `arg` against the current directory, returning the virtual
+    /// path and the matching local path. `..` never leaves the root.
+    fn resolve<S: AsRef<OsStr>>(&self, arg: S) -> (String, PathBuf) {
+        let arg = Path::new(arg.as_ref());
+        let joined = if arg.has_root() {
+            arg.to_path_buf()
+        } else {
+            Path::new(&self.cwd).join(arg)
+        };
+        let mut parts: Vec<&OsStr> = Vec::new();
+        for component in joined.components() {
+            match component {
+                Component::Normal(part) => parts.push(part),
+                Component::ParentDir => {
+                    parts.pop();
+                }
+                _ => {}
+            }
+        }
+        let local = parts.iter().fold(self.root.clone(), |path, p| path.join(p));
+        let virt = parts
+            .iter()
+            .map(|p| p.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        (format!("/{}", virt), local)
+    }
+
+    fn cwd<S: AsRef<OsStr>>(&mut self, arg: S) -> io::Result<()> {
+        let (virt, path) = self.resolve(arg);
+        if path.is_dir() {
+            self.cwd = virt;
+            self.reply(250, "Directory successfully changed.")
+        } else {
+            self.reply(550, "Failed to change directory.")
+        }
+    }
+
+    fn pasv(&mut self, cmd: &str) -> io::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        self.passive = Some(listener);
+        if cmd == "EPSV" {
+            let msg = format!("Entering Extended Passive Mode (|||{}|)", port);
+            return self.reply(229, &msg);
+        }
+        let msg = format!(
+            "Entering Passive Mode (127,0,0,1,{},{}).",
+            port >> 8,
+            port & 0xff
+        );
+        self.reply(227, &msg)
+    }
+
+    /// Sends the preliminary `150` reply with `msg`, then opens the data
+    /// connection announced by the last `PASV` or `PORT`.
+    fn data_connection(&mut self, msg: &str) -> io::Result<Option<TcpStream>> {
+        if let Some(listener) = self.passive.take() {
+            self.reply(150, msg)?;
+            return listener.accept().map(|(stream, _)| Some(stream));
+        }
+        if let Some(addr) = self.active.take() {
+            self.reply(150, msg)?;
+            return TcpStream::connect(addr).map(Some);
+        }
+        self.reply(425, "Use PORT or PASV first.")?;
+        Ok(None)
+    }
+
+    fn list(&mut self, cmd: &str, arg: &OsStr) -> io::Result<()> {
+        let listing = self.listing(cmd, arg)?;
+        let mut data = match self.data_connection("Here comes the directory listing.")? {
+            Some(data) => data,
+            None => return Ok(()),
+        };
+        self.send(&mut &listing[..], &mut data)?;
+        drop(data);
+        self.reply(226, "Directory send OK.")
+    }
+
+    fn stat(&mut self, arg: &OsStr) -> io::Result<()> {
+        if arg.is_empty() {
+            return self.reply(211, "rust-ftp test server status OK.");
+        }
+        let mut reply = format!("213-Status of {}:\r\n", arg.to_string_lossy()).into_bytes();
+        // Indent the lines like vsftpd does.
+        for line in self.listing("LIST", arg)?.split_inclusive(|&b| b == b'\n') {
+            reply.push(b' ');
+            reply.extend_from_slice(line);
+        }
+        reply.extend_from_slice(b"213 End of status\r\n");
+        self.writer.write_all(&reply)
+    }
+
+    /// The lines of a `LIST`, `NLST` or `MLSD` of `arg`.
+    fn listing(&self, cmd: &str, arg: &OsStr) -> io::Result<Vec<u8>> {
+        // Skip options such as `-a`.
+        let arg = match arg.to_str() {
+            Some(arg) => OsString::from(
+                arg.split_whitespace()
+                    .find(|a| !a.starts_with('-'))
+                    .unwrap_or(""),
+            ),
+            None => arg.to_owned(),
+        };
+        let path = self.resolve(&arg).1;
+        let mut entries = Vec::new();
+        if path.is_dir() {
+            for entry in fs::read_dir(&path)? {
+                let entry = entry?;
+                entries.push((entry.file_name(), entry.path()));
+            }
+        } else if path.exists() {
+            entries.push((arg, path));
+        }
+        entries.sort();
+
+        let mut data = Vec::new();
+        for (name, path) in entries {
+            if cmd == "NLST" {
+                data.write_all(&os_str_to_bytes(&name))?;
+                data.write_all(b"\r\n")?;
+                continue;
+            }
+            let meta = fs::symlink_metadata(&path)?;
+            let modified: DateTime<Utc> = meta.modified()?.into();
+            let target = if meta.file_type().is_symlink() {
+                Some(fs::read_link(&path)?)
+            } else {
+                None
+            };
+            if cmd == "MLSD" {
+                let kind = match target {
+                    Some(ref target) => format!("OS.unix=slink:{}", target.to_string_lossy()),
+                    None if meta.is_dir() => "dir".to_owned(),
+                    None => "file".to_owned(),
+                };
+                write!(
+                    data,
+                    "type={};size={};modify={}; {}\r\n",
+                    kind,
+                    meta.len(),
+                    modified.format("%Y%m%d%H%M%S"),
+                    name.to_string_lossy()
+                )?;
+            } else {
+                let (kind, link) = match target {
+                    Some(ref target) => ('l', format!(" -> {}", target.to_string_lossy())),
+                    None if meta.is_dir() => ('d', String::new()),
+                    None => ('-', String::new()),
+                };
+                write!(
+                    data,
+                    "{}rw-r--r--    1 ftp      ftp      {:>8} {} {}{}\r\n",
+                    kind,
+                    meta.len(),
+                    modified.format("%b %d %H:%M"),
+                    name.to_string_lossy(),
+                    link
+                )?;
+            }
+        }
+        Ok(data)
+    }
+
+    /// Sends `source` on a data connection in the current mode, with a
+    /// restart marker every `MARKER_INTERVAL` bytes in block mode.
+    fn send(&self, source: &mut dyn Read, data: &mut TcpStream) -> io::Result<()> {
+        if self.block {
+            let mut writer = BlockWriter::new(data);
+            writer.set_marker_interval(Some(MARKER_INTERVAL));
+            copy(source, &mut writer)?;
+            writer.finish()?;
+        } else {
+            copy(source, data)?;
+        }
+        Ok(())
+    }
+
+    fn retr(&mut self, arg: &OsStr) -> io::Result<()> {
+        let restart = std::mem::take(&mut self.restart);
+        let file = fs::File::open(self.resolve(arg).1)
+            .and_then(|mut file| file.seek(SeekFrom::Start(restart)).map(|_| file));
+        let mut file = match file {
+            Ok(file) => file,
+            Err(_) => {
+                self.passive = None;
+                return self.reply(550, "Failed to open file.");
+            }
+        };
+        let mut data = match self.data_connection("Opening BINARY mode data connection.")? {
+            Some(data) => data,
+            None => return Ok(()),
+        };
+        let abort_after = self.abort_after.load(Ordering::SeqCst);
+        if abort_after != u64::MAX {
+            self.send(&mut file.take(abort_after), &mut data)?;
+            let _ = data.shutdown(Shutdown::Both);
+            return self.reply(426, "Connection closed; transfer aborted.");
+        }
+        let sent = self.send(&mut file, &mut data);
+        // The client may have closed the connection without reading it all.
+        let _ = data.shutdown(Shutdown::Both);
+        match sent {
+            Ok(()) => self.reply(226, "Transfer complete."),
+            Err(_) => self.reply(426, "Connection closed; transfer aborted."),
+        }
+    }
+
+    fn mfmt(&mut self, arg: &OsStr) -> io::Result<()> {
+        let arg = arg.to_string_lossy();
+        let (time, path) = arg.split_at(arg.find(' ').unwrap_or(arg.len()));
+        let time = match NaiveDateTime::parse_from_str(time, "%Y%m%d%H%M%S") {
+            Ok(time) => time.and_utc(),
+            Err(_) => return self.reply(501, "Invalid time."),
+        };
+        let set = fs::File::options()
+            .write(true)
+            .open(self.resolve(path.trim_start()).1)
+            .and_then(|file| file.set_modified(time.into()));
+        match set {
+            Ok(()) => self.reply(
+                213,
+                &format!("Modify={};{}", time.format("%Y%m%d%H%M%S"), path),
+            ),
+            Err(_) => self.reply(550, "Could not set file modification time."),
+        }
+    }
+
+    fn stor(&mut self, arg: &OsStr, append: bool) -> io::Result<()> {
+        let restart = std::mem::take(&mut self.restart);
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append && restart == 0)
+            .open(self.resolve(arg).1)
+            .and_then(|mut file| file.seek(SeekFrom::Start(restart)).map(|_| file));
+        let mut file = match file {
+            Ok(file) => file,
+            Err(_) => {
+                self.passive = None;
+                return self.reply(553, "Could not create file.");
+            }
+        };
+        let mut data = match self.data_connection("Ok to send data.")? {
+            Some(data) => data,
+            None => return Ok(()),
+        };
+        if self.block {
+            let mut reader = BlockReader::new(data);
+            copy(&mut reader, &mut file)?;
+            for marker in reader.markers() {
+                let reply = format!("MARK {} = {}", marker.marker, restart + marker.offset);
+                self.reply(110, &reply)?;
+            }
+        } else {
+            copy(&mut data, &mut file)?;
+        }
+        self.reply(226, "Transfer complete.")
+    }
+}
+
+/// Parses the argument of `PORT h1,h2,h3,h4,p1,p2` or `EPRT |af|addr|port|`.
+fn parse_port(cmd: &str, arg: &str) -> Option<SocketAddr> {
+    if cmd == "EPRT" {
+        let delim = arg.chars().next()?;
+        let fields: Vec<&str> = arg.split(delim).collect();
+        let ip = fields.get(2)?.parse::<IpAddr>().ok()?;
+        let port = fields.get(3)?.parse().ok()?;
+        return Some(SocketAddr::new(ip, port));
+    }
+    let nums = arg
+        .split(',')
+        .map(|n| n.trim().parse::<u8>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    if nums.len() != 6 {
+        return None;
+    }
+    let ip = Ipv4Addr::new(nums[0], nums[1], nums[2], nums[3]);
+    let port = (u16::from(nums[4]) << 8) | u16::from(nums[5]);
+    Some(SocketAddr::new(IpAddr::V4(ip), port))
+}
+
+#[cfg(unix)]
+fn bytes_to_os_str(bytes: &[u8]) -> OsString {
+    use std::os::unix::ffi::OsStrExt;
+    OsStr::from_bytes(bytes).to_owned()
+}
+
+#[cfg(not(unix))]
+fn bytes_to_os_str(bytes: &[u8]) -> OsString {
+    OsString::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+#[cfg(unix)]
+fn os_str_to_bytes(name: &OsStr) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(name.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn os_str_to_bytes(name: &OsStr) -> Cow<'_, [u8]> {
+    match name.to_string_lossy() {
+        Cow::Borrowed(name) => Cow::Borrowed(name.as_bytes()),
+        Cow::Owned(name) => Cow::Owned(name.into_bytes()),
+    }
+}
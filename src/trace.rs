@@ -0,0 +1,226 @@
+//! Optional [tracing](https://crates.io/crates/tracing) instrumentation.
+//!
+//! With the `tracing` feature enabled every command gets a `ftp.command` span
+//! carrying its name, reply code and duration, and every data transfer a
+//! `ftp.transfer` span carrying the number of bytes moved. Without the
+//! feature the spans are left out; transfers still count their bytes in
+//! the metrics of the stream and send their events.
+
+use events::{self, Progress, SessionEvent};
+use metrics::Metrics;
+use status::Status;
+use std::io::{IoSlice, Read, Result, Write};
+use std::sync::{mpsc::Sender, Arc};
+
+#[cfg(feature = "tracing")]
+use {
+    std::time::Instant,
+    tracing::{field, Span},
+};
+
+/// Tracks the span of the command currently waiting for its reply.
+#[derive(Debug, Default)]
+pub struct CommandTrace {
+    #[cfg(feature = "tracing")]
+    current: Option<(Span, Instant)>,
+}
+
+impl CommandTrace {
+    /// Opens the span for `command`, closing the previous one if it is still open.
+    #[cfg(feature = "tracing")]
+    pub fn start(&mut self, command: &str) {
+        // Only the verb is recorded so that arguments such as passwords never
+        // end up in the traces.
+        let name = command.split_whitespace().next().unwrap_or_default();
+        let span = debug_span!(
+            "ftp.command",
+            command = name,
+            status = field::Empty,
+            duration_ms = field::Empty
+        );
+        self.current = Some((span, Instant::now()));
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub fn start(&mut self, _command: &str) {}
+
+    /// Records a reply code. Preliminary (1xx) replies keep the span open since
+    /// the final reply of the command is still to come.
+    #[cfg(feature = "tracing")]
+    pub fn reply(&mut self, status: Status) {
+        if let Some((ref span, started)) = self.current {
+            span.record("status", status.code());
+            if !status.is_preliminary() {
+                span.record("duration_ms", started.elapsed().as_millis() as u64);
+                self.current = None;
+            }
+        }
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub fn reply(&mut self, _status: Status) {}
+}
+
+/// The span of a single data transfer, also counting its bytes in the
+/// metrics of the stream and sending its events. A transfer dropped
+/// without `finish` is reported as failed.
+pub struct TransferTrace {
+    #[cfg(feature = "tracing")]
+    span: Span,
+    #[cfg(feature = "tracing")]
+    started: Instant,
+    upload: bool,
+    metrics: Option<Arc<dyn Metrics>>,
+    events: Option<Sender<SessionEvent>>,
+}
+
+impl TransferTrace {
+    /// Opens the span of a transfer started by `command` on `path`.
+    pub fn start(
+        command: &'static str,
+        path: Option<&str>,
+        metrics: Option<Arc<dyn Metrics>>,
+        events: Option<Sender<SessionEvent>>,
+    ) -> TransferTrace {
+        let path = path.map(str::to_owned);
+        events::send(
+            events.as_ref(),
+            SessionEvent::TransferStarted { command, path },
+        );
+        TransferTrace {
+            #[cfg(feature = "tracing")]
+            span: info_span!(
+                "ftp.transfer",
+                command = command,
+                bytes = field::Empty,
+                duration_ms = field::Empty
+            ),
+            #[cfg(feature = "tracing")]
+            started: Instant::now(),
+            upload: command == "STOR" || command == "APPE",
+            metrics,
+            events,
+        }
+    }
+
+    /// The progress reporter of the transfer, if events are sent.
+    pub fn progress(&self) -> Option<Progress> {
+        self.events.clone().map(Progress::new)
+    }
+
+    /// Closes the span, recording the number of bytes transferred.
+    pub fn finish(mut self, bytes: u64) {
+        self.record(bytes);
+        let events = self.events.take();
+        events::send(events.as_ref(), SessionEvent::TransferCompleted { bytes });
+    }
+
+    /// Same as `finish`, for a transfer which failed.
+    pub fn fail(mut self, bytes: u64) {
+        self.record(bytes);
+        let events = self.events.take();
+        events::send(events.as_ref(), SessionEvent::TransferFailed);
+    }
+
+    fn record(&self, bytes: u64) {
+        #[cfg(feature = "tracing")]
+        {
+            self.span.record("bytes", bytes);
+            self.span
+                .record("duration_ms", self.started.elapsed().as_millis() as u64);
+        }
+        match self.metrics {
+            Some(ref metrics) if self.upload => metrics.bytes_uploaded(bytes),
+            Some(ref metrics) => metrics.bytes_downloaded(bytes),
+            None => {}
+        }
+    }
+}
+
+impl Drop for TransferTrace {
+    fn drop(&mut self) {
+        events::send(self.events.as_ref(), SessionEvent::TransferFailed);
+    }
+}
+
+/// A reader counting the bytes read through it.
+pub struct CountingReader<R> {
+    pub inner: R,
+    pub bytes: u64,
+    progress: Option<Progress>,
+}
+
+impl<R> CountingReader<R> {
+    pub fn new(inner: R) -> CountingReader<R> {
+        CountingReader {
+            inner,
+            bytes: 0,
+            progress: None,
+        }
+    }
+
+    /// Reports the bytes read to `progress` as well.
+    pub fn with_progress(mut self, progress: Option<Progress>) -> CountingReader<R> {
+        self.progress = progress;
+        self
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes += n as u64;
+        if let Some(ref mut progress) = self.progress {
+            progress.update(self.bytes);
+        }
+        Ok(n)
+    }
+}
+
+/// A writer counting the bytes written through it.
+pub struct CountingWriter<W> {
+    pub inner: W,
+    pub bytes: u64,
+    progress: Option<Progress>,
+}
+
+impl<W> CountingWriter<W> {
+    pub fn new(inner: W) -> CountingWriter<W> {
+        CountingWriter {
+            inner,
+            bytes: 0,
+            progress: None,
+        }
+    }
+
+    /// Reports the bytes written to `progress` as well.
+    pub fn with_progress(mut self, progress: Option<Progress>) -> CountingWriter<W> {
+        self.progress = progress;
+        self
+    }
+
+    fn count(&mut self, n: usize) {
+        self.bytes += n as u64;
+        if let Some(ref mut progress) = self.progress {
+            progress.update(self.bytes);
+        }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count(n);
+        Ok(n)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> Result<usize> {
+        let n = self.inner.write_vectored(bufs)?;
+        self.count(n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,116 @@
+//! The transport carrying the control connection.
+//!
+//! `GenericFtpStream` runs over any `Transport`; `FtpStream` is the usual
+//! TCP one. Other transports, e.g. an in-memory one in tests or a tunneled
+//! connection, only need `Read` and `Write`: the other operations have
+//! defaults failing with `io::ErrorKind::Unsupported`.
+//!
+//! ```rust
+//! use ftp::transport::Transport;
+//! use ftp::GenericFtpStream;
+//! use std::io::{self, Cursor, Read, Write};
+//!
+//! #[derive(Debug)]
+//! struct Scripted(Cursor<&'static [u8]>);
+//!
+//! impl Read for Scripted {
+//!     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+//!         self.0.read(buf)
+//!     }
+//! }
+//!
+//! impl Write for Scripted {
+//!     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+//!         Ok(buf.len())
+//!     }
+//!     fn flush(&mut self) -> io::Result<()> {
+//!         Ok(())
+//!     }
+//! }
+//!
+//! impl Transport for Scripted {}
+//!
+//! let replies = Scripted(Cursor::new(b"220 Hi\r\n257 \"/\" is cwd\r\n"));
+//! let mut ftp_stream = GenericFtpStream::from_stream(replies).unwrap();
+//! assert_eq!(ftp_stream.pwd().unwrap(), "/");
+//! ```
+
+use super::types::Dialer;
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+/// A bidirectional stream carrying the control connection.
+pub trait Transport: Read + Write + fmt::Debug + Sized + 'static {
+    /// The address of the server. Passive mode uses it with
+    /// `PassiveAddressPolicy::UseControlPeer`, and reconnections need it.
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Err(unsupported("peer_addr"))
+    }
+
+    /// The local address, which active mode listens on.
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Err(unsupported("local_addr"))
+    }
+
+    /// Sets the timeout of reads, `None` blocking indefinitely.
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        let _ = timeout;
+        Err(unsupported("set_read_timeout"))
+    }
+
+    /// Sets the timeout of writes, `None` blocking indefinitely.
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        let _ = timeout;
+        Err(unsupported("set_write_timeout"))
+    }
+
+    /// A second handle to the same connection, needed to leave TLS mode.
+    fn try_clone(&self) -> io::Result<Self> {
+        Err(unsupported("try_clone"))
+    }
+
+    /// Opens a new connection to `addr` with `dialer`, for reconnections
+    /// and spawned sessions.
+    fn reopen(addr: SocketAddr, dialer: &Dialer) -> io::Result<Self> {
+        let _ = (addr, dialer);
+        Err(unsupported("reopen"))
+    }
+}
+
+impl Transport for TcpStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::local_addr(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn reopen(addr: SocketAddr, dialer: &Dialer) -> io::Result<Self> {
+        dialer.dial(addr)
+    }
+}
+
+fn unsupported(operation: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} is not supported by this transport", operation),
+    )
+}
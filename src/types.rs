@@ -1,7 +1,16 @@
 //! The set of valid values for FTP commands
 
+use chrono::{DateTime, FixedOffset, NaiveDateTime, Utc};
+use path::FtpPath;
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use status::Status;
+use std::collections::BTreeMap;
 use std::convert::From;
 use std::fmt;
+use std::io;
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex, MutexGuard};
 
 /// A shorthand for a Result whose error type is always an FtpError.
 pub type Result<T> = std::result::Result<T, FtpError>;
@@ -14,7 +23,43 @@ pub enum FtpError {
     #[cfg(feature = "secure")]
     SecureError(String),
     InvalidResponse(String),
+    /// The server answered with a reply other than the one expected.
+    UnexpectedResponse(Reply),
+    /// The server replied `421` and closed the session.
+    ServiceNotAvailable(Reply),
     InvalidAddress(std::net::AddrParseError),
+    /// An argument contained characters which can't be sent in a command,
+    /// such as CR or LF. Nothing was sent to the server.
+    InvalidArgument(String),
+    /// A download was larger than the limit set with
+    /// `FtpStream::set_max_download_size`, in bytes, and was aborted.
+    SizeLimitExceeded(u64),
+    /// The transfer was aborted with an `AbortHandle`.
+    Aborted,
+    /// The server aborted a transfer with `426` or `451` after `bytes`
+    /// bytes of data went through the data connection.
+    TransferAborted {
+        bytes: u64,
+        reply: Reply,
+    },
+    /// `login` was refused because the control connection isn't secured,
+    /// see `FtpStream::set_require_tls`. Nothing was sent to the server.
+    TlsRequired,
+    /// The remote file at the given path already exists: an upload with
+    /// `OverwritePolicy::Fail` found it, or `FtpStream::mv` couldn't replace
+    /// it.
+    AlreadyExists(String),
+    /// The remote file at the given path doesn't exist, see `FtpStream::mv`.
+    NotFound(String),
+    /// `FtpStream::ensure_available` found less space left on the server
+    /// than `needed`, in bytes. Nothing was uploaded.
+    InsufficientStorage {
+        needed: u64,
+        available: u64,
+    },
+    /// The file of the transfer at the given remote path changed since the
+    /// checkpoint it was to resume from, see `checkpoint::TransferCheckpoint`.
+    Changed(String),
 }
 
 impl From<std::io::Error> for FtpError {
@@ -29,6 +74,12 @@ impl<S: std::fmt::Debug + 'static> From<native_tls::HandshakeError<S>> for FtpEr
         FtpError::SecureError(err.to_string())
     }
 }
+#[cfg(all(feature = "secure", feature = "native-tls"))]
+impl From<native_tls::Error> for FtpError {
+    fn from(err: native_tls::Error) -> Self {
+        FtpError::SecureError(err.to_string())
+    }
+}
 #[cfg(all(feature = "secure", not(feature = "native-tls")))]
 impl From<openssl::error::ErrorStack> for FtpError {
     fn from(err: openssl::error::ErrorStack) -> Self {
@@ -48,6 +99,95 @@ impl<S: std::fmt::Debug> From<openssl::ssl::HandshakeError<S>> for FtpError {
     }
 }
 
+impl FtpError {
+    /// The reply code of an `UnexpectedResponse`, so callers can tell e.g. a
+    /// missing file (`Status::FileUnavailable`) from other failures.
+    pub fn status(&self) -> Option<Status> {
+        match *self {
+            FtpError::UnexpectedResponse(ref reply)
+            | FtpError::ServiceNotAvailable(ref reply)
+            | FtpError::TransferAborted { ref reply, .. } => Some(reply.line.0),
+            _ => None,
+        }
+    }
+
+    /// The command the server's reply answered, for errors carrying a reply,
+    /// with any password masked. `None` for a reply to no command, such as
+    /// the welcome message.
+    pub fn command(&self) -> Option<&str> {
+        match *self {
+            FtpError::UnexpectedResponse(ref reply)
+            | FtpError::ServiceNotAvailable(ref reply)
+            | FtpError::TransferAborted { ref reply, .. } => reply.command.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Every line of the server's reply, for errors carrying a reply.
+    pub fn reply_lines(&self) -> Option<&[String]> {
+        match *self {
+            FtpError::UnexpectedResponse(ref reply)
+            | FtpError::ServiceNotAvailable(ref reply)
+            | FtpError::TransferAborted { ref reply, .. } => Some(&reply.lines),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the operation may succeed when retried later: the
+    /// server replied with a 4xx code, such as `421` when it closes the
+    /// session, or the connection failed, was lost or timed out.
+    ///
+    /// ```rust
+    /// use ftp::status::Status;
+    /// use ftp::types::Line;
+    /// use ftp::FtpError;
+    ///
+    /// let busy = Line(Status::from(450), "450 Busy".to_owned());
+    /// assert!(FtpError::UnexpectedResponse(busy.into()).is_transient());
+    /// let missing = Line(Status::FileUnavailable, "550 No such file".to_owned());
+    /// assert!(FtpError::UnexpectedResponse(missing.into()).is_permanent());
+    /// ```
+    pub fn is_transient(&self) -> bool {
+        match *self {
+            FtpError::UnexpectedResponse(ref reply)
+            | FtpError::ServiceNotAvailable(ref reply)
+            | FtpError::TransferAborted { ref reply, .. } => reply.line.0.is_transient_negative(),
+            FtpError::ConnectionError(ref err) => matches!(
+                err.kind(),
+                io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::WouldBlock
+                    | io::ErrorKind::Interrupted
+                    | io::ErrorKind::UnexpectedEof
+            ),
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if retrying the same operation will fail again: the
+    /// server replied with a 5xx code, or the request itself was refused
+    /// before reaching the server. An error may be neither transient nor
+    /// permanent, e.g. an invalid reply.
+    pub fn is_permanent(&self) -> bool {
+        match *self {
+            FtpError::UnexpectedResponse(ref reply) => reply.line.0.is_permanent_negative(),
+            FtpError::InvalidAddress(_)
+            | FtpError::InvalidArgument(_)
+            | FtpError::SizeLimitExceeded(_)
+            | FtpError::TlsRequired
+            | FtpError::AlreadyExists(_)
+            | FtpError::NotFound(_)
+            | FtpError::InsufficientStorage { .. }
+            | FtpError::Changed(_) => true,
+            _ => false,
+        }
+    }
+}
+
 impl From<std::net::AddrParseError> for FtpError {
     fn from(err: std::net::AddrParseError) -> Self {
         FtpError::InvalidAddress(err)
@@ -92,23 +232,804 @@ impl FormatControl {
     }
 }
 
-/// `Line` contains a command code and the contents of a line of text read from the network.
-pub struct Line(pub u32, pub String);
+/// Transmission mode used in `MODE` command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TransmissionMode {
+    /// The data is sent as is, its end marked by closing the data connection.
+    #[default]
+    Stream,
+    /// The data is sent in blocks, possibly with restart markers, see the
+    /// `block` module.
+    Block,
+}
+
+/// A restart marker of a block mode transfer, see
+/// `FtpStream::restart_markers`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestartMarker {
+    /// The number of data bytes transferred before the marker.
+    pub offset: u64,
+    /// The server marker, passed to `FtpStream::restart` to resume the
+    /// transfer from `offset`.
+    pub marker: String,
+}
+
+/// Login convention of the FTP proxy the stream is connected to, see
+/// `FtpStream::set_proxy_scheme`. The `host` of every scheme is the target
+/// server, optionally followed by `:port`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProxyScheme {
+    /// `USER user@host` followed by `PASS password`.
+    UserAtHost { host: String },
+    /// `OPEN host`, followed by the regular login.
+    Open { host: String },
+    /// Log in to the proxy itself, send `SITE host`, then the regular login.
+    Site {
+        proxy_user: String,
+        proxy_password: String,
+        host: String,
+    },
+    /// Log in to the proxy itself, then `USER user@host` and `PASS password`.
+    UserAtHostAfterLogon {
+        proxy_user: String,
+        proxy_password: String,
+        host: String,
+    },
+}
+
+/// Which address to connect to for passive mode data connections, see
+/// `FtpStream::set_passive_address_policy`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PassiveAddressPolicy {
+    /// Connect to the address advertised in the `227` reply (the default).
+    #[default]
+    UseServerAddress,
+    /// Connect to the address of the control connection peer, keeping only
+    /// the advertised port. Useful for NATted servers advertising private
+    /// addresses.
+    UseControlPeer,
+    /// Always connect to the given address, keeping only the advertised port.
+    Override(IpAddr),
+}
 
-impl ToString for FormatControl {
-    fn to_string(&self) -> String {
-        self.as_str().to_owned()
+/// How data connections are established, see `FtpStream::set_mode`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// The client connects to the server after a `PASV` (the default).
+    #[default]
+    Passive,
+    /// The server connects back to the client after a `PORT` or `EPRT`.
+    Active(ActiveConfig),
+}
+
+/// The order of the steps starting a passive mode transfer, see
+/// `FtpStream::set_data_connect_order`. Active mode always waits for the
+/// preliminary reply before accepting the server's connection.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DataConnectOrder {
+    /// Connect to the data port, then send the command and read the `150`
+    /// reply (the default). A failed connection leaves no reply pending.
+    #[default]
+    ConnectFirst,
+    /// Send the command, connect, then read the reply. For servers which
+    /// only accept the data connection once the command is known. A failed
+    /// connection leaves the reply pending, so the session is closed, see
+    /// `FtpStream::is_closed`.
+    CommandFirst,
+    /// Send the command and read the reply before connecting. For servers
+    /// which don't accept the data connection before sending the reply. A
+    /// failed connection closes the session, like with `CommandFirst`.
+    ReplyFirst,
+}
+
+/// Opens TCP connections for an `FtpStream`, see `FtpStream::set_dialer`.
+/// The default dialer connects directly.
+#[derive(Clone)]
+pub struct Dialer(Arc<dyn Fn(SocketAddr) -> io::Result<TcpStream> + Send + Sync>);
+
+impl Dialer {
+    /// Creates a dialer calling `dial` for every connection.
+    pub fn new<F>(dial: F) -> Dialer
+    where
+        F: Fn(SocketAddr) -> io::Result<TcpStream> + Send + Sync + 'static,
+    {
+        Dialer(Arc::new(dial))
+    }
+
+    /// Opens a connection to `addr`.
+    pub fn dial(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        (self.0)(addr)
+    }
+}
+
+impl Default for Dialer {
+    fn default() -> Dialer {
+        Dialer::new(TcpStream::connect)
+    }
+}
+
+impl fmt::Debug for Dialer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Dialer")
+    }
+}
+
+/// Aborts the transfers of an `FtpStream` from another thread, see
+/// `FtpStream::abort_handle`.
+#[derive(Debug, Default, Clone)]
+pub struct AbortHandle(Arc<Mutex<AbortState>>);
+
+#[derive(Debug, Default)]
+struct AbortState {
+    aborted: bool,
+    data: Option<TcpStream>,
+}
+
+impl AbortHandle {
+    /// Aborts the transfer in progress, or the next one if none is. Its data
+    /// connection is shut down, then the stream sends `ABOR` and the
+    /// transfer fails with `FtpError::Aborted`.
+    pub fn abort(&self) {
+        let mut state = self.lock();
+        state.aborted = true;
+        if let Some(ref data) = state.data {
+            let _ = data.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Records the data connection of the transfer starting.
+    pub(crate) fn start(&self, data: &TcpStream) {
+        let mut state = self.lock();
+        state.data = data.try_clone().ok();
+        if state.aborted {
+            let _ = data.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Forgets the data connection of the transfer ending, returning `true`
+    /// if it was aborted.
+    pub(crate) fn finish(&self) -> bool {
+        let mut state = self.lock();
+        state.data = None;
+        std::mem::replace(&mut state.aborted, false)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, AbortState> {
+        // The state stays consistent even if a thread panicked holding it.
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
     }
 }
 
-impl ToString for FileType {
-    fn to_string(&self) -> String {
+/// How strictly replies from the server are parsed, see
+/// `FtpStream::set_parse_mode`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Replies must follow RFC 959 exactly (the default).
+    #[default]
+    Strict,
+    /// Tolerate servers which omit the space after the reply code, send a
+    /// bare code such as `220` or interleave blank lines with replies.
+    Lenient,
+}
+
+/// Settings of the active mode listener.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ActiveConfig {
+    /// Local ports the listener may use. `None` lets the system pick any port.
+    pub port_range: Option<RangeInclusive<u16>>,
+    /// Address advertised to the server instead of the local address of the
+    /// control connection, for clients behind a NAT with port forwarding.
+    pub external_ip: Option<IpAddr>,
+}
+
+/// Whether a control connection is secured with TLS, see
+/// `FtpStream::set_tls_mode`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TlsMode {
+    /// TLS is used only if the connection is secured with `into_secure`
+    /// (the default).
+    #[default]
+    Disabled,
+    /// The connection is secured if the server supports `AUTH TLS`, and
+    /// stays in plain text otherwise.
+    Opportunistic,
+    /// `login` fails unless the connection is secured.
+    Required,
+}
+
+/// The TLS context of the active backend: an `SslContext` with OpenSSL, a
+/// `TlsConnector` with the `native-tls` feature.
+#[cfg(all(feature = "secure", not(feature = "native-tls")))]
+pub type TlsContext = openssl::ssl::SslContext;
+/// The TLS context of the active backend: an `SslContext` with OpenSSL, a
+/// `TlsConnector` with the `native-tls` feature.
+#[cfg(all(feature = "secure", feature = "native-tls"))]
+pub type TlsContext = native_tls::TlsConnector;
+
+/// A version of the TLS protocol, see `TlsConfig`.
+#[cfg(feature = "secure")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+/// The cipher suites TLS connections may use, see `TlsConfig`.
+#[cfg(feature = "secure")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CipherPolicy {
+    /// The defaults of the TLS backend (the default).
+    #[default]
+    Default,
+    /// Only forward secret AEAD suites, ECDHE with AES-GCM or ChaCha20, and
+    /// TLS 1.2 at least. With `native-tls` the system picks the suites, so
+    /// only the minimum version is raised.
+    Modern,
+}
+
+/// Security settings of TLS connections, building the context of whichever
+/// TLS backend is active, to pass to `FtpStream::into_secure`.
+///
+/// ```rust,no_run
+/// use ftp::types::{CipherPolicy, TlsConfig, TlsVersion};
+///
+/// let config = TlsConfig {
+///     min_version: Some(TlsVersion::Tls13),
+///     cipher_policy: CipherPolicy::Modern,
+/// };
+/// let ctx = config.build().unwrap();
+/// ```
+#[cfg(feature = "secure")]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// The oldest protocol version accepted, `None` for the backend default.
+    pub min_version: Option<TlsVersion>,
+    /// The cipher suites allowed.
+    pub cipher_policy: CipherPolicy,
+}
+
+#[cfg(feature = "secure")]
+impl TlsConfig {
+    /// The minimum version to enforce, raised to TLS 1.2 by the modern
+    /// cipher policy.
+    fn effective_min_version(&self) -> Option<TlsVersion> {
+        match self.cipher_policy {
+            CipherPolicy::Default => self.min_version,
+            CipherPolicy::Modern => Some(
+                self.min_version
+                    .map_or(TlsVersion::Tls12, |min| min.max(TlsVersion::Tls12)),
+            ),
+        }
+    }
+
+    /// Builds an OpenSSL context verifying the server certificate against
+    /// the system roots.
+    #[cfg(not(feature = "native-tls"))]
+    pub fn build(&self) -> Result<TlsContext> {
+        use openssl::ssl::{SslConnector, SslMethod, SslVersion};
+
+        let mut builder = SslConnector::builder(SslMethod::tls())?;
+        builder.set_min_proto_version(self.effective_min_version().map(|min| match min {
+            TlsVersion::Tls12 => SslVersion::TLS1_2,
+            TlsVersion::Tls13 => SslVersion::TLS1_3,
+        }))?;
+        if self.cipher_policy == CipherPolicy::Modern {
+            // TLS 1.3 suites are all forward secret AEAD ones.
+            builder.set_cipher_list("ECDHE+AESGCM:ECDHE+CHACHA20")?;
+        }
+        Ok(builder.build().into_context())
+    }
+
+    /// Builds a `native-tls` connector verifying the server certificate
+    /// against the system roots.
+    #[cfg(feature = "native-tls")]
+    pub fn build(&self) -> Result<TlsContext> {
+        use native_tls::{Protocol, TlsConnector};
+
+        let mut builder = TlsConnector::builder();
+        builder.min_protocol_version(self.effective_min_version().map(|min| match min {
+            TlsVersion::Tls12 => Protocol::Tlsv12,
+            TlsVersion::Tls13 => Protocol::Tlsv13,
+        }));
+        Ok(builder.build()?)
+    }
+}
+
+/// The encoding of text files read with `FtpStream::retr_string`, see
+/// `FtpStream::set_text_encoding`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TextEncoding {
+    /// UTF-8 (the default). A leading byte order mark is stripped.
+    #[default]
+    Utf8,
+    /// ISO-8859-1, where every byte is the character of the same code point.
+    Latin1,
+}
+
+impl TextEncoding {
+    /// Decodes `bytes`, failing with `io::ErrorKind::InvalidData` if they
+    /// aren't valid in this encoding.
+    pub fn decode(self, bytes: Vec<u8>) -> io::Result<String> {
         match self {
-            &FileType::Ascii(ref fc) => format!("A {}", fc.as_str()),
-            &FileType::Ebcdic(ref fc) => format!("E {}", fc.as_str()),
-            &FileType::Image | &FileType::Binary => String::from("I"),
-            &FileType::Local(ref bits) => format!("L {}", bits),
+            TextEncoding::Utf8 => {
+                let text = String::from_utf8(bytes)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                Ok(match text.strip_prefix('\u{feff}') {
+                    Some(stripped) => stripped.to_owned(),
+                    None => text,
+                })
+            }
+            TextEncoding::Latin1 => Ok(bytes.into_iter().map(char::from).collect()),
+        }
+    }
+}
+
+/// Socket options for the control and data connections, see
+/// `FtpStream::connect_with_socket_config` and `FtpStream::set_socket_config`.
+///
+/// ```rust,no_run
+/// use ftp::types::SocketConfig;
+/// use ftp::FtpStream;
+///
+/// // Mark the traffic as DSCP AF11 (the upper six bits of the TOS byte).
+/// let config = SocketConfig {
+///     tos: Some(10 << 2),
+///     ..SocketConfig::default()
+/// };
+/// let mut ftp_stream = FtpStream::connect_with_socket_config("127.0.0.1:21", config).unwrap();
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SocketConfig {
+    /// The IP time-to-live of outgoing packets.
+    pub ttl: Option<u32>,
+    /// The IPv4 type-of-service byte, whose upper six bits are the DSCP.
+    /// Ignored for IPv6 connections.
+    pub tos: Option<u32>,
+    /// Sets `SO_REUSEADDR` on active mode listeners, so a port of
+    /// `ActiveConfig::port_range` can be reused while in `TIME_WAIT`.
+    pub reuse_address: bool,
+}
+
+impl SocketConfig {
+    /// Opens a connection to `addr` with the options set before connecting.
+    pub fn connect(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        let socket = self.socket(addr)?;
+        socket.connect(&addr.into())?;
+        Ok(socket.into())
+    }
+
+    /// Listens on `addr` with the options set before binding.
+    pub fn listen(&self, addr: SocketAddr) -> io::Result<TcpListener> {
+        let socket = self.socket(addr)?;
+        socket.set_reuse_address(self.reuse_address)?;
+        socket.bind(&addr.into())?;
+        socket.listen(128)?;
+        Ok(socket.into())
+    }
+
+    /// Sets the options on an open connection.
+    pub fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        self.set_options(&SockRef::from(stream), stream.local_addr()?)
+    }
+
+    fn socket(&self, addr: SocketAddr) -> io::Result<Socket> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        self.set_options(&socket, addr)?;
+        Ok(socket)
+    }
+
+    fn set_options(&self, socket: &Socket, addr: SocketAddr) -> io::Result<()> {
+        if let Some(ttl) = self.ttl {
+            socket.set_ttl(ttl)?;
+        }
+        if let (Some(tos), true) = (self.tos, addr.is_ipv4()) {
+            socket.set_tos(tos)?;
         }
+        Ok(())
+    }
+}
+
+/// The modification time of a file, as returned by `MDTM`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModificationTime {
+    /// The modification time, including fractional seconds if the server sent them.
+    pub time: DateTime<Utc>,
+    /// The UTC offset appended by some servers, which has already been applied
+    /// to `time`. Standard replies are in UTC and have none.
+    pub offset: Option<FixedOffset>,
+    /// Any text following the timestamp which wasn't understood.
+    pub remainder: String,
+}
+
+/// The size and modification time of a file, as returned by
+/// `FtpStream::stat_many`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FileStat {
+    /// The size in bytes, `None` if the server refused `SIZE`, e.g. for a
+    /// missing file or a directory.
+    pub size: Option<u64>,
+    /// The modification time, `None` if the server refused `MDTM`.
+    pub modified: Option<DateTime<Utc>>,
+}
+
+/// The kind of a directory entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    /// A symbolic link, with its target if the listing shows it.
+    Symlink {
+        target: String,
+    },
+    /// Devices, sockets, pipes and the like.
+    Other,
+}
+
+/// What can be done with a directory entry, see `parse::unix_permissions`
+/// and `parse::mlst_permissions`.
+///
+/// `LIST` shows Unix permissions without telling whether the user owns the
+/// entry, so the flags parsed from it are the owner's. Removing or renaming
+/// an entry depends on the permissions of its directory, which `LIST`
+/// doesn't show either, so they are assumed to go with `writable`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions {
+    /// The Unix mode bits, such as `0o755`, when parsed from `LIST`.
+    pub mode: Option<u32>,
+    /// The file can be downloaded.
+    pub readable: bool,
+    /// The file can be stored or appended to, or files and directories can
+    /// be created in the directory.
+    pub writable: bool,
+    /// The directory can be listed.
+    pub listable: bool,
+    /// The directory can be made the current directory.
+    pub enterable: bool,
+    /// The entry can be removed.
+    pub deletable: bool,
+    /// The entry can be renamed.
+    pub renamable: bool,
+}
+
+/// An entry of a directory listing, see `parse::list_line`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub kind: EntryKind,
+    /// Size in bytes, if the listing shows it.
+    pub size: Option<u64>,
+    /// Modification time in the server's time zone, which listings don't tell.
+    pub modified: Option<NaiveDateTime>,
+    /// Permissions, if the listing shows them.
+    pub permissions: Option<Permissions>,
+    /// The listing line the entry was parsed from.
+    pub raw: String,
+}
+
+impl DirEntry {
+    /// Returns `true` if the entry is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.kind == EntryKind::Directory
+    }
+
+    /// Returns `true` if the entry is a regular file.
+    pub fn is_file(&self) -> bool {
+        self.kind == EntryKind::File
+    }
+
+    /// Returns `true` if the entry is a symbolic link. Walkers shouldn't
+    /// descend into it as a directory, lest they loop.
+    pub fn is_symlink(&self) -> bool {
+        matches!(self.kind, EntryKind::Symlink { .. })
+    }
+
+    /// The target of a symbolic link, if the listing shows it.
+    pub fn link_target(&self) -> Option<&str> {
+        match self.kind {
+            EntryKind::Symlink { ref target } if !target.is_empty() => Some(target),
+            _ => None,
+        }
+    }
+
+    /// The extension of the name, after its last `.`, if any. Names starting
+    /// with their only `.`, such as `.profile`, have none.
+    pub fn extension(&self) -> Option<&str> {
+        match self.name.rfind('.') {
+            Some(0) | None => None,
+            Some(pos) => Some(&self.name[pos + 1..]),
+        }
+    }
+
+    /// The path of the entry, listed in the directory `dir`.
+    pub fn path_in(&self, dir: &FtpPath) -> FtpPath {
+        dir.join(&self.name)
+    }
+
+    /// The normalized path the symbolic link points to, a relative target
+    /// being resolved against `dir`, the directory the entry is listed in.
+    pub fn link_path_in(&self, dir: &FtpPath) -> Option<FtpPath> {
+        self.link_target()
+            .map(|target| dir.join(target).normalize())
+    }
+}
+
+/// What `DirEntries::sorted_by` sorts entries on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    /// The name, in byte order.
+    Name,
+    /// The size, entries without one first.
+    Size,
+    /// The modification time, entries without one first.
+    Modified,
+}
+
+/// Sorting and filtering of the entries returned by
+/// `FtpStream::list_entries`, chained like iterator adapters.
+///
+/// ```rust
+/// use ftp::parse;
+/// use ftp::types::{DirEntries, SortKey};
+///
+/// let entries: Vec<_> = [
+///     "-rw-r--r-- 1 ftp ftp 300 Jan 01 2024 b.CSV",
+///     "drwxr-xr-x 2 ftp ftp 4096 Jan 01 2024 data.csv",
+///     "-rw-r--r-- 1 ftp ftp 100 Jan 02 2024 a.csv",
+///     "-rw-r--r-- 1 ftp ftp 200 Jan 03 2024 notes.txt",
+/// ]
+/// .iter()
+/// .filter_map(|line| parse::list_line(line))
+/// .collect();
+/// let csv = entries.files().with_extension("csv").sorted_by(SortKey::Size);
+/// let names: Vec<_> = csv.iter().map(|entry| entry.name.as_str()).collect();
+/// assert_eq!(names, ["a.csv", "b.CSV"]);
+/// ```
+pub trait DirEntries: Sized {
+    /// Sorts the entries by `key`, keeping the order of equal ones.
+    fn sorted_by(self, key: SortKey) -> Self;
+
+    /// Keeps the regular files.
+    fn files(self) -> Self;
+
+    /// Keeps the directories.
+    fn dirs(self) -> Self;
+
+    /// Keeps the entries whose extension is `extension`, ignoring case.
+    fn with_extension(self, extension: &str) -> Self;
+}
+
+impl DirEntries for Vec<DirEntry> {
+    fn sorted_by(mut self, key: SortKey) -> Self {
+        match key {
+            SortKey::Name => self.sort_by(|a, b| a.name.cmp(&b.name)),
+            SortKey::Size => self.sort_by_key(|entry| entry.size),
+            SortKey::Modified => self.sort_by_key(|entry| entry.modified),
+        }
+        self
+    }
+
+    fn files(mut self) -> Self {
+        self.retain(DirEntry::is_file);
+        self
+    }
+
+    fn dirs(mut self) -> Self {
+        self.retain(DirEntry::is_dir);
+        self
+    }
+
+    fn with_extension(mut self, extension: &str) -> Self {
+        self.retain(|entry| {
+            entry
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+        });
+        self
+    }
+}
+
+/// The extensions a server advertises in its `FEAT` reply, see
+/// `FtpStream::feat`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Features {
+    /// The upper case feature names, with their parameters.
+    features: BTreeMap<String, String>,
+}
+
+impl Features {
+    /// Adds a feature and its parameters, e.g. `MLST` and
+    /// `type*;size*;modify*;`.
+    pub fn insert(&mut self, name: &str, params: &str) {
+        self.features
+            .insert(name.to_ascii_uppercase(), params.to_owned());
+    }
+
+    /// Returns `true` if the server advertised the feature, ignoring case.
+    pub fn supports(&self, name: &str) -> bool {
+        self.features.contains_key(&name.to_ascii_uppercase())
+    }
+
+    /// The parameters of a feature, empty if it has none, or `None` if the
+    /// server didn't advertise it.
+    pub fn params(&self, name: &str) -> Option<&str> {
+        self.features
+            .get(&name.to_ascii_uppercase())
+            .map(String::as_str)
+    }
+
+    /// The names of the advertised features, in upper case.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.features.keys().map(String::as_str)
+    }
+}
+
+/// The reply to a successful login, see `FtpStream::login`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LoginInfo {
+    /// The lines of the `230` reply, without the reply code. Servers put
+    /// the message of the day, the last login time or the user's quota
+    /// there.
+    pub lines: Vec<String>,
+}
+
+/// The FTP server software, as far as its welcome message and its reply to
+/// `SYST` tell, see `FtpStream::server_info`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ServerInfo {
+    /// The server software, `None` if it isn't one of the known servers.
+    pub software: Option<ServerSoftware>,
+    /// The version of the server software, if the server tells it.
+    pub version: Option<String>,
+    /// The system type reported by `SYST`, e.g. `UNIX Type: L8`.
+    pub system: Option<String>,
+}
+
+/// FTP server software whose quirks are known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerSoftware {
+    VsFtpd,
+    ProFtpd,
+    FileZilla,
+    /// The FTP service of Microsoft IIS.
+    Iis,
+}
+
+/// What an upload does when the remote file already exists, see
+/// `FtpStream::upload_from` and `FtpStream::put_with_policy`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Replace the remote file (the default).
+    #[default]
+    Overwrite,
+    /// Leave the remote file alone and upload nothing.
+    Skip,
+    /// Fail with `FtpError::AlreadyExists`.
+    Fail,
+    /// Take a remote file shorter than the local data as an interrupted
+    /// upload and append the rest to it with `APPE`. A remote file of the
+    /// same length is left alone, a longer one is replaced.
+    Resume,
+    /// Upload under the first free name given by `FtpPath::numbered`, such
+    /// as `report.1.csv`.
+    RenameNew,
+}
+
+/// The storage quota of the user, see `FtpStream::quota`. The amounts are
+/// in bytes, `None` when the server didn't report them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    /// The space used by the user's files.
+    pub used: Option<u64>,
+    /// The most space the user's files may use, `None` if unlimited.
+    pub limit: Option<u64>,
+}
+
+impl Quota {
+    /// The space left before reaching the limit, if both are known.
+    pub fn available(&self) -> Option<u64> {
+        Some(self.limit?.saturating_sub(self.used?))
+    }
+}
+
+/// The result of an upload with an `OverwritePolicy`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UploadOutcome {
+    /// The data was stored at `path`, `bytes` of it uploaded: fewer than
+    /// the whole data when resuming.
+    Stored { path: String, bytes: u64 },
+    /// The remote file was left alone.
+    Skipped,
+}
+
+/// A difference between the files of a local and a remote directory, see
+/// `FtpStream::diff`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffEntry {
+    /// The file only exists in the local directory.
+    OnlyLocal(String),
+    /// The file only exists in the remote directory.
+    OnlyRemote(String),
+    /// The file exists on both sides with a different size or modification
+    /// time. The remote values are `None` when the server didn't report them.
+    Differs {
+        name: String,
+        local_size: u64,
+        remote_size: Option<u64>,
+        local_modified: Option<DateTime<Utc>>,
+        remote_modified: Option<DateTime<Utc>>,
+    },
+}
+
+impl DiffEntry {
+    /// The name of the file.
+    pub fn name(&self) -> &str {
+        match *self {
+            DiffEntry::OnlyLocal(ref name)
+            | DiffEntry::OnlyRemote(ref name)
+            | DiffEntry::Differs { ref name, .. } => name,
+        }
+    }
+}
+
+/// `Line` contains a reply code and the contents of a line of text read from the network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line(pub Status, pub String);
+
+/// A reply carried by an error, with the command it answered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply {
+    /// The command the reply answered, with any password masked. `None` for
+    /// a reply to no command, such as the welcome message.
+    pub command: Option<String>,
+    /// The last line of the reply.
+    pub line: Line,
+    /// Every line of the reply, reply codes included.
+    pub lines: Vec<String>,
+}
+
+impl From<Line> for Reply {
+    fn from(line: Line) -> Reply {
+        Reply {
+            command: None,
+            lines: vec![line.1.clone()],
+            line,
+        }
+    }
+}
+
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.line.1)?;
+        match self.command {
+            Some(ref command) => write!(f, " (in reply to {})", command),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for FormatControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FileType::Ascii(ref fc) => write!(f, "A {}", fc.as_str()),
+            FileType::Ebcdic(ref fc) => write!(f, "E {}", fc.as_str()),
+            FileType::Image | FileType::Binary => f.write_str("I"),
+            FileType::Local(ref bits) => write!(f, "L {}", bits),
+        }
+    }
+}
+
+impl fmt::Display for TransmissionMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            TransmissionMode::Stream => "S",
+            TransmissionMode::Block => "B",
+        })
     }
 }
 
@@ -121,7 +1042,36 @@ impl fmt::Display for FtpError {
             FtpError::InvalidResponse(ref desc) => {
                 write!(f, "FTP InvalidResponse: {}", desc)
             }
+            FtpError::UnexpectedResponse(ref reply) => {
+                write!(f, "FTP UnexpectedResponse: {}", reply)
+            }
+            FtpError::ServiceNotAvailable(ref reply) => {
+                write!(f, "FTP ServiceNotAvailable: {}", reply)
+            }
             FtpError::InvalidAddress(ref aperr) => write!(f, "FTP InvalidAddress: {}", aperr),
+            FtpError::InvalidArgument(ref desc) => write!(f, "FTP InvalidArgument: {}", desc),
+            FtpError::SizeLimitExceeded(limit) => {
+                write!(f, "FTP SizeLimitExceeded: more than {} bytes", limit)
+            }
+            FtpError::Aborted => write!(f, "FTP Aborted: the transfer was aborted"),
+            FtpError::TransferAborted { bytes, ref reply } => {
+                write!(f, "FTP TransferAborted after {} bytes: {}", bytes, reply)
+            }
+            FtpError::TlsRequired => {
+                write!(f, "FTP TlsRequired: the control connection isn't secured")
+            }
+            FtpError::AlreadyExists(ref path) => {
+                write!(f, "FTP AlreadyExists: {} already exists", path)
+            }
+            FtpError::NotFound(ref path) => write!(f, "FTP NotFound: {} doesn't exist", path),
+            FtpError::InsufficientStorage { needed, available } => write!(
+                f,
+                "FTP InsufficientStorage: {} bytes needed, {} available",
+                needed, available
+            ),
+            FtpError::Changed(ref path) => {
+                write!(f, "FTP Changed: {} changed since the checkpoint", path)
+            }
         }
     }
 }
@@ -133,7 +1083,18 @@ impl std::error::Error for FtpError {
             #[cfg(feature = "secure")]
             FtpError::SecureError(_) => None,
             FtpError::InvalidResponse(_) => None,
+            FtpError::UnexpectedResponse(_) => None,
+            FtpError::ServiceNotAvailable(_) => None,
             FtpError::InvalidAddress(ref aperr) => Some(aperr),
+            FtpError::InvalidArgument(_) => None,
+            FtpError::SizeLimitExceeded(_) => None,
+            FtpError::Aborted => None,
+            FtpError::TransferAborted { .. } => None,
+            FtpError::TlsRequired => None,
+            FtpError::AlreadyExists(_) => None,
+            FtpError::NotFound(_) => None,
+            FtpError::InsufficientStorage { .. } => None,
+            FtpError::Changed(_) => None,
         }
     }
 }
@@ -143,6 +1104,48 @@ mod tests {
 
     use super::*;
 
+    fn entry(name: &str, kind: EntryKind, size: u64, day: u32) -> DirEntry {
+        DirEntry {
+            name: name.to_owned(),
+            kind,
+            size: Some(size),
+            modified: chrono::NaiveDate::from_ymd_opt(2024, 1, day)
+                .and_then(|date| date.and_hms_opt(0, 0, 0)),
+            permissions: None,
+            raw: String::new(),
+        }
+    }
+
+    #[test]
+    fn dir_entries() {
+        let entries = vec![
+            entry("b.txt", EntryKind::File, 3, 1),
+            entry("pub", EntryKind::Directory, 0, 3),
+            entry("a.TXT", EntryKind::File, 2, 2),
+            entry(".txt", EntryKind::File, 1, 4),
+        ];
+        let names = |entries: Vec<DirEntry>| -> Vec<String> {
+            entries.into_iter().map(|entry| entry.name).collect()
+        };
+        assert_eq!(
+            names(entries.clone().sorted_by(SortKey::Name)),
+            [".txt", "a.TXT", "b.txt", "pub"]
+        );
+        assert_eq!(
+            names(entries.clone().sorted_by(SortKey::Size)),
+            ["pub", ".txt", "a.TXT", "b.txt"]
+        );
+        assert_eq!(
+            names(entries.clone().sorted_by(SortKey::Modified)),
+            ["b.txt", "a.TXT", "pub", ".txt"]
+        );
+        assert_eq!(names(entries.clone().dirs()), ["pub"]);
+        assert_eq!(
+            names(entries.files().with_extension("txt")),
+            ["b.txt", "a.TXT"]
+        );
+    }
+
     #[test]
     fn error_str() {
         assert_eq!(
@@ -158,6 +1161,71 @@ mod tests {
             FtpError::InvalidResponse("500 Bad Command".to_owned()).to_string(),
             "FTP InvalidResponse: 500 Bad Command"
         );
+
+        let line = Line(Status::from(550), "550 No such file".to_owned());
+        let err = FtpError::UnexpectedResponse(line.clone().into());
+        assert_eq!(err.to_string(), "FTP UnexpectedResponse: 550 No such file");
+        assert_eq!(err.status(), Some(Status::FileUnavailable));
+        assert_eq!(err.command(), None);
+
+        let err = FtpError::UnexpectedResponse(Reply {
+            command: Some("RETR report.csv".to_owned()),
+            lines: vec!["550-report.csv:".to_owned(), line.1.clone()],
+            line,
+        });
+        assert_eq!(
+            err.to_string(),
+            "FTP UnexpectedResponse: 550 No such file (in reply to RETR report.csv)"
+        );
+        assert_eq!(err.command(), Some("RETR report.csv"));
+        assert_eq!(
+            err.reply_lines().unwrap(),
+            ["550-report.csv:", "550 No such file"]
+        );
+
+        assert_eq!(
+            FtpError::SizeLimitExceeded(1024).to_string(),
+            "FTP SizeLimitExceeded: more than 1024 bytes"
+        );
+
+        assert_eq!(
+            FtpError::TlsRequired.to_string(),
+            "FTP TlsRequired: the control connection isn't secured"
+        );
+    }
+
+    #[test]
+    fn error_classes() {
+        let reply = |code: u32| {
+            FtpError::UnexpectedResponse(Line(Status::from(code), String::new()).into())
+        };
+        assert!(reply(450).is_transient() && !reply(450).is_permanent());
+        assert!(reply(553).is_permanent() && !reply(553).is_transient());
+        assert!(!reply(257).is_transient() && !reply(257).is_permanent());
+        let closed =
+            FtpError::ServiceNotAvailable(Line(Status::NotAvailable, String::new()).into());
+        assert!(closed.is_transient());
+
+        let io_err = |kind| FtpError::ConnectionError(std::io::Error::new(kind, "io"));
+        assert!(io_err(std::io::ErrorKind::ConnectionReset).is_transient());
+        assert!(!io_err(std::io::ErrorKind::PermissionDenied).is_transient());
+        assert!(FtpError::TlsRequired.is_permanent());
+        assert!(!FtpError::InvalidResponse(String::new()).is_permanent());
+    }
+
+    #[test]
+    fn text_encoding_decode() {
+        assert_eq!(
+            TextEncoding::Utf8
+                .decode(b"\xef\xbb\xbfcaf\xc3\xa9".to_vec())
+                .unwrap(),
+            "caf\u{e9}"
+        );
+        assert!(TextEncoding::Utf8.decode(b"caf\xe9".to_vec()).is_err());
+        assert_eq!(
+            TextEncoding::Latin1.decode(b"caf\xe9".to_vec()).unwrap(),
+            "caf\u{e9}"
+        );
     }
 
     #[test]
@@ -176,4 +1244,24 @@ mod tests {
         assert_eq!(FileType::Binary.to_string(), "I");
         assert_eq!(FileType::Local(6).to_string(), "L 6");
     }
+
+    #[cfg(feature = "secure")]
+    #[test]
+    fn tls_config() {
+        let config = TlsConfig::default();
+        assert_eq!(config.effective_min_version(), None);
+        let modern = TlsConfig {
+            cipher_policy: CipherPolicy::Modern,
+            ..TlsConfig::default()
+        };
+        assert_eq!(modern.effective_min_version(), Some(TlsVersion::Tls12));
+        let tls13 = TlsConfig {
+            min_version: Some(TlsVersion::Tls13),
+            ..modern.clone()
+        };
+        assert_eq!(tls13.effective_min_version(), Some(TlsVersion::Tls13));
+        for config in [config, modern, tls13] {
+            config.build().unwrap();
+        }
+    }
 }
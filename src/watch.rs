@@ -0,0 +1,255 @@
+//! Watching a remote directory or file for changes.
+//!
+//! FTP has no change notifications, so `FtpStream::watch` polls the listing
+//! of a directory and compares it with the previous one. The `Watcher` it
+//! returns is an iterator over the changes, waiting for the next poll when
+//! none are left, which suits jobs ingesting the files partners drop on a
+//! server. A file still being uploaded shows up as created, then modified
+//! until the upload ends.
+//!
+//! `FtpStream::follow` polls the size of a file instead, like `tail -f`:
+//! its `Follower` yields the bytes appended to the file, downloaded with
+//! `REST` from where the previous download ended, e.g. to monitor the logs
+//! a legacy system writes on a server.
+//!
+//! ```rust,no_run
+//! use ftp::watch::WatchEvent;
+//! use ftp::FtpStream;
+//! use std::time::Duration;
+//!
+//! let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
+//! ftp_stream.login("anonymous", "anonymous").unwrap();
+//! for event in ftp_stream.watch("/incoming", Duration::from_secs(30)).unwrap() {
+//!     match event.unwrap() {
+//!         WatchEvent::Created(entry) => println!("new file {}", entry.name),
+//!         WatchEvent::Modified(entry) => println!("{} changed", entry.name),
+//!         WatchEvent::Deleted(entry) => println!("{} is gone", entry.name),
+//!     }
+//! }
+//! ```
+
+use super::{ftp::GenericFtpStream, path::FtpPath, transport::Transport, types::DirEntry};
+
+use {
+    chrono::NaiveDateTime,
+    std::{
+        collections::{BTreeMap, VecDeque},
+        fmt, thread,
+        time::Duration,
+    },
+};
+
+/// A change of a watched directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent {
+    /// The entry appeared in the directory.
+    Created(DirEntry),
+    /// The size or the modification time of the entry changed.
+    Modified(DirEntry),
+    /// The entry, as last listed, disappeared from the directory.
+    Deleted(DirEntry),
+}
+
+impl WatchEvent {
+    /// The entry which changed.
+    pub fn entry(&self) -> &DirEntry {
+        match *self {
+            WatchEvent::Created(ref entry)
+            | WatchEvent::Modified(ref entry)
+            | WatchEvent::Deleted(ref entry) => entry,
+        }
+    }
+}
+
+/// The size and modification time of an entry, compared from poll to poll.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Stamp {
+    size: Option<u64>,
+    modified: Option<NaiveDateTime>,
+}
+
+/// Polls a remote directory for changes, see `GenericFtpStream::watch`.
+///
+/// The first poll, done by `watch`, only records the entries present, so
+/// they aren't reported as created. The iterator never ends: it yields the
+/// changes found by each poll, in name order, and the errors of failed
+/// polls, after which polling goes on.
+pub struct Watcher<'a, S: Transport> {
+    ftp_stream: &'a mut GenericFtpStream<S>,
+    path: String,
+    interval: Duration,
+    entries: BTreeMap<String, (DirEntry, Stamp)>,
+    pending: VecDeque<WatchEvent>,
+}
+
+impl<'a, S: Transport> Watcher<'a, S> {
+    pub(crate) fn new(
+        ftp_stream: &'a mut GenericFtpStream<S>,
+        path: &str,
+        interval: Duration,
+    ) -> crate::Result<Watcher<'a, S>> {
+        let mut watcher = Watcher {
+            ftp_stream,
+            path: path.to_owned(),
+            interval,
+            entries: BTreeMap::new(),
+            pending: VecDeque::new(),
+        };
+        watcher.entries = watcher.snapshot()?;
+        Ok(watcher)
+    }
+
+    /// Lists the directory now and returns the changes since the previous
+    /// poll, for callers scheduling the polls themselves.
+    pub fn poll(&mut self) -> crate::Result<Vec<WatchEvent>> {
+        let current = self.snapshot()?;
+        let mut previous = std::mem::replace(&mut self.entries, current);
+        let mut events = Vec::new();
+        for (name, (entry, stamp)) in &self.entries {
+            match previous.remove(name) {
+                None => events.push(WatchEvent::Created(entry.clone())),
+                Some((_, ref old)) if old != stamp => {
+                    events.push(WatchEvent::Modified(entry.clone()))
+                }
+                Some(_) => {}
+            }
+        }
+        events.extend(
+            previous
+                .into_values()
+                .map(|(entry, _)| WatchEvent::Deleted(entry)),
+        );
+        events.sort_by(|a, b| a.entry().name.cmp(&b.entry().name));
+        Ok(events)
+    }
+
+    /// Lists the directory. Without `MLSD`, listings show modification
+    /// times to the minute at best, so files are also asked their `MDTM`.
+    fn snapshot(&mut self) -> crate::Result<BTreeMap<String, (DirEntry, Stamp)>> {
+        let precise = self.ftp_stream.supports("MLST");
+        let dir = FtpPath::new(self.path.as_str());
+        let mut entries = BTreeMap::new();
+        for entry in self.ftp_stream.list_entries(Some(&self.path))? {
+            let mut modified = entry.modified;
+            if !precise && entry.is_file() {
+                let path = entry.path_in(&dir);
+                match self.ftp_stream.mdtm(path.as_str()) {
+                    Ok(time) => modified = time.map(|time| time.naive_utc()),
+                    // Without MDTM only the listing can be compared.
+                    Err(crate::FtpError::UnexpectedResponse(_)) => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            let stamp = Stamp {
+                size: entry.size,
+                modified,
+            };
+            entries.insert(entry.name.clone(), (entry, stamp));
+        }
+        Ok(entries)
+    }
+}
+
+impl<S: Transport> Iterator for Watcher<'_, S> {
+    type Item = crate::Result<WatchEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            thread::sleep(self.interval);
+            match self.poll() {
+                Ok(events) => self.pending.extend(events),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+impl<S: Transport> fmt::Debug for Watcher<'_, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Watcher")
+            .field("path", &self.path)
+            .field("interval", &self.interval)
+            .field("entries", &self.entries.len())
+            .finish()
+    }
+}
+
+/// Follows the end of a remote file, see `GenericFtpStream::follow`.
+///
+/// The iterator never ends: it yields the data appended to the file by each
+/// poll which found some, and the errors of failed polls, after which
+/// polling goes on. A file which became shorter is taken as truncated or
+/// replaced, and followed again from its start.
+pub struct Follower<'a, S: Transport> {
+    ftp_stream: &'a mut GenericFtpStream<S>,
+    path: String,
+    interval: Duration,
+    offset: u64,
+}
+
+impl<'a, S: Transport> Follower<'a, S> {
+    pub(crate) fn new(
+        ftp_stream: &'a mut GenericFtpStream<S>,
+        path: &str,
+        interval: Duration,
+        offset: u64,
+    ) -> Follower<'a, S> {
+        Follower {
+            ftp_stream,
+            path: path.to_owned(),
+            interval,
+            offset,
+        }
+    }
+
+    /// The offset in the file the next data will be read from.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Checks the size of the file now and downloads the data appended
+    /// since the previous poll, empty if none, for callers scheduling the
+    /// polls themselves.
+    pub fn poll(&mut self) -> crate::Result<Vec<u8>> {
+        let size =
+            self.ftp_stream.size(&self.path)?.ok_or_else(|| {
+                crate::FtpError::InvalidResponse(format!("no size for {}", self.path))
+            })? as u64;
+        if size < self.offset {
+            self.offset = 0;
+        }
+        let mut data = Vec::new();
+        if size > self.offset {
+            if self.offset > 0 {
+                self.ftp_stream.restart_at(self.offset)?;
+            }
+            self.offset += self.ftp_stream.retr_to_writer(&self.path, &mut data)?;
+        }
+        Ok(data)
+    }
+}
+
+impl<S: Transport> Iterator for Follower<'_, S> {
+    type Item = crate::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            thread::sleep(self.interval);
+            match self.poll() {
+                Ok(data) if data.is_empty() => {}
+                res => return Some(res),
+            }
+        }
+    }
+}
+
+impl<S: Transport> fmt::Debug for Follower<'_, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Follower")
+            .field("path", &self.path)
+            .field("interval", &self.interval)
+            .field("offset", &self.offset)
+            .finish()
+    }
+}
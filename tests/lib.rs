@@ -1,16 +1,30 @@
 #[cfg(test)]
 extern crate ftp;
 
-use ftp::FtpStream;
-use std::io::Cursor;
+use ftp::observer::{FtpObserver, Transcript, TranscriptRecorder};
+use ftp::pool::FtpConnectionPool;
+use ftp::queue::{TransferJob, TransferQueue};
+use ftp::status::{ResponseClass, Status};
+use ftp::testing::{ReplayServer, TestServer};
+use ftp::transport::Transport;
+use ftp::types::{
+    ActiveConfig, DataConnectOrder, DirEntry, EntryKind, FileStat, FileType, FormatControl, Line,
+    Mode, OverwritePolicy, ParseMode, PassiveAddressPolicy, ServerSoftware, SocketConfig, TlsMode,
+    TransmissionMode, UploadOutcome,
+};
+use ftp::{DataStream, FtpError, FtpStream, GenericFtpStream};
+use std::io::{Cursor, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 #[test]
 fn test_ftp() {
-    let mut ftp_stream = FtpStream::connect("127.0.0.1:21").unwrap();
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
 
     println!("Welcome message: {:?}", ftp_stream.get_welcome_msg());
 
-    let _ = ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
 
     ftp_stream.mkdir("test_dir").unwrap();
     ftp_stream.cwd("test_dir").unwrap();
@@ -37,3 +51,2664 @@ fn test_ftp() {
         .and_then(|_| ftp_stream.quit())
         .is_ok());
 }
+
+#[test]
+fn test_transfer_queue() {
+    let server = TestServer::start().unwrap();
+    let connect = || {
+        let mut ftp_stream = FtpStream::connect(server.addr())?;
+        ftp_stream.login("Doe", "mumble")?;
+        Ok(ftp_stream)
+    };
+    let dir = std::env::temp_dir().join("ftp_transfer_queue");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut queue = TransferQueue::new(2, &connect);
+    for i in 0..4 {
+        let local = dir.join(format!("queue_{}.txt", i));
+        std::fs::write(&local, format!("queued file {}\n", i)).unwrap();
+        queue.push(TransferJob::upload(local, format!("queue_{}.txt", i)));
+    }
+    let report = queue.run();
+    assert_eq!(report.len(), 4);
+    assert_eq!(report.succeeded(), 4);
+
+    let mut queue = TransferQueue::new(2, &connect);
+    for i in 0..4 {
+        let local = dir.join(format!("queue_{}.copy", i));
+        queue.push(TransferJob::download(format!("queue_{}.txt", i), local));
+    }
+    queue.push(TransferJob::download(
+        "queue_missing.txt",
+        dir.join("missing"),
+    ));
+    let report = queue.run();
+    assert_eq!(report.succeeded(), 4);
+    assert_eq!(report.failures().count(), 1);
+    assert!(!dir.join("missing").exists());
+    for i in 0..4 {
+        assert_eq!(
+            std::fs::read_to_string(dir.join(format!("queue_{}.copy", i))).unwrap(),
+            format!("queued file {}\n", i)
+        );
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_put_from_path() {
+    let local = std::env::temp_dir().join("ftp_put_from_path.txt");
+    std::fs::write(&local, "uploaded from a path\n").unwrap();
+
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream
+        .put_from_path("put_from_path.txt", &local)
+        .unwrap();
+    assert_eq!(
+        ftp_stream
+            .simple_retr("put_from_path.txt")
+            .unwrap()
+            .into_inner(),
+        b"uploaded from a path\n"
+    );
+    ftp_stream.rm("put_from_path.txt").unwrap();
+    ftp_stream.quit().unwrap();
+    std::fs::remove_file(local).unwrap();
+}
+
+#[test]
+fn test_reusable_buffers() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.mkdir("buffers_dir").unwrap();
+    ftp_stream.cwd("buffers_dir").unwrap();
+    for name in &["a.txt", "b.txt"] {
+        let mut reader = Cursor::new(name.as_bytes());
+        ftp_stream.put(name, &mut reader).unwrap();
+    }
+
+    let mut buffer = b"stale".to_vec();
+    assert_eq!(ftp_stream.retr_into("a.txt", &mut buffer).unwrap(), 5);
+    assert_eq!(buffer, b"a.txt");
+
+    let mut writer = Cursor::new(Vec::new());
+    assert_eq!(ftp_stream.retr_to_writer("b.txt", &mut writer).unwrap(), 5);
+    assert_eq!(writer.into_inner(), b"b.txt");
+
+    let mut lines = vec![String::from("stale"); 5];
+    ftp_stream.nlst_into(None, &mut lines).unwrap();
+    assert_eq!(lines, vec!["a.txt", "b.txt"]);
+    ftp_stream.list_into(None, &mut lines).unwrap();
+    assert_eq!(lines.len(), 2);
+
+    ftp_stream.rm("a.txt").unwrap();
+    ftp_stream.rm("b.txt").unwrap();
+    ftp_stream.cdup().unwrap();
+    ftp_stream.rmdir("buffers_dir").unwrap();
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_passive_address_policy() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.set_passive_address_policy(PassiveAddressPolicy::UseControlPeer);
+    assert!(ftp_stream.list(None).unwrap().is_empty());
+    ftp_stream
+        .set_passive_address_policy(PassiveAddressPolicy::Override("127.0.0.1".parse().unwrap()));
+    assert!(ftp_stream.nlst(None).unwrap().is_empty());
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_active_mode() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.set_mode(Mode::Active(ActiveConfig {
+        port_range: Some(40000..=40100),
+        external_ip: None,
+    }));
+
+    let mut reader = Cursor::new("active data".as_bytes());
+    ftp_stream.put("active.txt", &mut reader).unwrap();
+    assert_eq!(ftp_stream.nlst(None).unwrap(), vec!["active.txt"]);
+    assert_eq!(
+        ftp_stream.simple_retr("active.txt").unwrap().into_inner(),
+        b"active data"
+    );
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_service_not_available() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    server.expire_sessions();
+    match ftp_stream.noop() {
+        Err(FtpError::ServiceNotAvailable(_)) => {}
+        res => panic!("expected a 421, got {:?}", res),
+    }
+    assert!(ftp_stream.is_closed());
+    assert!(ftp_stream.noop().is_err());
+
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.set_auto_reconnect(true);
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.mkdir("reconnect_dir").unwrap();
+    ftp_stream.cwd("reconnect_dir").unwrap();
+    server.expire_sessions();
+    // The session is restored in the same directory and the command retried.
+    assert_eq!(ftp_stream.pwd().unwrap(), "/reconnect_dir");
+    assert!(!ftp_stream.is_closed());
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_metrics() {
+    use ftp::metrics::Counters;
+
+    let server = TestServer::start().unwrap();
+    let counters = Arc::new(Counters::new());
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.set_metrics(Some(counters.clone()));
+    ftp_stream.set_auto_reconnect(true);
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let snapshot = counters.snapshot();
+    assert_eq!(snapshot.commands, 2);
+    assert_eq!((snapshot.replies(2), snapshot.replies(3)), (1, 1));
+
+    ftp_stream.put_bytes("hello.txt", b"hello").unwrap();
+    assert_eq!(ftp_stream.get_bytes("hello.txt").unwrap(), b"hello");
+    let mut file = ftp_stream.open("hello.txt").unwrap();
+    file.read_to_end(&mut Vec::new()).unwrap();
+    drop(file);
+    assert!(ftp_stream.cwd("missing").is_err());
+    let snapshot = counters.snapshot();
+    assert_eq!(snapshot.bytes_uploaded, 5);
+    assert_eq!(snapshot.bytes_downloaded, 10);
+    assert_eq!(snapshot.replies(5), 1);
+    // Every command got a final reply, transfers a preliminary one too.
+    assert_eq!(
+        snapshot.commands,
+        snapshot.replies(2) + snapshot.replies(3) + snapshot.replies(4) + snapshot.replies(5)
+    );
+    assert!(snapshot.replies(1) >= 3);
+
+    ftp_stream.list(None).unwrap();
+    assert!(counters.snapshot().bytes_downloaded > 10);
+    let mut other = ftp_stream.spawn_session().unwrap();
+    let commands = counters.snapshot().commands;
+    other.noop().unwrap();
+    assert_eq!(counters.snapshot().commands, commands + 1);
+
+    server.expire_sessions();
+    ftp_stream.pwd().unwrap();
+    assert_eq!(counters.snapshot().reconnects, 1);
+
+    // The bytes sent before an upload failed are counted too.
+    struct Failing;
+    impl Read for Failing {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("disk gone"))
+        }
+    }
+    let uploaded = counters.snapshot().bytes_uploaded;
+    let mut reader = Cursor::new("partial").chain(Failing);
+    assert!(ftp_stream.put("partial.txt", &mut reader).is_err());
+    assert_eq!(counters.snapshot().bytes_uploaded, uploaded + 7);
+    ftp_stream.set_metrics(None);
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_transfer_buffer_size() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let data: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+    // Smaller than the data, the commands and the listing lines.
+    ftp_stream.set_transfer_buffer_size(7);
+    for name in ["a.bin", "b.bin"] {
+        ftp_stream.put(name, &mut Cursor::new(&data)).unwrap();
+        assert_eq!(ftp_stream.get_bytes(name).unwrap(), data);
+    }
+    ftp_stream
+        .stor("c.txt", |writer| {
+            Ok(writer.write_all(b"line one\nline two\n")?)
+        })
+        .unwrap();
+    assert_eq!(ftp_stream.list(None).unwrap().len(), 3);
+    let mut names = ftp_stream.nlst(None).unwrap();
+    names.sort();
+    assert_eq!(names, ["a.bin", "b.bin", "c.txt"]);
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_session_events() {
+    use ftp::events::SessionEvent;
+    use std::sync::mpsc;
+
+    let server = TestServer::start().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.set_event_sender(Some(sender));
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.put_bytes("hello.txt", b"hello").unwrap();
+    assert_eq!(ftp_stream.get_bytes("hello.txt").unwrap(), b"hello");
+    assert!(ftp_stream.get_bytes("missing.txt").is_err());
+    ftp_stream.quit().unwrap();
+    drop(ftp_stream);
+
+    let events: Vec<_> = receiver
+        .into_iter()
+        .filter(|event| !matches!(event, SessionEvent::TransferProgress { .. }))
+        .collect();
+    let started = |command, path: &str| SessionEvent::TransferStarted {
+        command,
+        path: Some(path.to_owned()),
+    };
+    assert_eq!(
+        events,
+        vec![
+            SessionEvent::Connected {
+                peer: Some(server.addr())
+            },
+            SessionEvent::LoggedIn {
+                user: "Doe".to_owned()
+            },
+            started("STOR", "hello.txt"),
+            SessionEvent::TransferCompleted { bytes: 5 },
+            started("RETR", "hello.txt"),
+            SessionEvent::TransferCompleted { bytes: 5 },
+            started("RETR", "missing.txt"),
+            SessionEvent::TransferFailed,
+            SessionEvent::Disconnected,
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_raw_bytes_paths() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let name: &[u8] = b"caf\xe9.txt";
+    let server = TestServer::start().unwrap();
+    std::fs::write(server.root().join(OsStr::from_bytes(name)), "latin-1").unwrap();
+
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    assert_eq!(ftp_stream.nlst_bytes(None).unwrap(), vec![name.to_vec()]);
+    let data = ftp_stream
+        .retr_bytes(name, |reader| {
+            let mut data = Vec::new();
+            reader
+                .read_to_end(&mut data)
+                .map(|_| data)
+                .map_err(FtpError::ConnectionError)
+        })
+        .unwrap();
+    assert_eq!(data, b"latin-1");
+    ftp_stream.rm_bytes(name).unwrap();
+    assert!(ftp_stream.nlst_bytes(None).unwrap().is_empty());
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_command_injection() {
+    let server = TestServer::start().unwrap();
+    std::fs::write(server.root().join("bar"), "keep me").unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    match ftp_stream.cwd("foo\r\nDELE bar") {
+        Err(FtpError::InvalidArgument(_)) => {}
+        res => panic!("expected InvalidArgument, got {:?}", res),
+    }
+    assert!(ftp_stream.rm_bytes(b"bar\nDELE bar").is_err());
+    ftp_stream.noop().unwrap();
+    assert!(server.root().join("bar").exists());
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_auto_binary() {
+    let server = TestServer::start().unwrap();
+    std::fs::write(server.root().join("size.bin"), [0u8; 42]).unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    assert!(ftp_stream.size("size.bin").is_err());
+
+    ftp_stream.set_auto_binary(true);
+    assert_eq!(ftp_stream.size("size.bin").unwrap(), Some(42));
+    // The ASCII type is restored afterwards.
+    ftp_stream.set_auto_binary(false);
+    assert!(ftp_stream.size("size.bin").is_err());
+    ftp_stream.transfer_type(FileType::Binary).unwrap();
+    assert_eq!(ftp_stream.size("size.bin").unwrap(), Some(42));
+    ftp_stream.quit().unwrap();
+
+    // When the type can't be restored, the stream knows it stayed binary.
+    let transcript = Transcript::parse(
+        "< 220 ready\n\
+         > TYPE A N\n< 200 ok\n\
+         > TYPE I\n< 200 ok\n\
+         > SIZE a.bin\n< 213 5\n\
+         > TYPE A N\n< 500 no ASCII today\n\
+         > SIZE b.bin\n< 213 3\n",
+    );
+    let replay = ReplayServer::start(transcript).unwrap();
+    let mut ftp_stream = FtpStream::connect(replay.addr()).unwrap();
+    ftp_stream
+        .transfer_type(FileType::Ascii(FormatControl::Default))
+        .unwrap();
+    ftp_stream.set_auto_binary(true);
+    assert!(ftp_stream.size("a.bin").is_err());
+    assert_eq!(ftp_stream.size("b.bin").unwrap(), Some(3));
+    replay.finish().unwrap();
+}
+
+#[test]
+fn test_spawn_session() {
+    let server = TestServer::start().unwrap();
+    std::fs::create_dir(server.root().join("spawn_dir")).unwrap();
+    std::fs::write(server.root().join("spawn_dir/data.txt"), "concurrent").unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.cwd("spawn_dir").unwrap();
+
+    let mut other = ftp_stream.spawn_session().unwrap();
+    assert_eq!(other.pwd().unwrap(), "/spawn_dir");
+    let download = std::thread::spawn(move || {
+        let data = other.simple_retr("data.txt").map(Cursor::into_inner);
+        other.quit().unwrap();
+        data
+    });
+    assert_eq!(ftp_stream.nlst(None).unwrap(), vec!["data.txt"]);
+    assert_eq!(download.join().unwrap().unwrap(), b"concurrent");
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_connection_pool() {
+    let server = TestServer::start().unwrap();
+    let pool = FtpConnectionPool::new(2, || {
+        let mut ftp_stream = FtpStream::connect(server.addr())?;
+        ftp_stream.login("Doe", "mumble")?;
+        Ok(ftp_stream)
+    })
+    .unwrap();
+    assert_eq!(pool.idle(), 2);
+
+    std::thread::scope(|scope| {
+        for i in 0..6 {
+            let pool = &pool;
+            scope.spawn(move || {
+                let mut conn = pool.get().unwrap();
+                let mut reader = Cursor::new(format!("pooled {}", i).into_bytes());
+                conn.put(&format!("pool_{}.txt", i), &mut reader).unwrap();
+            });
+        }
+    });
+    assert_eq!(pool.idle(), 2);
+    assert_eq!(pool.get().unwrap().nlst(None).unwrap().len(), 6);
+
+    // Connections dropped by the server are replaced on checkout.
+    server.expire_sessions();
+    let mut conn = pool.get().unwrap();
+    conn.noop().unwrap();
+    conn.discard();
+    assert_eq!(pool.idle(), 1);
+}
+
+#[test]
+fn test_observer() {
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl FtpObserver for Recorder {
+        fn command_sent(&mut self, command: &str) {
+            self.0.lock().unwrap().push(format!("> {}", command));
+        }
+
+        fn response_received(&mut self, response: &Line) {
+            self.0.lock().unwrap().push(format!("< {}", response.0));
+        }
+    }
+
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.set_observer(Some(Box::new(Recorder(Arc::clone(&log)))));
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.noop().unwrap();
+    ftp_stream.set_observer(None);
+    ftp_stream.quit().unwrap();
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            "> USER Doe",
+            "< 331",
+            "> PASS ****",
+            "< 230",
+            "> NOOP",
+            "< 200"
+        ]
+    );
+}
+
+#[test]
+fn test_transcript_replay() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    let recorder = TranscriptRecorder::attach(&mut ftp_stream);
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.mkdir("replayed").unwrap();
+    ftp_stream.cwd("replayed").unwrap();
+    let pwd = ftp_stream.pwd().unwrap();
+    let features = ftp_stream.feat().unwrap().clone();
+    ftp_stream.quit().unwrap();
+
+    let log = recorder.transcript().to_string();
+    assert!(log.contains("> PASS ****\n"));
+    assert!(log.contains("< 211-"));
+    let replay = ReplayServer::start(Transcript::parse(&log)).unwrap();
+    let mut ftp_stream = FtpStream::connect(replay.addr()).unwrap();
+    ftp_stream.login("Doe", "another password").unwrap();
+    ftp_stream.mkdir("replayed").unwrap();
+    ftp_stream.cwd("replayed").unwrap();
+    assert_eq!(ftp_stream.pwd().unwrap(), pwd);
+    assert_eq!(*ftp_stream.feat().unwrap(), features);
+    ftp_stream.quit().unwrap();
+    replay.finish().unwrap();
+}
+
+#[test]
+fn test_list_entries() {
+    let server = TestServer::start().unwrap();
+    std::fs::create_dir(server.root().join("sub")).unwrap();
+    std::fs::write(server.root().join("file.txt"), "12345").unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let entries = ftp_stream.list_entries(None).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].name, "file.txt");
+    assert!(entries[0].is_file());
+    assert_eq!(entries[0].size, Some(5));
+    assert_eq!(entries[1].name, "sub");
+    assert!(entries[1].is_dir());
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_list_via_stat() {
+    let server = TestServer::start().unwrap();
+    std::fs::write(server.root().join("stat.txt"), "abc").unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let lines = ftp_stream.list_via_stat(None).unwrap();
+    assert_eq!(lines, ftp_stream.list(None).unwrap());
+
+    // Nothing listens on 127.0.0.2, so the data connection fails and the
+    // typed listing falls back to STAT.
+    ftp_stream
+        .set_passive_address_policy(PassiveAddressPolicy::Override("127.0.0.2".parse().unwrap()));
+    assert!(ftp_stream.list(None).is_err());
+    let entries = ftp_stream.list_entries(None).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].name, "stat.txt");
+    ftp_stream.quit().unwrap();
+
+    // Once LIST was sent, its reply is pending: no fallback.
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream
+        .set_passive_address_policy(PassiveAddressPolicy::Override("127.0.0.2".parse().unwrap()));
+    ftp_stream.set_data_connect_order(DataConnectOrder::CommandFirst);
+    assert!(ftp_stream.list_entries(None).is_err());
+    assert!(ftp_stream.is_closed());
+}
+
+#[test]
+fn test_data_connect_order() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    for (i, order) in [
+        DataConnectOrder::ConnectFirst,
+        DataConnectOrder::CommandFirst,
+        DataConnectOrder::ReplyFirst,
+    ]
+    .iter()
+    .enumerate()
+    {
+        ftp_stream.set_data_connect_order(*order);
+        let name = format!("order_{}.txt", i);
+        ftp_stream
+            .put(&name, &mut Cursor::new(name.clone().into_bytes()))
+            .unwrap();
+        assert_eq!(
+            ftp_stream.simple_retr(&name).unwrap().into_inner(),
+            name.as_bytes()
+        );
+    }
+    assert_eq!(ftp_stream.nlst(None).unwrap().len(), 3);
+    ftp_stream.quit().unwrap();
+
+    // Nothing listens on 127.0.0.2. A failed data connection leaves no
+    // reply unread: none is pending yet, or the session is closed.
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.set_auto_reconnect(true);
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream
+        .set_passive_address_policy(PassiveAddressPolicy::Override("127.0.0.2".parse().unwrap()));
+    for order in [
+        DataConnectOrder::ConnectFirst,
+        DataConnectOrder::CommandFirst,
+        DataConnectOrder::ReplyFirst,
+    ]
+    .iter()
+    {
+        ftp_stream.set_data_connect_order(*order);
+        assert!(ftp_stream.list(None).is_err());
+        assert_eq!(
+            ftp_stream.is_closed(),
+            *order != DataConnectOrder::ConnectFirst
+        );
+        assert_eq!(ftp_stream.pwd().unwrap(), "/");
+    }
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_lenient_parsing() {
+    let transcript = Transcript::parse(
+        "< 220\n\
+         > USER Doe\n\
+         < 331Password required\n\
+         > PASS ****\n\
+         < \n\
+         < 230 Logged in\n\
+         > NOOP\n\
+         < 200-Still\n\
+         < \n\
+         < 200 here\n\
+         > PWD\n\
+         < 257\"/odd\" is cwd\n\
+         > QUIT\n\
+         < 221\n",
+    );
+    let replay = ReplayServer::start(transcript).unwrap();
+    let mut ftp_stream =
+        FtpStream::connect_with_parse_mode(replay.addr(), ParseMode::Lenient).unwrap();
+    assert_eq!(ftp_stream.get_welcome_msg(), Some("220"));
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.noop().unwrap();
+    assert_eq!(ftp_stream.pwd().unwrap(), "/odd");
+    ftp_stream.quit().unwrap();
+    replay.finish().unwrap();
+}
+
+#[test]
+fn test_reply_code_classes() {
+    let transcript = Transcript::parse(
+        "< 220 ready\n\
+         > NOOP\n\
+         < 600 beyond the classes\n",
+    );
+    let replay = ReplayServer::start(transcript).unwrap();
+    let mut ftp_stream = FtpStream::connect(replay.addr()).unwrap();
+    match ftp_stream.noop() {
+        Err(FtpError::InvalidResponse(_)) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+    replay.finish().unwrap();
+}
+
+#[test]
+fn test_any_positive_completion() {
+    let transcript = Transcript::parse(
+        "< 220 ready\n\
+         > NOOP\n\
+         < 250 fine\n\
+         > TYPE I\n\
+         < 202 always binary\n\
+         > CWD /pub\n\
+         < 200 ok\n\
+         > DELE a.txt\n\
+         < 200 deleted\n\
+         > DELE b.txt\n\
+         < 550 no such file\n\
+         > NOOP\n\
+         < 226 odd but fine\n",
+    );
+    let replay = ReplayServer::start(transcript).unwrap();
+    let mut ftp_stream = FtpStream::connect(replay.addr()).unwrap();
+    ftp_stream.noop().unwrap();
+    ftp_stream.transfer_type(FileType::Binary).unwrap();
+    ftp_stream.cwd("/pub").unwrap();
+    ftp_stream.rm("a.txt").unwrap();
+    assert_eq!(
+        ftp_stream.rm("b.txt").unwrap_err().status(),
+        Some(Status::FileUnavailable)
+    );
+    ftp_stream.get_ref().write_all(b"NOOP\r\n").unwrap();
+    let line = ftp_stream
+        .read_response_class(ResponseClass::PositiveCompletion)
+        .unwrap();
+    assert_eq!(
+        line,
+        Line(Status::ClosingDataConnection, "226 odd but fine".to_owned())
+    );
+    replay.finish().unwrap();
+}
+
+#[test]
+fn test_raw_command_streaming() {
+    let transcript = Transcript::parse(
+        "< 220 ready\n\
+         > SITE EXEC unpack.sh\n\
+         < 150 Unpacking\n\
+         < 110-a.txt\n\
+         < 110 b.txt\n\
+         < 200-c.txt\n\
+         < 200-d.txt\n\
+         < 200 Done\n\
+         > SITE EXEC missing.sh\n\
+         < 150 Starting\n\
+         < 550 No such script\n",
+    );
+    let replay = ReplayServer::start(transcript).unwrap();
+    let mut ftp_stream = FtpStream::connect(replay.addr()).unwrap();
+    let mut lines = Vec::new();
+    let last = ftp_stream
+        .raw_command_streaming("SITE EXEC unpack.sh", |line| lines.push(line.to_owned()))
+        .unwrap();
+    assert_eq!(
+        lines,
+        vec![
+            "150 Unpacking",
+            "110-a.txt",
+            "110 b.txt",
+            "200-c.txt",
+            "200-d.txt"
+        ]
+    );
+    assert_eq!(last, Line(Status::CommandOk, "200 Done".to_owned()));
+
+    lines.clear();
+    let err = ftp_stream
+        .raw_command_streaming("SITE EXEC missing.sh", |line| lines.push(line.to_owned()))
+        .unwrap_err();
+    assert_eq!(err.status(), Some(Status::FileUnavailable));
+    assert_eq!(lines, vec!["150 Starting"]);
+    replay.finish().unwrap();
+}
+
+#[test]
+fn test_custom_dialer() {
+    let server = TestServer::start().unwrap();
+    let stream = std::net::TcpStream::connect(server.addr()).unwrap();
+    let mut ftp_stream = FtpStream::from_stream(stream).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+
+    let dialed = Arc::new(Mutex::new(Vec::new()));
+    let log = Arc::clone(&dialed);
+    ftp_stream.set_dialer(move |addr| {
+        log.lock().unwrap().push(addr);
+        std::net::TcpStream::connect(addr)
+    });
+    ftp_stream
+        .put("dialed.txt", &mut Cursor::new("tunneled"))
+        .unwrap();
+    assert_eq!(ftp_stream.nlst(None).unwrap(), vec!["dialed.txt"]);
+    assert_eq!(dialed.lock().unwrap().len(), 2);
+
+    let mut other = ftp_stream.spawn_session().unwrap();
+    assert_eq!(dialed.lock().unwrap()[2], server.addr());
+    other.quit().unwrap();
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_in_memory_transport() {
+    #[derive(Debug)]
+    struct Memory {
+        replies: Cursor<Vec<u8>>,
+        sent: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Read for Memory {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.replies.read(buf)
+        }
+    }
+
+    impl Write for Memory {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.sent.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for Memory {}
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let memory = Memory {
+        replies: Cursor::new(b"220 Hi\r\n331 Password\r\n230 In\r\n257 \"/mem\"\r\n".to_vec()),
+        sent: Arc::clone(&sent),
+    };
+    let mut ftp_stream = GenericFtpStream::from_stream(memory).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    // A second session needs the server address, which is unknown here.
+    assert!(ftp_stream.spawn_session().is_err());
+    assert_eq!(
+        *sent.lock().unwrap(),
+        b"USER Doe\r\nPASS mumble\r\nPWD\r\n".to_vec()
+    );
+}
+
+#[test]
+fn test_socket_config() {
+    let server = TestServer::start().unwrap();
+    let config = SocketConfig {
+        ttl: Some(42),
+        tos: Some(0x28),
+        reuse_address: true,
+    };
+    let mut ftp_stream = FtpStream::connect_with_socket_config(server.addr(), config).unwrap();
+    assert_eq!(ftp_stream.get_ref().ttl().unwrap(), 42);
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream
+        .put("socket.txt", &mut Cursor::new("configured"))
+        .unwrap();
+    ftp_stream.set_mode(Mode::Active(ActiveConfig::default()));
+    assert_eq!(ftp_stream.nlst(None).unwrap(), vec!["socket.txt"]);
+
+    ftp_stream
+        .set_socket_config(SocketConfig {
+            ttl: Some(7),
+            ..SocketConfig::default()
+        })
+        .unwrap();
+    assert_eq!(ftp_stream.get_ref().ttl().unwrap(), 7);
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_set_timeouts() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    let timeout = Some(Duration::from_secs(5));
+    ftp_stream.set_timeouts(timeout, None).unwrap();
+    match ftp_stream.get_data_stream_ref() {
+        DataStream::Plain(stream) => {
+            assert_eq!(stream.read_timeout().unwrap(), timeout);
+            assert_eq!(stream.write_timeout().unwrap(), None);
+        }
+        #[allow(unreachable_patterns)]
+        _ => panic!("the control connection isn't secured"),
+    }
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_data_connection_timeouts() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let timeout = Some(Duration::from_secs(3));
+    ftp_stream.set_read_timeout(timeout).unwrap();
+    ftp_stream.set_write_timeout(timeout).unwrap();
+    assert_eq!(ftp_stream.get_ref().read_timeout().unwrap(), timeout);
+
+    ftp_stream
+        .put("timeouts.txt", &mut Cursor::new("data"))
+        .unwrap();
+    let reader = ftp_stream.get("timeouts.txt").unwrap();
+    assert_eq!(reader.get_ref().get_ref().read_timeout().unwrap(), timeout);
+    assert_eq!(reader.get_ref().get_ref().write_timeout().unwrap(), timeout);
+    drop(reader);
+    ftp_stream
+        .read_response_in(&[Status::ClosingDataConnection, Status::RequestedFileActionOk])
+        .unwrap();
+
+    let other = ftp_stream.spawn_session().unwrap();
+    assert_eq!(other.get_ref().read_timeout().unwrap(), timeout);
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_stor() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let written = ftp_stream
+        .stor("stor.txt", |writer| {
+            writer.write_all(b"written ")?;
+            writer.write_all(b"by a closure")?;
+            Ok(20)
+        })
+        .unwrap();
+    assert_eq!(written, 20);
+    assert_eq!(
+        ftp_stream.simple_retr("stor.txt").unwrap().into_inner(),
+        b"written by a closure"
+    );
+
+    // A failing writer aborts the upload.
+    assert!(ftp_stream
+        .stor("stor.txt", |_| Err::<(), _>(FtpError::InvalidArgument(
+            "no".into()
+        )))
+        .is_err());
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_max_download_size() {
+    let server = TestServer::start().unwrap();
+    std::fs::write(server.root().join("ten.bin"), [7u8; 10]).unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+
+    ftp_stream.set_max_download_size(Some(10));
+    assert_eq!(
+        ftp_stream
+            .simple_retr("ten.bin")
+            .unwrap()
+            .into_inner()
+            .len(),
+        10
+    );
+    ftp_stream.set_max_download_size(Some(9));
+    match ftp_stream.simple_retr("ten.bin") {
+        Err(FtpError::SizeLimitExceeded(9)) => {}
+        res => panic!("expected SizeLimitExceeded, got {:?}", res),
+    }
+    let mut sink = Vec::new();
+    assert!(ftp_stream.retr_to_writer("ten.bin", &mut sink).is_err());
+    // The control connection is still usable after an aborted download.
+    ftp_stream.noop().unwrap();
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_transfer_checkpoints() {
+    use ftp::checkpoint::TransferCheckpoint;
+    use std::io::Error;
+
+    /// Saves the checkpoints as JSON, as a batch job would, and fails from
+    /// `crash_at` on.
+    fn crash(
+        saved: &mut Option<String>,
+        crash_at: u64,
+    ) -> impl FnMut(&TransferCheckpoint) -> std::io::Result<()> + '_ {
+        move |checkpoint| {
+            *saved = Some(checkpoint.to_json());
+            match checkpoint.offset >= crash_at {
+                true => Err(Error::other("crash")),
+                false => Ok(()),
+            }
+        }
+    }
+
+    let data: Vec<u8> = (0..100_000u32).map(|i| (i % 253) as u8).collect();
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.put_bytes("dump.sql", &data).unwrap();
+
+    let mut local = Vec::new();
+    let mut saved = None;
+    let start = TransferCheckpoint::new("dump.sql");
+    assert!(ftp_stream
+        .retr_checkpointed(start, &mut local, 16384, crash(&mut saved, 40_000))
+        .is_err());
+    let checkpoint = TransferCheckpoint::from_json(&saved.unwrap()).unwrap();
+    assert_eq!(checkpoint.size, Some(100_000));
+    assert!(checkpoint.offset >= 40_000 && checkpoint.offset < 100_000);
+    local.truncate(checkpoint.offset as usize);
+    let end = ftp_stream
+        .retr_checkpointed(checkpoint.clone(), &mut local, 16384, |_| Ok(()))
+        .unwrap();
+    assert_eq!(end.offset, 100_000);
+    assert_eq!(local, data);
+    // Resuming from a completed checkpoint downloads nothing.
+    let mut rest = Vec::new();
+    let again = ftp_stream
+        .retr_checkpointed(end.clone(), &mut rest, 16384, |_| Ok(()))
+        .unwrap();
+    assert_eq!((again, rest.len()), (end, 0));
+
+    ftp_stream.put_bytes("dump.sql", &data[..50_000]).unwrap();
+    match ftp_stream.retr_checkpointed(checkpoint.clone(), &mut Vec::new(), 16384, |_| Ok(())) {
+        Err(FtpError::Changed(path)) => assert_eq!(path, "dump.sql"),
+        res => panic!("expected Changed, got {:?}", res),
+    }
+
+    let mut saved = None;
+    let start = TransferCheckpoint::new("upload.bin");
+    let mut source = Cursor::new(&data);
+    assert!(ftp_stream
+        .put_checkpointed(start, &mut source, 16384, crash(&mut saved, 60_000))
+        .is_err());
+    let checkpoint = TransferCheckpoint::from_json(&saved.unwrap()).unwrap();
+    assert!(checkpoint.offset >= 60_000 && checkpoint.size == Some(100_000));
+    let end = ftp_stream
+        .put_checkpointed(checkpoint.clone(), &mut source, 16384, |_| Ok(()))
+        .unwrap();
+    assert!(end.is_complete());
+    assert_eq!(ftp_stream.get_bytes("upload.bin").unwrap(), data);
+    assert!(matches!(
+        ftp_stream.put_checkpointed(
+            checkpoint.clone(),
+            &mut Cursor::new(b"short"),
+            16384,
+            |_| Ok(())
+        ),
+        Err(FtpError::Changed(_))
+    ));
+
+    ftp_stream
+        .transmission_mode(TransmissionMode::Block)
+        .unwrap();
+    match ftp_stream.put_checkpointed(checkpoint, &mut source, 16384, |_| Ok(())) {
+        Err(FtpError::InvalidArgument(_)) => {}
+        res => panic!("expected InvalidArgument, got {:?}", res),
+    }
+    ftp_stream.quit().unwrap();
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_put_mmap() {
+    let dir = std::env::temp_dir();
+    let data: Vec<u8> = (0..5_000_000u32).map(|i| (i % 251) as u8).collect();
+    let (large, empty) = (
+        dir.join("ftp_put_mmap.bin"),
+        dir.join("ftp_put_mmap_empty.bin"),
+    );
+    std::fs::write(&large, &data).unwrap();
+    std::fs::write(&empty, b"").unwrap();
+
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    assert_eq!(ftp_stream.put_mmap("large.bin", &large).unwrap(), 5_000_000);
+    assert_eq!(ftp_stream.get_bytes("large.bin").unwrap(), data);
+    ftp_stream
+        .transmission_mode(TransmissionMode::Block)
+        .unwrap();
+    assert_eq!(ftp_stream.put_mmap("empty.bin", &empty).unwrap(), 0);
+    assert!(ftp_stream.get_bytes("empty.bin").unwrap().is_empty());
+    ftp_stream.quit().unwrap();
+    std::fs::remove_file(large).unwrap();
+    std::fs::remove_file(empty).unwrap();
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_put_gzip() {
+    let rows: String = (0..10_000).map(|i| format!("{},ok\r\n", i % 7)).collect();
+
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    // ASCII mode stays in effect for other transfers.
+    ftp_stream
+        .transfer_type(FileType::Ascii(FormatControl::Default))
+        .unwrap();
+    assert_eq!(
+        ftp_stream
+            .put_gzip("rows.csv", &mut rows.as_bytes())
+            .unwrap(),
+        rows.len() as u64
+    );
+    assert!(ftp_stream
+        .nlst(None)
+        .unwrap()
+        .contains(&"rows.csv.gz".to_owned()));
+    let read = |reader: &mut dyn Read| {
+        let mut data = String::new();
+        reader.read_to_string(&mut data)?;
+        Ok(data)
+    };
+    assert_eq!(ftp_stream.retr_gzip("rows.csv", read).unwrap(), rows);
+    assert_eq!(ftp_stream.size("rows.csv.gz").ok(), None);
+
+    ftp_stream
+        .transmission_mode(TransmissionMode::Block)
+        .unwrap();
+    ftp_stream.put_gzip("empty", &mut &b""[..]).unwrap();
+    assert_eq!(ftp_stream.retr_gzip("empty", read).unwrap(), "");
+    ftp_stream.put_bytes("plain.gz", b"not compressed").unwrap();
+    assert!(ftp_stream.retr_gzip("plain", read).is_err());
+    ftp_stream.quit().unwrap();
+}
+
+#[cfg(feature = "digest")]
+#[test]
+fn test_transfer_digests() {
+    use ftp::digest::Algorithm;
+
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let uploaded = ftp_stream
+        .put_with_digest(
+            "digest.txt",
+            &mut Cursor::new("123456789"),
+            Algorithm::Crc32,
+        )
+        .unwrap();
+    assert_eq!(uploaded.to_string(), "cbf43926");
+    let (_, downloaded) = ftp_stream
+        .retr_with_digest("digest.txt", Algorithm::Crc32, |reader| {
+            Ok(std::io::copy(reader, &mut std::io::sink())?)
+        })
+        .unwrap();
+    assert_eq!(downloaded, uploaded);
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_diff() {
+    use ftp::types::DiffEntry;
+    use std::time::SystemTime;
+
+    let server = TestServer::start().unwrap();
+    let remote = server.root().join("site");
+    std::fs::create_dir(&remote).unwrap();
+    let local = std::env::temp_dir().join("ftp_diff");
+    std::fs::create_dir_all(&local).unwrap();
+    for (name, data) in &[
+        ("same.txt", "same"),
+        ("size.txt", "local"),
+        ("time.txt", "time"),
+    ] {
+        std::fs::write(local.join(name), data).unwrap();
+    }
+    for (name, data) in &[
+        ("same.txt", "same"),
+        ("size.txt", "remote"),
+        ("time.txt", "time"),
+    ] {
+        std::fs::write(remote.join(name), data).unwrap();
+    }
+    std::fs::write(local.join("new.txt"), "new").unwrap();
+    std::fs::write(remote.join("old.txt"), "old").unwrap();
+    std::fs::create_dir_all(local.join("subdir")).unwrap();
+    let remote_time = std::fs::metadata(remote.join("same.txt"))
+        .unwrap()
+        .modified()
+        .unwrap();
+    let set_modified = |name: &str, time: SystemTime| {
+        let file = std::fs::File::options()
+            .write(true)
+            .open(local.join(name))
+            .unwrap();
+        file.set_modified(time).unwrap();
+    };
+    set_modified("same.txt", remote_time);
+    set_modified("time.txt", SystemTime::UNIX_EPOCH);
+
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let diff = ftp_stream.diff(&local, "/site").unwrap();
+    let names: Vec<_> = diff.iter().map(DiffEntry::name).collect();
+    assert_eq!(names, vec!["new.txt", "old.txt", "size.txt", "time.txt"]);
+    assert_eq!(diff[0], DiffEntry::OnlyLocal("new.txt".to_owned()));
+    assert_eq!(diff[1], DiffEntry::OnlyRemote("old.txt".to_owned()));
+    match diff[2] {
+        DiffEntry::Differs {
+            local_size: 5,
+            remote_size: Some(6),
+            ..
+        } => {}
+        ref entry => panic!("unexpected {:?}", entry),
+    }
+    match diff[3] {
+        DiffEntry::Differs {
+            remote_modified: Some(_),
+            ..
+        } => {}
+        ref entry => panic!("unexpected {:?}", entry),
+    }
+    ftp_stream.quit().unwrap();
+    std::fs::remove_dir_all(local).unwrap();
+}
+
+#[test]
+fn test_mirror() {
+    use ftp::sync::{self, SyncAction, SyncOptions};
+
+    let server = TestServer::start().unwrap();
+    let remote = server.root().join("www");
+    std::fs::create_dir(&remote).unwrap();
+    let local = std::env::temp_dir().join("ftp_mirror");
+    std::fs::create_dir_all(&local).unwrap();
+    std::fs::write(local.join("index.html"), "<html>").unwrap();
+    std::fs::write(local.join("scratch.tmp"), "scratch").unwrap();
+    std::fs::write(remote.join("stale.html"), "stale").unwrap();
+
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let mut options = SyncOptions {
+        dry_run: true,
+        delete: true,
+        preserve_times: true,
+        exclude: vec!["*.tmp".to_owned()],
+        ..SyncOptions::default()
+    };
+    let plan = sync::mirror(&mut ftp_stream, &local, "/www", &options).unwrap();
+    assert_eq!(plan.len(), 3);
+    assert_eq!(plan[0], SyncAction::Upload("index.html".to_owned()));
+    match plan[1] {
+        SyncAction::SetModified(ref name, _) => assert_eq!(name, "index.html"),
+        ref action => panic!("unexpected {:?}", action),
+    }
+    assert_eq!(plan[2], SyncAction::Delete("stale.html".to_owned()));
+    assert!(!remote.join("index.html").exists());
+    assert!(remote.join("stale.html").exists());
+
+    options.dry_run = false;
+    assert_eq!(
+        sync::mirror(&mut ftp_stream, &local, "/www", &options).unwrap(),
+        plan
+    );
+    assert_eq!(
+        std::fs::read_to_string(remote.join("index.html")).unwrap(),
+        "<html>"
+    );
+    assert!(!remote.join("stale.html").exists());
+    assert!(!remote.join("scratch.tmp").exists());
+    // The preserved times make the next run a no-op.
+    assert_eq!(
+        sync::mirror(&mut ftp_stream, &local, "/www", &options).unwrap(),
+        vec![]
+    );
+    ftp_stream.quit().unwrap();
+    std::fs::remove_dir_all(local).unwrap();
+}
+
+#[test]
+fn test_batch() {
+    use ftp::batch::FtpBatch;
+
+    let server = TestServer::start().unwrap();
+    let local = std::env::temp_dir().join("ftp_batch.txt");
+    std::fs::write(&local, "batch").unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+
+    let mut batch = FtpBatch::new();
+    batch
+        .mkdir("out")
+        .put(&local, "out/data.part")
+        .rm("missing.txt")
+        .rename("out/data.part", "out/data.txt");
+    let results = batch.run(&mut ftp_stream);
+    assert_eq!(results.len(), 4);
+    assert!(results[0].is_ok() && results[1].is_ok() && results[3].is_ok());
+    assert!(results[2].is_err());
+    assert_eq!(
+        std::fs::read_to_string(server.root().join("out/data.txt")).unwrap(),
+        "batch"
+    );
+
+    batch.stop_on_error(true);
+    let results = batch.run(&mut ftp_stream);
+    assert_eq!(results.len(), 1);
+    assert!(results[0].is_err());
+    ftp_stream.quit().unwrap();
+    std::fs::remove_file(local).unwrap();
+}
+
+#[test]
+fn test_mirror_resumable() {
+    use ftp::sync::{self, SyncAction, SyncOptions, SyncState};
+
+    let server = TestServer::start().unwrap();
+    let remote = server.root().join("www");
+    std::fs::create_dir(&remote).unwrap();
+    let local = std::env::temp_dir().join("ftp_mirror_resumable");
+    std::fs::create_dir_all(&local).unwrap();
+    std::fs::write(local.join("done.txt"), "done").unwrap();
+    std::fs::write(local.join("partial.txt"), "0123456789").unwrap();
+    std::fs::write(local.join("todo.txt"), "todo").unwrap();
+    // An interrupted run uploaded done.txt, then the server got 4 of the 6
+    // bytes of partial.txt it was sent.
+    std::fs::write(remote.join("partial.txt"), "0123").unwrap();
+    let state_path = std::env::temp_dir().join("ftp_mirror_resumable.json");
+    let mut state = SyncState::default();
+    state.completed.insert("done.txt".to_owned());
+    state.partial.insert("partial.txt".to_owned(), 6);
+    state.save(&state_path).unwrap();
+
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let options = SyncOptions::default();
+    let actions =
+        sync::mirror_resumable(&mut ftp_stream, &local, "/www", &options, &state_path).unwrap();
+    assert_eq!(
+        actions,
+        vec![
+            SyncAction::Upload("partial.txt".to_owned()),
+            SyncAction::Upload("todo.txt".to_owned()),
+        ]
+    );
+    assert!(!remote.join("done.txt").exists());
+    assert_eq!(
+        std::fs::read_to_string(remote.join("partial.txt")).unwrap(),
+        "0123456789"
+    );
+    assert_eq!(
+        std::fs::read_to_string(remote.join("todo.txt")).unwrap(),
+        "todo"
+    );
+    assert!(!state_path.exists());
+    ftp_stream.quit().unwrap();
+    std::fs::remove_dir_all(local).unwrap();
+}
+
+#[test]
+fn test_ftp_path() {
+    use ftp::path::FtpPath;
+
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let dir = FtpPath::new("/pub");
+    ftp_stream.mkdir(&dir).unwrap();
+    ftp_stream.mkdir(dir.join("docs")).unwrap();
+    ftp_stream
+        .cwd(dir.join("docs/../docs").normalize())
+        .unwrap();
+    assert_eq!(ftp_stream.pwd().unwrap(), "/pub/docs");
+    ftp_stream.cwd(&dir).unwrap();
+    let entries = ftp_stream.list_entries(None).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].path_in(&dir).as_str(), "/pub/docs");
+    ftp_stream.rmdir(entries[0].path_in(&dir)).unwrap();
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_current_dir() {
+    let server = TestServer::start().unwrap();
+    std::fs::create_dir_all(server.root().join("pub/docs")).unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    assert_eq!(ftp_stream.current_dir(), None);
+    assert_eq!(ftp_stream.resolve("pub"), None);
+    ftp_stream.cwd("pub").unwrap();
+    assert_eq!(ftp_stream.current_dir(), None);
+    assert_eq!(ftp_stream.pwd().unwrap(), "/pub");
+    ftp_stream.cwd("docs").unwrap();
+    assert_eq!(ftp_stream.current_dir().unwrap().as_str(), "/pub/docs");
+    ftp_stream.cdup().unwrap();
+    assert_eq!(ftp_stream.current_dir().unwrap().as_str(), "/pub");
+    ftp_stream.cdup().unwrap();
+    ftp_stream.cdup().unwrap();
+    assert_eq!(ftp_stream.current_dir().unwrap().as_str(), "/");
+    assert_eq!(ftp_stream.pwd().unwrap(), "/");
+    assert_eq!(
+        ftp_stream.resolve("pub/./docs/").unwrap().as_str(),
+        "/pub/docs"
+    );
+    assert_eq!(ftp_stream.resolve("/etc").unwrap().as_str(), "/etc");
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_shared_stream() {
+    use ftp::shared::SharedFtpStream;
+
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let shared = SharedFtpStream::new(ftp_stream);
+    let handles: Vec<_> = (0..4)
+        .map(|i| {
+            let shared = shared.clone();
+            std::thread::spawn(move || {
+                let name = format!("shared_{}.txt", i);
+                shared.with(|ftp_stream| ftp_stream.put(&name, &mut Cursor::new(name.clone())))?;
+                shared.with(|ftp_stream| ftp_stream.simple_retr(&name))
+            })
+        })
+        .collect();
+    for (i, handle) in handles.into_iter().enumerate() {
+        let data = handle.join().unwrap().unwrap().into_inner();
+        assert_eq!(data, format!("shared_{}.txt", i).into_bytes());
+    }
+
+    // A 421 doesn't break the other handles.
+    server.expire_sessions();
+    let other = shared.clone();
+    assert_eq!(other.with(|ftp_stream| ftp_stream.pwd()).unwrap(), "/");
+    drop(other);
+    shared.try_unwrap().unwrap().quit().unwrap();
+}
+
+#[test]
+fn test_keepalive() {
+    use ftp::observer::TranscriptEntry;
+    use ftp::shared::SharedFtpStream;
+
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    assert!(!ftp_stream.keepalive().unwrap());
+    ftp_stream.set_keepalive_interval(Some(Duration::from_millis(50)));
+    assert!(!ftp_stream.keepalive().unwrap());
+    std::thread::sleep(Duration::from_millis(80));
+    assert!(ftp_stream.idle_time() >= Duration::from_millis(50));
+    assert!(ftp_stream.keepalive().unwrap());
+    assert!(!ftp_stream.keepalive().unwrap());
+
+    let recorder = TranscriptRecorder::attach(&mut ftp_stream);
+    let shared = SharedFtpStream::new(ftp_stream);
+    shared.start_keepalive(Duration::from_millis(50));
+    std::thread::sleep(Duration::from_millis(300));
+    shared.with(|ftp_stream| ftp_stream.quit()).unwrap();
+    let noops = recorder
+        .transcript()
+        .entries
+        .into_iter()
+        .filter(|entry| *entry == TranscriptEntry::Command("NOOP".to_owned()))
+        .count();
+    assert!(noops >= 2, "{} NOOP sent", noops);
+}
+
+#[test]
+fn test_idle_revalidation() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.set_idle_revalidation(Some(Duration::from_millis(50)));
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.mkdir("work").unwrap();
+    ftp_stream.cwd("work").unwrap();
+
+    // The server drops the session while the client is idle.
+    server.expire_sessions();
+    std::thread::sleep(Duration::from_millis(80));
+    assert_eq!(ftp_stream.pwd().unwrap(), "/work");
+    assert!(!ftp_stream.is_closed());
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_reader_capacity() {
+    let server = TestServer::start().unwrap();
+    let ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    // Replies span many reads of a tiny buffer.
+    let mut ftp_stream = ftp_stream.with_reader_capacity(4).unwrap();
+    ftp_stream.set_auto_reconnect(true);
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.mkdir("capacity").unwrap();
+    assert_eq!(ftp_stream.pwd().unwrap(), "/");
+    server.expire_sessions();
+    assert_eq!(ftp_stream.nlst(None).unwrap(), vec!["capacity"]);
+    assert_eq!(ftp_stream.reader_capacity(), 4);
+    let other = ftp_stream.spawn_session().unwrap();
+    assert_eq!(other.reader_capacity(), 4);
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_put_get_bytes() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.put_bytes("bytes.bin", &[0, 1, 2, 255]).unwrap();
+    assert_eq!(ftp_stream.get_bytes("bytes.bin").unwrap(), [0, 1, 2, 255]);
+    ftp_stream.put_bytes("empty.bin", b"").unwrap();
+    assert!(ftp_stream.get_bytes("empty.bin").unwrap().is_empty());
+    assert!(ftp_stream.get_bytes("missing.bin").is_err());
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_require_tls() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    assert!(!ftp_stream.is_secure());
+    ftp_stream.set_require_tls(true);
+    match ftp_stream.login("Doe", "mumble") {
+        Err(FtpError::TlsRequired) => {}
+        res => panic!("expected TlsRequired, got {:?}", res),
+    }
+    assert_eq!(ftp_stream.tls_mode(), TlsMode::Required);
+    // Nothing was sent, so the connection is still usable.
+    ftp_stream.set_tls_mode(TlsMode::Opportunistic);
+    ftp_stream.login("Doe", "mumble").unwrap();
+    assert_eq!(ftp_stream.pwd().unwrap(), "/");
+    ftp_stream.quit().unwrap();
+
+    // Not requiring TLS only undoes `Required`.
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.set_tls_mode(TlsMode::Opportunistic);
+    ftp_stream.set_require_tls(false);
+    assert_eq!(ftp_stream.tls_mode(), TlsMode::Opportunistic);
+    ftp_stream.set_require_tls(true);
+    ftp_stream.set_require_tls(false);
+    assert_eq!(ftp_stream.tls_mode(), TlsMode::Disabled);
+}
+
+#[test]
+fn test_features() {
+    use ftp::observer::TranscriptEntry;
+
+    let server = TestServer::start().unwrap();
+    std::fs::write(server.root().join("file.txt"), "12345").unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    assert!(ftp_stream.features().is_none());
+    assert!(!ftp_stream.supports("EPSV"));
+    let recorder = TranscriptRecorder::attach(&mut ftp_stream);
+    let features = ftp_stream.feat().unwrap();
+    assert_eq!(features.params("MLST"), Some("type*;size*;modify*;"));
+    assert!(ftp_stream.supports("utf8"));
+
+    let entries = ftp_stream.list_entries(None).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].name, "file.txt");
+    assert_eq!(entries[0].size, Some(5));
+    assert!(entries[0].raw.starts_with("type=file;"));
+    assert_eq!(ftp_stream.get_bytes("file.txt").unwrap(), b"12345");
+    let commands: Vec<_> = recorder
+        .transcript()
+        .entries
+        .into_iter()
+        .filter_map(|entry| match entry {
+            TranscriptEntry::Command(command) => Some(command),
+            TranscriptEntry::Reply(_) => None,
+        })
+        .collect();
+    assert_eq!(
+        commands,
+        [
+            "FEAT",
+            "OPTS UTF8 ON",
+            "EPSV",
+            "MLSD",
+            "EPSV",
+            "RETR file.txt"
+        ]
+    );
+
+    ftp_stream.set_legacy_commands(true);
+    assert!(!ftp_stream.supports("EPSV"));
+    let entries = ftp_stream.list_entries(None).unwrap();
+    assert!(entries[0].raw.starts_with("-rw-r--r--"));
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_pret() {
+    use ftp::observer::TranscriptEntry;
+
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.set_pret(true);
+    let recorder = TranscriptRecorder::attach(&mut ftp_stream);
+    ftp_stream.put_bytes("file.txt", b"12345").unwrap();
+    assert_eq!(ftp_stream.get_bytes("file.txt").unwrap(), b"12345");
+    assert_eq!(ftp_stream.nlst_bytes(Some(b"file.txt")).unwrap().len(), 1);
+    let commands: Vec<_> = recorder
+        .transcript()
+        .entries
+        .into_iter()
+        .filter_map(|entry| match entry {
+            TranscriptEntry::Command(command) => Some(command),
+            TranscriptEntry::Reply(_) => None,
+        })
+        .collect();
+    assert_eq!(
+        commands,
+        [
+            "PRET STOR file.txt",
+            "PASV",
+            "STOR file.txt",
+            "PRET RETR file.txt",
+            "PASV",
+            "RETR file.txt",
+            "PRET NLST file.txt",
+            "PASV",
+            "NLST file.txt"
+        ]
+    );
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_block_mode() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream
+        .transmission_mode(TransmissionMode::Block)
+        .unwrap();
+    ftp_stream.set_restart_marker_interval(Some(1000));
+    let data: Vec<u8> = (0..2500u32).map(|i| i as u8).collect();
+
+    ftp_stream.put_bytes("blocks.bin", &data).unwrap();
+    assert_eq!(
+        std::fs::read(server.root().join("blocks.bin")).unwrap(),
+        data
+    );
+    let offsets: Vec<_> = ftp_stream
+        .restart_markers()
+        .iter()
+        .map(|marker| (marker.offset, marker.marker.as_str()))
+        .collect();
+    assert_eq!(offsets, [(1000, "1000"), (2000, "2000")]);
+
+    assert_eq!(ftp_stream.get_bytes("blocks.bin").unwrap(), data);
+    let markers = ftp_stream.restart_markers().to_vec();
+    let offsets: Vec<_> = markers.iter().map(|marker| marker.offset).collect();
+    assert_eq!(offsets, [1024, 2048]);
+    assert_eq!(ftp_stream.nlst(None).unwrap(), ["blocks.bin"]);
+
+    // Resume the download from the last checkpoint.
+    ftp_stream.restart(&markers[1].marker).unwrap();
+    assert_eq!(ftp_stream.get_bytes("blocks.bin").unwrap(), &data[2048..]);
+
+    // Resume an upload from the server acknowledgement of a marker.
+    ftp_stream.restart("2000").unwrap();
+    ftp_stream.put_bytes("blocks.bin", &[0; 500]).unwrap();
+    let stored = std::fs::read(server.root().join("blocks.bin")).unwrap();
+    assert_eq!(&stored[..2000], &data[..2000]);
+    assert_eq!(&stored[2000..], &[0; 500][..]);
+
+    ftp_stream
+        .transmission_mode(TransmissionMode::Stream)
+        .unwrap();
+    assert_eq!(ftp_stream.get_bytes("blocks.bin").unwrap(), stored);
+    assert!(ftp_stream.restart_markers().is_empty());
+    ftp_stream.quit().unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlinks() {
+    let server = TestServer::start().unwrap();
+    std::fs::create_dir_all(server.root().join("pub/v1")).unwrap();
+    std::os::unix::fs::symlink("v1", server.root().join("pub/latest")).unwrap();
+    std::os::unix::fs::symlink("..", server.root().join("pub/v1/up")).unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+
+    for mlsd in [false, true] {
+        if mlsd {
+            ftp_stream.feat().unwrap();
+        }
+        let entries = ftp_stream.list_entries(Some("pub")).unwrap();
+        let latest = entries.iter().find(|e| e.name == "latest").unwrap();
+        assert!(latest.is_symlink() && !latest.is_dir() && !latest.is_file());
+        assert_eq!(latest.link_target(), Some("v1"));
+
+        let target = ftp_stream.readlink("pub/latest").unwrap().unwrap();
+        assert_eq!(target.as_str(), "pub/v1");
+        let target = ftp_stream.readlink("/pub/v1/up").unwrap().unwrap();
+        assert_eq!(target.as_str(), "/pub");
+        assert_eq!(ftp_stream.readlink("pub/v1").unwrap(), None);
+        assert_eq!(ftp_stream.readlink("pub/missing").unwrap(), None);
+    }
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_quoted_paths() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let created = ftp_stream.mkdir("say \"hi\"").unwrap();
+    assert_eq!(created.as_deref(), Some("/say \"hi\""));
+    ftp_stream.cwd("say \"hi\"").unwrap();
+    assert_eq!(ftp_stream.pwd().unwrap(), "/say \"hi\"");
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_mkdir_path() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.mkdir("pub").unwrap();
+    ftp_stream.cwd("pub").unwrap();
+    let created = ftp_stream.mkdir("./docs//").unwrap();
+    assert_eq!(created.as_deref(), Some("/pub/docs"));
+    ftp_stream.quit().unwrap();
+
+    // Servers may leave the path out of the reply.
+    let replay = ReplayServer::start(Transcript::parse(
+        "< 220 ready\n> USER Doe\n< 331 password\n> PASS ****\n< 230 ok\n\
+         > PWD\n< 257 \"/home/doe\"\n> MKD ../shared\n< 257 Directory created.\n\
+         > QUIT\n< 221 bye\n",
+    ))
+    .unwrap();
+    let mut ftp_stream = FtpStream::connect(replay.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.pwd().unwrap();
+    let created = ftp_stream.mkdir("../shared").unwrap();
+    assert_eq!(created.as_deref(), Some("/home/shared"));
+    ftp_stream.quit().unwrap();
+    replay.finish().unwrap();
+}
+
+#[test]
+fn test_server_info() {
+    let replay = ReplayServer::start(Transcript::parse(
+        "< 220 (vsFTPd 3.0.3)\n> USER Doe\n< 331 password\n> PASS ****\n< 230 ok\n\
+         > SYST\n< 502 not implemented\n> QUIT\n< 221 bye\n",
+    ))
+    .unwrap();
+    let mut ftp_stream = FtpStream::connect(replay.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let info = ftp_stream.server_info().unwrap().clone();
+    assert_eq!(info.software, Some(ServerSoftware::VsFtpd));
+    assert_eq!(info.version.as_deref(), Some("3.0.3"));
+    assert_eq!(info.system, None);
+    // The result is cached, SYST isn't sent again.
+    assert_eq!(ftp_stream.server_info().unwrap(), &info);
+    ftp_stream.quit().unwrap();
+    replay.finish().unwrap();
+}
+
+#[test]
+fn test_supports_resume() {
+    // Advertised in FEAT, no probe needed.
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.feat().unwrap();
+    assert!(ftp_stream.supports_resume().unwrap());
+    ftp_stream.quit().unwrap();
+
+    let replay = ReplayServer::start(Transcript::parse(
+        "< 220 ready\n> USER Doe\n< 331 password\n> PASS ****\n< 230 ok\n\
+         > REST 0\n< 502 not implemented\n> QUIT\n< 221 bye\n",
+    ))
+    .unwrap();
+    let mut ftp_stream = FtpStream::connect(replay.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    assert!(!ftp_stream.supports_resume().unwrap());
+    // The result is cached, REST isn't sent again.
+    assert!(!ftp_stream.supports_resume().unwrap());
+    ftp_stream.quit().unwrap();
+    replay.finish().unwrap();
+}
+
+#[test]
+fn test_login_info() {
+    let replay = ReplayServer::start(Transcript::parse(
+        "< 220 ready\n> USER Doe\n< 331 password\n> PASS ****\n\
+         < 230-Last login: Mon Jan 15 10:04:11 2024\n< 230-Quota: 12 of 100 MB used\n\
+         < 230 Login successful.\n> QUIT\n< 221 bye\n",
+    ))
+    .unwrap();
+    let mut ftp_stream = FtpStream::connect(replay.addr()).unwrap();
+    let info = ftp_stream.login("Doe", "mumble").unwrap();
+    assert_eq!(
+        info.lines,
+        [
+            "Last login: Mon Jan 15 10:04:11 2024",
+            "Quota: 12 of 100 MB used",
+            "Login successful."
+        ]
+    );
+    ftp_stream.quit().unwrap();
+    replay.finish().unwrap();
+}
+
+#[test]
+fn test_error_context() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let err = ftp_stream.simple_retr("missing.txt").unwrap_err();
+    assert_eq!(err.command(), Some("RETR missing.txt"));
+    assert_eq!(err.reply_lines().unwrap().len(), 1);
+    assert!(err.to_string().ends_with("(in reply to RETR missing.txt)"));
+    ftp_stream.quit().unwrap();
+
+    // Multi-line replies are kept whole, passwords are masked.
+    let replay = ReplayServer::start(Transcript::parse(
+        "< 220 ready\n> USER Doe\n< 331 password\n> PASS ****\n\
+         < 530-Login incorrect.\n< 530 3 attempts left.\n",
+    ))
+    .unwrap();
+    let mut ftp_stream = FtpStream::connect(replay.addr()).unwrap();
+    let err = ftp_stream.login("Doe", "mumble").unwrap_err();
+    assert_eq!(err.command(), Some("PASS ****"));
+    assert_eq!(
+        err.reply_lines().unwrap(),
+        ["530-Login incorrect.", "530 3 attempts left."]
+    );
+    replay.finish().unwrap();
+}
+
+#[test]
+fn test_abort_handle() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.put_bytes("big.bin", &vec![7; 1 << 20]).unwrap();
+
+    let handle = ftp_stream.abort_handle();
+    let res = ftp_stream.retr("big.bin", |reader| {
+        reader.read_exact(&mut [0; 1024])?;
+        let handle = handle.clone();
+        std::thread::spawn(move || handle.abort()).join().unwrap();
+        Ok(std::io::copy(reader, &mut std::io::sink())?)
+    });
+    match res {
+        Err(FtpError::Aborted) => {}
+        res => panic!("expected an aborted download, got {:?}", res),
+    }
+
+    let res: ftp::Result<()> = ftp_stream.stor("upload.bin", |writer| {
+        writer.write_all(&[7; 1024])?;
+        handle.abort();
+        loop {
+            writer.write_all(&[7; 1024])?;
+        }
+    });
+    match res {
+        Err(FtpError::Aborted) => {}
+        res => panic!("expected an aborted upload, got {:?}", res),
+    }
+
+    // The session goes on, and later transfers aren't aborted.
+    assert_eq!(ftp_stream.get_bytes("big.bin").unwrap().len(), 1 << 20);
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_transfer_aborted() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.put_bytes("big.bin", &[7; 4096]).unwrap();
+
+    server.abort_downloads_after(Some(1000));
+    match ftp_stream.retr_to_writer("big.bin", &mut Vec::new()) {
+        Err(err @ FtpError::TransferAborted { bytes: 1000, .. }) => {
+            assert_eq!(err.status(), Some(Status::TransferAborted));
+            assert!(err.is_transient());
+        }
+        res => panic!("expected an aborted transfer, got {:?}", res),
+    }
+
+    // The session goes on after the abort.
+    server.abort_downloads_after(None);
+    assert_eq!(ftp_stream.get_bytes("big.bin").unwrap().len(), 4096);
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_deadline() {
+    use std::time::Instant;
+
+    let server = TestServer::start().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let mut ftp_stream = FtpStream::connect_with_deadline(server.addr(), deadline).unwrap();
+    let data = ftp_stream
+        .with_deadline(deadline, |ftp_stream| {
+            ftp_stream.login("Doe", "mumble")?;
+            ftp_stream.put_bytes("report.csv", b"a,b")?;
+            ftp_stream.get_bytes("report.csv")
+        })
+        .unwrap();
+    assert_eq!(data, b"a,b");
+    ftp_stream.noop().unwrap();
+
+    // A server which stops answering once the welcome message is sent.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let stalled = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"220 ready\r\n").unwrap();
+        let _ = stream.read_to_end(&mut Vec::new());
+    });
+    let mut ftp_stream = FtpStream::connect(addr).unwrap();
+    let started = Instant::now();
+    let deadline = started + Duration::from_millis(200);
+    // A nested call is bound by the outer deadline, even with a later one.
+    let later = started + Duration::from_secs(60);
+    match ftp_stream.with_deadline(deadline, |ftp_stream| {
+        let res = ftp_stream.with_deadline(later, |ftp_stream| ftp_stream.login("Doe", "mumble"));
+        assert!(ftp_stream.is_closed());
+        res
+    }) {
+        Err(FtpError::ConnectionError(err)) => {
+            assert_eq!(err.kind(), std::io::ErrorKind::TimedOut)
+        }
+        res => panic!("expected a timeout, got {:?}", res),
+    }
+    assert!(started.elapsed() < Duration::from_secs(5));
+    // The login reply may still come, the session can't be trusted anymore.
+    assert!(ftp_stream.is_closed());
+    drop(ftp_stream);
+    stalled.join().unwrap();
+}
+
+#[test]
+fn test_welcome_banner() {
+    let replay = ReplayServer::start(Transcript::parse(
+        "< 220-Welcome to the archive.\n< 220-Uploads go to /incoming.\n< 220 ready\n\
+         > QUIT\n< 221 bye\n",
+    ))
+    .unwrap();
+    let mut ftp_stream =
+        FtpStream::connect_with_banner_timeout(replay.addr(), Duration::from_secs(5)).unwrap();
+    assert_eq!(ftp_stream.get_welcome_msg(), Some("220 ready"));
+    assert_eq!(
+        ftp_stream.get_welcome_lines(),
+        [
+            "Welcome to the archive.",
+            "Uploads go to /incoming.",
+            "ready"
+        ]
+    );
+    let recorder = TranscriptRecorder::attach(&mut ftp_stream);
+    assert_eq!(
+        recorder.transcript(),
+        Transcript::parse(
+            "< 220-Welcome to the archive.\n< 220-Uploads go to /incoming.\n< 220 ready\n"
+        )
+    );
+    assert_eq!(ftp_stream.take_welcome_msg().as_deref(), Some("220 ready"));
+    assert_eq!(ftp_stream.get_welcome_msg(), None);
+    assert_eq!(ftp_stream.get_welcome_lines().len(), 3);
+    assert_eq!(ftp_stream.take_welcome_msg(), None);
+    ftp_stream.quit().unwrap();
+    replay.finish().unwrap();
+
+    // A server which accepts the connection but never sends its banner.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let err = FtpStream::connect_with_banner_timeout(
+        listener.local_addr().unwrap(),
+        Duration::from_millis(100),
+    )
+    .unwrap_err();
+    match err {
+        FtpError::ConnectionError(err) => assert_eq!(err.kind(), std::io::ErrorKind::TimedOut),
+        err => panic!("unexpected error: {:?}", err),
+    }
+}
+
+#[cfg(all(feature = "secure", not(feature = "native-tls")))]
+#[test]
+fn test_opportunistic_tls() {
+    use ftp::openssl::ssl::{SslContext, SslMethod};
+
+    // The test server doesn't support `AUTH TLS`.
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.set_tls_mode(TlsMode::Opportunistic);
+    let ctx = SslContext::builder(SslMethod::tls()).unwrap().build();
+    let mut ftp_stream = ftp_stream.apply_tls_mode(ctx).unwrap();
+    assert!(!ftp_stream.is_secure());
+    ftp_stream.login("Doe", "mumble").unwrap();
+    assert_eq!(ftp_stream.pwd().unwrap(), "/");
+
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.set_tls_mode(TlsMode::Required);
+    let ctx = SslContext::builder(SslMethod::tls()).unwrap().build();
+    assert!(ftp_stream.apply_tls_mode(ctx).is_err());
+}
+
+#[cfg(all(feature = "secure", not(feature = "native-tls")))]
+#[test]
+fn test_secure_in_place() {
+    use ftp::openssl::ssl::{SslContext, SslMethod};
+
+    // The test server refuses `AUTH TLS`, the stream stays usable.
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    let ctx = SslContext::builder(SslMethod::tls()).unwrap().build();
+    match ftp_stream.secure(ctx) {
+        Err(FtpError::UnexpectedResponse(_)) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+    assert!(!ftp_stream.is_secure());
+    ftp_stream.login("Doe", "mumble").unwrap();
+    assert_eq!(ftp_stream.pwd().unwrap(), "/");
+    ftp_stream.quit().unwrap();
+
+    // The server accepts `AUTH TLS` but the handshake fails: the stream
+    // can't go on in clear.
+    let replay =
+        ReplayServer::start(Transcript::parse("< 220 ready\n> AUTH TLS\n< 234 go\n")).unwrap();
+    let mut ftp_stream = FtpStream::connect(replay.addr()).unwrap();
+    let ctx = SslContext::builder(SslMethod::tls()).unwrap().build();
+    assert!(ftp_stream.secure(ctx).is_err());
+    assert!(ftp_stream.is_closed());
+    match ftp_stream.noop() {
+        Err(FtpError::ConnectionError(ref err))
+            if err.kind() == std::io::ErrorKind::NotConnected => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+    replay.finish().unwrap();
+}
+
+#[test]
+fn test_open_without_size() {
+    // A server refusing the argument of `SIZE` still lets files be opened.
+    let transcript = Transcript::parse(
+        "< 220 ready\n\
+         > TYPE I\n< 200 ok\n\
+         > SIZE data.bin\n< 504 Not implemented for that parameter\n",
+    );
+    let replay = ReplayServer::start(transcript).unwrap();
+    let mut ftp_stream = FtpStream::connect(replay.addr()).unwrap();
+    let file = ftp_stream.open("data.bin").unwrap();
+    assert_eq!(file.len(), None);
+    drop(file);
+    drop(ftp_stream);
+    replay.finish().unwrap();
+}
+
+#[test]
+fn test_open_seek() {
+    use std::io::{Seek, SeekFrom};
+
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let data: Vec<u8> = (0..8192).map(|i| (i % 251) as u8).collect();
+    ftp_stream.put_bytes("data.bin", &data).unwrap();
+
+    {
+        let mut file = ftp_stream.open("data.bin").unwrap();
+        assert_eq!(file.len(), Some(8192));
+        let mut buf = [0; 16];
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[..16]);
+
+        // Seeking ends the download, the next read resumes at the offset.
+        assert_eq!(file.seek(SeekFrom::Current(4000)).unwrap(), 4016);
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[4016..4032]);
+
+        file.seek(SeekFrom::End(-100)).unwrap();
+        let mut end = Vec::new();
+        file.read_to_end(&mut end).unwrap();
+        assert_eq!(end, data[8092..]);
+        assert_eq!(file.read(&mut buf).unwrap(), 0);
+
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let mut all = Vec::new();
+        file.read_to_end(&mut all).unwrap();
+        assert_eq!(all, data);
+
+        let err = file.seek(SeekFrom::Current(-10_000)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+        // Dropped in the middle of a download.
+        file.seek(SeekFrom::Start(10)).unwrap();
+        file.read_exact(&mut buf).unwrap();
+    }
+    ftp_stream.noop().unwrap();
+
+    assert!(ftp_stream.open("missing.bin").is_err());
+    ftp_stream.quit().unwrap();
+}
+
+/// Expires the sessions of a test server once, after the first `350`
+/// reply, so that the command following `REST` gets a `421`.
+struct ExpireAfterRest(Arc<TestServer>, bool);
+
+impl FtpObserver for ExpireAfterRest {
+    fn response_received(&mut self, response: &Line) {
+        if response.0 == Status::RequestFilePending && !self.1 {
+            self.1 = true;
+            self.0.expire_sessions();
+        }
+    }
+}
+
+#[test]
+fn test_resume_after_reconnect() {
+    use std::io::{Seek, SeekFrom};
+
+    let server = Arc::new(TestServer::start().unwrap());
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.set_auto_reconnect(true);
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let data: Vec<u8> = (0..8192).map(|i| (i % 251) as u8).collect();
+    ftp_stream.put_bytes("data.bin", &data).unwrap();
+
+    // The retried RETR resumes from the offset too.
+    ftp_stream.set_observer(Some(Box::new(ExpireAfterRest(Arc::clone(&server), false))));
+    {
+        let mut file = ftp_stream.open("data.bin").unwrap();
+        file.seek(SeekFrom::Start(4000)).unwrap();
+        let mut end = Vec::new();
+        file.read_to_end(&mut end).unwrap();
+        assert_eq!(end, data[4000..]);
+    }
+
+    ftp_stream.set_observer(Some(Box::new(ExpireAfterRest(Arc::clone(&server), false))));
+    let local = std::env::temp_dir().join("ftp_resume_after_reconnect.bin");
+    std::fs::write(&local, &data[..3000]).unwrap();
+    assert_eq!(ftp_stream.download_to("data.bin", &local).unwrap(), 5192);
+    assert_eq!(std::fs::read(&local).unwrap(), data);
+    std::fs::remove_file(&local).unwrap();
+
+    let mut checkpoint = ftp::checkpoint::TransferCheckpoint::new("data.bin");
+    checkpoint.offset = 5000;
+    checkpoint.size = Some(8192);
+    checkpoint.mdtm = ftp_stream.mdtm("data.bin").unwrap();
+    let mut local = data[..5000].to_vec();
+    ftp_stream.set_observer(Some(Box::new(ExpireAfterRest(Arc::clone(&server), false))));
+    ftp_stream
+        .retr_checkpointed(checkpoint.clone(), &mut local, 1024, |_| Ok(()))
+        .unwrap();
+    assert_eq!(local, data);
+
+    ftp_stream.put_bytes("upload.bin", &data[..5000]).unwrap();
+    checkpoint.remote_path = "upload.bin".to_owned();
+    checkpoint.mdtm = None;
+    ftp_stream.set_observer(Some(Box::new(ExpireAfterRest(Arc::clone(&server), false))));
+    ftp_stream
+        .put_checkpointed(checkpoint, &mut Cursor::new(&data), 1024, |_| Ok(()))
+        .unwrap();
+    assert_eq!(ftp_stream.get_bytes("upload.bin").unwrap(), data);
+
+    ftp_stream.set_observer(Some(Box::new(ExpireAfterRest(Arc::clone(&server), false))));
+    {
+        let mut follower = ftp_stream
+            .follow("data.bin", Duration::from_millis(10))
+            .unwrap();
+        let mut writer = FtpStream::connect(server.addr()).unwrap();
+        writer.login("Doe", "mumble").unwrap();
+        writer.append("data.bin", &mut &b"appended"[..]).unwrap();
+        assert_eq!(follower.poll().unwrap(), b"appended");
+    }
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_create_seek_write() {
+    use std::io::{Seek, SeekFrom};
+
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+
+    {
+        let mut file = ftp_stream.create("out.bin").unwrap();
+        assert!(file.is_empty());
+        file.write_all(b"size=????;").unwrap();
+        file.write_all(&[7; 4000]).unwrap();
+        assert_eq!(file.len(), Some(4010));
+        file.seek(SeekFrom::Start(5)).unwrap();
+        file.write_all(b"4010").unwrap();
+
+        // Reading ends the upload first.
+        let mut header = [0; 10];
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_exact(&mut header).unwrap();
+        assert_eq!(&header, b"size=4010;");
+
+        // Only an empty file can be written from the start.
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let err = file.write_all(b"x").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+        file.close().unwrap();
+    }
+    let data = ftp_stream.get_bytes("out.bin").unwrap();
+    assert_eq!(data.len(), 4010);
+    assert_eq!(&data[..10], b"size=4010;");
+
+    // Existing files are patched in place.
+    let mut file = ftp_stream.open("out.bin").unwrap();
+    file.seek(SeekFrom::End(-2)).unwrap();
+    file.write_all(b"!!!").unwrap();
+    assert_eq!(file.len(), Some(4011));
+    drop(file);
+    let data = ftp_stream.get_bytes("out.bin").unwrap();
+    assert_eq!(&data[4008..], b"!!!");
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_download_to_upload_from() {
+    use std::time::SystemTime;
+
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.set_preserve_mtime(true);
+    // The helpers transfer in binary mode whatever the current type.
+    ftp_stream
+        .transfer_type(FileType::Ascii(FormatControl::Default))
+        .unwrap();
+
+    let local = std::env::temp_dir().join("ftp_upload_from.bin");
+    std::fs::write(&local, [0, 1, 2, 3]).unwrap();
+    let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    std::fs::File::options()
+        .write(true)
+        .open(&local)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+    assert_eq!(
+        ftp_stream
+            .upload_from(&local, "data.bin", OverwritePolicy::Overwrite)
+            .unwrap(),
+        UploadOutcome::Stored {
+            path: "data.bin".to_owned(),
+            bytes: 4
+        }
+    );
+    let remote_mtime = ftp_stream.mdtm("data.bin").unwrap().unwrap();
+    assert_eq!(SystemTime::from(remote_mtime), mtime);
+
+    let downloaded = std::env::temp_dir().join("ftp_download_to.bin");
+    assert_eq!(ftp_stream.download_to("data.bin", &downloaded).unwrap(), 4);
+    assert_eq!(std::fs::read(&downloaded).unwrap(), [0, 1, 2, 3]);
+    let local_mtime = std::fs::metadata(&downloaded).unwrap().modified().unwrap();
+    assert_eq!(local_mtime, mtime);
+
+    // A failed download leaves no partial file behind.
+    ftp_stream.set_restart_downloads(true);
+    ftp_stream.set_max_download_size(Some(2));
+    assert!(ftp_stream.download_to("data.bin", &downloaded).is_err());
+    assert!(!downloaded.exists());
+    assert!(ftp_stream.download_to("missing.bin", &downloaded).is_err());
+    assert!(!downloaded.exists());
+
+    std::fs::remove_file(&local).unwrap();
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_download_to_resume() {
+    use std::time::SystemTime;
+
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let data: Vec<u8> = (0..8192).map(|i| (i % 251) as u8).collect();
+    ftp_stream.put_bytes("data.bin", &data).unwrap();
+
+    // An interrupted download is resumed from the local length.
+    let local = std::env::temp_dir().join("ftp_download_to_resume.bin");
+    std::fs::write(&local, &data[..3000]).unwrap();
+    assert_eq!(ftp_stream.download_to("data.bin", &local).unwrap(), 5192);
+    assert_eq!(std::fs::read(&local).unwrap(), data);
+    assert_eq!(ftp_stream.download_to("data.bin", &local).unwrap(), 0);
+
+    // The remote file changed since the partial download started.
+    std::fs::write(&local, [0; 3000]).unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(&local)
+        .unwrap()
+        .set_modified(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    assert_eq!(ftp_stream.download_to("data.bin", &local).unwrap(), 8192);
+    assert_eq!(std::fs::read(&local).unwrap(), data);
+
+    // A local file longer than the remote one isn't a part of it.
+    std::fs::write(&local, [0; 9000]).unwrap();
+    assert_eq!(ftp_stream.download_to("data.bin", &local).unwrap(), 8192);
+
+    std::fs::write(&local, &data[..3000]).unwrap();
+    ftp_stream.set_restart_downloads(true);
+    assert_eq!(ftp_stream.download_to("data.bin", &local).unwrap(), 8192);
+    assert_eq!(std::fs::read(&local).unwrap(), data);
+
+    std::fs::remove_file(&local).unwrap();
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_overwrite_policy() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let stored = |path: &str, bytes| UploadOutcome::Stored {
+        path: path.to_owned(),
+        bytes,
+    };
+
+    let mut put =
+        |data: &[u8], policy| ftp_stream.put_with_policy("notes.txt", &mut &data[..], policy);
+    assert_eq!(
+        put(b"first", OverwritePolicy::Fail).unwrap(),
+        stored("notes.txt", 5)
+    );
+    match put(b"second", OverwritePolicy::Fail) {
+        Err(err @ FtpError::AlreadyExists(_)) => assert!(err.is_permanent()),
+        res => panic!("expected AlreadyExists, got {:?}", res),
+    }
+    assert_eq!(
+        put(b"second", OverwritePolicy::Skip).unwrap(),
+        UploadOutcome::Skipped
+    );
+    assert_eq!(
+        put(b"first, then more", OverwritePolicy::Resume).unwrap(),
+        stored("notes.txt", 11)
+    );
+    assert_eq!(
+        put(b"renamed", OverwritePolicy::RenameNew).unwrap(),
+        stored("notes.1.txt", 7)
+    );
+    assert_eq!(
+        put(b"renamed again", OverwritePolicy::RenameNew).unwrap(),
+        stored("notes.2.txt", 13)
+    );
+    assert_eq!(
+        ftp_stream.get_bytes("notes.txt").unwrap(),
+        b"first, then more"
+    );
+    assert_eq!(
+        ftp_stream.get_bytes("notes.2.txt").unwrap(),
+        b"renamed again"
+    );
+
+    // Local files are resumed by seeking.
+    let local = std::env::temp_dir().join("ftp_overwrite_policy.txt");
+    std::fs::write(&local, b"first, then more, and the end").unwrap();
+    let mut upload = |policy| ftp_stream.upload_from(&local, "notes.txt", policy);
+    assert_eq!(
+        upload(OverwritePolicy::Resume).unwrap(),
+        stored("notes.txt", 13)
+    );
+    assert_eq!(
+        upload(OverwritePolicy::Resume).unwrap(),
+        UploadOutcome::Skipped
+    );
+    assert_eq!(
+        ftp_stream.get_bytes("notes.txt").unwrap(),
+        b"first, then more, and the end"
+    );
+    std::fs::remove_file(&local).unwrap();
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_storage_availability() {
+    let replay = ReplayServer::start(Transcript::parse(
+        "< 220 ready\n> USER Doe\n< 331 password\n> PASS ****\n< 230 ok\n\
+         > AVBL /incoming\n< 213 1048576\n\
+         > AVBL /incoming\n< 213 1048576\n\
+         > AVBL /incoming\n< 502 Command not implemented.\n\
+         > SITE QUOTA\n< 200-The current quota for this session are [current/limit]:\n\
+         < 200-Name: doe\n< 200-  Uploaded bytes:  786432.00/1048576.00\n\
+         < 200 Please contact root if these entries are inaccurate\n\
+         > QUIT\n< 221 Goodbye.\n",
+    ))
+    .unwrap();
+    let mut ftp_stream = FtpStream::connect(replay.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    assert_eq!(ftp_stream.avail("/incoming").unwrap(), 1 << 20);
+    ftp_stream.ensure_available("/incoming", 1000).unwrap();
+    // Without `AVBL`, the space left is derived from the quota.
+    match ftp_stream.ensure_available("/incoming", 1 << 20) {
+        Err(err @ FtpError::InsufficientStorage { .. }) => {
+            assert!(err.is_permanent());
+            assert_eq!(
+                err.to_string(),
+                "FTP InsufficientStorage: 1048576 bytes needed, 262144 available"
+            );
+        }
+        res => panic!("expected InsufficientStorage, got {:?}", res),
+    }
+    ftp_stream.quit().unwrap();
+    replay.finish().unwrap();
+}
+
+#[test]
+fn test_mv() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.put_bytes("a.txt", b"a").unwrap();
+
+    ftp_stream.mv("a.txt", "/x/y/b.txt", true).unwrap();
+    assert_eq!(ftp_stream.get_bytes("/x/y/b.txt").unwrap(), b"a");
+    match ftp_stream.mv("a.txt", "c.txt", false) {
+        Err(FtpError::NotFound(path)) => assert_eq!(path, "a.txt"),
+        res => panic!("expected NotFound, got {:?}", res),
+    }
+    // Without `create_dirs` the target directory must exist.
+    assert!(ftp_stream.mv("/x/y/b.txt", "/z/b.txt", false).is_err());
+    ftp_stream.quit().unwrap();
+
+    let replay = ReplayServer::start(Transcript::parse(
+        "< 220 ready\n> USER Doe\n< 331 password\n> PASS ****\n< 230 ok\n\
+         > RNFR a.txt\n< 350 Ready for RNTO.\n> RNTO b.txt\n< 553 Rename failed.\n\
+         > TYPE I\n< 200 ok\n> SIZE b.txt\n< 213 1\n> TYPE A N\n< 200 ok\n\
+         > RNFR a.txt\n< 350 Ready for RNTO.\n> RNTO b.txt\n< 553 Rename failed.\n\
+         > TYPE I\n< 200 ok\n> SIZE b.txt\n< 500 Unknown command.\n> TYPE A N\n< 200 ok\n\
+         > QUIT\n< 221 Goodbye.\n",
+    ))
+    .unwrap();
+    let mut ftp_stream = FtpStream::connect(replay.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    match ftp_stream.mv("a.txt", "b.txt", false) {
+        Err(FtpError::AlreadyExists(path)) => assert_eq!(path, "b.txt"),
+        res => panic!("expected AlreadyExists, got {:?}", res),
+    }
+    // The refusal is kept when the target can't be checked.
+    match ftp_stream.mv("a.txt", "b.txt", false) {
+        Err(FtpError::UnexpectedResponse(reply)) => {
+            assert_eq!(reply.line.0, Status::BadFilename)
+        }
+        res => panic!("expected UnexpectedResponse, got {:?}", res),
+    }
+    ftp_stream.quit().unwrap();
+    replay.finish().unwrap();
+}
+
+#[test]
+fn test_copy() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+    ftp_stream.put_bytes("big.bin", &data).unwrap();
+
+    // The test server has no SITE CPFR, the data goes through the client.
+    ftp_stream.mkdir("copies").unwrap();
+    ftp_stream.copy("big.bin", "copies/big.bin").unwrap();
+    assert_eq!(ftp_stream.get_bytes("copies/big.bin").unwrap(), data);
+    assert!(ftp_stream
+        .copy("missing.bin", "copies/missing.bin")
+        .is_err());
+    ftp_stream.noop().unwrap();
+    ftp_stream.quit().unwrap();
+
+    let replay = ReplayServer::start(Transcript::parse(
+        "< 220 ready\n> USER Doe\n< 331 password\n> PASS ****\n< 230 ok\n\
+         > SITE CPFR a.txt\n< 350 File or directory exists, ready for destination name\n\
+         > SITE CPTO b.txt\n< 250 Copy successful\n\
+         > SITE CPFR c.txt\n< 550 c.txt: No such file or directory\n\
+         > QUIT\n< 221 Goodbye.\n",
+    ))
+    .unwrap();
+    let mut ftp_stream = FtpStream::connect(replay.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.copy("a.txt", "b.txt").unwrap();
+    match ftp_stream.copy("c.txt", "d.txt") {
+        Err(FtpError::NotFound(path)) => assert_eq!(path, "c.txt"),
+        res => panic!("expected NotFound, got {:?}", res),
+    }
+    ftp_stream.quit().unwrap();
+    replay.finish().unwrap();
+}
+
+#[test]
+fn test_watch() {
+    use ftp::watch::WatchEvent;
+
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.mkdir("incoming").unwrap();
+    ftp_stream.put_bytes("incoming/old.csv", b"a").unwrap();
+    let mut partner = FtpStream::connect(server.addr()).unwrap();
+    partner.login("Doe", "mumble").unwrap();
+
+    for mlsd in [false, true] {
+        if mlsd {
+            ftp_stream.feat().unwrap();
+        }
+        let mut watcher = ftp_stream
+            .watch("incoming", Duration::from_millis(10))
+            .unwrap();
+        // Files present at the start aren't reported.
+        assert_eq!(watcher.poll().unwrap(), []);
+
+        partner.put_bytes("incoming/new.csv", b"1,2").unwrap();
+        partner.append("incoming/old.csv", &mut &b"b"[..]).unwrap();
+        let events = watcher.poll().unwrap();
+        assert!(matches!(events[..], [
+            WatchEvent::Created(ref new),
+            WatchEvent::Modified(ref old),
+        ] if new.name == "new.csv" && old.name == "old.csv"));
+
+        partner.rm("incoming/new.csv").unwrap();
+        match watcher.next() {
+            Some(Ok(WatchEvent::Deleted(entry))) => assert_eq!(entry.name, "new.csv"),
+            event => panic!("expected a deletion, got {:?}", event),
+        }
+    }
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_follow() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let mut writer = FtpStream::connect(server.addr()).unwrap();
+    writer.login("Doe", "mumble").unwrap();
+    writer.put_bytes("app.log", b"old line\n").unwrap();
+
+    let mut follower = ftp_stream
+        .follow("app.log", Duration::from_millis(10))
+        .unwrap();
+    assert_eq!(follower.offset(), 9);
+    assert_eq!(follower.poll().unwrap(), b"");
+    writer.append("app.log", &mut &b"one\n"[..]).unwrap();
+    writer.append("app.log", &mut &b"two\n"[..]).unwrap();
+    assert_eq!(follower.poll().unwrap(), b"one\ntwo\n");
+    writer.append("app.log", &mut &b"three\n"[..]).unwrap();
+    assert_eq!(follower.next().unwrap().unwrap(), b"three\n");
+    assert_eq!(follower.offset(), 23);
+
+    // A rotated log is followed from its start.
+    writer.put_bytes("app.log", b"new\n").unwrap();
+    assert_eq!(follower.poll().unwrap(), b"new\n");
+    writer.rm("app.log").unwrap();
+    assert!(follower.poll().is_err());
+    writer.quit().unwrap();
+}
+
+#[test]
+fn test_clnt() {
+    let replay = ReplayServer::start(Transcript::parse(&format!(
+        "< 220 ready\n> CLNT rust-ftp {}\n< 200 Noted.\n\
+         > USER Doe\n< 331 password\n> PASS ****\n< 230 ok\n\
+         > CLNT backup-agent 2.1\n< 500 Unknown command.\n\
+         > QUIT\n< 221 Goodbye.\n",
+        env!("CARGO_PKG_VERSION")
+    )))
+    .unwrap();
+    let mut ftp_stream = FtpStream::connect(replay.addr()).unwrap();
+    ftp_stream.clnt(None).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    match ftp_stream.clnt(Some("backup-agent 2.1")) {
+        Err(FtpError::UnexpectedResponse(reply)) => assert_eq!(reply.line.0, Status::BadCommand),
+        res => panic!("expected UnexpectedResponse, got {:?}", res),
+    }
+    ftp_stream.quit().unwrap();
+    replay.finish().unwrap();
+}
+
+#[test]
+fn test_nlst_paths() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.mkdir("pub").unwrap();
+    ftp_stream.put_bytes("pub/a.txt", b"a").unwrap();
+    ftp_stream.put_bytes("pub/b.txt", b"b").unwrap();
+    let paths = |ftp_stream: &mut FtpStream, path| {
+        ftp_stream
+            .nlst_paths(path)
+            .unwrap()
+            .iter()
+            .map(|path| path.to_string())
+            .collect::<Vec<_>>()
+    };
+    // The test server lists bare names.
+    assert_eq!(
+        paths(&mut ftp_stream, Some("pub")),
+        ["pub/a.txt", "pub/b.txt"]
+    );
+    assert_eq!(
+        paths(&mut ftp_stream, Some("/pub/")),
+        ["/pub/a.txt", "/pub/b.txt"]
+    );
+    assert_eq!(paths(&mut ftp_stream, Some("pub/a.txt")), ["pub/a.txt"]);
+    for path in paths(&mut ftp_stream, Some("pub")) {
+        ftp_stream.get_bytes(&path).unwrap();
+    }
+    ftp_stream.cwd("pub").unwrap();
+    assert_eq!(paths(&mut ftp_stream, None), ["a.txt", "b.txt"]);
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_stat_many() {
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl FtpObserver for Recorder {
+        fn command_sent(&mut self, command: &str) {
+            self.0.lock().unwrap().push(format!("> {}", command));
+        }
+
+        fn response_received(&mut self, response: &Line) {
+            self.0.lock().unwrap().push(format!("< {}", response.0));
+        }
+    }
+
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.mkdir("dir").unwrap();
+    let mut paths: Vec<String> = (0..20).map(|i| format!("file{}.txt", i)).collect();
+    for (i, path) in paths.iter().enumerate() {
+        ftp_stream.put_bytes(path, &vec![b'x'; i]).unwrap();
+    }
+    paths.extend(["dir".to_owned(), "missing.txt".to_owned()]);
+
+    let log = Arc::new(Mutex::new(Vec::new()));
+    ftp_stream.set_observer(Some(Box::new(Recorder(Arc::clone(&log)))));
+    let stats = ftp_stream.stat_many(&paths).unwrap();
+    ftp_stream.set_observer(None);
+    assert_eq!(stats.len(), 22);
+    for (i, stat) in stats[..20].iter().enumerate() {
+        assert_eq!(stat.size, Some(i as u64));
+        assert_eq!(stat.modified, ftp_stream.mdtm(&paths[i]).unwrap());
+    }
+    assert_eq!(stats[20], FileStat::default());
+    assert_eq!(stats[21], FileStat::default());
+    // The first batch of commands is sent before any reply is read, after
+    // `TYPE I`, which is restored at the end.
+    let log = log.lock().unwrap();
+    let batch = log.iter().skip_while(|line| !line.starts_with("> SIZE"));
+    assert!(batch.take(16).all(|line| line.starts_with('>')));
+    assert_eq!(
+        log.iter().filter(|line| line.starts_with('<')).count(),
+        2 + 44
+    );
+
+    ftp_stream.set_pipeline_depth(1);
+    assert_eq!(ftp_stream.stat_many(&paths).unwrap(), stats);
+    assert!(ftp_stream.stat_many::<&str>(&[]).unwrap().is_empty());
+    match ftp_stream.stat_many(&["a.txt", "b.txt\r\nDELE a.txt"]) {
+        Err(FtpError::InvalidArgument(_)) => {}
+        res => panic!("expected InvalidArgument, got {:?}", res),
+    }
+    ftp_stream.noop().unwrap();
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_list_entries_fallback() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    ftp_stream.feat().unwrap();
+    ftp_stream.mkdir("pub").unwrap();
+    ftp_stream.mkdir("pub/sub").unwrap();
+    ftp_stream.put_bytes("pub/a.txt", b"abc").unwrap();
+    let summary = |entries: Vec<DirEntry>| {
+        entries
+            .into_iter()
+            .map(|entry| (entry.name, entry.kind, entry.size, entry.modified.is_some()))
+            .collect::<Vec<_>>()
+    };
+
+    let mlsd = summary(ftp_stream.list_entries(Some("pub")).unwrap());
+    assert_eq!(
+        mlsd[0],
+        ("a.txt".to_owned(), EntryKind::File, Some(3), true)
+    );
+    assert_eq!(mlsd[1].1, EntryKind::Directory);
+    // Without MLSD, LIST is parsed instead.
+    server.disable_commands(&["MLSD"]);
+    assert_eq!(
+        summary(ftp_stream.list_entries(Some("pub")).unwrap())
+            .into_iter()
+            .map(|(name, kind, _, _)| (name, kind))
+            .collect::<Vec<_>>(),
+        [
+            ("a.txt".to_owned(), EntryKind::File),
+            ("sub".to_owned(), EntryKind::Directory)
+        ]
+    );
+    // Without LIST either, NLST only tells the names, and SIZE and MDTM
+    // only answer for files.
+    server.disable_commands(&["MLSD", "LIST"]);
+    assert_eq!(
+        summary(ftp_stream.list_entries(Some("pub")).unwrap()),
+        [
+            ("a.txt".to_owned(), EntryKind::File, Some(3), true),
+            ("sub".to_owned(), EntryKind::Other, None, false)
+        ]
+    );
+    ftp_stream.quit().unwrap();
+}
+
+#[test]
+fn test_put_chunks() {
+    let server = TestServer::start().unwrap();
+    let mut ftp_stream = FtpStream::connect(server.addr()).unwrap();
+    ftp_stream.login("Doe", "mumble").unwrap();
+    let chunks = vec![
+        Ok(b"a,b\n".to_vec()),
+        Ok(Vec::new()),
+        Ok(vec![b'x'; 100_000]),
+    ];
+    assert_eq!(ftp_stream.put_chunks("rows.csv", chunks).unwrap(), 100_004);
+    let data = ftp_stream.get_bytes("rows.csv").unwrap();
+    assert_eq!(data.len(), 100_004);
+    assert!(data.starts_with(b"a,b\nxxx"));
+
+    let failing = vec![
+        Ok(b"partial".to_vec()),
+        Err(std::io::Error::other("generator failed")),
+        Ok(b"never sent".to_vec()),
+    ];
+    match ftp_stream.put_chunks("failed.csv", failing) {
+        Err(FtpError::ConnectionError(err)) => assert_eq!(err.to_string(), "generator failed"),
+        res => panic!("expected the generator's error, got {:?}", res),
+    }
+    // The connection is still usable.
+    assert_eq!(ftp_stream.get_bytes("failed.csv").unwrap(), b"partial");
+    ftp_stream.quit().unwrap();
+}